@@ -6,6 +6,7 @@ pub mod local_webserver;
 mod logger;
 mod routines;
 pub mod settings;
+pub mod tracing;
 mod watcher;
 use super::metrics::Metrics;
 use std::cmp::Ordering;
@@ -16,17 +17,21 @@ use std::sync::Arc;
 use clap::Parser;
 use commands::{
     AggregationCommands, Commands, ConsumptionCommands, FunctionCommands, GenerateCommand,
+    StateCommands,
 };
 use config::ConfigError;
 use display::with_spinner_async;
 use home::home_dir;
-use log::{debug, info};
+use log::{debug, info, warn};
 use logger::setup_logging;
 use regex::Regex;
+use routines::lineage::{show_impact, show_lineage};
 use routines::ls::{list_db, list_streaming};
-use routines::plan;
 use routines::ps::show_processes;
+use routines::state::{export_state_file, import_state_file};
+use routines::{history, plan, rollback};
 use settings::{read_settings, Settings};
+use tracing::{setup_tracing, shutdown_tracing};
 
 use crate::cli::routines::aggregation::create_aggregation_file;
 use crate::cli::routines::consumption::create_consumption_file;
@@ -35,7 +40,10 @@ use crate::cli::routines::dev::copy_old_schema;
 use crate::cli::routines::initialize::initialize_project;
 use crate::cli::routines::logs::{follow_logs, show_logs};
 use crate::cli::routines::migrate::generate_migration;
-use crate::cli::routines::streaming::create_streaming_function_file;
+use crate::cli::routines::streaming::{
+    build_wasm_function, create_streaming_function_file, create_wasm_function_crate,
+    replay_streaming_function, swap_staged_replay, test_streaming_function,
+};
 use crate::cli::routines::templates;
 use crate::cli::routines::version::bump_version;
 use crate::cli::routines::{RoutineFailure, RoutineSuccess};
@@ -48,7 +56,8 @@ use crate::framework::core::code_loader::load_framework_objects;
 use crate::framework::languages::SupportedLanguages;
 use crate::framework::sdk::ingest::generate_sdk;
 use crate::infrastructure::olap::clickhouse::version_sync::{parse_version, version_to_string};
-use crate::project::Project;
+use crate::project::python_project::KAFKA_CLIENT_DEPENDENCY;
+use crate::project::{LanguageProjectConfig, Project};
 use crate::utilities::constants::{CLI_VERSION, PROJECT_NAME_ALLOW_PATTERN};
 use crate::utilities::git::is_git_repo;
 
@@ -103,6 +112,23 @@ fn check_project_name(name: &str) -> Result<(), RoutineFailure> {
     Ok(())
 }
 
+// The python runners of the streaming functions import confluent-kafka, which the projects
+// created with older versions of moose don't install
+fn check_python_dependencies(project: &Project) -> Result<(), RoutineFailure> {
+    if let LanguageProjectConfig::Python(python_project) = &project.language_project_config {
+        if !python_project.depends_on(KAFKA_CLIENT_DEPENDENCY) {
+            return Err(RoutineFailure::error(Message {
+                action: "Dependencies".to_string(),
+                details: format!(
+                    "The streaming functions need {0}: replace kafka-python-ng with {0}==2.4.0 in the install_requires of setup.py, then run `pip install .`",
+                    KAFKA_CLIENT_DEPENDENCY
+                ),
+            }));
+        }
+    }
+    Ok(())
+}
+
 async fn top_command_handler(
     settings: Settings,
     commands: &Commands,
@@ -281,6 +307,7 @@ async fn top_command_handler(
             );
 
             check_project_name(&project_arc.name())?;
+            check_python_dependencies(&project_arc)?;
             run_local_infrastructure(&project_arc)?.show();
 
             routines::start_development_mode(project_arc, &settings.features, metrics)
@@ -374,7 +401,12 @@ async fn top_command_handler(
                 details: "Please provide a subcommand".to_string(),
             })),
         },
-        Commands::Prod {} => {
+        Commands::Prod {
+            plan,
+            allow_destructive,
+            resume,
+            rollback,
+        } => {
             info!("Running prod command");
             let mut project = load_project()?;
 
@@ -388,17 +420,46 @@ async fn top_command_handler(
             );
 
             check_project_name(&project_arc.name())?;
+            check_python_dependencies(&project_arc)?;
 
-            routines::start_production_mode(project_arc, settings.features, metrics)
-                .await
-                .unwrap();
+            if *rollback {
+                routines::rollback_production_deployment(&project_arc)
+                    .await
+                    .map_err(|e| {
+                        RoutineFailure::error(Message {
+                            action: "Rollback".to_string(),
+                            details: format!("Failed to roll back the deployment: {:?}", e),
+                        })
+                    })?;
+
+                return Ok(RoutineSuccess::success(Message::new(
+                    "Rolled back".to_string(),
+                    "production infrastructure".to_string(),
+                )));
+            }
+
+            routines::start_production_mode(
+                project_arc,
+                settings.features,
+                plan.as_deref(),
+                *allow_destructive,
+                *resume,
+                metrics,
+            )
+            .await
+            .map_err(|e| {
+                RoutineFailure::error(Message {
+                    action: "Prod".to_string(),
+                    details: format!("Failed to start production mode: {:?}", e),
+                })
+            })?;
 
             Ok(RoutineSuccess::success(Message::new(
                 "Ran".to_string(),
                 "production infrastructure".to_string(),
             )))
         }
-        Commands::Plan {} => {
+        Commands::Plan { out, refresh } => {
             info!("Running plan command");
             let project = load_project()?;
 
@@ -409,16 +470,64 @@ async fn top_command_handler(
             );
 
             check_project_name(&project.name())?;
-            plan(&project).await.map_err(|e| {
+            plan(&project, out.as_deref(), *refresh)
+                .await
+                .map_err(|e| {
+                    RoutineFailure::error(Message {
+                        action: "Plan".to_string(),
+                        details: format!("Failed to plan changes: {:?}", e),
+                    })
+                })?;
+
+            Ok(RoutineSuccess::success(Message::new(
+                "Plan".to_string(),
+                "Successfuly planned changes to the infrastructure".to_string(),
+            )))
+        }
+        Commands::History { diff } => {
+            info!("Running history command");
+            let project = load_project()?;
+
+            crate::utilities::capture::capture!(
+                ActivityType::HistoryCommand,
+                project.name().clone(),
+                &settings
+            );
+
+            check_project_name(&project.name())?;
+            history(&project, diff.as_deref()).await.map_err(|e| {
                 RoutineFailure::error(Message {
-                    action: "Plan".to_string(),
-                    details: format!("Failed to plan changes: {:?}", e),
+                    action: "History".to_string(),
+                    details: format!("Failed to read the history: {:?}", e),
                 })
             })?;
 
             Ok(RoutineSuccess::success(Message::new(
-                "Plan".to_string(),
-                "Successfuly planned changes to the infrastructure".to_string(),
+                "".to_string(),
+                "".to_string(),
+            )))
+        }
+        Commands::Rollback { id, out } => {
+            info!("Running rollback command");
+            let project = load_project()?;
+
+            crate::utilities::capture::capture!(
+                ActivityType::RollbackCommand,
+                project.name().clone(),
+                &settings
+            );
+
+            check_project_name(&project.name())?;
+            rollback(&project, id, out.as_deref()).await.map_err(|e| {
+                RoutineFailure::error(Message {
+                    action: "Rollback".to_string(),
+                    details: format!("Failed to plan the rollback: {:?}", e),
+                })
+            })?;
+
+            Ok(RoutineSuccess::success(Message::new(
+                "Rollback".to_string(),
+                "planned".to_string(),
             )))
         }
         Commands::BumpVersion { new_version } => {
@@ -493,13 +602,76 @@ async fn top_command_handler(
                     );
 
                     check_project_name(&project_arc.name())?;
-                    create_streaming_function_file(
-                        &project_arc,
-                        init.source.clone(),
-                        init.destination.clone(),
+                    if init.wasm {
+                        create_wasm_function_crate(&project_arc, &init.source, &init.destination)
+                            .await
+                    } else {
+                        create_streaming_function_file(
+                            &project_arc,
+                            init.source.clone(),
+                            init.destination.clone(),
+                        )
+                        .await
+                    }
+                }
+                FunctionCommands::Test(test) => {
+                    let project = load_project()?;
+
+                    crate::utilities::capture::capture!(
+                        ActivityType::FuncTestCommand,
+                        project.name().clone(),
+                        &settings
+                    );
+
+                    test_streaming_function(
+                        &project,
+                        &test.name,
+                        test.input.as_deref(),
+                        test.samples,
+                        test.expected.as_deref(),
+                    )
+                    .await
+                }
+                FunctionCommands::Replay(replay) => {
+                    let project = load_project()?;
+
+                    crate::utilities::capture::capture!(
+                        ActivityType::FuncReplayCommand,
+                        project.name().clone(),
+                        &settings
+                    );
+
+                    replay_streaming_function(
+                        &project,
+                        &replay.name,
+                        replay.from,
+                        replay.to,
+                        replay.staging,
                     )
                     .await
                 }
+                FunctionCommands::Swap(swap) => {
+                    let project = load_project()?;
+
+                    crate::utilities::capture::capture!(
+                        ActivityType::FuncSwapCommand,
+                        project.name().clone(),
+                        &settings
+                    );
+
+                    swap_staged_replay(&project, &swap.name, swap.discard).await
+                }
+                FunctionCommands::Build(build) => {
+                    let project = load_project()?;
+
+                    crate::utilities::capture::capture!(
+                        ActivityType::FuncBuildCommand,
+                        project.name().clone(),
+                        &settings
+                    );
+
+                    build_wasm_function(&project, &build.name).await
+                }
             }
         }
         Commands::Aggregation(aggregation) => {
@@ -581,7 +753,47 @@ async fn top_command_handler(
                 &settings
             );
 
-            show_processes(project_arc)
+            show_processes(project_arc).await
+        }
+        Commands::State(state_args) => {
+            info!("Running state command");
+            let project = load_project()?;
+
+            crate::utilities::capture::capture!(
+                ActivityType::StateCommand,
+                project.name().clone(),
+                &settings
+            );
+
+            check_project_name(&project.name())?;
+            match state_args.command.as_ref().unwrap() {
+                StateCommands::Export { file, storage } => {
+                    export_state_file(&project, file, *storage).await
+                }
+                StateCommands::Import { file, storage } => {
+                    import_state_file(&project, file, *storage).await
+                }
+            }
+        }
+        Commands::Lineage {
+            format,
+            out,
+            impact,
+        } => {
+            info!("Running lineage command");
+            let project = load_project()?;
+
+            crate::utilities::capture::capture!(
+                ActivityType::LineageCommand,
+                project.name().clone(),
+                &settings
+            );
+
+            check_project_name(&project.name())?;
+            match impact {
+                Some(data_model) => show_impact(&project, data_model).await,
+                None => show_lineage(&project, *format, out.as_deref()).await,
+            }
         }
         Commands::Ls {
             version,
@@ -627,6 +839,12 @@ pub async fn cli_run() {
 
     let config = read_settings().unwrap();
     setup_logging(&config.logger, &config.telemetry.machine_id).expect("Failed to setup logging");
+    if let Err(e) = setup_tracing(&config.tracing, &config.telemetry.machine_id) {
+        warn!(
+            "Failed to setup tracing, the traces won't be exported: {:?}",
+            e
+        );
+    }
 
     info!("CLI Configuration loaded and logging setup: {:?}", config);
 
@@ -636,7 +854,10 @@ pub async fn cli_run() {
     let arc_metrics = Arc::new(metrics);
     arc_metrics.start_listening_to_metrics(rx).await;
 
-    match top_command_handler(config, &cli.command, arc_metrics).await {
+    let result = top_command_handler(config, &cli.command, arc_metrics).await;
+    shutdown_tracing().await;
+
+    match result {
         Ok(s) => {
             show_message!(s.message_type, s.message);
             exit(0);
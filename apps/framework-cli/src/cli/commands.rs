@@ -5,7 +5,10 @@ use std::path::PathBuf;
 
 use clap::{Args, Subcommand};
 
+use crate::framework::core::lineage::LineageFormat;
 use crate::framework::languages::SupportedLanguages;
+use crate::infrastructure::state_storage::StateStorageKind;
+use crate::infrastructure::stream::redpanda::TopicPosition;
 
 #[derive(Subcommand)]
 pub enum Commands {
@@ -40,13 +43,55 @@ pub enum Commands {
         #[arg(short, long)]
         docker: bool,
     },
-    /// [Not Ready] Displays the changes that will be applied to the infrastructure during the next deployment
+    /// Displays the changes that will be applied to the infrastructure during the next deployment
     /// to production, consdering the current state of the project
-    Plan {},
+    Plan {
+        /// Write the plan to a file that `moose prod --plan` can apply
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+
+        /// Compare the stored infrastructure with the live tables and topics, report the
+        /// differences and plan the creation of the missing ones
+        #[arg(long)]
+        refresh: bool,
+    },
+    /// Lists the infrastructure deployed over time
+    History {
+        /// Display the changes between two deployments of the history
+        #[arg(long, num_args = 2, value_names = ["FROM", "TO"])]
+        diff: Option<Vec<String>>,
+    },
+    /// Plans the changes back to the infrastructure of a previous deployment
+    Rollback {
+        /// Id of the deployment, as listed by `moose history`
+        id: String,
+
+        /// Write the plan to a file that `moose prod --plan` can apply
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+    },
     /// Starts a local development environment to build your data-intensive app or service
     Dev {},
     /// Start a remote environment for use in cloud deployments
-    Prod {},
+    Prod {
+        /// Apply the plan written by `moose plan --out` instead of planning again. Fails if the
+        /// infrastructure changed since the plan was made
+        #[arg(long)]
+        plan: Option<PathBuf>,
+
+        /// Apply the changes that drop data, such as table drops, topic deletions, column drops
+        /// and type narrowings. The dropped tables and topics are backed up first
+        #[arg(long)]
+        allow_destructive: bool,
+
+        /// Resume the deployment that failed, applying the changes it didn't apply
+        #[arg(long, conflicts_with = "rollback")]
+        resume: bool,
+
+        /// Undo the changes applied by the deployment that failed, then exit
+        #[arg(long)]
+        rollback: bool,
+    },
     /// Generates missing migration files
     Generate(GenerateArgs),
     /// Bumps the version of the project
@@ -71,6 +116,23 @@ pub enum Commands {
     },
     /// View Moose processes
     Ps {},
+    /// Exports or imports the state of the infrastructure
+    State(StateArgs),
+    /// Displays how data flows through the infrastructure, from the ingest endpoints to the
+    /// consumption APIs
+    Lineage {
+        /// Format of the graph
+        #[arg(short, long, value_enum, default_value_t = LineageFormat::Dot)]
+        format: LineageFormat,
+
+        /// Write the graph to a file instead of the terminal
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+
+        /// List the infrastructure that may break when this data model changes
+        #[arg(long, value_name = "DATA_MODEL")]
+        impact: Option<String>,
+    },
     /// View Moose primitives & infrastructure
     Ls {
         /// Limit output to a specific number of data models
@@ -124,6 +186,19 @@ pub enum FunctionCommands {
     /// Structures the project's directory & files for a new streaming function
     #[command(arg_required_else_help = true)]
     Init(FuncInitArgs),
+    /// Runs a streaming function on test records, without the rest of the stack, and checks its
+    /// outputs
+    #[command(arg_required_else_help = true)]
+    Test(FuncTestArgs),
+    /// Runs a streaming function again over a range of its source topic
+    #[command(arg_required_else_help = true)]
+    Replay(FuncReplayArgs),
+    /// Moves the outputs of a staged replay to the destination topics of a streaming function
+    #[command(arg_required_else_help = true)]
+    Swap(FuncSwapArgs),
+    /// Compiles a streaming function written in Rust to WebAssembly
+    #[command(arg_required_else_help = true)]
+    Build(FuncBuildArgs),
 }
 
 #[derive(Debug, Args)]
@@ -135,6 +210,93 @@ pub struct FuncInitArgs {
     /// Name of your destination data model
     #[arg(short, long, required = true)]
     pub destination: String,
+
+    /// Creates a Rust crate compiled to WebAssembly, which runs inside moose
+    #[arg(long)]
+    pub wasm: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct FuncTestArgs {
+    /// Name of the streaming function, its file name without extension
+    pub name: String,
+
+    /// JSON or NDJSON file of source records. Records are generated from the source data model
+    /// when it is omitted
+    #[arg(short, long)]
+    pub input: Option<PathBuf>,
+
+    /// Number of source records to generate when no input file is given
+    #[arg(long, default_value_t = 10)]
+    pub samples: usize,
+
+    /// JSON or NDJSON file of the expected output records. For a function with several
+    /// destination data models, a JSON object with the records of each data model
+    #[arg(short, long)]
+    pub expected: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct FuncBuildArgs {
+    /// Name of the streaming function, the name of its crate
+    pub name: String,
+}
+
+#[derive(Debug, Args)]
+pub struct FuncReplayArgs {
+    /// Name of the streaming function, its file name without extension
+    pub name: String,
+
+    /// Offset or RFC 3339 timestamp of the source topic to start the replay from
+    #[arg(long)]
+    pub from: TopicPosition,
+
+    /// Offset or RFC 3339 timestamp of the source topic to stop the replay at. Defaults to the
+    /// end of the topic
+    #[arg(long)]
+    pub to: Option<TopicPosition>,
+
+    /// Writes the outputs to staging `<topic>_replay` topics instead of the destination topics,
+    /// to swap them in with `moose function swap` once checked
+    #[arg(long)]
+    pub staging: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct FuncSwapArgs {
+    /// Name of the streaming function, its file name without extension
+    pub name: String,
+
+    /// Deletes the staging topics without moving their records
+    #[arg(long)]
+    pub discard: bool,
+}
+
+#[derive(Debug, Args)]
+#[command(arg_required_else_help = true)]
+pub struct StateArgs {
+    #[command(subcommand)]
+    pub command: Option<StateCommands>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum StateCommands {
+    /// Writes the stored state to a JSON file
+    Export {
+        file: PathBuf,
+
+        /// Read the state from this storage instead of the configured one
+        #[arg(long, value_enum)]
+        storage: Option<StateStorageKind>,
+    },
+    /// Loads a state written by `moose state export` into the storage
+    Import {
+        file: PathBuf,
+
+        /// Write the state to this storage instead of the configured one
+        #[arg(long, value_enum)]
+        storage: Option<StateStorageKind>,
+    },
 }
 
 #[derive(Debug, Args)]
@@ -7,6 +7,7 @@ use std::sync::{Arc, RwLock};
 use tokio::macros::support::Future;
 
 use crate::framework::core::{
+    infrastructure::table::ColumnChange,
     infrastructure_map::{ApiChange, Change, OlapChange, ProcessChange, StreamingChange},
     plan::InfraPlan,
 };
@@ -303,8 +304,23 @@ pub fn show_changes(infra_plan: &InfraPlan) {
             OlapChange::Table(Change::Removed(infra)) => {
                 infra_removed(&infra.short_display());
             }
-            OlapChange::Table(Change::Updated { before, after: _ }) => {
-                infra_updated(&before.expanded_display());
+            OlapChange::Table(Change::Updated { before, after }) => {
+                infra_updated(&before.short_display());
+                let diff = before.diff(after);
+                diff.column_changes.iter().for_each(|change| match change {
+                    ColumnChange::Added { .. } => infra_added(&change.display()),
+                    ColumnChange::Removed(_) => infra_removed(&change.display()),
+                    ColumnChange::Renamed { .. } | ColumnChange::Updated { .. } => {
+                        infra_updated(&change.display())
+                    }
+                });
+                if let Some(order_by_change) = diff.order_by_change {
+                    infra_updated(&format!(
+                        "Order By: {} changed to {}",
+                        order_by_change.before.join(","),
+                        order_by_change.after.join(",")
+                    ));
+                }
             }
         });
 
@@ -4,19 +4,27 @@ use super::display::MessageType;
 use crate::cli::routines::stop::StopLocalInfrastructure;
 use crate::cli::routines::Routine;
 use crate::cli::routines::RunMode;
+use crate::cli::tracing::shutdown_tracing;
 use crate::framework::controller::RouteMeta;
 
-use crate::framework::core::infrastructure::api_endpoint::APIType;
+use crate::framework::core::infrastructure::api_endpoint::{APIType, ApiEndpoint};
+use crate::framework::core::infrastructure::table::Table;
 use crate::framework::core::infrastructure_map::ApiChange;
 use crate::framework::core::infrastructure_map::Change;
 
 use super::super::metrics::{Metrics, MetricsMessage};
 use crate::framework::data_model::config::EndpointIngestionFormat;
+use crate::infrastructure::olap::clickhouse::egress::EgressQuery;
+use crate::infrastructure::olap::clickhouse::graphql;
+use crate::infrastructure::olap::clickhouse_alt_client::{get_pool, select_as_json};
 use crate::infrastructure::stream::redpanda;
+use crate::infrastructure::stream::redpanda::trace_headers;
 use crate::infrastructure::stream::redpanda::ConfiguredProducer;
+use crate::utilities::constants::TRACER_NAME;
 
 use crate::framework::typescript::ts_node::CliMessage;
 use crate::project::Project;
+use async_graphql::dynamic::Schema;
 use bytes::Buf;
 use http_body_util::BodyExt;
 use http_body_util::Full;
@@ -30,13 +38,17 @@ use hyper_util::rt::TokioIo;
 use hyper_util::{rt::TokioExecutor, server::conn::auto};
 use log::debug;
 use log::error;
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::{SpanKind, Status, TraceContextExt, Tracer};
+use opentelemetry::{global, Context, KeyValue};
 use rdkafka::error::KafkaError;
-use rdkafka::message::OwnedMessage;
+use rdkafka::message::{OwnedHeaders, OwnedMessage};
 use rdkafka::producer::future_producer::OwnedDeliveryResult;
 use rdkafka::producer::{DeliveryFuture, FutureRecord};
 use rdkafka::util::Timeout;
 use serde::Deserialize;
 use serde::Serialize;
+use serde_json::json;
 use serde_json::Value;
 use std::collections::{HashMap, HashSet};
 use std::future::Future;
@@ -54,17 +66,26 @@ use tokio::sync::RwLock;
 pub struct RouterRequest {
     req: Request<hyper::body::Incoming>,
     route_table: &'static RwLock<HashMap<PathBuf, RouteMeta>>,
+    egress_routes: &'static RwLock<HashMap<PathBuf, ApiEndpoint>>,
+    graphql_schema: &'static RwLock<Option<Schema>>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LocalWebserverConfig {
     pub host: String,
     pub port: u16,
+    /// Serves a GraphQL API generated from the tables at `/graphql`
+    #[serde(default)]
+    pub graphql: bool,
 }
 
 impl LocalWebserverConfig {
     pub fn new(host: String, port: u16) -> Self {
-        Self { host, port }
+        Self {
+            host,
+            port,
+            graphql: false,
+        }
     }
 
     pub fn url(&self) -> String {
@@ -77,15 +98,22 @@ impl Default for LocalWebserverConfig {
         Self {
             host: "localhost".to_string(),
             port: 4000,
+            graphql: false,
         }
     }
 }
 
+// Tells the consumption runners which snapshot of the apis should serve the request
+const CONSUMPTION_VERSION_HEADER: &str = "x-moose-api-version";
+
 async fn create_client(
     req: Request<hyper::body::Incoming>,
     host: String,
     consumption_apis: &RwLock<HashSet<String>>,
     is_prod: bool,
+    api_name: &str,
+    // None when the request targets the current version
+    old_version: Option<&str>,
 ) -> Result<Response<Full<Bytes>>, anyhow::Error> {
     // local only for now
     let url = format!("http://{}:{}", host, 4001).parse::<hyper::Uri>()?;
@@ -93,18 +121,22 @@ async fn create_client(
     let host = url.host().expect("uri has no host");
     let port = url.port_u16().unwrap();
     let address = format!("{}:{}", host, port);
-    let path = req.uri().to_string();
-    let cleaned_path = path.strip_prefix("/consumption").unwrap_or(&path);
+    let cleaned_path = match req.uri().query() {
+        Some(query) => format!("/{}?{}", api_name, query),
+        None => format!("/{}", api_name),
+    };
 
-    debug!("Creating client for route: {:?}", cleaned_path);
+    debug!(
+        "Creating client for route: {:?}, version: {:?}",
+        cleaned_path, old_version
+    );
     {
         let consumption_apis = consumption_apis.read().await;
-        let consumption_name = req
-            .uri()
-            .path()
-            .strip_prefix("/consumption/")
-            .unwrap_or(cleaned_path);
-        if !consumption_apis.contains(consumption_name) {
+        let consumption_name = match old_version {
+            Some(version) => format!("{}/{}", api_name, version),
+            None => api_name.to_string(),
+        };
+        if !consumption_apis.contains(&consumption_name) {
             if !is_prod {
                 println!(
                     "Consumption API {} not found. Available consumption paths: {}",
@@ -135,16 +167,27 @@ async fn create_client(
 
     let authority = url.authority().unwrap().clone();
 
-    let req = Request::builder()
+    let mut req = Request::builder()
         .uri(cleaned_path)
-        .header(hyper::header::HOST, authority.as_str())
-        .body(Full::new(Bytes::new()))?;
+        .header(hyper::header::HOST, authority.as_str());
+    if let Some(version) = old_version {
+        req = req.header(CONSUMPTION_VERSION_HEADER, version);
+    }
+    let req = req.body(Full::new(Bytes::new()))?;
 
     let res = sender.send_request(req).await?;
+    // The runners map user errors to 4xx and failures to 5xx, we forward them as is.
+    let status = res.status();
+    let content_type = res
+        .headers()
+        .get(hyper::header::CONTENT_TYPE)
+        .cloned()
+        .unwrap_or(hyper::header::HeaderValue::from_static("application/json"));
     let body = res.collect().await.unwrap().to_bytes().to_vec();
 
     Ok(Response::builder()
-        .status(StatusCode::OK)
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, content_type)
         .header("Access-Control-Allow-Origin", "*")
         .header("Access-Control-Allow-Method", "GET, POST")
         .header("Access-Control-Allow-Headers", "Content-Type")
@@ -156,8 +199,11 @@ async fn create_client(
 struct RouteService {
     host: String,
     route_table: &'static RwLock<HashMap<PathBuf, RouteMeta>>,
+    egress_routes: &'static RwLock<HashMap<PathBuf, ApiEndpoint>>,
+    graphql_schema: &'static RwLock<Option<Schema>>,
     consumption_apis: &'static RwLock<HashSet<String>>,
     configured_producer: ConfiguredProducer,
+    clickhouse_pool: clickhouse_rs::Pool,
     current_version: String,
     is_prod: bool,
     metrics: Arc<Metrics>,
@@ -173,12 +219,15 @@ impl Service<Request<Incoming>> for RouteService {
             self.current_version.clone(),
             self.consumption_apis,
             self.configured_producer.clone(),
+            self.clickhouse_pool.clone(),
             self.host.clone(),
             self.is_prod,
             self.metrics.clone(),
             RouterRequest {
                 req,
                 route_table: self.route_table,
+                egress_routes: self.egress_routes,
+                graphql_schema: self.graphql_schema,
             },
         ))
     }
@@ -188,7 +237,7 @@ fn options_route() -> Result<Response<Full<Bytes>>, hyper::http::Error> {
     let response = Response::builder()
         .status(StatusCode::OK)
         .header("Access-Control-Allow-Origin", "*")
-        .header("Access-Control-Allow-Methods", "POST, OPTIONS")
+        .header("Access-Control-Allow-Methods", "GET, POST, OPTIONS")
         .header(
             "Access-Control-Allow-Headers",
             "Content-Type, Baggage, Sentry-Trace",
@@ -241,6 +290,35 @@ async fn metrics_route(metrics: Arc<Metrics>) -> Result<Response<Full<Bytes>>, h
     Ok(response)
 }
 
+// Status of the streaming functions, read by `moose ps`. The last error lines of the functions
+// can hold records, they are left out in prod
+async fn functions_status_route(
+    metrics: Arc<Metrics>,
+    is_prod: bool,
+) -> Result<Response<Full<Bytes>>, hyper::http::Error> {
+    match metrics.get_streaming_function_statuses().await {
+        Ok(mut statuses) => {
+            if is_prod {
+                for status in statuses.iter_mut() {
+                    status.last_stderr.clear();
+                }
+            }
+            Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "application/json")
+                .body(Full::new(Bytes::from(
+                    serde_json::to_string(&statuses).unwrap(),
+                )))
+        }
+        Err(e) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Full::new(Bytes::from(format!(
+                "Unable to retrieve the status of the functions: {}",
+                e
+            )))),
+    }
+}
+
 fn bad_json_response(e: serde_json::Error) -> Response<Full<Bytes>> {
     show_message!(
         MessageType::Error,
@@ -268,6 +346,14 @@ fn success_response(uri: String) -> Response<Full<Bytes>> {
     Response::new(Full::new(Bytes::from("SUCCESS")))
 }
 
+fn json_response(status: StatusCode, body: Value) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Full::new(Bytes::from(body.to_string())))
+        .unwrap()
+}
+
 fn internal_server_error_response() -> Response<Full<Bytes>> {
     Response::builder()
         .status(StatusCode::INTERNAL_SERVER_ERROR)
@@ -279,6 +365,7 @@ async fn send_payload_to_topic(
     configured_producer: &ConfiguredProducer,
     topic_name: &str,
     payload: Value,
+    headers: OwnedHeaders,
 ) -> Result<(i32, i64), (KafkaError, OwnedMessage)> {
     let payload = serde_json::to_vec(&payload).unwrap();
 
@@ -289,12 +376,47 @@ async fn send_payload_to_topic(
         .send(
             FutureRecord::to(topic_name)
                 .key(topic_name) // This should probably be generated by the client that pushes data to the API
-                .payload(payload.as_slice()),
+                .payload(payload.as_slice())
+                .headers(headers),
             Timeout::After(Duration::from_secs(1)),
         )
         .await
 }
 
+struct HeaderExtractor<'a>(&'a hyper::HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+// The span of the send to the topic, its context is propagated in the headers of the records
+fn produce_span(parent_cx: &Context, topic_name: &str) -> Context {
+    let tracer = global::tracer(TRACER_NAME);
+    let span = tracer
+        .span_builder("produce")
+        .with_kind(SpanKind::Producer)
+        .with_attributes(vec![KeyValue::new(
+            "messaging.destination.name",
+            topic_name.to_string(),
+        )])
+        .start_with_context(&tracer, parent_cx);
+
+    parent_cx.with_span(span)
+}
+
+fn end_span(cx: &Context, error: Option<String>) {
+    if let Some(error) = error {
+        cx.span().set_status(Status::error(error));
+    }
+    cx.span().end();
+}
+
 async fn to_reader(req: Request<Incoming>) -> bytes::buf::Reader<impl Buf + Sized> {
     req.collect().await.unwrap().aggregate().reader()
 }
@@ -302,27 +424,52 @@ async fn to_reader(req: Request<Incoming>) -> bytes::buf::Reader<impl Buf + Size
 async fn handle_json_req(
     configured_producer: &ConfiguredProducer,
     topic_name: &str,
+    data_model: &str,
     req: Request<Incoming>,
+    cx: &Context,
+    metrics: &Metrics,
 ) -> Response<Full<Bytes>> {
     // TODO probably a refactor to be done here with the array json but it doesn't seem to be
     // straightforward to do it in a generic way.
     let url = req.uri().to_string();
-    let body = to_reader(req).await;
-    let parsed: Result<Value, serde_json::Error> = serde_json::from_reader(body);
+    let body = req.collect().await.unwrap().to_bytes();
+    let parsed: Result<Value, serde_json::Error> = serde_json::from_slice(&body);
     // TODO add check that the payload has the proper schema
 
     if let Err(e) = parsed {
         return bad_json_response(e);
     }
 
-    let res = send_payload_to_topic(configured_producer, topic_name, parsed.ok().unwrap()).await;
+    let produce_cx = produce_span(cx, topic_name);
+    let res = send_payload_to_topic(
+        configured_producer,
+        topic_name,
+        parsed.ok().unwrap(),
+        trace_headers(&produce_cx),
+    )
+    .await;
     if let Err((kafka_error, _)) = res {
         debug!(
             "Failed to deliver message to {} with error: {}",
             topic_name, kafka_error
         );
+        end_span(&produce_cx, Some(kafka_error.to_string()));
+        metrics
+            .send_metric(MetricsMessage::ProduceErrors {
+                topic: topic_name.to_string(),
+                records: 1,
+            })
+            .await;
         return internal_server_error_response();
     }
+    end_span(&produce_cx, None);
+    metrics
+        .send_metric(MetricsMessage::IngestedRecords {
+            data_model: data_model.to_string(),
+            records: 1,
+            bytes: body.len() as u64,
+        })
+        .await;
 
     success_response(url)
 }
@@ -345,14 +492,17 @@ async fn wait_for_batch_complete(
 async fn handle_json_array_body(
     configured_producer: &ConfiguredProducer,
     topic_name: &str,
+    data_model: &str,
     req: Request<Incoming>,
+    cx: &Context,
+    metrics: &Metrics,
 ) -> Response<Full<Bytes>> {
     // TODO probably a refactor to be done here with the json but it doesn't seem to be
     // straightforward to do it in a generic way.
     let url = req.uri().to_string();
-    let body = to_reader(req).await;
+    let body = req.collect().await.unwrap().to_bytes();
 
-    let parsed: Result<Vec<Value>, serde_json::Error> = serde_json::from_reader(body);
+    let parsed: Result<Vec<Value>, serde_json::Error> = serde_json::from_slice(&body);
     if let Err(e) = parsed {
         return bad_json_response(e);
     }
@@ -360,13 +510,18 @@ async fn handle_json_array_body(
     let mut res_arr: Vec<Result<OwnedDeliveryResult, KafkaError>> = Vec::new();
     let mut temp_res: Vec<Result<DeliveryFuture, KafkaError>> = Vec::new();
 
+    // All the records of the request share the same produce span
+    let produce_cx = produce_span(cx, topic_name);
+    let headers = trace_headers(&produce_cx);
+
     for (count, payload) in parsed.ok().unwrap().into_iter().enumerate() {
         let payload = serde_json::to_vec(&payload).unwrap();
 
         debug!("Sending payload {:?} to topic: {}", payload, topic_name);
         let record = FutureRecord::to(topic_name)
             .key(topic_name) // This should probably be generated by the client that pushes data to the API
-            .payload(payload.as_slice());
+            .payload(payload.as_slice())
+            .headers(headers.clone());
         temp_res.push(
             configured_producer
                 .producer
@@ -382,9 +537,28 @@ async fn handle_json_array_body(
     }
     wait_for_batch_complete(&mut res_arr, temp_res).await;
 
-    if res_arr.iter().any(|res| res.is_err()) {
+    let failed_records = res_arr.iter().filter(|res| res.is_err()).count() as u64;
+    if failed_records > 0 {
+        metrics
+            .send_metric(MetricsMessage::ProduceErrors {
+                topic: topic_name.to_string(),
+                records: failed_records,
+            })
+            .await;
+    }
+
+    if let Some(Err(kafka_error)) = res_arr.iter().find(|res| res.is_err()) {
+        end_span(&produce_cx, Some(kafka_error.to_string()));
         return internal_server_error_response();
     }
+    end_span(&produce_cx, None);
+    metrics
+        .send_metric(MetricsMessage::IngestedRecords {
+            data_model: data_model.to_string(),
+            records: res_arr.len() as u64,
+            bytes: body.len() as u64,
+        })
+        .await;
 
     success_response(url)
 }
@@ -394,6 +568,7 @@ async fn ingest_route(
     route: PathBuf,
     configured_producer: ConfiguredProducer,
     route_table: &RwLock<HashMap<PathBuf, RouteMeta>>,
+    metrics: &Metrics,
 ) -> Result<Response<Full<Bytes>>, hyper::http::Error> {
     show_message!(
         MessageType::Info,
@@ -403,27 +578,216 @@ async fn ingest_route(
         }
     );
 
-    match route_table.read().await.get(&route) {
-        Some(route_meta) => match route_meta.format {
-            EndpointIngestionFormat::Json => {
-                Ok(handle_json_req(&configured_producer, &route_meta.topic_name, req).await)
-            }
-            EndpointIngestionFormat::JsonArray => {
-                Ok(handle_json_array_body(&configured_producer, &route_meta.topic_name, req).await)
+    // Continues the trace of the caller if there is one
+    let parent_cx = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(req.headers()))
+    });
+    let tracer = global::tracer(TRACER_NAME);
+    let span = tracer
+        .span_builder("ingest")
+        .with_kind(SpanKind::Server)
+        .with_attributes(vec![KeyValue::new(
+            "http.route",
+            route.to_string_lossy().to_string(),
+        )])
+        .start_with_context(&tracer, &parent_cx);
+    let cx = parent_cx.with_span(span);
+
+    // routes are ingest/<data model>/<version>
+    let data_model = route
+        .iter()
+        .nth(1)
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let response = match route_table.read().await.get(&route) {
+        Some(route_meta) => {
+            cx.span().set_attribute(KeyValue::new(
+                "messaging.destination.name",
+                route_meta.topic_name.clone(),
+            ));
+            match route_meta.format {
+                EndpointIngestionFormat::Json => Ok(handle_json_req(
+                    &configured_producer,
+                    &route_meta.topic_name,
+                    &data_model,
+                    req,
+                    &cx,
+                    metrics,
+                )
+                .await),
+                EndpointIngestionFormat::JsonArray => Ok(handle_json_array_body(
+                    &configured_producer,
+                    &route_meta.topic_name,
+                    &data_model,
+                    req,
+                    &cx,
+                    metrics,
+                )
+                .await),
             }
-        },
+        }
         None => Response::builder()
             .status(StatusCode::NOT_FOUND)
             .body(Full::new(Bytes::from(
                 "Please run `moose ls` to view your routes",
             ))),
+    };
+
+    if let Ok(response) = &response {
+        cx.span().set_attribute(KeyValue::new(
+            "http.response.status_code",
+            response.status().as_u16() as i64,
+        ));
+        let error = response
+            .status()
+            .is_server_error()
+            .then(|| "Failed to send to the topic".to_string());
+        end_span(&cx, error);
+    }
+
+    response
+}
+
+async fn consumption_route(
+    req: Request<hyper::body::Incoming>,
+    host: String,
+    consumption_apis: &RwLock<HashSet<String>>,
+    is_prod: bool,
+    api_name: &str,
+    old_version: Option<&str>,
+) -> Result<Response<Full<Bytes>>, hyper::http::Error> {
+    match create_client(req, host, consumption_apis, is_prod, api_name, old_version).await {
+        Ok(response) => Ok(response),
+        Err(e) => {
+            debug!("Error: {:?}", e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Full::new(Bytes::from("Error")))
+        }
+    }
+}
+
+async fn egress_route(
+    req: Request<hyper::body::Incoming>,
+    route: PathBuf,
+    clickhouse_pool: clickhouse_rs::Pool,
+    egress_routes: &RwLock<HashMap<PathBuf, ApiEndpoint>>,
+) -> Result<Response<Full<Bytes>>, hyper::http::Error> {
+    show_message!(
+        MessageType::Info,
+        Message {
+            action: "GET".to_string(),
+            details: route.to_str().unwrap().to_string(),
+        }
+    );
+
+    let table = match egress_routes.read().await.get(&route) {
+        Some(ApiEndpoint {
+            api_type: APIType::EGRESS { source_table },
+            ..
+        }) => source_table.clone(),
+        _ => {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Full::new(Bytes::from(
+                    "Please run `moose ls` to view your routes",
+                )))
+        }
+    };
+
+    let query =
+        serde_urlencoded::from_str::<Vec<(String, String)>>(req.uri().query().unwrap_or_default())
+            .map_err(|e| e.to_string())
+            .and_then(|params| {
+                EgressQuery::from_query_params(&table, &params).map_err(|e| e.to_string())
+            });
+
+    let query = match query {
+        Ok(query) => query,
+        Err(e) => {
+            return Ok(json_response(
+                StatusCode::BAD_REQUEST,
+                json!({ "error": e }),
+            ))
+        }
+    };
+
+    let sql = query.to_sql(&table);
+    debug!("Egress query: {}", sql);
+
+    let rows = match clickhouse_pool.get_handle().await {
+        Ok(mut client) => {
+            select_as_json(&mut client, &sql, &query.selected_column_types(&table)).await
+        }
+        Err(e) => Err(e),
+    };
+
+    match rows {
+        Ok(rows) => Ok(json_response(
+            StatusCode::OK,
+            query.to_response(&table, rows),
+        )),
+        Err(e) => {
+            error!("Egress query failed: {:?}", e);
+            Ok(json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({ "error": "Error querying the table" }),
+            ))
+        }
     }
 }
 
+async fn graphql_route(
+    req: Request<hyper::body::Incoming>,
+    graphql_schema: &RwLock<Option<Schema>>,
+) -> Result<Response<Full<Bytes>>, hyper::http::Error> {
+    // Cloning the schema is cheap, it avoids holding the lock while the query runs
+    let schema = match graphql_schema.read().await.as_ref() {
+        Some(schema) => schema.clone(),
+        None => {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Full::new(Bytes::from(
+                    "GraphQL is not enabled or there are no tables to query",
+                )))
+        }
+    };
+
+    let request = if req.method() == hyper::Method::GET {
+        async_graphql::http::parse_query_string(req.uri().query().unwrap_or_default())
+            .map_err(|e| e.to_string())
+    } else {
+        let body = to_reader(req).await;
+        serde_json::from_reader::<_, async_graphql::Request>(body).map_err(|e| e.to_string())
+    };
+
+    let request = match request {
+        Ok(request) => request,
+        Err(e) => {
+            return Ok(json_response(
+                StatusCode::BAD_REQUEST,
+                json!({ "errors": [{ "message": e }] }),
+            ))
+        }
+    };
+
+    let response = schema.execute(request).await;
+    match serde_json::to_value(&response) {
+        Ok(body) => Ok(json_response(StatusCode::OK, body)),
+        Err(e) => {
+            error!("Failed to serialize GraphQL response: {:?}", e);
+            Ok(internal_server_error_response())
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn router(
     current_version: String,
     consumption_apis: &RwLock<HashSet<String>>,
     configured_producer: ConfiguredProducer,
+    clickhouse_pool: clickhouse_rs::Pool,
     host: String,
     is_prod: bool,
     metrics: Arc<Metrics>,
@@ -433,6 +797,8 @@ async fn router(
 
     let req = request.req;
     let route_table = request.route_table;
+    let egress_routes = request.egress_routes;
+    let graphql_schema = request.graphql_schema;
 
     debug!(
         "HTTP Request Received: {:?}, with Route Table {:?}",
@@ -464,26 +830,45 @@ async fn router(
                 route.join(current_version),
                 configured_producer,
                 route_table,
+                &metrics,
             )
             .await
         }
         (&hyper::Method::POST, ["ingest", _, _]) => {
-            ingest_route(req, route, configured_producer, route_table).await
+            ingest_route(req, route, configured_producer, route_table, &metrics).await
         }
 
-        (&hyper::Method::GET, ["consumption", _rt]) => {
-            match create_client(req, host, consumption_apis, is_prod).await {
-                Ok(response) => Ok(response),
-                Err(e) => {
-                    debug!("Error: {:?}", e);
-                    Response::builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(Full::new(Bytes::from("Error")))
-                }
-            }
+        (&hyper::Method::GET, ["egress", _]) => {
+            egress_route(
+                req,
+                // without explicit version, go to current project version
+                route.join(current_version),
+                clickhouse_pool,
+                egress_routes,
+            )
+            .await
+        }
+        (&hyper::Method::GET, ["egress", _, _]) => {
+            egress_route(req, route, clickhouse_pool, egress_routes).await
+        }
+
+        (&hyper::Method::GET | &hyper::Method::POST, ["graphql"]) => {
+            graphql_route(req, graphql_schema).await
+        }
+
+        (&hyper::Method::GET, ["consumption", name]) => {
+            consumption_route(req, host, consumption_apis, is_prod, name, None).await
+        }
+        (&hyper::Method::GET, ["consumption", name, version]) => {
+            // without an old version, go to current project version
+            let old_version = Some(*version).filter(|v| *v != current_version);
+            consumption_route(req, host, consumption_apis, is_prod, name, old_version).await
         }
         (&hyper::Method::POST, ["logs"]) if !is_prod => Ok(log_route(req).await),
         (&hyper::Method::GET, ["health"]) => health_route(),
+        (&hyper::Method::GET, ["health", "functions"]) => {
+            functions_status_route(metrics.clone(), is_prod).await
+        }
         (&hyper::Method::GET, ["metrics"]) => metrics_route(metrics.clone()).await,
 
         (&hyper::Method::OPTIONS, _) => options_route(),
@@ -505,11 +890,18 @@ async fn router(
 pub struct Webserver {
     host: String,
     port: u16,
+    egress_routes: &'static RwLock<HashMap<PathBuf, ApiEndpoint>>,
+    graphql_schema: &'static RwLock<Option<Schema>>,
 }
 
 impl Webserver {
     pub fn new(host: String, port: u16) -> Self {
-        Self { host, port }
+        Self {
+            host,
+            port,
+            egress_routes: Box::leak(Box::new(RwLock::new(HashMap::new()))),
+            graphql_schema: Box::leak(Box::new(RwLock::new(None))),
+        }
     }
 
     pub async fn socket(&self) -> SocketAddr {
@@ -527,31 +919,41 @@ impl Webserver {
         log::info!("Spawning API update listener");
 
         let (tx, mut rx) = mpsc::channel::<ApiChange>(32);
+        let egress_routes = self.egress_routes;
 
         tokio::spawn(async move {
             while let Some(api_change) = rx.recv().await {
                 let mut route_table = route_table.write().await;
+                let mut egress_routes = egress_routes.write().await;
                 match api_change {
                     ApiChange::ApiEndpoint(Change::Added(api_endpoint)) => {
                         log::info!("Adding route: {:?}", api_endpoint.path);
-                        match api_endpoint.api_type {
+                        match &api_endpoint.api_type {
                             APIType::INGRESS { target_topic } => {
                                 route_table.insert(
                                     api_endpoint.path.clone(),
                                     RouteMeta {
                                         format: api_endpoint.format.clone(),
-                                        topic_name: target_topic,
+                                        topic_name: target_topic.clone(),
                                     },
                                 );
                             }
-                            APIType::EGRESS => {
-                                log::warn!("Egress API not supported yet")
+                            APIType::EGRESS { .. } => {
+                                egress_routes
+                                    .insert(api_endpoint.path.clone(), api_endpoint.clone());
                             }
                         }
                     }
                     ApiChange::ApiEndpoint(Change::Removed(api_endpoint)) => {
                         log::info!("Removing route: {:?}", api_endpoint.path);
-                        route_table.remove(&api_endpoint.path);
+                        match &api_endpoint.api_type {
+                            APIType::INGRESS { .. } => {
+                                route_table.remove(&api_endpoint.path);
+                            }
+                            APIType::EGRESS { .. } => {
+                                egress_routes.remove(&api_endpoint.path);
+                            }
+                        }
                     }
                     ApiChange::ApiEndpoint(Change::Updated { before, after }) => {
                         match &after.api_type {
@@ -567,8 +969,11 @@ impl Webserver {
                                     },
                                 );
                             }
-                            APIType::EGRESS => {
-                                log::warn!("Egress API not supported yet")
+                            APIType::EGRESS { .. } => {
+                                log::info!("Replacing route: {:?} with {:?}", before, after);
+
+                                egress_routes.remove(&before.path);
+                                egress_routes.insert(after.path.clone(), after.clone());
                             }
                         }
                     }
@@ -579,6 +984,38 @@ impl Webserver {
         tx
     }
 
+    /// Rebuilds the GraphQL schema every time the set of tables changes. The tables are
+    /// dropped when GraphQL is not enabled for the project.
+    pub async fn spawn_graphql_update_listener(
+        &self,
+        project: &Project,
+    ) -> mpsc::Sender<Vec<Table>> {
+        log::info!("Spawning GraphQL update listener");
+
+        let (tx, mut rx) = mpsc::channel::<Vec<Table>>(32);
+        let graphql_schema = self.graphql_schema;
+        let enabled = project.http_server_config.graphql;
+        let clickhouse_pool = get_pool(&project.clickhouse_config);
+
+        tokio::spawn(async move {
+            while let Some(tables) = rx.recv().await {
+                if !enabled {
+                    continue;
+                }
+
+                match graphql::build_schema(&tables, clickhouse_pool.clone()) {
+                    Ok(schema) => {
+                        log::info!("Updating GraphQL schema for {} table(s)", tables.len());
+                        *graphql_schema.write().await = schema;
+                    }
+                    Err(e) => error!("Failed to build the GraphQL schema: {:?}", e),
+                }
+            }
+        });
+
+        tx
+    }
+
     // TODO - when we retire the the old core, we should remove routeTable from the start method and using only
     // the channel to update the routes
     pub async fn start(
@@ -621,9 +1058,12 @@ impl Webserver {
         let route_service = RouteService {
             host: self.host.clone(),
             route_table,
+            egress_routes: self.egress_routes,
+            graphql_schema: self.graphql_schema,
             consumption_apis,
             current_version: project.cur_version().to_string(),
             configured_producer: producer,
+            clickhouse_pool: get_pool(&project.clickhouse_config),
             is_prod: project.is_production,
             metrics,
         };
@@ -633,11 +1073,13 @@ impl Webserver {
                 _ = sigint.recv() => {
                     let run_mode = RunMode::Explicit;
                     StopLocalInfrastructure::new(project.clone()).run(run_mode).unwrap().show();
+                    shutdown_tracing().await;
                     std::process::exit(0);
                 }
                 _ = sigterm.recv() => {
                     let run_mode = RunMode::Explicit;
                     StopLocalInfrastructure::new(project.clone()).run(run_mode).unwrap().show();
+                    shutdown_tracing().await;
                     std::process::exit(0);
                 }
                 listener_result = listener.accept() => {
@@ -81,7 +81,7 @@
 
 use std::collections::{HashMap, HashSet};
 use std::ops::DerefMut;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use log::{debug, error, info};
@@ -93,8 +93,15 @@ use crate::cli::watcher::{
 use crate::framework::core::code_loader::{
     load_framework_objects, FrameworkObject, FrameworkObjectVersions, SchemaVersion,
 };
-use crate::framework::core::execute::execute_initial_infra_change;
-use crate::framework::core::plan::plan_changes;
+use crate::framework::core::drift::spawn_drift_check;
+use crate::framework::core::execute::{execute_initial_infra_change, rollback_deployment};
+use crate::framework::core::guardrails::{check_destructive_changes, destructive_changes};
+use crate::framework::core::history;
+use crate::framework::core::journal::Deployment;
+use crate::framework::core::plan::{
+    check_plan_is_current, plan_between, plan_changes, plan_refreshed_changes, plan_rollback,
+    InfraPlan,
+};
 use crate::infrastructure::olap::clickhouse::{
     fetch_table_names, fetch_table_schema, table_schema_to_hash,
 };
@@ -103,14 +110,13 @@ use crate::cli::routines::streaming::verify_streaming_functions_against_datamode
 use crate::framework::controller::{create_or_replace_version_sync, process_objects, RouteMeta};
 use crate::infrastructure::olap;
 use crate::infrastructure::olap::clickhouse::version_sync::{get_all_version_syncs, VersionSync};
-use crate::infrastructure::olap::clickhouse_alt_client::{
-    get_pool, store_current_state, store_infrastructure_map,
-};
+use crate::infrastructure::olap::clickhouse_alt_client::{get_pool, ApplicationState};
 use crate::infrastructure::processes::aggregations_registry::AggregationProcessRegistry;
 use crate::infrastructure::processes::consumption_registry::ConsumptionProcessRegistry;
 use crate::infrastructure::processes::functions_registry::FunctionProcessRegistry;
 use crate::infrastructure::processes::kafka_clickhouse_sync::SyncingProcessesRegistry;
 use crate::infrastructure::processes::process_registry::ProcessRegistries;
+use crate::infrastructure::state_storage::state_storage;
 use crate::infrastructure::stream::redpanda::fetch_topics;
 use crate::project::Project;
 
@@ -127,10 +133,12 @@ pub mod consumption;
 pub mod dev;
 pub mod docker_packager;
 pub mod initialize;
+pub mod lineage;
 pub mod logs;
 pub mod ls;
 pub mod migrate;
 pub mod ps;
+pub mod state;
 pub mod stop;
 pub mod streaming;
 pub mod templates;
@@ -293,22 +301,41 @@ pub async fn start_development_mode(
         Box::leak(Box::new(RwLock::new(HashSet::new())));
 
     let route_update_channel = web_server.spawn_api_update_listener(route_table).await;
+    let graphql_update_channel = web_server.spawn_graphql_update_listener(&project).await;
 
     let (syncing_processes_registry, process_registry) = if features.core_v2 {
         let mut client = get_pool(&project.clickhouse_config).get_handle().await?;
+        let storage = state_storage(&project);
 
-        let plan_result = plan_changes(&mut client, &project).await?;
+        let plan_result = plan_changes(storage.as_ref(), &project).await?;
         log::info!("Plan Changes: {:?}", plan_result.changes);
+        let mut deployment = Deployment::unjournaled(&plan_result.changes);
         let api_changes_channel = web_server.spawn_api_update_listener(route_table).await;
-        let (syncing_registry, process_registry) =
-            execute_initial_infra_change(&project, features, &plan_result, api_changes_channel)
-                .await?;
-        // TODO - need to add a lock on the table to prevent concurrent updates as migrations are going through.
-
-        // Storing the result of the changes in the table
-        store_infrastructure_map(
+        let (syncing_registry, process_registry) = execute_initial_infra_change(
+            &project,
+            features,
+            &plan_result,
             &mut client,
-            &project.clickhouse_config,
+            &mut deployment,
+            api_changes_channel,
+            metrics.clone(),
+        )
+        .await?;
+        graphql_update_channel
+            .send(
+                plan_result
+                    .target_infra_map
+                    .tables
+                    .values()
+                    .cloned()
+                    .collect(),
+            )
+            .await?;
+
+        // Storing the result of the changes in the state storage
+        history::store_infrastructure_map(
+            storage.as_ref(),
+            &project,
             &plan_result.target_infra_map,
         )
         .await?;
@@ -318,6 +345,7 @@ pub async fn start_development_mode(
         let mut syncing_processes_registry = SyncingProcessesRegistry::new(
             project.redpanda_config.clone(),
             project.clickhouse_config.clone(),
+            metrics.clone(),
         );
 
         let _ = syncing_processes_registry
@@ -327,7 +355,7 @@ pub async fn start_development_mode(
         let topics = fetch_topics(&project.redpanda_config).await?;
 
         let mut function_process_registry =
-            FunctionProcessRegistry::new(project.redpanda_config.clone());
+            FunctionProcessRegistry::new(project.redpanda_config.clone(), metrics.clone());
         // Once the below function is optimized to act on events, this
         // will need to get refactored out.
 
@@ -357,6 +385,7 @@ pub async fn start_development_mode(
             project.language,
             project.clickhouse_config.clone(),
             project.consumption_dir(),
+            project.old_versions_consumption_dir(),
         );
         process_consumption_changes(
             &project,
@@ -375,15 +404,13 @@ pub async fn start_development_mode(
     };
 
     {
-        let mut client = get_pool(&project.clickhouse_config).get_handle().await?;
         let aggregations = project.get_aggregations();
-        store_current_state(
-            &mut client,
-            &framework_object_versions,
-            &aggregations,
-            &project.clickhouse_config,
-        )
-        .await?
+        state_storage(&project)
+            .store_application_state(&ApplicationState::from((
+                &framework_object_versions,
+                &aggregations,
+            )))
+            .await?
     }
 
     let file_watcher = FileWatcher::new();
@@ -393,6 +420,7 @@ pub async fn start_development_mode(
         framework_object_versions,
         route_table,          // Deprecated way of updating the routes,
         route_update_channel, // The new way of updating the routes
+        graphql_update_channel,
         consumption_apis,
         syncing_processes_registry,
         process_registry,
@@ -410,6 +438,9 @@ pub async fn start_development_mode(
 pub async fn start_production_mode(
     project: Arc<Project>,
     features: Features,
+    plan_file: Option<&Path>,
+    allow_destructive: bool,
+    resume: bool,
     metrics: Arc<Metrics>,
 ) -> anyhow::Result<()> {
     show_message!(
@@ -420,6 +451,13 @@ pub async fn start_production_mode(
         }
     );
 
+    if plan_file.is_some() && !features.core_v2 {
+        anyhow::bail!("Applying a plan file requires the core_v2 feature");
+    }
+    if resume && !features.core_v2 {
+        anyhow::bail!("Resuming a deployment requires the core_v2 feature");
+    }
+
     let server_config = project.http_server_config.clone();
     let web_server = Webserver::new(server_config.host.clone(), server_config.port);
 
@@ -437,33 +475,108 @@ pub async fn start_production_mode(
 
     if features.core_v2 {
         let mut client = get_pool(&project.clickhouse_config).get_handle().await?;
+        let storage = state_storage(&project);
+
+        // The lock is held while planning, so that no other deployment changes the
+        // infrastructure the plan is checked against
+        let mut deployment = if resume {
+            Deployment::load_locked(&mut client, &project.clickhouse_config).await?
+        } else {
+            Deployment::lock(&mut client, &project.clickhouse_config).await?
+        };
+
+        let planned = async {
+            let plan_result = match plan_file {
+                Some(plan_file) => {
+                    let plan = InfraPlan::load(plan_file)?;
+                    check_plan_is_current(storage.as_ref(), &plan).await?;
+                    plan
+                }
+                None => plan_changes(storage.as_ref(), &project).await?,
+            };
+            log::info!("Plan Changes: {:?}", plan_result.changes);
+            check_destructive_changes(
+                &plan_result.changes,
+                &project.migration_config,
+                allow_destructive,
+            )?;
+            anyhow::Ok(plan_result)
+        }
+        .await;
+        let plan_result = match planned {
+            Ok(plan_result) => plan_result,
+            // A failed deployment keeps the lock until it is resumed or rolled back
+            Err(e) if resume => return Err(e),
+            Err(e) => {
+                deployment
+                    .finish(&mut client, &project.clickhouse_config)
+                    .await?;
+                return Err(e);
+            }
+        };
+
+        if resume {
+            deployment.check_resumable_with(&plan_result.changes)?;
+        } else {
+            deployment
+                .start(
+                    &mut client,
+                    &project.clickhouse_config,
+                    &plan_result.changes,
+                )
+                .await?;
+        }
+        info!("<core> Executing deployment {}", deployment.id);
 
-        let plan_result = plan_changes(&mut client, &project).await?;
-        log::info!("Plan Changes: {:?}", plan_result.changes);
         let api_changes_channel = web_server.spawn_api_update_listener(route_table).await;
-        execute_initial_infra_change(&project, &features, &plan_result, api_changes_channel)
+        execute_initial_infra_change(
+            &project,
+            &features,
+            &plan_result,
+            &mut client,
+            &mut deployment,
+            api_changes_channel,
+            metrics.clone(),
+        )
+        .await?;
+        web_server
+            .spawn_graphql_update_listener(&project)
+            .await
+            .send(
+                plan_result
+                    .target_infra_map
+                    .tables
+                    .values()
+                    .cloned()
+                    .collect(),
+            )
             .await?;
-        // TODO - need to add a lock on the table to prevent concurrent updates as migrations are going through.
 
-        // Storing the result of the changes in the table
-        store_infrastructure_map(
-            &mut client,
-            &project.clickhouse_config,
+        // Storing the result of the changes in the state storage
+        history::store_infrastructure_map(
+            storage.as_ref(),
+            &project,
             &plan_result.target_infra_map,
         )
         .await?;
+        deployment
+            .finish(&mut client, &project.clickhouse_config)
+            .await?;
+
+        spawn_drift_check(project.clone(), plan_result.target_infra_map.clone());
     } else {
         let topics = fetch_topics(&project.redpanda_config).await?;
         let mut syncing_processes_registry = SyncingProcessesRegistry::new(
             project.redpanda_config.clone(),
             project.clickhouse_config.clone(),
+            metrics.clone(),
         );
         let _ = syncing_processes_registry
             .start_all(&framework_object_versions, &version_syncs)
             .await;
 
         let mut function_process_registry =
-            FunctionProcessRegistry::new(project.redpanda_config.clone());
+            FunctionProcessRegistry::new(project.redpanda_config.clone(), metrics.clone());
         // Once the below function is optimized to act on events, this
         // will need to get refactored out.
         process_streaming_func_changes(
@@ -490,6 +603,7 @@ pub async fn start_production_mode(
             project.language,
             project.clickhouse_config.clone(),
             project.consumption_dir(),
+            project.old_versions_consumption_dir(),
         );
         process_consumption_changes(
             &project,
@@ -507,12 +621,63 @@ pub async fn start_production_mode(
     Ok(())
 }
 
-pub async fn plan(project: &Project) -> anyhow::Result<()> {
+// Undoes the changes applied by the deployment that failed and releases its lock
+pub async fn rollback_production_deployment(project: &Project) -> anyhow::Result<()> {
     let mut client = get_pool(&project.clickhouse_config).get_handle().await?;
 
-    let plan_results = plan_changes(&mut client, project).await?;
+    let mut deployment = Deployment::load_locked(&mut client, &project.clickhouse_config).await?;
+    rollback_deployment(project, &mut client, &mut deployment).await?;
 
-    display::show_changes(&plan_results);
+    show_message!(
+        MessageType::Success,
+        Message {
+            action: "Rolled back".to_string(),
+            details: format!("deployment {}", deployment.id),
+        }
+    );
+
+    Ok(())
+}
+
+pub async fn plan(project: &Project, out: Option<&Path>, refresh: bool) -> anyhow::Result<()> {
+    let storage = state_storage(project);
+
+    let plan_results = if refresh {
+        let (plan_results, drifts) = plan_refreshed_changes(storage.as_ref(), project).await?;
+        for drift in drifts.iter() {
+            show_message!(
+                MessageType::Highlight,
+                Message {
+                    action: "Drift".to_string(),
+                    details: drift.display(),
+                }
+            );
+        }
+        plan_results
+    } else {
+        plan_changes(storage.as_ref(), project).await?
+    };
+
+    show_plan(&plan_results, out)
+}
+
+// Displays the changes of a plan and writes it to `out` if given
+fn show_plan(plan_results: &InfraPlan, out: Option<&Path>) -> anyhow::Result<()> {
+    display::show_changes(plan_results);
+
+    for change in destructive_changes(&plan_results.changes) {
+        show_message!(
+            MessageType::Error,
+            Message {
+                action: "Destructive".to_string(),
+                details: format!(
+                    "{}, blocked unless allowed with --allow-destructive or for {} in the migration_config",
+                    change.display(),
+                    change.object_id()
+                ),
+            }
+        );
+    }
 
     if plan_results.changes.is_empty() {
         show_message!(
@@ -524,9 +689,79 @@ pub async fn plan(project: &Project) -> anyhow::Result<()> {
         );
     }
 
+    if let Some(out) = out {
+        plan_results.write(out)?;
+        show_message!(
+            MessageType::Info,
+            Message {
+                action: "Plan".to_string(),
+                details: format!("written to {}", out.display()),
+            }
+        );
+    }
+
     Ok(())
 }
 
+// Lists the infrastructure maps stored, or the changes between two of them
+pub async fn history(project: &Project, diff: Option<&[String]>) -> anyhow::Result<()> {
+    let storage = state_storage(project);
+
+    if let Some([from, to]) = diff {
+        let plan_results = plan_between(storage.as_ref(), from, to).await?;
+        display::show_changes(&plan_results);
+        if plan_results.changes.is_empty() {
+            show_message!(
+                MessageType::Info,
+                Message {
+                    action: "No".to_string(),
+                    details: "changes between the two deployments".to_string(),
+                }
+            );
+        }
+        return Ok(());
+    }
+
+    let rows = storage
+        .load_infra_map_records()
+        .await?
+        .into_iter()
+        .rev()
+        .map(|record| {
+            vec![
+                record.id,
+                record.timestamp.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+                record.commit.unwrap_or_default(),
+                record.version,
+                record.infra_map.tables.len().to_string(),
+                record.infra_map.topics.len().to_string(),
+            ]
+        })
+        .collect();
+
+    display::show_table(
+        vec![
+            "Id".to_string(),
+            "Deployed at".to_string(),
+            "Commit".to_string(),
+            "Version".to_string(),
+            "Tables".to_string(),
+            "Topics".to_string(),
+        ],
+        rows,
+    );
+
+    Ok(())
+}
+
+// Plans the changes back to the infrastructure map of a previous deployment
+pub async fn rollback(project: &Project, id: &str, out: Option<&Path>) -> anyhow::Result<()> {
+    let storage = state_storage(project);
+    let plan_results = plan_rollback(storage.as_ref(), id).await?;
+
+    show_plan(&plan_results, out)
+}
+
 async fn check_for_model_changes(
     project: Arc<Project>,
     framework_object_versions: FrameworkObjectVersions,
@@ -1,8 +1,13 @@
 use std::{fs, io::Write};
 
 use crate::{
-    cli::display::Message, framework::typescript::templates::BASE_CONSUMPTION_TEMPLATE,
+    cli::display::Message,
+    framework::{
+        languages::SupportedLanguages, python::templates::PYTHON_BASE_CONSUMPTION_TEMPLATE,
+        typescript::templates::BASE_CONSUMPTION_TEMPLATE,
+    },
     project::Project,
+    utilities::constants::{PYTHON_FILE_EXTENSION, TYPESCRIPT_FILE_EXTENSION},
 };
 
 use super::{RoutineFailure, RoutineSuccess};
@@ -12,7 +17,11 @@ pub fn create_consumption_file(
     filename: String,
 ) -> Result<RoutineSuccess, RoutineFailure> {
     let apis_dir = project.consumption_dir();
-    let apis_file_path = apis_dir.join(format!("{}.ts", filename));
+    let (extension, template) = match project.language {
+        SupportedLanguages::Typescript => (TYPESCRIPT_FILE_EXTENSION, BASE_CONSUMPTION_TEMPLATE),
+        SupportedLanguages::Python => (PYTHON_FILE_EXTENSION, PYTHON_BASE_CONSUMPTION_TEMPLATE),
+    };
+    let apis_file_path = apis_dir.join(format!("{}.{}", filename, extension));
 
     let mut apis_file = fs::File::create(&apis_file_path).map_err(|err| {
         RoutineFailure::new(
@@ -24,17 +33,15 @@ pub fn create_consumption_file(
         )
     })?;
 
-    apis_file
-        .write_all(BASE_CONSUMPTION_TEMPLATE.as_bytes())
-        .map_err(|err| {
-            RoutineFailure::new(
-                Message::new(
-                    "Failed".to_string(),
-                    format!("to write to consumption file {}", apis_file_path.display()),
-                ),
-                err,
-            )
-        })?;
+    apis_file.write_all(template.as_bytes()).map_err(|err| {
+        RoutineFailure::new(
+            Message::new(
+                "Failed".to_string(),
+                format!("to write to consumption file {}", apis_file_path.display()),
+            ),
+            err,
+        )
+    })?;
 
     Ok(RoutineSuccess::success(Message::new(
         "Created".to_string(),
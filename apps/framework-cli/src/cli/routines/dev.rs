@@ -7,6 +7,7 @@ use crate::utilities::constants::CLI_PROJECT_INTERNAL_DIR;
 use crate::utilities::docker;
 use crate::utilities::git::dump_old_version_schema;
 use crate::{cli::display::Message, project::Project};
+use git2::ErrorCode;
 use log::debug;
 
 use super::{
@@ -72,18 +73,54 @@ pub fn copy_old_schema(project: &Project) -> Result<RoutineSuccess, RoutineFailu
             )
         })?;
 
-        dump_old_version_schema(project, commit_hash.clone(), &dest).map_err(|git_err| {
+        dump_old_version_schema(
+            project,
+            commit_hash.clone(),
+            &project.data_models_dir(),
+            &dest,
+        )
+        .map_err(|git_err| {
             debug!("<DCM> Failed to retrieve old schema: {}", git_err);
             RoutineFailure::new(
                 Message::new("Failed".to_string(), "to retrieve old schema".to_string()),
                 git_err,
             )
         })?;
+
+        // The whole app directory, for the imports of the apis to resolve in the snapshot
+        let app_dest = project.old_versions_consumption_dir().join(version);
+        fs::create_dir_all(app_dest.clone()).map_err(|err| {
+            RoutineFailure::new(
+                Message::new("Failed".to_string(), "to create directory".to_string()),
+                err,
+            )
+        })?;
+
+        match dump_old_version_schema(project, commit_hash.clone(), &project.app_dir(), &app_dest) {
+            // Versions that predate consumption apis don't have an apis directory
+            Ok(()) if !project.old_version_consumption_dir(version).exists() => {
+                debug!("<DCM> No consumption apis for version {}", version);
+            }
+            Ok(()) => {}
+            Err(git_err) if git_err.code() == ErrorCode::NotFound => {
+                debug!("<DCM> No app directory for version {}", version);
+            }
+            Err(git_err) => {
+                debug!("<DCM> Failed to retrieve old consumption apis: {}", git_err);
+                return Err(RoutineFailure::new(
+                    Message::new(
+                        "Failed".to_string(),
+                        "to retrieve old consumption apis".to_string(),
+                    ),
+                    git_err,
+                ));
+            }
+        }
     }
 
     Ok(RoutineSuccess::success(Message::new(
         "Loaded".to_string(),
-        "old schemas".to_string(),
+        "old schemas and consumption apis".to_string(),
     )))
 }
 
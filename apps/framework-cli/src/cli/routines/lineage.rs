@@ -0,0 +1,101 @@
+use std::path::Path;
+
+use crate::{
+    cli::display::{show_table, Message},
+    framework::core::{
+        infrastructure_map::InfrastructureMap,
+        lineage::{LineageFormat, LineageGraph},
+        primitive_map::PrimitiveMap,
+    },
+    project::Project,
+};
+
+use super::{RoutineFailure, RoutineSuccess};
+
+async fn load_lineage(project: &Project) -> Result<LineageGraph, RoutineFailure> {
+    let primitive_map = PrimitiveMap::load(project).await.map_err(|e| {
+        RoutineFailure::new(
+            Message::new("Failed".to_string(), "to load the primitives".to_string()),
+            e,
+        )
+    })?;
+    Ok(LineageGraph::new(&InfrastructureMap::new(primitive_map)))
+}
+
+// Writes the lineage of the project to `out`, or to the standard output
+pub async fn show_lineage(
+    project: &Project,
+    format: LineageFormat,
+    out: Option<&Path>,
+) -> Result<RoutineSuccess, RoutineFailure> {
+    let lineage = load_lineage(project).await?;
+    let rendered = lineage.render(format).map_err(|e| {
+        RoutineFailure::new(
+            Message::new("Failed".to_string(), "to serialize the lineage".to_string()),
+            e,
+        )
+    })?;
+
+    match out {
+        Some(out) => {
+            std::fs::write(out, rendered).map_err(|e| {
+                RoutineFailure::new(
+                    Message::new(
+                        "Failed".to_string(),
+                        format!("to write the lineage to {}", out.display()),
+                    ),
+                    e,
+                )
+            })?;
+            Ok(RoutineSuccess::success(Message::new(
+                "Lineage".to_string(),
+                format!("written to {}", out.display()),
+            )))
+        }
+        None => {
+            println!("{}", rendered);
+            Ok(RoutineSuccess::success(Message::new(
+                "".to_string(),
+                "".to_string(),
+            )))
+        }
+    }
+}
+
+// Lists the infrastructure that may break when the data model changes
+pub async fn show_impact(
+    project: &Project,
+    data_model: &str,
+) -> Result<RoutineSuccess, RoutineFailure> {
+    let lineage = load_lineage(project).await?;
+    let impacted = lineage.impacted_by(data_model);
+    if impacted.is_empty() {
+        return Err(RoutineFailure::error(Message::new(
+            "Failed".to_string(),
+            format!("to find the data model {}", data_model),
+        )));
+    }
+
+    show_table(
+        vec!["Infrastructure".to_string(), "Defined by".to_string()],
+        impacted
+            .into_iter()
+            .map(|node| {
+                vec![
+                    node.label.clone(),
+                    node.source_primitive
+                        .as_ref()
+                        .map(|primitive| {
+                            format!("{:?} {}", primitive.primitive_type, primitive.name)
+                        })
+                        .unwrap_or_default(),
+                ]
+            })
+            .collect(),
+    );
+
+    Ok(RoutineSuccess::success(Message::new(
+        "".to_string(),
+        "".to_string(),
+    )))
+}
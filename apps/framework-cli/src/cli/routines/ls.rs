@@ -7,10 +7,9 @@ use crate::{
     cli::display::{show_table, Message},
     infrastructure::{
         olap::{
-            self,
-            clickhouse::model::ClickHouseSystemTable,
-            clickhouse_alt_client::{get_state, ApplicationState},
+            self, clickhouse::model::ClickHouseSystemTable, clickhouse_alt_client::ApplicationState,
         },
+        state_storage::state_storage,
         stream::redpanda,
     },
     project::Project,
@@ -73,7 +72,8 @@ pub async fn list_streaming(
 }
 
 async fn get_current_state(project: &Project) -> Result<ApplicationState, RoutineFailure> {
-    get_state(&project.clickhouse_config)
+    state_storage(project)
+        .load_application_state()
         .await
         .map_err(|_| {
             RoutineFailure::error(Message::new(
@@ -3,10 +3,11 @@ use std::{
     sync::Arc,
 };
 
-use log::error;
+use log::{debug, error};
 
 use crate::{
     cli::display::{show_table, Message},
+    framework::streaming::model::FunctionStatus,
     project::Project,
 };
 
@@ -30,7 +31,7 @@ impl MooseProcess {
     }
 }
 
-pub fn show_processes(project: Arc<Project>) -> Result<RoutineSuccess, RoutineFailure> {
+pub async fn show_processes(project: Arc<Project>) -> Result<RoutineSuccess, RoutineFailure> {
     let processes = vec![
         get_webserver_process(&project),
         get_clickhouse_process(&project),
@@ -54,12 +55,63 @@ pub fn show_processes(project: Arc<Project>) -> Result<RoutineSuccess, RoutineFa
         data,
     );
 
+    if let Some(statuses) = get_function_statuses(&project).await {
+        show_table(
+            vec![
+                "Streaming Function".to_string(),
+                "Worker".to_string(),
+                "State".to_string(),
+                "Restarts".to_string(),
+                "Last Exit Code".to_string(),
+                "Last Errors".to_string(),
+            ],
+            statuses
+                .into_iter()
+                .map(|status| {
+                    vec![
+                        status.function,
+                        status.worker.to_string(),
+                        format!("{:?}", status.state),
+                        status.restarts.to_string(),
+                        status
+                            .last_exit_code
+                            .map(|code| code.to_string())
+                            .unwrap_or_default(),
+                        status.last_stderr.join("\n"),
+                    ]
+                })
+                .collect(),
+        );
+    }
+
     Ok(RoutineSuccess::success(Message::new(
         "".to_string(),
         "".to_string(),
     )))
 }
 
+// The streaming functions are supervised by the running moose server
+async fn get_function_statuses(project: &Project) -> Option<Vec<FunctionStatus>> {
+    let url = format!(
+        "http://{}:{}/health/functions",
+        project.http_server_config.host, project.http_server_config.port
+    );
+    let statuses = async {
+        reqwest::get(&url)
+            .await?
+            .json::<Vec<FunctionStatus>>()
+            .await
+    };
+
+    match statuses.await {
+        Ok(statuses) => Some(statuses),
+        Err(e) => {
+            debug!("Failed to get the status of the streaming functions: {}", e);
+            None
+        }
+    }
+}
+
 fn get_webserver_process(project: &Arc<Project>) -> Option<MooseProcess> {
     get_process_by_port(project.http_server_config.port, "moose", None, "N/A")
 }
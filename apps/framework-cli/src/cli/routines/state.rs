@@ -0,0 +1,84 @@
+use std::path::Path;
+
+use crate::{
+    cli::display::Message,
+    infrastructure::state_storage::{
+        export_state, import_state, state_storage_of_kind, StateExport, StateStorageKind,
+    },
+    project::Project,
+};
+
+use super::{RoutineFailure, RoutineSuccess};
+
+// Writes the state held by the storage, the configured one by default, to a JSON file
+pub async fn export_state_file(
+    project: &Project,
+    file: &Path,
+    storage: Option<StateStorageKind>,
+) -> Result<RoutineSuccess, RoutineFailure> {
+    let storage = state_storage_of_kind(project, storage.unwrap_or(project.state_config.storage));
+
+    let state = export_state(storage.as_ref()).await.map_err(|e| {
+        RoutineFailure::new(
+            Message::new("Failed".to_string(), "to read the state".to_string()),
+            e,
+        )
+    })?;
+
+    let content = serde_json::to_string_pretty(&state).map_err(|e| {
+        RoutineFailure::new(
+            Message::new("Failed".to_string(), "to serialize the state".to_string()),
+            e,
+        )
+    })?;
+    std::fs::write(file, content).map_err(|e| {
+        RoutineFailure::new(
+            Message::new(
+                "Failed".to_string(),
+                format!("to write the state to {}", file.display()),
+            ),
+            e,
+        )
+    })?;
+
+    Ok(RoutineSuccess::success(Message::new(
+        "Exported".to_string(),
+        format!("the state to {}", file.display()),
+    )))
+}
+
+// Loads a state written by `moose state export` into the storage, the configured one by default
+pub async fn import_state_file(
+    project: &Project,
+    file: &Path,
+    storage: Option<StateStorageKind>,
+) -> Result<RoutineSuccess, RoutineFailure> {
+    let content = std::fs::read_to_string(file).map_err(|e| {
+        RoutineFailure::new(
+            Message::new(
+                "Failed".to_string(),
+                format!("to read the state from {}", file.display()),
+            ),
+            e,
+        )
+    })?;
+    let state: StateExport = serde_json::from_str(&content).map_err(|e| {
+        RoutineFailure::new(
+            Message::new("Failed".to_string(), "to parse the state".to_string()),
+            e,
+        )
+    })?;
+
+    let storage = state_storage_of_kind(project, storage.unwrap_or(project.state_config.storage));
+    import_state(storage.as_ref(), &state).await.map_err(|e| {
+        RoutineFailure::new(
+            Message::new("Failed".to_string(), "to store the state".to_string()),
+            e,
+        )
+    })?;
+
+    Ok(RoutineSuccess::success(Message::new(
+        "Imported".to_string(),
+        format!("the state from {}", file.display()),
+    )))
+}
@@ -1,19 +1,47 @@
-use log::debug;
+use convert_case::{Case, Casing};
+use log::{debug, warn};
 use pathdiff::diff_paths;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
 use std::{fs, io::Write, process::Stdio};
 
-use crate::cli::display::{Message, MessageType};
+use crate::cli::display::{with_spinner_async, Message, MessageType};
 use crate::framework::core::code_loader::{
     load_framework_objects, FrameworkObject, FrameworkObjectVersions,
 };
+use crate::framework::core::infrastructure::function_process::{
+    FunctionProcess, REPLAY_TOPIC_SUFFIX,
+};
 use crate::framework::core::infrastructure::table::ColumnType;
+use crate::framework::core::primitive_map::PrimitiveMap;
+use crate::framework::streaming::harness::{
+    check_outputs, read_expected_outputs, read_records, run_function, sample_records,
+    FunctionTestError,
+};
+use crate::framework::streaming::loader::{
+    get_all_current_streaming_functions, parse_streaming_function,
+};
+use crate::framework::streaming::model::{ReplayRange, RunnerMode, StreamingFunction};
 use crate::framework::typescript::templates::BASE_STREAMING_FUNCTION_TEMPLATE;
+use crate::framework::wasm;
+use crate::framework::wasm::bindings::generate_bindings;
+use crate::framework::wasm::templates::{
+    WASM_FUNCTION_CARGO_TEMPLATE, WASM_FUNCTION_GITIGNORE_TEMPLATE,
+    WASM_STREAMING_FUNCTION_TEMPLATE,
+};
+use crate::infrastructure::processes::functions_registry::spawn_runner;
+use crate::infrastructure::stream::redpanda::{
+    committed_range_ends, copy_topic, create_topics, delete_consumer_groups, delete_topics,
+    fetch_topics, partition_offsets, RedpandaConfig, TopicPosition,
+};
 use crate::project::Project;
+use crate::utilities::constants::{WASM_FILE_EXTENSION, WASM_FUNCTIONS_DIR};
 
 use super::{RoutineFailure, RoutineSuccess};
 
+// The target the WebAssembly streaming functions are compiled to, they don't use WASI
+const WASM_TARGET: &str = "wasm32-unknown-unknown";
+
 pub struct StreamingFunctionFileBuilder {
     function_file_path: PathBuf,
     function_file_template: String,
@@ -197,6 +225,514 @@ pub async fn create_streaming_function_file(
     Ok(success)
 }
 
+// The crate of the streaming function compiled to WebAssembly
+fn wasm_function_crate(project: &Project, name: &str) -> PathBuf {
+    project.project_location.join(WASM_FUNCTIONS_DIR).join(name)
+}
+
+// Generates the types of the data models of the WebAssembly function in its crate
+async fn write_wasm_bindings(
+    project: &Project,
+    name: &str,
+    crate_dir: &Path,
+) -> Result<(), RoutineFailure> {
+    let (source, targets) = parse_streaming_function(name).ok_or_else(|| {
+        RoutineFailure::error(Message::new(
+            "Failed".to_string(),
+            format!("to parse the name {}, expected Source__Destination", name),
+        ))
+    })?;
+    let primitive_map = PrimitiveMap::load(project).await.map_err(|e| {
+        RoutineFailure::new(
+            Message::new("Failed".to_string(), "to load the data models".to_string()),
+            e,
+        )
+    })?;
+    let data_model = |data_model_name: &str| {
+        primitive_map
+            .datamodels
+            .get(data_model_name, project.cur_version())
+            .cloned()
+            .ok_or_else(|| {
+                RoutineFailure::error(Message::new(
+                    "Failed".to_string(),
+                    format!("to find the data model {}", data_model_name),
+                ))
+            })
+    };
+    let source = data_model(source)?;
+    let targets = targets
+        .into_iter()
+        .map(data_model)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let bindings_path = crate_dir.join("src").join("moose.rs");
+    fs::write(&bindings_path, generate_bindings(&source, &targets)).map_err(|e| {
+        RoutineFailure::new(
+            Message::new(
+                "Failed".to_string(),
+                format!("to write {}", bindings_path.display()),
+            ),
+            e,
+        )
+    })
+}
+
+/**
+ * Creates the crate of a streaming function compiled to WebAssembly, in the wasm directory of the
+ * project, with the types of its data models.
+ */
+pub async fn create_wasm_function_crate(
+    project: &Project,
+    source: &str,
+    destination: &str,
+) -> Result<RoutineSuccess, RoutineFailure> {
+    let name = format!("{}__{}", source, destination);
+    let crate_dir = wasm_function_crate(project, &name);
+    if crate_dir.exists() {
+        return Err(RoutineFailure::error(Message::new(
+            "Failed".to_string(),
+            format!("to create {}, it already exists", crate_dir.display()),
+        )));
+    }
+
+    let files = [
+        (
+            "Cargo.toml",
+            WASM_FUNCTION_CARGO_TEMPLATE.replace("{{crate_name}}", &name.to_case(Case::Snake)),
+        ),
+        (".gitignore", WASM_FUNCTION_GITIGNORE_TEMPLATE.to_string()),
+        (
+            "src/lib.rs",
+            WASM_STREAMING_FUNCTION_TEMPLATE
+                .replace("{{source}}", source)
+                .replace("{{destination}}", destination),
+        ),
+    ];
+    fs::create_dir_all(crate_dir.join("src"))
+        .and_then(|_| {
+            files
+                .iter()
+                .try_for_each(|(file, content)| fs::write(crate_dir.join(file), content))
+        })
+        .map_err(|e| {
+            RoutineFailure::new(
+                Message::new(
+                    "Failed".to_string(),
+                    format!("to create the crate {}", crate_dir.display()),
+                ),
+                e,
+            )
+        })?;
+    write_wasm_bindings(project, &name, &crate_dir).await?;
+
+    Ok(RoutineSuccess::success(Message::new(
+        "Created".to_string(),
+        format!("WebAssembly streaming function {}", crate_dir.display()),
+    )))
+}
+
+/**
+ * Generates the types of the data models of the WebAssembly streaming function again, compiles
+ * its crate and copies the module to the functions directory, where moose runs it from.
+ */
+pub async fn build_wasm_function(
+    project: &Project,
+    name: &str,
+) -> Result<RoutineSuccess, RoutineFailure> {
+    let crate_dir = wasm_function_crate(project, name);
+    if !crate_dir.exists() {
+        return Err(RoutineFailure::error(Message::new(
+            "Failed".to_string(),
+            format!(
+                "to find the crate {}, create it with moose function init --wasm",
+                crate_dir.display()
+            ),
+        )));
+    }
+    write_wasm_bindings(project, name, &crate_dir).await?;
+
+    let build_failure = |e: std::io::Error| {
+        RoutineFailure::new(
+            Message::new("Failed".to_string(), format!("to build {}", name)),
+            e,
+        )
+    };
+    let output = with_spinner_async(
+        &format!("Building {}", name),
+        tokio::process::Command::new("cargo")
+            .args(["build", "--release", "--target", WASM_TARGET])
+            .current_dir(&crate_dir)
+            .output(),
+        true,
+    )
+    .await
+    .map_err(build_failure)?;
+    if !output.status.success() {
+        return Err(RoutineFailure::error(Message::new(
+            "Failed".to_string(),
+            format!(
+                "to build {}:\n{}",
+                name,
+                String::from_utf8_lossy(&output.stderr)
+            ),
+        )));
+    }
+
+    let module = project
+        .streaming_func_dir()
+        .join(format!("{}.{}", name, WASM_FILE_EXTENSION));
+    fs::copy(
+        crate_dir
+            .join("target")
+            .join(WASM_TARGET)
+            .join("release")
+            .join("moose_function.wasm"),
+        &module,
+    )
+    .map_err(build_failure)?;
+
+    Ok(RoutineSuccess::success(Message::new(
+        "Built".to_string(),
+        module.display().to_string(),
+    )))
+}
+
+// Loads the streaming function by file name, with or without its extension
+async fn load_function(project: &Project, name: &str) -> Result<StreamingFunction, RoutineFailure> {
+    let primitive_map = PrimitiveMap::load(project).await.map_err(|e| {
+        RoutineFailure::new(
+            Message::new("Failed".to_string(), "to load the data models".to_string()),
+            e,
+        )
+    })?;
+    let functions = get_all_current_streaming_functions(project, &primitive_map.datamodels)
+        .await
+        .map_err(|e| {
+            RoutineFailure::new(
+                Message::new(
+                    "Failed".to_string(),
+                    "to load the streaming functions".to_string(),
+                ),
+                e,
+            )
+        })?;
+    functions
+        .into_iter()
+        .find(|function| {
+            function.name == name || Path::new(&function.name).with_extension("") == Path::new(name)
+        })
+        .ok_or_else(|| {
+            RoutineFailure::error(Message::new(
+                "Failed".to_string(),
+                format!("to find the streaming function {}", name),
+            ))
+        })
+}
+
+/**
+ * Runs the streaming function on the records of the input file, or on records generated from its
+ * source data model, and checks its outputs against the columns of its destination data models
+ * and against the expected outputs.
+ */
+pub async fn test_streaming_function(
+    project: &Project,
+    name: &str,
+    input: Option<&Path>,
+    samples: usize,
+    expected: Option<&Path>,
+) -> Result<RoutineSuccess, RoutineFailure> {
+    let function = load_function(project, name).await?;
+    let function_process = FunctionProcess::from_function(&function, &[]);
+
+    let test_failure = |e: FunctionTestError| {
+        RoutineFailure::new(
+            Message::new("Failed".to_string(), format!("to test {}", name)),
+            e,
+        )
+    };
+    let records = match input {
+        Some(input) => read_records(input).map_err(test_failure)?,
+        None => sample_records(&function.source_data_model.columns, samples),
+    };
+    let expected = expected
+        .map(|expected| read_expected_outputs(expected, &function_process))
+        .transpose()
+        .map_err(test_failure)?;
+
+    let run = run_function(&project.redpanda_config, &function_process, &records)
+        .await
+        .map_err(test_failure)?;
+
+    let mut problems = check_outputs(&function_process, &run.outputs, expected.as_ref());
+    if run.failed {
+        for line in run.logs.iter() {
+            println!("{}", line);
+        }
+        problems.insert(0, "the function failed on some records".to_string());
+    }
+
+    let output_count: usize = run.outputs.values().map(Vec::len).sum();
+    if problems.is_empty() {
+        Ok(RoutineSuccess::success(Message::new(
+            "Tested".to_string(),
+            format!(
+                "{}: {} record(s) in, {} record(s) out",
+                name,
+                records.len(),
+                output_count
+            ),
+        )))
+    } else {
+        Err(RoutineFailure::error(Message::new(
+            "Failed".to_string(),
+            format!(
+                "{}: {} record(s) in, {} record(s) out\n{}",
+                name,
+                records.len(),
+                output_count,
+                problems.join("\n")
+            ),
+        )))
+    }
+}
+
+/**
+ * Runs the streaming function over a range of its source topic, in a consumer group of its own
+ * which is deleted once the replay is done, to reprocess records with a fixed function. The
+ * outputs go to the destination topics of the function, or to staging `<topic>_replay` topics,
+ * recreated empty, to check them first before swapping them in with `moose function swap`.
+ */
+pub async fn replay_streaming_function(
+    project: &Project,
+    name: &str,
+    from: TopicPosition,
+    to: Option<TopicPosition>,
+    staging: bool,
+) -> Result<RoutineSuccess, RoutineFailure> {
+    let function = load_function(project, name).await?;
+    let kafka_config = &project.redpanda_config;
+    let kafka_failure = |action: &str, e: anyhow::Error| {
+        RoutineFailure::new(Message::new("Failed".to_string(), action.to_string()), e)
+    };
+
+    let topics = fetch_topics(kafka_config)
+        .await
+        .map_err(|e| kafka_failure("to fetch the topics", e.into()))?;
+    let mut function_process = FunctionProcess::from_function(&function, &topics);
+    if staging {
+        for target in function_process.targets.iter_mut() {
+            target.topic = staging_topic(&target.topic);
+        }
+        // the outputs of a previous replay are dropped
+        let previous_staging_topics: Vec<String> = function_process
+            .target_topics()
+            .into_iter()
+            .filter(|topic| topics.contains(topic))
+            .collect();
+        if !previous_staging_topics.is_empty() {
+            delete_topics(kafka_config, previous_staging_topics)
+                .await
+                .map_err(|e| kafka_failure("to delete the previous staging topics", e))?;
+        }
+        create_topics(kafka_config, function_process.target_topics())
+            .await
+            .map_err(|e| kafka_failure("to create the staging topics", e))?;
+    }
+
+    let source_topic = &function_process.source_topic;
+    let starts = partition_offsets(kafka_config, source_topic, from)
+        .await
+        .map_err(|e| kafka_failure("to find the start of the replay", e.into()))?;
+    // the offsets past the end of the partitions are at their end
+    let ends = partition_offsets(
+        kafka_config,
+        source_topic,
+        to.unwrap_or(TopicPosition::Offset(i64::MAX)),
+    )
+    .await
+    .map_err(|e| kafka_failure("to find the end of the replay", e.into()))?;
+
+    let partitions: BTreeMap<i32, (i64, i64)> = starts
+        .iter()
+        .map(|(partition, start)| {
+            let end = ends.get(partition).copied().unwrap_or(*start);
+            (*partition, (*start, end.max(*start)))
+        })
+        .collect();
+    // the runners can only tell they reached the end of a range from a record they consume
+    let partitions = committed_range_ends(kafka_config, source_topic, &partitions)
+        .await
+        .map_err(|e| kafka_failure("to find the end of the replay", e.into()))?;
+
+    let range = ReplayRange {
+        group_id: format!(
+            "flow-{}-replay-{}",
+            function_process.id(),
+            chrono::Utc::now().timestamp()
+        ),
+        partitions,
+    };
+    let group_id = range.group_id.clone();
+    let record_count: i64 = range
+        .partitions
+        .values()
+        .map(|(start, end)| end - start)
+        .sum();
+
+    let replay_message = format!("Replaying {} record(s) of {}", record_count, source_topic);
+    let replayed = run_replay(
+        kafka_config,
+        name,
+        &function_process,
+        range,
+        &replay_message,
+    )
+    .await;
+    if let Err(e) = delete_consumer_groups(kafka_config, &[&group_id]).await {
+        warn!(
+            "Failed to delete the consumer group {} of the replay: {}",
+            group_id, e
+        );
+    }
+    replayed?;
+
+    Ok(RoutineSuccess::success(Message::new(
+        "Replayed".to_string(),
+        format!(
+            "{} record(s) of {} to {}",
+            record_count,
+            source_topic,
+            function_process.target_topics().join(", ")
+        ),
+    )))
+}
+
+// The staging topic of a destination topic of a function, which the replays target
+fn staging_topic(topic: &str) -> String {
+    format!("{}{}", topic, REPLAY_TOPIC_SUFFIX)
+}
+
+// The existing staging topics of the destination topics of the function, paired with them
+fn staged_topics(function_process: &FunctionProcess, topics: &[String]) -> Vec<(String, String)> {
+    function_process
+        .target_topics()
+        .into_iter()
+        .map(|topic| (staging_topic(&topic), topic))
+        .filter(|(staging_topic, _)| topics.contains(staging_topic))
+        .collect()
+}
+
+async fn run_replay(
+    kafka_config: &RedpandaConfig,
+    name: &str,
+    function_process: &FunctionProcess,
+    range: ReplayRange,
+    replay_message: &str,
+) -> Result<(), RoutineFailure> {
+    let kafka_failure = |action: &str, e: anyhow::Error| {
+        RoutineFailure::new(Message::new("Failed".to_string(), action.to_string()), e)
+    };
+
+    if function_process.is_wasm_function_process() {
+        // The WebAssembly functions run in this process
+        with_spinner_async(
+            replay_message,
+            wasm::streaming::run(
+                kafka_config.clone(),
+                function_process.clone(),
+                0,
+                Some(range),
+            ),
+            true,
+        )
+        .await
+        .map_err(|e| {
+            RoutineFailure::new(
+                Message::new("Failed".to_string(), format!("to replay {}", name)),
+                e,
+            )
+        })?;
+    } else {
+        let runner = spawn_runner(
+            kafka_config,
+            function_process,
+            0,
+            &RunnerMode::Replay(range),
+        )
+        .map_err(|e| kafka_failure("to start the function runner", e.into()))?;
+        let output = with_spinner_async(replay_message, runner.wait_with_output(), true)
+            .await
+            .map_err(|e| kafka_failure("to run the function runner", e.into()))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let last_lines: Vec<&str> = stderr.lines().rev().take(20).collect();
+            return Err(RoutineFailure::error(Message::new(
+                "Failed".to_string(),
+                format!(
+                    "to replay {}:\n{}",
+                    name,
+                    last_lines.into_iter().rev().collect::<Vec<_>>().join("\n")
+                ),
+            )));
+        }
+    }
+    Ok(())
+}
+
+/**
+ * Moves the outputs of a staged replay to the destination topics of the function, then deletes
+ * the staging topics. When discarding the outputs, the staging topics are only deleted.
+ */
+pub async fn swap_staged_replay(
+    project: &Project,
+    name: &str,
+    discard: bool,
+) -> Result<RoutineSuccess, RoutineFailure> {
+    let function = load_function(project, name).await?;
+    let kafka_config = &project.redpanda_config;
+    let kafka_failure = |action: &str, e: anyhow::Error| {
+        RoutineFailure::new(Message::new("Failed".to_string(), action.to_string()), e)
+    };
+
+    let topics = fetch_topics(kafka_config)
+        .await
+        .map_err(|e| kafka_failure("to fetch the topics", e.into()))?;
+    let function_process = FunctionProcess::from_function(&function, &topics);
+    let staged = staged_topics(&function_process, &topics);
+    if staged.is_empty() {
+        return Err(RoutineFailure::error(Message::new(
+            "Failed".to_string(),
+            format!("{} has no staged replay", name),
+        )));
+    }
+
+    if !discard {
+        for (staging_topic, topic) in staged.iter() {
+            with_spinner_async(
+                &format!("Moving {} to {}", staging_topic, topic),
+                copy_topic(kafka_config, staging_topic, topic),
+                true,
+            )
+            .await
+            .map_err(|e| kafka_failure(&format!("to move {} to {}", staging_topic, topic), e))?;
+        }
+    }
+    let staging_topics: Vec<String> = staged
+        .into_iter()
+        .map(|(staging_topic, _)| staging_topic)
+        .collect();
+    delete_topics(kafka_config, staging_topics.clone())
+        .await
+        .map_err(|e| kafka_failure("to delete the staging topics", e))?;
+
+    Ok(RoutineSuccess::success(Message::new(
+        if discard { "Discarded" } else { "Swapped" }.to_string(),
+        staging_topics.join(", "),
+    )))
+}
+
 pub fn verify_streaming_functions_against_datamodels(
     project: &Project,
     framework_object_versions: &FrameworkObjectVersions,
@@ -352,3 +888,53 @@ fn get_default_value_for_type(column_type: &ColumnType) -> String {
         ColumnType::Bytes => "[]".to_string(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framework::data_model::model::DataModel;
+
+    fn data_model(name: &str) -> DataModel {
+        DataModel {
+            columns: vec![],
+            name: name.to_string(),
+            config: Default::default(),
+            abs_file_path: PathBuf::from(format!("app/datamodels/{}.ts", name)),
+            version: "0.0".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_replays_and_swaps_target_the_live_topics() {
+        let function = StreamingFunction {
+            name: "User__Event".to_string(),
+            source_data_model: data_model("User"),
+            target_data_models: vec![data_model("Event")],
+            executable: PathBuf::from("app/functions/User__Event.ts"),
+            version: "0.0".to_string(),
+            parallelism: 1,
+            stateful: false,
+            window: None,
+        };
+        let mut topics = vec!["User_0_0".to_string(), "Event_0_0".to_string()];
+        assert!(
+            staged_topics(&FunctionProcess::from_function(&function, &topics), &topics).is_empty()
+        );
+
+        // the first replay creates the staging topic
+        topics.push(staging_topic("Event_0_0"));
+        let staged = vec![("Event_0_0_replay".to_string(), "Event_0_0".to_string())];
+
+        // the live function keeps writing to the destination topic, which is swapped with it
+        let function_process = FunctionProcess::from_function(&function, &topics);
+        assert_eq!(function_process.source_topic, "User_0_0");
+        assert_eq!(function_process.target_topics(), vec!["Event_0_0"]);
+        assert_eq!(staged_topics(&function_process, &topics), staged);
+
+        // a second replay replaces the staging topic of the first one
+        topics.push("User__Event_state".to_string());
+        let function_process = FunctionProcess::from_function(&function, &topics);
+        assert_eq!(function_process.source_topic, "User_0_0");
+        assert_eq!(staged_topics(&function_process, &topics), staged);
+    }
+}
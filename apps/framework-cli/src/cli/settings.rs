@@ -8,6 +8,7 @@ use uuid::Uuid;
 
 use super::display::{Message, MessageType};
 use super::logger::LoggerSettings;
+use super::tracing::TracingSettings;
 use crate::utilities::constants::{CLI_CONFIG_FILE, CLI_USER_DIRECTORY};
 
 /// # Config
@@ -55,6 +56,8 @@ pub struct Settings {
     pub logger: LoggerSettings,
     #[serde(default)]
     pub telemetry: Telemetry,
+    #[serde(default)]
+    pub tracing: TracingSettings,
 
     #[serde(default)]
     pub features: Features,
@@ -0,0 +1,104 @@
+//! # Tracing Module
+//!
+//! This module exports OpenTelemetry traces that follow a record from its ingestion to its
+//! ClickHouse row.
+//!
+//! ## Spans
+//!
+//! - `ingest`: the HTTP ingest request. It continues the trace of the caller if the request has a `traceparent` header.
+//! - `produce`: the send of a record to its topic. The trace context is propagated to the consumers in the Kafka headers.
+//! - `streaming_function`: the processing of a record by a streaming function, emitted by the TS and Python runners.
+//! - `clickhouse.insert`: the batch insert of records in a table, added to the trace of every record of the batch.
+//!
+//! ## Configuration
+//!
+//! Traces are exported over OTLP when `export_to` is set in the `[tracing]` section of the CLI config,
+//! e.g. `export_to = "http://localhost:4318"` for a local collector. The trace context is propagated
+//! whether or not the traces are exported.
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use opentelemetry::trace::TraceError;
+use opentelemetry::{global, KeyValue};
+use opentelemetry_http::hyper::HyperClient;
+use opentelemetry_otlp::{Protocol, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Config, TracerProvider};
+use opentelemetry_sdk::Resource;
+use opentelemetry_semantic_conventions::resource::SERVICE_NAME;
+use serde::Deserialize;
+
+use crate::utilities::constants::{CONTEXT, CTX_SESSION_ID};
+
+// Read by the OpenTelemetry SDKs of the streaming function runners
+const OTLP_TRACES_ENDPOINT_ENV: &str = "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT";
+const OTLP_TRACES_PATH: &str = "/v1/traces";
+
+// The traces endpoint passed to the runners, set once the traces are exported
+static RUNNER_TRACES_ENDPOINT: OnceLock<String> = OnceLock::new();
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct TracingSettings {
+    pub export_to: Option<reqwest::Url>,
+}
+
+pub fn setup_tracing(settings: &TracingSettings, machine_id: &str) -> Result<(), TraceError> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let otel_endpoint = match &settings.export_to {
+        None => return Ok(()),
+        Some(otel_endpoint) => otel_endpoint,
+    };
+
+    // An endpoint already set in the environment is inherited by the runners as is
+    if std::env::var(OTLP_TRACES_ENDPOINT_ENV).is_err() {
+        let _ = RUNNER_TRACES_ENDPOINT.set(format!(
+            "{}{}",
+            otel_endpoint.as_str().trim_end_matches('/'),
+            OTLP_TRACES_PATH
+        ));
+    }
+
+    let session_id = CONTEXT.get(CTX_SESSION_ID).unwrap();
+
+    let https = hyper_tls_0_5::HttpsConnector::new();
+    let client = hyper_0_14::Client::builder().build::<_, hyper_0_14::Body>(https);
+
+    let otel_exporter = opentelemetry_otlp::new_exporter()
+        .http()
+        .with_endpoint(otel_endpoint.clone())
+        .with_protocol(Protocol::HttpJson)
+        .with_http_client(HyperClient::new_with_timeout(
+            client,
+            Duration::from_millis(5000),
+        ))
+        .with_timeout(Duration::from_millis(5000))
+        .build_span_exporter()?;
+
+    let tracer_provider = TracerProvider::builder()
+        .with_config(Config::default().with_resource(Resource::new(vec![
+            KeyValue::new(SERVICE_NAME, "moose-cli"),
+            KeyValue::new("session_id", session_id.as_str()),
+            KeyValue::new("machine_id", String::from(machine_id)),
+        ])))
+        .with_batch_exporter(otel_exporter, opentelemetry_sdk::runtime::Tokio)
+        .build();
+
+    global::set_tracer_provider(tracer_provider);
+
+    Ok(())
+}
+
+// The environment of the streaming function runners, for their SDKs to export to the same endpoint
+pub fn runner_env() -> Vec<(&'static str, String)> {
+    RUNNER_TRACES_ENDPOINT
+        .get()
+        .map(|endpoint| vec![(OTLP_TRACES_ENDPOINT_ENV, endpoint.clone())])
+        .unwrap_or_default()
+}
+
+// Flushes the spans left in the batch exporter, before the process exits
+pub async fn shutdown_tracing() {
+    let _ = tokio::task::spawn_blocking(global::shutdown_tracer_provider).await;
+}
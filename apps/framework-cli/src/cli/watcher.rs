@@ -8,7 +8,7 @@ use std::time::Duration;
 use std::{
     collections::HashMap,
     io::{Error, ErrorKind},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 use tokio::sync::RwLock;
 
@@ -21,23 +21,25 @@ use crate::framework::core::code_loader::{
     get_framework_objects_from_schema_file, FrameworkObjectVersions,
 };
 use crate::framework::core::infrastructure::olap_process::OlapProcess;
+use crate::framework::core::infrastructure::table::Table;
 use crate::framework::core::infrastructure_map::ApiChange;
 use crate::framework::data_model::model::DataModelSet;
 use crate::framework::data_model::{is_schema_file, DuplicateModelError};
+use crate::framework::languages::SupportedLanguages;
 use crate::framework::streaming::loader::get_all_current_streaming_functions;
 
-use crate::infrastructure::olap::clickhouse_alt_client::{
-    get_pool, store_current_state, store_infrastructure_map,
-};
+use crate::infrastructure::olap::clickhouse_alt_client::ApplicationState;
 use crate::infrastructure::processes::aggregations_registry::AggregationProcessRegistry;
 use crate::infrastructure::processes::consumption_registry::ConsumptionProcessRegistry;
 use crate::infrastructure::processes::functions_registry::FunctionProcessRegistry;
 use crate::infrastructure::processes::kafka_clickhouse_sync::SyncingProcessesRegistry;
 use crate::infrastructure::processes::process_registry::ProcessRegistries;
+use crate::infrastructure::state_storage::state_storage;
 use crate::infrastructure::stream::redpanda::{self, fetch_topics};
 use crate::project::AggregationSet;
 use crate::utilities::constants::{
-    AGGREGATIONS_DIR, BLOCKS_DIR, CONSUMPTION_DIR, FUNCTIONS_DIR, SCHEMAS_DIR,
+    AGGREGATIONS_DIR, BLOCKS_DIR, CONSUMPTION_DIR, FUNCTIONS_DIR, PYTHON_FILE_EXTENSION,
+    PYTHON_INIT_FILE, SCHEMAS_DIR, TYPESCRIPT_FILE_EXTENSION,
 };
 use crate::{
     framework::controller::RouteMeta,
@@ -301,13 +303,14 @@ async fn watch(
     framework_object_versions: &mut FrameworkObjectVersions,
     route_table: &RwLock<HashMap<PathBuf, RouteMeta>>,
     route_update_channel: tokio::sync::mpsc::Sender<ApiChange>,
+    graphql_update_channel: tokio::sync::mpsc::Sender<Vec<Table>>,
     consumption_apis: &RwLock<HashSet<String>>,
     syncing_process_registry: &mut SyncingProcessesRegistry,
     project_registries: &mut ProcessRegistries,
 ) -> Result<(), anyhow::Error> {
     let configured_client = olap::clickhouse::create_client(project.clickhouse_config.clone());
 
-    let mut clickhouse_client_v2 = get_pool(&project.clickhouse_config).get_handle().await?;
+    let storage = state_storage(&project);
 
     let (tx, mut rx) = tokio::sync::mpsc::channel(32);
 
@@ -335,11 +338,9 @@ async fn watch(
                     with_spinner_async(
                         "Processing Infrastructure changes from file watcher",
                         async {
-                            let plan_result = framework::core::plan::plan_changes(
-                                &mut clickhouse_client_v2,
-                                &project,
-                            )
-                            .await;
+                            let plan_result =
+                                framework::core::plan::plan_changes(storage.as_ref(), &project)
+                                    .await;
 
                             match plan_result {
                                 Ok(plan_result) => {
@@ -355,9 +356,20 @@ async fn watch(
                                     )
                                     .await?;
 
-                                    store_infrastructure_map(
-                                        &mut clickhouse_client_v2,
-                                        &project.clickhouse_config,
+                                    graphql_update_channel
+                                        .send(
+                                            plan_result
+                                                .target_infra_map
+                                                .tables
+                                                .values()
+                                                .cloned()
+                                                .collect(),
+                                        )
+                                        .await?;
+
+                                    framework::core::history::store_infrastructure_map(
+                                        storage.as_ref(),
+                                        &project,
                                         &plan_result.target_infra_map,
                                     )
                                     .await?;
@@ -454,15 +466,13 @@ async fn watch(
                 }
 
                 if !features.core_v2 {
-                    let mut client = get_pool(&project.clickhouse_config).get_handle().await?;
                     let aggregations = project.get_aggregations();
-                    store_current_state(
-                        &mut client,
-                        framework_object_versions,
-                        &aggregations,
-                        &project.clickhouse_config,
-                    )
-                    .await?
+                    storage
+                        .store_application_state(&ApplicationState::from((
+                            &*framework_object_versions,
+                            &aggregations,
+                        )))
+                        .await?
                 }
 
                 let _ = verify_streaming_functions_against_datamodels(
@@ -495,7 +505,9 @@ pub async fn process_streaming_func_changes(
 ) -> anyhow::Result<()> {
     let functions = get_all_current_streaming_functions(project, data_models_set).await?;
     function_process_registry.stop_all().await?;
-    function_process_registry.start_all(&functions, topics)?;
+    function_process_registry
+        .start_all(&functions, topics)
+        .await?;
 
     Ok(())
 }
@@ -515,19 +527,17 @@ pub async fn process_consumption_changes(
     paths: &mut HashSet<String>,
 ) -> anyhow::Result<()> {
     paths.clear();
-    walkdir::WalkDir::new(project.consumption_dir())
-        .into_iter()
-        .for_each(|f| {
-            if let Ok(f) = f {
-                if f.file_type().is_file() && f.path().extension() == Some("ts".as_ref()) {
-                    if let Ok(path) = f.path().strip_prefix(project.consumption_dir()) {
-                        let mut path = path.to_path_buf();
-                        path.set_extension("");
-                        paths.insert(path.to_string_lossy().to_string());
-                    }
-                }
-            }
-        });
+    paths.extend(consumption_api_paths(project, &project.consumption_dir()));
+
+    // Old versions are routed as `<name>/<version>`
+    for version in project.supported_old_versions.keys() {
+        let version_dir = project.old_version_consumption_dir(version);
+        paths.extend(
+            consumption_api_paths(project, &version_dir)
+                .into_iter()
+                .map(|path| format!("{}/{}", path, version)),
+        );
+    }
 
     debug!("Consumption API paths: {:?}", paths);
     consumption_process_registry.stop().await?;
@@ -536,6 +546,28 @@ pub async fn process_consumption_changes(
     Ok(())
 }
 
+fn consumption_api_paths(project: &Project, dir: &Path) -> Vec<String> {
+    let extension = match project.language {
+        SupportedLanguages::Typescript => TYPESCRIPT_FILE_EXTENSION,
+        SupportedLanguages::Python => PYTHON_FILE_EXTENSION,
+    };
+
+    walkdir::WalkDir::new(dir)
+        .into_iter()
+        .flatten()
+        .filter(|f| {
+            f.file_type().is_file()
+                && f.path().extension() == Some(extension.as_ref())
+                && f.file_name() != PYTHON_INIT_FILE
+        })
+        .filter_map(|f| {
+            let mut path = f.path().strip_prefix(dir).ok()?.to_path_buf();
+            path.set_extension("");
+            Some(path.to_string_lossy().to_string())
+        })
+        .collect()
+}
+
 pub struct FileWatcher;
 
 impl FileWatcher {
@@ -552,6 +584,7 @@ impl FileWatcher {
         framework_object_versions: FrameworkObjectVersions,
         route_table: &'static RwLock<HashMap<PathBuf, RouteMeta>>,
         route_update_channel: tokio::sync::mpsc::Sender<ApiChange>,
+        graphql_update_channel: tokio::sync::mpsc::Sender<Vec<Table>>,
         consumption_apis: &'static RwLock<HashSet<String>>,
         syncing_process_registry: SyncingProcessesRegistry,
         project_registries: ProcessRegistries,
@@ -575,6 +608,7 @@ impl FileWatcher {
                 &mut framework_object_versions,
                 route_table,
                 route_update_channel,
+                graphql_update_channel,
                 consumption_apis,
                 &mut syncing_process_registry,
                 &mut project_registry,
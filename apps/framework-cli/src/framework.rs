@@ -11,6 +11,7 @@ pub mod python;
 pub mod sdk;
 pub mod streaming;
 pub mod typescript;
+pub mod wasm;
 
 pub enum Insights {
     Metric,
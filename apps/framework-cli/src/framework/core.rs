@@ -20,9 +20,15 @@
 /// │              │                     │              │
 /// └──────────────┘                     └──────────────┘
 ///
+pub mod change_graph;
 pub mod code_loader;
+pub mod drift;
 pub mod execute;
+pub mod guardrails;
+pub mod history;
 pub mod infrastructure;
 pub mod infrastructure_map;
+pub mod journal;
+pub mod lineage;
 pub mod plan;
 pub mod primitive_map;
@@ -0,0 +1,334 @@
+//! Dependency graph between the infrastructure changes, used to apply the independent ones
+//! concurrently.
+//!
+//! A change depends on the changes creating the tables and topics it uses: a sync process
+//! depends on its topic and its table, a function process on its source and target topics.
+//! The drop of a table or a topic depends on the changes stopping the processes using it.
+
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::future::Future;
+
+use futures::stream::{FuturesUnordered, StreamExt};
+
+use super::infrastructure_map::{
+    ApiChange, Change, InfraChange, OlapChange, ProcessChange, StreamingChange,
+};
+
+#[derive(Debug, Clone)]
+pub struct ChangeNode {
+    // The position of the change in the journal, None for the changes that are not journaled
+    pub seq: Option<u32>,
+    pub change: InfraChange,
+}
+
+#[derive(Debug)]
+pub struct ChangeFailure<E> {
+    pub change: String,
+    pub error: E,
+}
+
+/// The changes that failed, and the ones that were not applied because they depend on them.
+#[derive(Debug)]
+pub struct ChangeGraphError<E> {
+    pub failures: Vec<ChangeFailure<E>>,
+    pub skipped: Vec<String>,
+}
+
+impl<E: fmt::Debug> fmt::Display for ChangeGraphError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let failures = self
+            .failures
+            .iter()
+            .map(|failure| format!("{} failed: {:?}", failure.change, failure.error))
+            .collect::<Vec<String>>()
+            .join(", ");
+        write!(f, "{}", failures)?;
+        if !self.skipped.is_empty() {
+            write!(f, ". Not applied: {}", self.skipped.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl<E: fmt::Debug> std::error::Error for ChangeGraphError<E> {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Resource {
+    Table(String),
+    Topic(String),
+}
+
+// The tables and topics a change acts on
+#[derive(Debug, Default)]
+struct Footprint {
+    creates: Vec<Resource>,
+    uses: Vec<Resource>,
+    stops_using: Vec<Resource>,
+    drops: Vec<Resource>,
+}
+
+fn verb<T>(change: &Change<T>) -> &'static str {
+    match change {
+        Change::Added(_) => "create",
+        Change::Removed(_) => "remove",
+        Change::Updated { .. } => "update",
+    }
+}
+
+fn subject<T>(change: &Change<T>) -> &T {
+    match change {
+        Change::Added(t) | Change::Removed(t) | Change::Updated { after: t, .. } => t,
+    }
+}
+
+pub fn describe(change: &InfraChange) -> String {
+    match change {
+        InfraChange::Olap(OlapChange::Table(c)) => format!("{} table {}", verb(c), subject(c).name),
+        InfraChange::Streaming(StreamingChange::Topic(c)) => {
+            format!("{} topic {}", verb(c), subject(c).id())
+        }
+        InfraChange::Api(ApiChange::ApiEndpoint(c)) => {
+            format!("{} api endpoint {}", verb(c), subject(c).id())
+        }
+        InfraChange::Process(ProcessChange::TopicToTableSyncProcess(c)) => {
+            format!("{} sync process {}", verb(c), subject(c).id())
+        }
+        InfraChange::Process(ProcessChange::FunctionProcess(c)) => {
+            format!("{} function process {}", verb(c), subject(c).id())
+        }
+        InfraChange::Process(ProcessChange::OlapProcess(c)) => {
+            format!("{} olap process {}", verb(c), subject(c).id())
+        }
+        InfraChange::Process(ProcessChange::ConsumptionApiWebServer(c)) => {
+            format!("{} consumption web server", verb(c))
+        }
+    }
+}
+
+// Splits a change into what is used after it is applied and what was used before
+fn before_after<T>(change: &Change<T>) -> (Option<&T>, Option<&T>) {
+    match change {
+        Change::Added(t) => (None, Some(t)),
+        Change::Removed(t) => (Some(t), None),
+        Change::Updated { before, after } => (Some(before), Some(after)),
+    }
+}
+
+fn footprint(change: &InfraChange) -> Footprint {
+    let mut footprint = Footprint::default();
+    match change {
+        InfraChange::Olap(OlapChange::Table(change)) => match change {
+            Change::Removed(table) => footprint.drops.push(Resource::Table(table.name.clone())),
+            Change::Added(table) | Change::Updated { after: table, .. } => {
+                footprint.creates.push(Resource::Table(table.name.clone()))
+            }
+        },
+        // Function processes refer to the topics by name or by id depending on how they were built
+        InfraChange::Streaming(StreamingChange::Topic(change)) => match change {
+            Change::Removed(topic) => footprint.drops.extend([
+                Resource::Topic(topic.id()),
+                Resource::Topic(topic.name.clone()),
+            ]),
+            Change::Added(topic) | Change::Updated { after: topic, .. } => {
+                footprint.creates.extend([
+                    Resource::Topic(topic.id()),
+                    Resource::Topic(topic.name.clone()),
+                ])
+            }
+        },
+        InfraChange::Process(ProcessChange::TopicToTableSyncProcess(change)) => {
+            let (before, after) = before_after(change);
+            for (sync, resources) in [
+                (before, &mut footprint.stops_using),
+                (after, &mut footprint.uses),
+            ] {
+                if let Some(sync) = sync {
+                    resources.push(Resource::Topic(sync.source_topic_id.clone()));
+                    resources.push(Resource::Table(sync.target_table_id.clone()));
+                }
+            }
+        }
+        InfraChange::Process(ProcessChange::FunctionProcess(change)) => {
+            let (before, after) = before_after(change);
+            for (function, resources) in [
+                (before, &mut footprint.stops_using),
+                (after, &mut footprint.uses),
+            ] {
+                if let Some(function) = function {
+                    resources.push(Resource::Topic(function.source_topic.clone()));
+                    resources.extend(function.target_topics().into_iter().map(Resource::Topic));
+                }
+            }
+        }
+        InfraChange::Api(_)
+        | InfraChange::Process(ProcessChange::OlapProcess(_))
+        | InfraChange::Process(ProcessChange::ConsumptionApiWebServer(_)) => {}
+    }
+    footprint
+}
+
+pub struct ChangeGraph {
+    nodes: Vec<ChangeNode>,
+    // Number of changes each change waits for
+    dependencies: Vec<usize>,
+    // The changes waiting for each change
+    dependents: Vec<Vec<usize>>,
+}
+
+impl ChangeGraph {
+    pub fn new(nodes: Vec<ChangeNode>) -> ChangeGraph {
+        let footprints: Vec<Footprint> = nodes.iter().map(|node| footprint(&node.change)).collect();
+
+        let mut dependencies = vec![0; nodes.len()];
+        let mut dependents = vec![Vec::new(); nodes.len()];
+        for (i, first) in footprints.iter().enumerate() {
+            let created: HashSet<&Resource> = first.creates.iter().collect();
+            let released: HashSet<&Resource> = first.stops_using.iter().collect();
+
+            for (j, then) in footprints.iter().enumerate() {
+                if i == j {
+                    continue;
+                }
+
+                let depends = then.uses.iter().any(|r| created.contains(r))
+                    || then.drops.iter().any(|r| released.contains(r));
+                if depends {
+                    dependents[i].push(j);
+                    dependencies[j] += 1;
+                }
+            }
+        }
+
+        ChangeGraph {
+            nodes,
+            dependencies,
+            dependents,
+        }
+    }
+
+    /**
+     * Applies the changes, each one as soon as the changes it depends on are applied, with at most
+     * `max_parallelism` changes in flight. A change that fails doesn't stop the independent
+     * ones, but the changes depending on it are not applied.
+     */
+    pub async fn execute<F, Fut, E>(
+        &self,
+        max_parallelism: usize,
+        execute: F,
+    ) -> Result<(), ChangeGraphError<E>>
+    where
+        F: Fn(ChangeNode) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+    {
+        let mut waiting_for = self.dependencies.clone();
+        let mut started = vec![false; self.nodes.len()];
+        let mut ready: VecDeque<usize> = (0..self.nodes.len())
+            .filter(|i| waiting_for[*i] == 0)
+            .collect();
+        let mut running = FuturesUnordered::new();
+        let mut failures = Vec::new();
+
+        loop {
+            while running.len() < max_parallelism.max(1) {
+                let i = match ready.pop_front() {
+                    Some(i) => i,
+                    None => break,
+                };
+                started[i] = true;
+                let change = execute(self.nodes[i].clone());
+                running.push(async move { (i, change.await) });
+            }
+
+            match running.next().await {
+                None => break,
+                Some((i, Ok(()))) => {
+                    for &j in self.dependents[i].iter() {
+                        waiting_for[j] -= 1;
+                        if waiting_for[j] == 0 {
+                            ready.push_back(j);
+                        }
+                    }
+                }
+                Some((i, Err(error))) => failures.push(ChangeFailure {
+                    change: describe(&self.nodes[i].change),
+                    error,
+                }),
+            }
+        }
+
+        let skipped: Vec<String> = self
+            .nodes
+            .iter()
+            .zip(started.iter())
+            .filter(|(_, started)| !**started)
+            .map(|(node, _)| describe(&node.change))
+            .collect();
+
+        if failures.is_empty() && skipped.is_empty() {
+            Ok(())
+        } else {
+            Err(ChangeGraphError { failures, skipped })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use crate::framework::core::infrastructure::topic_to_table_sync_process::TopicToTableSyncProcess;
+    use crate::framework::core::infrastructure_map::fixtures::{table, topic};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_dependent_changes_run_after_their_dependencies() {
+        let topic = topic("User");
+        let table = table("User", vec![]);
+        let sync = TopicToTableSyncProcess::new(&topic, &table);
+
+        let node = |change: InfraChange| ChangeNode { seq: None, change };
+        let graph = ChangeGraph::new(vec![
+            node(InfraChange::Process(
+                ProcessChange::TopicToTableSyncProcess(Change::Added(sync)),
+            )),
+            node(InfraChange::Olap(OlapChange::Table(Change::Added(table)))),
+            node(InfraChange::Streaming(StreamingChange::Topic(
+                Change::Added(topic),
+            ))),
+        ]);
+
+        let applied = Mutex::new(Vec::new());
+        graph
+            .execute(4, |node| {
+                applied.lock().unwrap().push(describe(&node.change));
+                async { Ok::<(), ()>(()) }
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            applied.into_inner().unwrap().last().unwrap(),
+            "create sync process User_0_0_User_0_0_0_0"
+        );
+
+        let result = graph
+            .execute(4, |node| {
+                let fails = matches!(node.change, InfraChange::Olap(_));
+                async move {
+                    if fails {
+                        Err("table")
+                    } else {
+                        Ok(())
+                    }
+                }
+            })
+            .await;
+        let error = result.unwrap_err();
+        assert_eq!(error.failures.len(), 1);
+        assert_eq!(
+            error.skipped,
+            vec!["create sync process User_0_0_User_0_0_0_0"]
+        );
+    }
+}
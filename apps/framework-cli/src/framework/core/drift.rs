@@ -0,0 +1,381 @@
+//! Detection of the differences between the stored infrastructure map and the live
+//! infrastructure, created by tables and topics added, dropped or altered by hand.
+//!
+//! The live tables are read from the `system` tables of Clickhouse and the live topics from
+//! the metadata and configs of Redpanda. The tables and topics whose name starts with `_` are
+//! internal, and the backups and migration tables, and the state and replay topics of the
+//! streaming functions, are left out.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::warn;
+
+use crate::infrastructure::olap::clickhouse::errors::ClickhouseError;
+use crate::infrastructure::olap::clickhouse::mapper::std_column_to_clickhouse_column;
+use crate::infrastructure::olap::clickhouse::model::sanitize_column_name;
+use crate::infrastructure::olap::clickhouse::{
+    create_client, fetch_all_tables, fetch_table_schema,
+};
+use crate::infrastructure::stream::redpanda::{describe_topic_config, fetch_topics};
+use crate::project::Project;
+
+use super::infrastructure::function_process::{REPLAY_TOPIC_SUFFIX, STATE_TOPIC_SUFFIX};
+use super::infrastructure_map::InfrastructureMap;
+
+#[derive(Debug, thiserror::Error)]
+pub enum DriftError {
+    #[error("Failed to read the tables from Clickhouse")]
+    Clickhouse(#[from] clickhouse::error::Error),
+
+    #[error("Failed to map the tables to Clickhouse")]
+    ClickhouseMapping(#[from] ClickhouseError),
+
+    #[error("Failed to read the topics from Redpanda")]
+    Redpanda(#[from] rdkafka::error::KafkaError),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Drift {
+    // The table is in the infrastructure map but not in Clickhouse
+    MissingTable {
+        table: String,
+    },
+    // The table is in Clickhouse but not in the infrastructure map
+    ExtraTable {
+        table: String,
+    },
+    MissingColumn {
+        table: String,
+        column: String,
+    },
+    ExtraColumn {
+        table: String,
+        column: String,
+    },
+    MismatchedColumn {
+        table: String,
+        column: String,
+        expected: String,
+        actual: String,
+    },
+    MissingTopic {
+        topic: String,
+    },
+    ExtraTopic {
+        topic: String,
+    },
+    MismatchedTopicConfig {
+        topic: String,
+        config: String,
+        expected: String,
+        actual: Option<String>,
+    },
+}
+
+impl Drift {
+    pub fn display(&self) -> String {
+        match self {
+            Drift::MissingTable { table } => format!("table {} is missing", table),
+            Drift::ExtraTable { table } => format!("table {} is not managed by moose", table),
+            Drift::MissingColumn { table, column } => {
+                format!("column {} of table {} is missing", column, table)
+            }
+            Drift::ExtraColumn { table, column } => {
+                format!(
+                    "column {} of table {} is not in the data model",
+                    column, table
+                )
+            }
+            Drift::MismatchedColumn {
+                table,
+                column,
+                expected,
+                actual,
+            } => format!(
+                "column {} of table {} is {} instead of {}",
+                column, table, actual, expected
+            ),
+            Drift::MissingTopic { topic } => format!("topic {} is missing", topic),
+            Drift::ExtraTopic { topic } => format!("topic {} is not managed by moose", topic),
+            Drift::MismatchedTopicConfig {
+                topic,
+                config,
+                expected,
+                actual,
+            } => format!(
+                "{} of topic {} is {} instead of {}",
+                config,
+                topic,
+                actual.clone().unwrap_or("not set".to_string()),
+                expected
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LiveInfrastructure {
+    // Columns of the tables, by table name, as (name, type)
+    pub tables: HashMap<String, Vec<(String, String)>>,
+    // Configs of the topics, by topic name
+    pub topics: HashMap<String, HashMap<String, String>>,
+}
+
+fn is_managed_name(name: &str) -> bool {
+    !name.starts_with('_')
+        && !name.contains("_backup_")
+        && !name.ends_with("_migration")
+        && !name.ends_with(STATE_TOPIC_SUFFIX)
+        && !name.ends_with(REPLAY_TOPIC_SUFFIX)
+}
+
+impl LiveInfrastructure {
+    pub async fn fetch(project: &Project) -> Result<LiveInfrastructure, DriftError> {
+        let configured_client = create_client(project.clickhouse_config.clone());
+
+        let mut tables = HashMap::new();
+        for table in fetch_all_tables(&configured_client).await? {
+            if table.database != project.clickhouse_config.db_name
+                || table.engine.contains("View")
+                || !is_managed_name(&table.name)
+            {
+                continue;
+            }
+            let columns = fetch_table_schema(&configured_client, &table.name).await?;
+            tables.insert(table.name, columns);
+        }
+
+        let mut topics = HashMap::new();
+        for topic in fetch_topics(&project.redpanda_config).await? {
+            if !is_managed_name(&topic) {
+                continue;
+            }
+            let config = describe_topic_config(&project.redpanda_config, &topic).await?;
+            topics.insert(topic, config);
+        }
+
+        Ok(LiveInfrastructure { tables, topics })
+    }
+}
+
+/**
+ * Compares the infrastructure map with the live infrastructure. The topics are expected to have
+ * the retention configured for the project, which is the one they are created with.
+ */
+pub fn detect_drift(
+    infra_map: &InfrastructureMap,
+    live: &LiveInfrastructure,
+    retention_ms: i32,
+) -> Result<Vec<Drift>, DriftError> {
+    let mut drifts = Vec::new();
+
+    let mut tables: Vec<_> = infra_map.tables.values().collect();
+    tables.sort_by(|a, b| a.name.cmp(&b.name));
+    for table in tables {
+        let live_columns = match live.tables.get(&table.name) {
+            Some(live_columns) => live_columns,
+            None => {
+                drifts.push(Drift::MissingTable {
+                    table: table.name.clone(),
+                });
+                continue;
+            }
+        };
+
+        for column in table.columns.iter() {
+            let column = std_column_to_clickhouse_column(column.clone())?;
+            let name = sanitize_column_name(column.name.clone());
+            let actual = live_columns
+                .iter()
+                .find(|(live_name, _)| *live_name == name)
+                .map(|(_, live_type)| live_type.clone());
+
+            match (actual, column.system_type()) {
+                (None, _) => drifts.push(Drift::MissingColumn {
+                    table: table.name.clone(),
+                    column: name,
+                }),
+                (Some(actual), Some(expected)) if actual != expected => {
+                    drifts.push(Drift::MismatchedColumn {
+                        table: table.name.clone(),
+                        column: name,
+                        expected,
+                        actual,
+                    })
+                }
+                // When the type isn't listed as declared, only the presence of the column is checked
+                _ => {}
+            }
+        }
+
+        for (live_name, _) in live_columns.iter() {
+            let is_declared = table
+                .columns
+                .iter()
+                .any(|column| sanitize_column_name(column.name.clone()) == *live_name);
+            if !is_declared {
+                drifts.push(Drift::ExtraColumn {
+                    table: table.name.clone(),
+                    column: live_name.clone(),
+                });
+            }
+        }
+    }
+
+    let mut extra_tables: Vec<&String> = live
+        .tables
+        .keys()
+        .filter(|name| !infra_map.tables.values().any(|table| table.name == **name))
+        .collect();
+    extra_tables.sort();
+    drifts.extend(extra_tables.into_iter().map(|table| Drift::ExtraTable {
+        table: table.clone(),
+    }));
+
+    let mut topics: Vec<String> = infra_map.topics.values().map(|topic| topic.id()).collect();
+    topics.sort();
+    let expected_retention = retention_ms.to_string();
+    for topic in topics.iter() {
+        match live.topics.get(topic) {
+            None => drifts.push(Drift::MissingTopic {
+                topic: topic.clone(),
+            }),
+            Some(config) => {
+                let actual = config.get("retention.ms");
+                if actual != Some(&expected_retention) {
+                    drifts.push(Drift::MismatchedTopicConfig {
+                        topic: topic.clone(),
+                        config: "retention.ms".to_string(),
+                        expected: expected_retention.clone(),
+                        actual: actual.cloned(),
+                    });
+                }
+            }
+        }
+    }
+
+    let mut extra_topics: Vec<&String> = live
+        .topics
+        .keys()
+        .filter(|name| !topics.contains(name))
+        .collect();
+    extra_topics.sort();
+    drifts.extend(extra_topics.into_iter().map(|topic| Drift::ExtraTopic {
+        topic: topic.clone(),
+    }));
+
+    Ok(drifts)
+}
+
+const DRIFT_CHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/**
+ * Checks periodically that the live infrastructure still matches the deployed infrastructure
+ * map, and logs the drift found.
+ */
+pub fn spawn_drift_check(project: Arc<Project>, infra_map: InfrastructureMap) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(DRIFT_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            let drifts = match LiveInfrastructure::fetch(&project).await {
+                Ok(live) => detect_drift(&infra_map, &live, project.redpanda_config.retention_ms),
+                Err(e) => Err(e),
+            };
+            match drifts {
+                Ok(drifts) => {
+                    for drift in drifts.iter() {
+                        warn!("<drift> {}", drift.display());
+                    }
+                }
+                Err(e) => warn!("<drift> Failed to check the infrastructure: {:?}", e),
+            }
+        }
+    });
+}
+
+/**
+ * Removes the tables and topics that are missing from the infrastructure map, so that planning
+ * from the refreshed map creates them again. The other drifts can't be fixed automatically
+ * and are only reported.
+ */
+pub fn refresh(infra_map: &mut InfrastructureMap, drifts: &[Drift]) {
+    for drift in drifts.iter() {
+        match drift {
+            Drift::MissingTable { table } => infra_map.tables.retain(|_, t| t.name != *table),
+            Drift::MissingTopic { topic } => infra_map.topics.retain(|_, t| t.id() != *topic),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::framework::core::infrastructure::table::ColumnType;
+    use crate::framework::core::infrastructure_map::fixtures::{column, table};
+    use crate::framework::core::primitive_map::PrimitiveMap;
+
+    use super::*;
+
+    #[test]
+    fn test_detect_drift() {
+        let mut infra_map = InfrastructureMap::new(PrimitiveMap::default());
+        let table = table(
+            "User",
+            vec![
+                column("id", ColumnType::Int, true),
+                column("age", ColumnType::Int, false),
+                column("score", ColumnType::Int, true),
+            ],
+        );
+        infra_map.tables.insert(table.id(), table);
+
+        let live = LiveInfrastructure {
+            tables: HashMap::from([
+                (
+                    "User_0_0".to_string(),
+                    vec![
+                        ("age".to_string(), "Int64".to_string()),
+                        ("id".to_string(), "Int64".to_string()),
+                        ("nickname".to_string(), "String".to_string()),
+                    ],
+                ),
+                ("Manual".to_string(), vec![]),
+            ]),
+            topics: HashMap::new(),
+        };
+
+        assert_eq!(
+            detect_drift(&infra_map, &live, 1000).unwrap(),
+            vec![
+                Drift::MismatchedColumn {
+                    table: "User_0_0".to_string(),
+                    column: "age".to_string(),
+                    expected: "Nullable(Int64)".to_string(),
+                    actual: "Int64".to_string(),
+                },
+                Drift::MissingColumn {
+                    table: "User_0_0".to_string(),
+                    column: "score".to_string(),
+                },
+                Drift::ExtraColumn {
+                    table: "User_0_0".to_string(),
+                    column: "nickname".to_string(),
+                },
+                Drift::ExtraTable {
+                    table: "Manual".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_function_topics_are_not_extra() {
+        assert!(is_managed_name("User_0_0"));
+        assert!(!is_managed_name("User__Event_state"));
+        assert!(!is_managed_name("Event_0_0_replay"));
+    }
+}
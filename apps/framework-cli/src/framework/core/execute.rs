@@ -1,4 +1,7 @@
-use tokio::sync::mpsc::Sender;
+use std::sync::Arc;
+
+use clickhouse_rs::ClientHandle;
+use tokio::sync::{mpsc::Sender, Mutex};
 
 use crate::{
     cli::settings::Features,
@@ -11,10 +14,16 @@ use crate::{
         },
         stream,
     },
+    metrics::Metrics,
     project::Project,
 };
 
-use super::{infrastructure_map::ApiChange, plan::InfraPlan};
+use super::{
+    change_graph::{ChangeGraph, ChangeGraphError, ChangeNode},
+    infrastructure_map::{ApiChange, InfraChange, OlapChange, StreamingChange},
+    journal::{ChangeStatus, Deployment, JournalError},
+    plan::InfraPlan,
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum ExecutionError {
@@ -29,17 +38,62 @@ pub enum ExecutionError {
 
     #[error("Failed to communicate with Sync Processes")]
     SyncProcessesChange(#[from] processes::SyncProcessChangesError),
+
+    #[error("Failed to record the changes in the journal")]
+    Journal(#[from] JournalError),
+
+    #[error("Failed to apply the infrastructure changes: {0}")]
+    Changes(#[from] ChangeGraphError<ExecutionError>),
 }
 
+// Maximum number of infrastructure changes applied at the same time
+const MAX_PARALLEL_CHANGES: usize = 8;
+
+#[allow(clippy::too_many_arguments)]
 pub async fn execute_initial_infra_change(
     project: &Project,
     features: &Features, // We should get rid of this when we moved away from aggregations
     plan: &InfraPlan,
+    client: &mut ClientHandle,
+    deployment: &mut Deployment,
     api_changes_channel: Sender<ApiChange>,
+    metrics: Arc<Metrics>,
 ) -> Result<(SyncingProcessesRegistry, ProcessRegistries), ExecutionError> {
-    // This probably can be paralelized through Tokio Spawn
-    olap::execute_changes(project, &plan.changes.olap_changes).await?;
-    stream::execute_changes(project, &plan.changes.streaming_engine_changes).await?;
+    let mut syncing_processes_registry = SyncingProcessesRegistry::new(
+        project.redpanda_config.clone(),
+        project.clickhouse_config.clone(),
+        metrics.clone(),
+    );
+    let mut process_registries = ProcessRegistries::new(project, features, metrics);
+
+    // The deployment holds the OLAP and streaming changes of the plan and records which of
+    // them are already applied. The processes are started from scratch.
+    let nodes = deployment
+        .entries
+        .iter()
+        .filter(|entry| entry.status != ChangeStatus::Applied)
+        .map(|entry| ChangeNode {
+            seq: Some(entry.seq),
+            change: entry.change.clone(),
+        })
+        .chain(
+            plan.target_infra_map
+                .init_processes()
+                .into_iter()
+                .map(|change| ChangeNode {
+                    seq: None,
+                    change: InfraChange::Process(change),
+                }),
+        )
+        .collect();
+
+    let journal = Mutex::new((client, deployment));
+    let registries = Mutex::new((&mut syncing_processes_registry, &mut process_registries));
+    ChangeGraph::new(nodes)
+        .execute(MAX_PARALLEL_CHANGES, |node| {
+            execute_node(project, node, &journal, &registries)
+        })
+        .await?;
 
     // In prod, the webserver is part of the current process that gets spawned. As succh
     // it is initialized from 0 and we don't need to apply diffs to it.
@@ -49,19 +103,6 @@ pub async fn execute_initial_infra_change(
     )
     .await?;
 
-    let mut syncing_processes_registry = SyncingProcessesRegistry::new(
-        project.redpanda_config.clone(),
-        project.clickhouse_config.clone(),
-    );
-    let mut process_registries = ProcessRegistries::new(project, features);
-
-    processes::execute_changes(
-        &mut syncing_processes_registry,
-        &mut process_registries,
-        &plan.target_infra_map.init_processes(),
-    )
-    .await?;
-
     Ok((syncing_processes_registry, process_registries))
 }
 
@@ -89,3 +130,97 @@ pub async fn execute_online_change(
 
     Ok(())
 }
+
+async fn execute_change(project: &Project, change: &InfraChange) -> Result<(), ExecutionError> {
+    match change {
+        InfraChange::Olap(change) => {
+            olap::execute_changes(project, std::slice::from_ref(change)).await?
+        }
+        InfraChange::Streaming(change) => {
+            stream::execute_changes(project, std::slice::from_ref(change)).await?
+        }
+        // API and process changes are not journaled
+        InfraChange::Api(_) | InfraChange::Process(_) => {}
+    }
+    Ok(())
+}
+
+// Applies a change of the graph, recording the outcome of the journaled ones in the journal
+async fn execute_node(
+    project: &Project,
+    node: ChangeNode,
+    journal: &Mutex<(&mut ClientHandle, &mut Deployment)>,
+    registries: &Mutex<(&mut SyncingProcessesRegistry, &mut ProcessRegistries)>,
+) -> Result<(), ExecutionError> {
+    if let InfraChange::Process(change) = &node.change {
+        let mut registries = registries.lock().await;
+        let (syncing_processes_registry, process_registries) = &mut *registries;
+        processes::execute_changes(
+            syncing_processes_registry,
+            process_registries,
+            std::slice::from_ref(change),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let result = execute_change(project, &node.change).await;
+    if let Some(seq) = node.seq {
+        let (status, error) = match &result {
+            Ok(()) => (ChangeStatus::Applied, None),
+            Err(e) => (ChangeStatus::Failed, Some(format!("{:?}", e))),
+        };
+        let mut journal = journal.lock().await;
+        let (client, deployment) = &mut *journal;
+        deployment
+            .record(client, &project.clickhouse_config, seq, status, error)
+            .await?;
+    }
+    result
+}
+
+/**
+ * Undoes the applied changes of a failed deployment, last one first, and releases its lock.
+ * The tables and topics it created are dropped, the ones it dropped are created again, empty,
+ * their data being in the backups made before they were dropped.
+ */
+pub async fn rollback_deployment(
+    project: &Project,
+    client: &mut ClientHandle,
+    deployment: &mut Deployment,
+) -> Result<(), ExecutionError> {
+    let to_revert: Vec<(u32, InfraChange)> = deployment
+        .entries
+        .iter()
+        .rev()
+        .filter(|entry| entry.status == ChangeStatus::Applied)
+        .map(|entry| (entry.seq, entry.change.clone()))
+        .collect();
+
+    for (seq, change) in to_revert {
+        let reverse = match &change {
+            InfraChange::Olap(OlapChange::Table(change)) => {
+                InfraChange::Olap(OlapChange::Table(change.reverse()))
+            }
+            InfraChange::Streaming(StreamingChange::Topic(change)) => {
+                InfraChange::Streaming(StreamingChange::Topic(change.reverse()))
+            }
+            InfraChange::Api(_) | InfraChange::Process(_) => continue,
+        };
+        execute_change(project, &reverse).await?;
+        deployment
+            .record(
+                client,
+                &project.clickhouse_config,
+                seq,
+                ChangeStatus::RolledBack,
+                None,
+            )
+            .await?;
+    }
+
+    deployment
+        .finish(client, &project.clickhouse_config)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,250 @@
+//! Guardrails against the changes that lose data when they are applied in production.
+//!
+//! A change is destructive when it drops a table, deletes a topic, drops a column or narrows
+//! the type of a column. These changes are blocked unless `--allow-destructive` is passed or
+//! the table or topic is listed in the `allow_destructive` of the project's `migration_config`.
+//!
+//! The data models have no annotation to opt out: the objects are listed by name, which includes
+//! the version of their data model, and all the destructive changes of a listed object are
+//! allowed.
+
+use serde::{Deserialize, Serialize};
+
+use super::infrastructure::table::{ColumnChange, Table};
+use super::infrastructure_map::{Change, InfraChanges, OlapChange, StreamingChange};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MigrationConfig {
+    // Names of the tables and topics whose destructive changes are allowed, as in ClickHouse and
+    // Redpanda
+    #[serde(default)]
+    pub allow_destructive: Vec<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("The plan contains destructive changes: {}. Pass --allow-destructive or allow them in the migration_config of the project", .changes.join(", "))]
+pub struct DestructiveChangesError {
+    pub changes: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DestructiveChange {
+    TableDrop { table: String },
+    TopicDelete { topic: String },
+    ColumnDrop { table: String, column: String },
+    TypeNarrowing { table: String, column: String },
+}
+
+impl DestructiveChange {
+    // The name of the table or topic the change applies to
+    pub fn object_id(&self) -> &str {
+        match self {
+            DestructiveChange::TableDrop { table } => table,
+            DestructiveChange::TopicDelete { topic } => topic,
+            DestructiveChange::ColumnDrop { table, .. } => table,
+            DestructiveChange::TypeNarrowing { table, .. } => table,
+        }
+    }
+
+    pub fn display(&self) -> String {
+        match self {
+            DestructiveChange::TableDrop { table } => format!("drop of table {}", table),
+            DestructiveChange::TopicDelete { topic } => format!("deletion of topic {}", topic),
+            DestructiveChange::ColumnDrop { table, column } => {
+                format!("drop of column {} of table {}", column, table)
+            }
+            DestructiveChange::TypeNarrowing { table, column } => {
+                format!("narrowing of column {} of table {}", column, table)
+            }
+        }
+    }
+}
+
+pub fn destructive_changes(changes: &InfraChanges) -> Vec<DestructiveChange> {
+    let mut destructive = Vec::new();
+
+    for change in changes.olap_changes.iter() {
+        match change {
+            OlapChange::Table(Change::Removed(table)) => {
+                destructive.push(DestructiveChange::TableDrop {
+                    table: table.name.clone(),
+                })
+            }
+            OlapChange::Table(Change::Updated { before, after }) => {
+                destructive.extend(destructive_column_changes(before, after))
+            }
+            OlapChange::Table(Change::Added(_)) => {}
+        }
+    }
+
+    for change in changes.streaming_engine_changes.iter() {
+        if let StreamingChange::Topic(Change::Removed(topic)) = change {
+            destructive.push(DestructiveChange::TopicDelete { topic: topic.id() })
+        }
+    }
+
+    destructive
+}
+
+pub fn destructive_column_changes(before: &Table, after: &Table) -> Vec<DestructiveChange> {
+    before
+        .diff(after)
+        .column_changes
+        .iter()
+        .filter_map(|change| match change {
+            ColumnChange::Removed(column) => Some(DestructiveChange::ColumnDrop {
+                table: before.name.clone(),
+                column: column.name.clone(),
+            }),
+            ColumnChange::Renamed {
+                before: before_column,
+                after,
+            }
+            | ColumnChange::Updated {
+                before: before_column,
+                after,
+            } if !before_column.data_type.is_widened_to(&after.data_type)
+                || (!before_column.required && after.required) =>
+            {
+                Some(DestructiveChange::TypeNarrowing {
+                    table: before.name.clone(),
+                    column: before_column.name.clone(),
+                })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/**
+ * Fails with the destructive changes of the plan that are not allowed, either by `allow_all`
+ * or by the migration config of the project.
+ */
+pub fn check_destructive_changes(
+    changes: &InfraChanges,
+    migration_config: &MigrationConfig,
+    allow_all: bool,
+) -> Result<(), DestructiveChangesError> {
+    if allow_all {
+        return Ok(());
+    }
+
+    let blocked: Vec<String> = destructive_changes(changes)
+        .iter()
+        .filter(|change| {
+            !migration_config
+                .allow_destructive
+                .iter()
+                .any(|id| id == change.object_id())
+        })
+        .map(DestructiveChange::display)
+        .collect();
+
+    if blocked.is_empty() {
+        Ok(())
+    } else {
+        Err(DestructiveChangesError { changes: blocked })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framework::core::infrastructure::table::ColumnType;
+    use crate::framework::core::infrastructure_map::fixtures::{column, table, topic};
+
+    fn user_table(columns: Vec<(&str, ColumnType, bool)>) -> Table {
+        table(
+            "User",
+            columns
+                .into_iter()
+                .map(|(name, data_type, required)| column(name, data_type, required))
+                .collect(),
+        )
+    }
+
+    fn table_update(before: Table, after: Table) -> InfraChanges {
+        InfraChanges {
+            olap_changes: vec![OlapChange::Table(Change::Updated { before, after })],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_drops_are_blocked_unless_allowed() {
+        let changes = InfraChanges {
+            olap_changes: vec![OlapChange::Table(Change::Removed(user_table(vec![])))],
+            streaming_engine_changes: vec![StreamingChange::Topic(Change::Removed(topic("User")))],
+            ..Default::default()
+        };
+        assert_eq!(
+            destructive_changes(&changes),
+            vec![
+                DestructiveChange::TableDrop {
+                    table: "User_0_0".to_string()
+                },
+                DestructiveChange::TopicDelete {
+                    topic: "User_0_0".to_string()
+                },
+            ]
+        );
+
+        let none_allowed = MigrationConfig::default();
+        assert!(check_destructive_changes(&changes, &none_allowed, false).is_err());
+        assert!(check_destructive_changes(&changes, &none_allowed, true).is_ok());
+
+        let allowed = MigrationConfig {
+            allow_destructive: vec!["User_0_0".to_string()],
+        };
+        assert!(check_destructive_changes(&changes, &allowed, false).is_ok());
+    }
+
+    #[test]
+    fn test_column_drops_and_narrowings_are_destructive() {
+        let before = user_table(vec![
+            ("id", ColumnType::String, true),
+            ("age", ColumnType::Int, false),
+            ("email", ColumnType::String, false),
+        ]);
+
+        let dropped = user_table(vec![
+            ("id", ColumnType::String, true),
+            ("age", ColumnType::Int, false),
+        ]);
+        assert_eq!(
+            destructive_changes(&table_update(before.clone(), dropped)),
+            vec![DestructiveChange::ColumnDrop {
+                table: "User_0_0".to_string(),
+                column: "email".to_string()
+            }]
+        );
+
+        let narrowed = user_table(vec![
+            ("id", ColumnType::String, true),
+            ("age", ColumnType::String, false),
+            ("email", ColumnType::String, true),
+        ]);
+        let changes = table_update(before.clone(), narrowed);
+        assert_eq!(destructive_changes(&changes).len(), 2);
+        let error =
+            check_destructive_changes(&changes, &MigrationConfig::default(), false).unwrap_err();
+        assert_eq!(
+            error.changes,
+            vec![
+                "narrowing of column age of table User_0_0",
+                "narrowing of column email of table User_0_0"
+            ]
+        );
+
+        // columns can be added and made optional
+        let widened = user_table(vec![
+            ("id", ColumnType::String, false),
+            ("age", ColumnType::Int, false),
+            ("email", ColumnType::String, false),
+            ("name", ColumnType::String, false),
+        ]);
+        let changes = table_update(before, widened);
+        assert!(destructive_changes(&changes).is_empty());
+        assert!(check_destructive_changes(&changes, &MigrationConfig::default(), false).is_ok());
+    }
+}
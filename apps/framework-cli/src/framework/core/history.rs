@@ -0,0 +1,210 @@
+//! History of the infrastructure maps stored by moose.
+//!
+//! Every time an infrastructure map is stored by a production deployment, a record of it is kept
+//! along with the time, the commit and the version of the project it was built from. The maps
+//! stored on each change in development aren't recorded. `moose history` lists and compares
+//! them, and `moose rollback` plans the changes back to one of them.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::infrastructure::olap::clickhouse_alt_client::StateStorageError;
+use crate::infrastructure::state_storage::StateStorage;
+use crate::project::Project;
+use crate::utilities::git::head_commit_hash;
+
+use super::infrastructure_map::InfrastructureMap;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InfraMapRecord {
+    pub id: String,
+    pub timestamp: DateTime<Utc>,
+    // Commit checked out in the project, None if the project isn't in a git repository
+    pub commit: Option<String>,
+    pub version: String,
+    pub infra_map: InfrastructureMap,
+}
+
+impl InfraMapRecord {
+    pub fn new(project: &Project, infra_map: &InfrastructureMap) -> InfraMapRecord {
+        InfraMapRecord {
+            id: uuid::Uuid::new_v4().to_string(),
+            timestamp: Utc::now(),
+            commit: head_commit_hash(project).ok(),
+            version: project.cur_version().to_string(),
+            infra_map: infra_map.clone(),
+        }
+    }
+}
+
+/**
+ * Stores the infrastructure map as the current one and, in production, keeps a record of it in
+ * the history.
+ */
+pub async fn store_infrastructure_map(
+    storage: &dyn StateStorage,
+    project: &Project,
+    infra_map: &InfrastructureMap,
+) -> Result<(), StateStorageError> {
+    storage.store_infrastructure_map(infra_map).await?;
+    if !project.is_production {
+        return Ok(());
+    }
+    storage
+        .store_infra_map_record(&InfraMapRecord::new(project, infra_map))
+        .await
+}
+
+/**
+ * Finds the record with the given id. A prefix of the id is enough as long as only one record
+ * starts with it.
+ */
+pub async fn find_record(
+    storage: &dyn StateStorage,
+    id: &str,
+) -> Result<Option<InfraMapRecord>, StateStorageError> {
+    let mut matching: Vec<InfraMapRecord> = storage
+        .load_infra_map_records()
+        .await?
+        .into_iter()
+        .filter(|record| record.id.starts_with(id))
+        .collect();
+
+    match matching.len() {
+        1 => Ok(matching.pop()),
+        _ => Ok(matching.into_iter().find(|record| record.id == id)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use git2::{Repository, RepositoryInitOptions, Signature};
+
+    use super::*;
+    use crate::framework::core::infrastructure::table::ColumnType;
+    use crate::framework::core::infrastructure_map::fixtures::{column, table};
+    use crate::framework::core::infrastructure_map::{Change, OlapChange};
+    use crate::framework::core::plan::plan_rollback;
+    use crate::framework::core::primitive_map::PrimitiveMap;
+    use crate::framework::languages::SupportedLanguages;
+    use crate::infrastructure::state_storage::{state_storage, StateStorageKind};
+
+    fn project(directory: &PathBuf, is_production: bool) -> Project {
+        std::fs::create_dir_all(directory).unwrap();
+        let mut project = Project::new(
+            directory,
+            "history".to_string(),
+            SupportedLanguages::Typescript,
+        );
+        project.state_config.storage = StateStorageKind::File;
+        project.set_is_production_env(is_production);
+        project
+    }
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("moose-history-{}", uuid::Uuid::new_v4()))
+    }
+
+    fn infra_map(data_models: &[&str]) -> InfrastructureMap {
+        let mut infra_map = InfrastructureMap::new(PrimitiveMap::default());
+        for data_model in data_models {
+            let table = table(data_model, vec![column("id", ColumnType::String, true)]);
+            infra_map.tables.insert(table.id(), table);
+        }
+        infra_map
+    }
+
+    #[tokio::test]
+    async fn test_maps_are_recorded_in_production_only() {
+        let directory = temp_dir();
+
+        let dev = project(&directory, false);
+        let storage = state_storage(&dev);
+        store_infrastructure_map(storage.as_ref(), &dev, &infra_map(&["User"]))
+            .await
+            .unwrap();
+        assert!(storage.load_infrastructure_map().await.unwrap().is_some());
+        assert!(storage.load_infra_map_records().await.unwrap().is_empty());
+
+        let prod = project(&directory, true);
+        store_infrastructure_map(storage.as_ref(), &prod, &infra_map(&["User"]))
+            .await
+            .unwrap();
+        let records = storage.load_infra_map_records().await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].commit, None);
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_record_commit_is_the_checked_out_one() {
+        let directory = temp_dir();
+        let project = project(&directory, true);
+
+        let mut options = RepositoryInitOptions::new();
+        options.initial_head("main");
+        let repo = Repository::init_opts(&project.project_location, &options).unwrap();
+        let author = Signature::now("moose", "moose@example.com").unwrap();
+        let tree = repo
+            .find_tree(repo.index().unwrap().write_tree().unwrap())
+            .unwrap();
+        let main = repo
+            .commit(Some("HEAD"), &author, &author, "main", &tree, &[])
+            .unwrap();
+        repo.branch("feature", &repo.find_commit(main).unwrap(), false)
+            .unwrap();
+        repo.set_head("refs/heads/feature").unwrap();
+        let feature = repo
+            .commit(
+                Some("HEAD"),
+                &author,
+                &author,
+                "feature",
+                &tree,
+                &[&repo.find_commit(main).unwrap()],
+            )
+            .unwrap();
+
+        let record = InfraMapRecord::new(&project, &infra_map(&[]));
+        assert_eq!(record.commit, Some(feature.to_string()[..7].to_string()));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rollback_plans_the_changes_back_to_a_record() {
+        let directory = temp_dir();
+        let project = project(&directory, true);
+        let storage = state_storage(&project);
+
+        store_infrastructure_map(storage.as_ref(), &project, &infra_map(&["User"]))
+            .await
+            .unwrap();
+        store_infrastructure_map(storage.as_ref(), &project, &infra_map(&["User", "Event"]))
+            .await
+            .unwrap();
+        let records = storage.load_infra_map_records().await.unwrap();
+        let first = &records[0];
+
+        let found = find_record(storage.as_ref(), &first.id[..8])
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.id, first.id);
+        assert!(find_record(storage.as_ref(), "").await.unwrap().is_none());
+
+        let plan = plan_rollback(storage.as_ref(), &first.id[..8])
+            .await
+            .unwrap();
+        assert_eq!(plan.target_infra_map.tables.len(), 1);
+        assert!(matches!(
+            plan.changes.olap_changes.as_slice(),
+            [OlapChange::Table(Change::Removed(table))] if table.name == "Event_0_0"
+        ));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -8,13 +8,16 @@ pub mod table;
 pub mod topic;
 pub mod topic_to_table_sync_process;
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 
 pub enum InfrastructureSignature {
     Table { id: String },
     Topic { id: String },
     ApiEndpoint { id: String },
     TopicToTableSyncProcess { id: String },
+    FunctionProcess { id: String },
+    OlapProcess { id: String },
+    ConsumptionApiWebServer {},
 }
 
 pub trait DataLineage {
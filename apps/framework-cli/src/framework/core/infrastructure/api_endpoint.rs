@@ -7,12 +7,12 @@ use crate::framework::{
     data_model::{config::EndpointIngestionFormat, model::DataModel},
 };
 
-use super::{topic::Topic, DataLineage, InfrastructureSignature};
+use super::{table::Table, topic::Topic, DataLineage, InfrastructureSignature};
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum APIType {
     INGRESS { target_topic: String },
-    EGRESS,
+    EGRESS { source_table: Table },
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -58,14 +58,41 @@ impl ApiEndpoint {
         }
     }
 
+    /// Egress endpoints read directly from the data model's table. The query is built
+    /// from the request's query string, see `infrastructure::olap::clickhouse::egress`.
+    pub fn egress_from_data_model(data_model: &DataModel, table: &Table) -> Self {
+        ApiEndpoint {
+            name: data_model.name.clone(),
+            api_type: APIType::EGRESS {
+                source_table: table.clone(),
+            },
+            path: PathBuf::from("egress")
+                .join(data_model.name.clone())
+                .join(data_model.version.clone()),
+            method: Method::GET,
+            format: EndpointIngestionFormat::Json,
+            version: data_model.version.clone(),
+            source_primitive: PrimitiveSignature {
+                name: data_model.name.clone(),
+                primitive_type: PrimitiveTypes::DataModel,
+            },
+        }
+    }
+
     pub fn id(&self) -> String {
         // TODO have a proper version object that standardizes transformations
-        format!(
-            "{:?}_{}_{}",
-            self.api_type,
-            self.name,
-            self.version.replace('.', "_")
-        )
+        match &self.api_type {
+            APIType::INGRESS { .. } => format!(
+                "{:?}_{}_{}",
+                self.api_type,
+                self.name,
+                self.version.replace('.', "_")
+            ),
+            // The table is not part of the id, the endpoint is updated when the table changes
+            APIType::EGRESS { .. } => {
+                format!("EGRESS_{}_{}", self.name, self.version.replace('.', "_"))
+            }
+        }
     }
 
     pub fn expanded_display(&self) -> String {
@@ -90,7 +117,12 @@ impl DataLineage for ApiEndpoint {
     }
 
     fn pulls_data_from(&self) -> Vec<InfrastructureSignature> {
-        vec![]
+        match &self.api_type {
+            APIType::INGRESS { .. } => vec![],
+            APIType::EGRESS { source_table } => vec![InfrastructureSignature::Table {
+                id: source_table.id(),
+            }],
+        }
     }
 
     fn pushes_data_to(&self) -> Vec<InfrastructureSignature> {
@@ -100,7 +132,7 @@ impl DataLineage for ApiEndpoint {
                     id: target_topic.clone(),
                 }]
             }
-            APIType::EGRESS => vec![],
+            APIType::EGRESS { .. } => vec![],
         }
     }
 }
@@ -1,18 +1,37 @@
-use itertools::sorted;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
 
 use crate::{
     framework::{
         core::infrastructure_map::{PrimitiveSignature, PrimitiveTypes},
-        streaming::model::StreamingFunction,
+        streaming::{
+            loader::target_data_model_names,
+            model::{StreamingFunction, WindowSpec},
+        },
     },
-    utilities::constants::{PYTHON_FILE_EXTENSION, TYPESCRIPT_FILE_EXTENSION},
+    utilities::constants::{PYTHON_FILE_EXTENSION, TYPESCRIPT_FILE_EXTENSION, WASM_FILE_EXTENSION},
 };
 
-use super::{table::Column, topic::Topic};
+use super::{table::Column, topic::Topic, DataLineage, InfrastructureSignature};
+
+// Suffixes of the topics moose creates for the functions, outside of the infrastructure map
+pub const STATE_TOPIC_SUFFIX: &str = "_state";
+pub const REPLAY_TOPIC_SUFFIX: &str = "_replay";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionTarget {
+    // The name of the target data model, used by the functions with several targets to route
+    // their output
+    pub data_model: String,
+    pub topic: String,
+    pub columns: Vec<Column>,
+}
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "StoredFunctionProcess")]
 pub struct FunctionProcess {
     // The name used here is the name of the file that contains the function
     // since we have the current assumption that there is 1 function per file
@@ -23,14 +42,88 @@ pub struct FunctionProcess {
     pub source_topic: String,
     pub source_columns: Vec<Column>,
 
-    pub target_topic: String,
+    pub targets: Vec<FunctionTarget>,
     pub target_topic_config: HashMap<String, String>,
-    pub target_columns: Vec<Column>,
 
     pub executable: PathBuf,
 
     pub version: String,
     pub source_primitive: PrimitiveSignature,
+
+    // Number of workers consuming the source topic in the same consumer group
+    pub parallelism: u16,
+
+    // Whether the function keeps state in its changelog topic
+    pub stateful: bool,
+
+    // Functions with a window are called with the records of each window
+    pub window: Option<WindowSpec>,
+}
+
+// The function processes stored before they could have several targets had a single
+// `target_topic` and `target_columns`
+#[derive(Deserialize)]
+struct StoredFunctionProcess {
+    name: String,
+    source_topic: String,
+    source_columns: Vec<Column>,
+    #[serde(default)]
+    targets: Vec<FunctionTarget>,
+    target_topic: Option<String>,
+    #[serde(default)]
+    target_columns: Vec<Column>,
+    target_topic_config: HashMap<String, String>,
+    executable: PathBuf,
+    version: String,
+    source_primitive: PrimitiveSignature,
+    #[serde(default = "default_parallelism")]
+    parallelism: u16,
+    #[serde(default)]
+    stateful: bool,
+    #[serde(default)]
+    window: Option<WindowSpec>,
+}
+
+fn default_parallelism() -> u16 {
+    1
+}
+
+impl From<StoredFunctionProcess> for FunctionProcess {
+    fn from(stored: StoredFunctionProcess) -> Self {
+        let mut targets = stored.targets;
+        if let Some(topic) = stored.target_topic {
+            // The target data model is named in the file of the function, like when it is loaded
+            let data_model = stored
+                .executable
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| {
+                    target_data_model_names(stem)
+                        .first()
+                        .map(|name| name.to_string())
+                })
+                .unwrap_or_else(|| topic.clone());
+            targets.push(FunctionTarget {
+                data_model,
+                topic,
+                columns: stored.target_columns,
+            });
+        }
+
+        FunctionProcess {
+            name: stored.name,
+            source_topic: stored.source_topic,
+            source_columns: stored.source_columns,
+            targets,
+            target_topic_config: stored.target_topic_config,
+            executable: stored.executable,
+            version: stored.version,
+            source_primitive: stored.source_primitive,
+            parallelism: stored.parallelism,
+            stateful: stored.stateful,
+            window: stored.window,
+        }
+    }
 }
 
 impl FunctionProcess {
@@ -48,10 +141,16 @@ impl FunctionProcess {
             // This probably should be a reference to the topic ingested from the
             // infra map insteaad of using a convention for the topic name.
             // Leaving it as is for compatibility with the current code.
-            target_topic: get_latest_topic(topics, &function.target_data_model.name)
-                .unwrap_or_else(|| function.target_data_model.name.clone()),
-
-            target_columns: function.target_data_model.columns.clone(),
+            targets: function
+                .target_data_models
+                .iter()
+                .map(|data_model| FunctionTarget {
+                    data_model: data_model.name.clone(),
+                    topic: get_latest_topic(topics, &data_model.name)
+                        .unwrap_or_else(|| data_model.name.clone()),
+                    columns: data_model.columns.clone(),
+                })
+                .collect(),
             target_topic_config: HashMap::from([
                 ("max.message.bytes".to_string(), (1024 * 1024).to_string()),
                 ("message.max.bytes".to_string(), (1024 * 1024).to_string()),
@@ -64,6 +163,9 @@ impl FunctionProcess {
                 name: function.name.clone(),
                 primitive_type: PrimitiveTypes::Function,
             },
+            parallelism: function.parallelism,
+            stateful: function.stateful,
+            window: function.window.clone(),
         }
     }
 
@@ -78,9 +180,11 @@ impl FunctionProcess {
             source_topic: source_topic.name.clone(),
             source_columns: function.source_data_model.columns.clone(),
 
-            target_topic: target_topic.name.clone(),
-
-            target_columns: function.target_data_model.columns.clone(),
+            targets: vec![FunctionTarget {
+                data_model: function.target_data_model().name.clone(),
+                topic: target_topic.name.clone(),
+                columns: function.target_data_model().columns.clone(),
+            }],
             target_topic_config: HashMap::from([
                 ("max.message.bytes".to_string(), (1024 * 1024).to_string()),
                 ("message.max.bytes".to_string(), (1024 * 1024).to_string()),
@@ -93,6 +197,9 @@ impl FunctionProcess {
                 name: function.name.clone(),
                 primitive_type: PrimitiveTypes::Function,
             },
+            parallelism: function.parallelism,
+            stateful: function.stateful,
+            window: function.window.clone(),
         }
     }
 
@@ -104,17 +211,45 @@ impl FunctionProcess {
         self.executable.extension().unwrap().to_str().unwrap() == PYTHON_FILE_EXTENSION
     }
 
+    // Functions compiled to WebAssembly run inside the moose process
+    pub fn is_wasm_function_process(&self) -> bool {
+        self.executable.extension().unwrap().to_str().unwrap() == WASM_FILE_EXTENSION
+    }
+
+    // The compacted topic logging the changes of the state of the function, and the open windows
+    // of a windowed function, from which they are restored when the function starts
+    pub fn state_topic(&self) -> Option<String> {
+        if self.stateful || self.window.is_some() {
+            Some(format!("{}{}", self.name, STATE_TOPIC_SUFFIX))
+        } else {
+            None
+        }
+    }
+
+    pub fn target_topics(&self) -> Vec<String> {
+        self.targets
+            .iter()
+            .map(|target| target.topic.clone())
+            .collect()
+    }
+
     pub fn id(&self) -> String {
+        let target_topics: Vec<&str> = self.targets_by_data_model().into_values().collect();
         format!(
             "{}_{}_{}_{}",
-            self.name, self.source_topic, self.target_topic, self.version
+            self.name,
+            self.source_topic,
+            target_topics.join("_"),
+            self.version
         )
     }
 
     pub fn expanded_display(&self) -> String {
         format!(
             "Reloading Function: from topic {} to topic {} - Version: {}",
-            self.source_topic, self.target_topic, self.version
+            self.source_topic,
+            self.target_topics().join(", "),
+            self.version
         )
     }
 
@@ -122,9 +257,56 @@ impl FunctionProcess {
         self.expanded_display()
     }
 
+    pub fn window_json(&self) -> Option<String> {
+        self.window
+            .as_ref()
+            .map(|window| serde_json::to_string(window).unwrap())
+    }
+
     pub fn target_topic_config_json(&self) -> String {
         serde_json::to_string(&self.target_topic_config).unwrap()
     }
+
+    // The target topics are joined in the id of the function and the consumer group id of its
+    // workers, so they are sorted to keep them the same from one start to the next
+    fn targets_by_data_model(&self) -> BTreeMap<&str, &str> {
+        self.targets
+            .iter()
+            .map(|target| (target.data_model.as_str(), target.topic.as_str()))
+            .collect()
+    }
+
+    // The topic of each target data model, as passed to the function runners
+    pub fn targets_json(&self) -> String {
+        serde_json::to_string(&self.targets_by_data_model()).unwrap()
+    }
+
+    // The consumer group of the workers, as the typescript runner builds it from its targets
+    pub fn consumer_group_id(&self) -> String {
+        let target_topics: Vec<&str> = self.targets_by_data_model().into_values().collect();
+        format!("flow-{}-{}", self.source_topic, target_topics.join("-"))
+    }
+}
+
+impl DataLineage for FunctionProcess {
+    fn receives_data_from(&self) -> Vec<InfrastructureSignature> {
+        vec![]
+    }
+
+    fn pulls_data_from(&self) -> Vec<InfrastructureSignature> {
+        vec![InfrastructureSignature::Topic {
+            id: self.source_topic.clone(),
+        }]
+    }
+
+    fn pushes_data_to(&self) -> Vec<InfrastructureSignature> {
+        self.targets
+            .iter()
+            .map(|target| InfrastructureSignature::Topic {
+                id: target.topic.clone(),
+            })
+            .collect()
+    }
 }
 
 /**
@@ -134,12 +316,95 @@ impl FunctionProcess {
 fn get_latest_topic(topics: &[String], data_model: &str) -> Option<String> {
     // Ths algorithm is not super efficient. We probably should have a
     // good way to retrieve the topics for a given data model from the state
-    sorted(
-        topics
-            .iter()
-            .filter(|&topic| topic.starts_with(data_model))
-            .collect::<Vec<&String>>(),
-    )
-    .last()
-    .map(|topic| topic.to_string())
+    topics
+        .iter()
+        .filter_map(|topic| topic_version(topic, data_model).map(|version| (version, topic)))
+        .max()
+        .map(|(_, topic)| topic.to_string())
+}
+
+// The version of a `<DataModel>_<version>` topic, e.g. [0, 0] for `User_0_0`. The topics of
+// other data models sharing the prefix and the state and replay topics of the functions
+// don't have that shape.
+fn topic_version(topic: &str, data_model: &str) -> Option<Vec<u64>> {
+    topic
+        .strip_prefix(data_model)?
+        .strip_prefix('_')?
+        .split('_')
+        .map(|part| part.parse::<u64>().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_target_function_process_is_still_deserialized() {
+        let stored = r#"{
+            "name": "User__Event",
+            "source_topic": "User_0_0",
+            "source_columns": [],
+            "target_topic": "Event_0_0",
+            "target_topic_config": {},
+            "target_columns": [],
+            "executable": "app/functions/User__Event.ts",
+            "version": "0.0",
+            "source_primitive": {"name": "User__Event", "primitive_type": "Function"}
+        }"#;
+
+        let function_process: FunctionProcess = serde_json::from_str(stored).unwrap();
+        assert_eq!(function_process.target_topics(), vec!["Event_0_0"]);
+        assert_eq!(function_process.targets[0].data_model, "Event");
+        assert_eq!(function_process.parallelism, 1);
+        assert_eq!(function_process.id(), "User__Event_User_0_0_Event_0_0_0.0");
+    }
+
+    #[test]
+    fn test_targets_are_sorted_in_the_ids() {
+        let stored = r#"{
+            "name": "Order__LineItem__Invoice",
+            "source_topic": "Order_0_0",
+            "source_columns": [],
+            "targets": [
+                {"data_model": "LineItem", "topic": "LineItem_0_0", "columns": []},
+                {"data_model": "Invoice", "topic": "Invoice_0_0", "columns": []}
+            ],
+            "target_topic_config": {},
+            "executable": "app/functions/Order__LineItem__Invoice.ts",
+            "version": "0.0",
+            "source_primitive": {"name": "Order__LineItem__Invoice", "primitive_type": "Function"}
+        }"#;
+
+        let function_process: FunctionProcess = serde_json::from_str(stored).unwrap();
+        assert_eq!(
+            function_process.id(),
+            "Order__LineItem__Invoice_Order_0_0_Invoice_0_0_LineItem_0_0_0.0"
+        );
+        assert_eq!(
+            function_process.consumer_group_id(),
+            "flow-Order_0_0-Invoice_0_0-LineItem_0_0"
+        );
+    }
+
+    #[test]
+    fn test_latest_topic_ignores_the_topics_of_the_functions() {
+        let topics: Vec<String> = [
+            "User_0_0",
+            "User_0_9",
+            "User_0_10",
+            "User__Event_state",
+            "User_0_10_replay",
+            "Users_1_0",
+        ]
+        .iter()
+        .map(|topic| topic.to_string())
+        .collect();
+
+        assert_eq!(
+            get_latest_topic(&topics, "User"),
+            Some("User_0_10".to_string())
+        );
+        assert_eq!(get_latest_topic(&topics, "Event"), None);
+    }
 }
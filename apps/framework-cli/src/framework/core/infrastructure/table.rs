@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::fmt;
 
 use serde::de::{Error, MapAccess, Visitor};
@@ -19,6 +20,9 @@ pub struct Table {
     pub name: String,
     pub columns: Vec<Column>,
     pub order_by: Vec<String>,
+    // New column name -> previous column name, for the columns renamed in place
+    #[serde(default)]
+    pub renamed_columns: HashMap<String, String>,
 
     pub version: String,
     pub source_primitive: PrimitiveSignature,
@@ -48,6 +52,146 @@ impl Table {
     pub fn short_display(&self) -> String {
         format!("Table: {} Version {}", self.name, self.version)
     }
+
+    /**
+     * Column level changes to go from this table to the target table. Renames are only detected
+     * through the `renamed_columns` of the target, otherwise they show up as a removed and an
+     * added column.
+     */
+    pub fn diff(&self, target: &Table) -> TableDiff {
+        let mut column_changes = Vec::new();
+
+        let find_column = |name: &str| self.columns.iter().find(|column| column.name == name);
+
+        // only the renames of a column that is gone to a column that is new
+        let renamed_from: HashMap<&str, &str> = target
+            .renamed_columns
+            .iter()
+            .filter(|(new_name, previous_name)| {
+                find_column(previous_name).is_some()
+                    && find_column(new_name).is_none()
+                    && !target
+                        .columns
+                        .iter()
+                        .any(|column| &column.name == *previous_name)
+            })
+            .map(|(new_name, previous_name)| (new_name.as_str(), previous_name.as_str()))
+            .collect();
+
+        for column in self.columns.iter() {
+            let kept = target.columns.iter().any(|target_column| {
+                target_column.name == column.name
+                    || renamed_from.get(target_column.name.as_str()) == Some(&column.name.as_str())
+            });
+            if !kept {
+                column_changes.push(ColumnChange::Removed(column.clone()));
+            }
+        }
+
+        for (index, column) in target.columns.iter().enumerate() {
+            let source_name = renamed_from
+                .get(column.name.as_str())
+                .copied()
+                .unwrap_or(column.name.as_str());
+
+            match find_column(source_name) {
+                Some(before) if before.name != column.name => {
+                    column_changes.push(ColumnChange::Renamed {
+                        before: before.clone(),
+                        after: column.clone(),
+                    })
+                }
+                Some(before) if before != column => column_changes.push(ColumnChange::Updated {
+                    before: before.clone(),
+                    after: column.clone(),
+                }),
+                Some(_) => {}
+                None => column_changes.push(ColumnChange::Added {
+                    column: column.clone(),
+                    after_column: index
+                        .checked_sub(1)
+                        .map(|previous| target.columns[previous].name.clone()),
+                }),
+            }
+        }
+
+        let order_by_change = (self.order_by != target.order_by).then(|| OrderByChange {
+            before: self.order_by.clone(),
+            after: target.order_by.clone(),
+        });
+
+        TableDiff {
+            column_changes,
+            order_by_change,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableDiff {
+    pub column_changes: Vec<ColumnChange>,
+    pub order_by_change: Option<OrderByChange>,
+}
+
+impl TableDiff {
+    pub fn is_empty(&self) -> bool {
+        self.column_changes.is_empty() && self.order_by_change.is_none()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnChange {
+    Added {
+        column: Column,
+        // The column it is positioned after, None if it is the first one
+        after_column: Option<String>,
+    },
+    Removed(Column),
+    // The type of the column can change along with its name
+    Renamed {
+        before: Column,
+        after: Column,
+    },
+    Updated {
+        before: Column,
+        after: Column,
+    },
+}
+
+impl ColumnChange {
+    pub fn display(&self) -> String {
+        match self {
+            ColumnChange::Added { column, .. } => format!("Column: {}", column.display()),
+            ColumnChange::Removed(column) => format!("Column: {}", column.display()),
+            ColumnChange::Renamed { before, after } => {
+                format!(
+                    "Column: {} renamed to {}",
+                    before.display(),
+                    after.display()
+                )
+            }
+            ColumnChange::Updated { before, after } if before.is_widened_to(after) => {
+                format!(
+                    "Column: {} widened to {}",
+                    before.display(),
+                    after.display()
+                )
+            }
+            ColumnChange::Updated { before, after } => {
+                format!(
+                    "Column: {} changed to {}",
+                    before.display(),
+                    after.display()
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderByChange {
+    pub before: Vec<String>,
+    pub after: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
@@ -60,6 +204,29 @@ pub struct Column {
     pub default: Option<ColumnDefaults>,
 }
 
+impl Column {
+    pub fn display(&self) -> String {
+        if self.required {
+            format!("{} {}", self.name, self.data_type)
+        } else {
+            format!("{} {}?", self.name, self.data_type)
+        }
+    }
+
+    /**
+     * Whether the values of this column fit in the target column without any loss, such that
+     * the column can be converted in place. The name is not compared.
+     */
+    pub fn is_widened_to(&self, target: &Column) -> bool {
+        // a column can become optional, not the other way around
+        (self.required || !target.required)
+            && self.unique == target.unique
+            && self.primary_key == target.primary_key
+            && self.default == target.default
+            && self.data_type.is_widened_to(&target.data_type)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 pub enum ColumnDefaults {
     AutoIncrement,
@@ -84,6 +251,20 @@ pub enum ColumnType {
     Bytes, // TODO: Explore if we ever need this type
 }
 
+impl ColumnType {
+    pub fn is_widened_to(&self, target: &ColumnType) -> bool {
+        match (self, target) {
+            // members can be added to an enum, the existing ones must keep their value
+            (ColumnType::Enum(before), ColumnType::Enum(after)) => before
+                .values
+                .iter()
+                .all(|member| after.values.contains(member)),
+            (ColumnType::Array(before), ColumnType::Array(after)) => before.is_widened_to(after),
+            (before, after) => before == after,
+        }
+    }
+}
+
 impl fmt::Display for ColumnType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -41,8 +41,8 @@ impl Topic {
                 "{}_{}_{}_{}_input",
                 function.source_data_model.name,
                 function.source_data_model.version.replace('.', "_"),
-                function.target_data_model.name,
-                function.target_data_model.version.replace('.', "_")
+                function.target_data_model().name,
+                function.target_data_model().version.replace('.', "_")
             ),
             version: function.version.clone(),
             retention_period: Topic::default_duration(),
@@ -58,12 +58,12 @@ impl Topic {
                 "{}_{}_{}_{}_output",
                 function.source_data_model.name,
                 function.source_data_model.version.replace('.', "_"),
-                function.target_data_model.name,
-                function.target_data_model.version.replace('.', "_")
+                function.target_data_model().name,
+                function.target_data_model().version.replace('.', "_")
             ),
             version: function.version.clone(),
             retention_period: Topic::default_duration(),
-            columns: function.target_data_model.columns.clone(),
+            columns: function.target_data_model().columns.clone(),
             source_primitive: PrimitiveSignature {
                 name: function.id(),
                 primitive_type: PrimitiveTypes::Function,
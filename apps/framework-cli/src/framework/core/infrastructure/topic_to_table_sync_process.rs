@@ -5,6 +5,7 @@ use crate::framework::core::infrastructure_map::PrimitiveSignature;
 use super::{
     table::{Column, Table},
     topic::Topic,
+    DataLineage, InfrastructureSignature,
 };
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -57,3 +58,21 @@ impl TopicToTableSyncProcess {
         )
     }
 }
+
+impl DataLineage for TopicToTableSyncProcess {
+    fn receives_data_from(&self) -> Vec<InfrastructureSignature> {
+        vec![]
+    }
+
+    fn pulls_data_from(&self) -> Vec<InfrastructureSignature> {
+        vec![InfrastructureSignature::Topic {
+            id: self.source_topic_id.clone(),
+        }]
+    }
+
+    fn pushes_data_to(&self) -> Vec<InfrastructureSignature> {
+        vec![InfrastructureSignature::Table {
+            id: self.target_table_id.clone(),
+        }]
+    }
+}
@@ -1,4 +1,6 @@
+use crypto_hash::{hex_digest, Algorithm};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::collections::HashMap;
 
 use super::infrastructure::api_endpoint::ApiEndpoint;
@@ -24,15 +26,29 @@ pub struct PrimitiveSignature {
     pub primitive_type: PrimitiveTypes,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Change<T> {
     Added(T),
     Removed(T),
     Updated { before: T, after: T },
 }
 
+impl<T: Clone> Change<T> {
+    // The change that undoes this one
+    pub fn reverse(&self) -> Change<T> {
+        match self {
+            Change::Added(t) => Change::Removed(t.clone()),
+            Change::Removed(t) => Change::Added(t.clone()),
+            Change::Updated { before, after } => Change::Updated {
+                before: after.clone(),
+                after: before.clone(),
+            },
+        }
+    }
+}
+
 #[allow(clippy::large_enum_variant)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum InfraChange {
     Olap(OlapChange),
     Streaming(StreamingChange),
@@ -40,22 +56,22 @@ pub enum InfraChange {
     Process(ProcessChange),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum OlapChange {
     Table(Change<Table>),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum StreamingChange {
     Topic(Change<Topic>),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ApiChange {
     ApiEndpoint(Change<ApiEndpoint>),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ProcessChange {
     TopicToTableSyncProcess(Change<TopicToTableSyncProcess>),
     FunctionProcess(Change<FunctionProcess>),
@@ -63,7 +79,7 @@ pub enum ProcessChange {
     ConsumptionApiWebServer(Change<ConsumptionApiWebServer>),
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct InfraChanges {
     pub olap_changes: Vec<OlapChange>,
     pub processes_changes: Vec<ProcessChange>,
@@ -98,6 +114,24 @@ pub struct InfrastructureMap {
     pub consumption_api_web_server: ConsumptionApiWebServer,
 }
 
+// The value with the keys of all its objects sorted, whatever the order they were serialized in
+fn canonical_json(value: Value) -> Value {
+    match value {
+        Value::Object(object) => {
+            let mut entries: Vec<(String, Value)> = object.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, canonical_json(value)))
+                    .collect(),
+            )
+        }
+        Value::Array(values) => Value::Array(values.into_iter().map(canonical_json).collect()),
+        value => value,
+    }
+}
+
 impl InfrastructureMap {
     pub fn new(primitive_map: PrimitiveMap) -> InfrastructureMap {
         let mut tables = HashMap::new();
@@ -114,6 +148,11 @@ impl InfrastructureMap {
                 let table = data_model.to_table();
                 let topic_to_table_sync_process = TopicToTableSyncProcess::new(&topic, &table);
 
+                if data_model.config.egress.enabled {
+                    let egress_endpoint = ApiEndpoint::egress_from_data_model(data_model, &table);
+                    api_endpoints.insert(egress_endpoint.id(), egress_endpoint);
+                }
+
                 tables.insert(table.id(), table);
                 topic_to_table_sync_processes.insert(
                     topic_to_table_sync_process.id(),
@@ -168,6 +207,15 @@ impl InfrastructureMap {
         }
     }
 
+    /**
+     * Hash of the content of the map, independent of the iteration order of its hashmaps.
+     * Used to detect that the stored map changed between a plan and its application.
+     */
+    pub fn hash(&self) -> Result<String, serde_json::Error> {
+        let canonical = canonical_json(serde_json::to_value(self)?).to_string();
+        Ok(hex_digest(Algorithm::SHA256, canonical.as_bytes()))
+    }
+
     // The current implementation is simple in which it goes over some of the
     // changes several times. Something could be done to optimize this.
     // There is probably a way to make this also more generic so that we don't have very
@@ -464,10 +512,63 @@ impl InfrastructureMap {
     }
 }
 
+// Infrastructure components shared by the tests of the modules working on the map
+#[cfg(test)]
+pub mod fixtures {
+    use std::collections::HashMap;
+
+    use super::{PrimitiveSignature, PrimitiveTypes};
+    use crate::framework::core::infrastructure::table::{Column, ColumnType, Table, TableType};
+    use crate::framework::core::infrastructure::topic::Topic;
+
+    pub fn data_model_signature(name: &str) -> PrimitiveSignature {
+        PrimitiveSignature {
+            name: name.to_string(),
+            primitive_type: PrimitiveTypes::DataModel,
+        }
+    }
+
+    // The topic of the version 0.0 of a data model
+    pub fn topic(data_model: &str) -> Topic {
+        Topic {
+            version: "0.0".to_string(),
+            name: data_model.to_string(),
+            retention_period: std::time::Duration::from_secs(60),
+            columns: vec![],
+            source_primitive: data_model_signature(data_model),
+        }
+    }
+
+    pub fn column(name: &str, data_type: ColumnType, required: bool) -> Column {
+        Column {
+            name: name.to_string(),
+            data_type,
+            required,
+            unique: false,
+            primary_key: false,
+            default: None,
+        }
+    }
+
+    // The table of the version 0.0 of a data model, ordered by its first column
+    pub fn table(data_model: &str, columns: Vec<Column>) -> Table {
+        Table {
+            table_type: TableType::Table,
+            name: format!("{}_0_0", data_model),
+            order_by: columns.iter().take(1).map(|c| c.name.clone()).collect(),
+            columns,
+            renamed_columns: HashMap::new(),
+            version: "0.0".to_string(),
+            source_primitive: data_model_signature(data_model),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::path::{Path, PathBuf};
 
+    use super::fixtures::{table, topic};
     use crate::{
         framework::{
             core::primitive_map::PrimitiveMap, data_model::model::DataModel,
@@ -476,6 +577,26 @@ mod tests {
         project::Project,
     };
 
+    #[test]
+    fn test_hash_is_independent_of_insertion_order() {
+        let names = ["User", "Event", "Order", "Invoice", "Session", "Page"];
+        let build = |names: &mut dyn Iterator<Item = &&str>| {
+            let mut infra_map = super::InfrastructureMap::new(PrimitiveMap::default());
+            for name in names {
+                let topic = topic(name);
+                infra_map.topics.insert(topic.id(), topic);
+                let table = table(name, vec![]);
+                infra_map.tables.insert(table.id(), table);
+            }
+            infra_map
+        };
+
+        assert_eq!(
+            build(&mut names.iter()).hash().unwrap(),
+            build(&mut names.iter().rev()).hash().unwrap()
+        );
+    }
+
     #[tokio::test]
     #[ignore]
     async fn test_infra_map() {
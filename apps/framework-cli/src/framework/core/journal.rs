@@ -0,0 +1,265 @@
+//! Journal of the infrastructure changes applied by `moose prod`.
+//!
+//! Every OLAP and streaming change of a deployment is recorded in Clickhouse before it is
+//! applied, then marked as applied or failed. A deployment holds a lock until its changes are
+//! all applied, which blocks concurrent deployments. When a deployment fails midway, the lock
+//! stays held and the deployment has to be resumed or rolled back from its journal.
+//!
+//! API and process changes are not journaled, they are rebuilt from the target infrastructure
+//! map every time the production server starts.
+
+use clickhouse_rs::ClientHandle;
+use serde::{Deserialize, Serialize};
+
+use crate::infrastructure::olap::clickhouse::config::ClickHouseConfig;
+use crate::infrastructure::olap::clickhouse_alt_client::{
+    insert_journal_entries, retrieve_deployment_journal, retrieve_lock_owner, store_lock,
+    StateStorageError,
+};
+
+use super::infrastructure_map::{InfraChange, InfraChanges};
+
+#[derive(Debug, thiserror::Error)]
+pub enum JournalError {
+    #[error("Failed to communicate with state storage")]
+    StateStorage(#[from] StateStorageError),
+
+    #[error("Deployment {owner} is in progress or failed. If it failed, resume it with `moose prod --resume` or roll it back with `moose prod --rollback`")]
+    Locked { owner: String },
+
+    #[error("There is no failed deployment to resume or roll back")]
+    NothingToRecover,
+
+    #[error("The planned changes differ from the ones of the failed deployment {deployment_id}, roll it back instead")]
+    PlanMismatch { deployment_id: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ChangeStatus {
+    Pending,
+    Applied,
+    Failed,
+    RolledBack,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub deployment_id: String,
+    // Position of the change in the plan of the deployment. The changes which don't depend on
+    // each other are applied in parallel, so they can complete out of this order
+    pub seq: u32,
+    pub change: InfraChange,
+    pub status: ChangeStatus,
+    pub error: Option<String>,
+}
+
+impl JournalEntry {
+    pub fn with_status(&self, status: ChangeStatus, error: Option<String>) -> JournalEntry {
+        JournalEntry {
+            status,
+            error,
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Deployment {
+    pub id: String,
+    pub entries: Vec<JournalEntry>,
+    // In dev, the changes are applied without being journaled nor locked
+    journaled: bool,
+}
+
+impl Deployment {
+    pub fn new(changes: &InfraChanges) -> Deployment {
+        let id = uuid::Uuid::new_v4().to_string();
+        Deployment {
+            entries: journal_entries(&id, changes),
+            id,
+            journaled: true,
+        }
+    }
+
+    pub fn unjournaled(changes: &InfraChanges) -> Deployment {
+        Deployment {
+            journaled: false,
+            ..Deployment::new(changes)
+        }
+    }
+
+    /**
+     * Loads the deployment holding the lock, which is the one that is in progress or failed.
+     */
+    pub async fn load_locked(
+        client: &mut ClientHandle,
+        clickhouse_config: &ClickHouseConfig,
+    ) -> Result<Deployment, JournalError> {
+        let id = retrieve_lock_owner(client, clickhouse_config)
+            .await?
+            .ok_or(JournalError::NothingToRecover)?;
+        let entries = retrieve_deployment_journal(client, clickhouse_config, &id).await?;
+
+        Ok(Deployment {
+            id,
+            entries,
+            journaled: true,
+        })
+    }
+
+    /**
+     * Takes the lock for a new deployment, before its changes are planned. Fails if another
+     * deployment holds the lock.
+     *
+     * Clickhouse doesn't offer a compare-and-set, the lock is read back after it is taken: when
+     * two deployments start at the same time, both see the first one that took it as the owner,
+     * and the other one fails.
+     */
+    pub async fn lock(
+        client: &mut ClientHandle,
+        clickhouse_config: &ClickHouseConfig,
+    ) -> Result<Deployment, JournalError> {
+        let deployment = Deployment::new(&InfraChanges::default());
+
+        if let Some(owner) = retrieve_lock_owner(client, clickhouse_config).await? {
+            return Err(JournalError::Locked { owner });
+        }
+
+        store_lock(client, clickhouse_config, &deployment.id, true).await?;
+        let owner = retrieve_lock_owner(client, clickhouse_config)
+            .await?
+            .unwrap_or_default();
+        if owner != deployment.id {
+            return Err(JournalError::Locked { owner });
+        }
+
+        Ok(deployment)
+    }
+
+    /**
+     * Records the planned changes of the locked deployment as pending.
+     */
+    pub async fn start(
+        &mut self,
+        client: &mut ClientHandle,
+        clickhouse_config: &ClickHouseConfig,
+        changes: &InfraChanges,
+    ) -> Result<(), JournalError> {
+        self.entries = journal_entries(&self.id, changes);
+        insert_journal_entries(client, clickhouse_config, &self.entries).await?;
+        Ok(())
+    }
+
+    /**
+     * Checks that the changes planned to resume the deployment are the ones it was started with.
+     * They are, unless the project changed since, because the infrastructure map is only stored
+     * once the deployment completed.
+     */
+    pub fn check_resumable_with(&self, changes: &InfraChanges) -> Result<(), JournalError> {
+        let journaled: Vec<&InfraChange> = self.entries.iter().map(|entry| &entry.change).collect();
+        let planned = journaled_changes(changes);
+
+        if serde_json::to_value(journaled).ok() != serde_json::to_value(&planned).ok() {
+            return Err(JournalError::PlanMismatch {
+                deployment_id: self.id.clone(),
+            });
+        }
+
+        Ok(())
+    }
+
+    pub async fn record(
+        &mut self,
+        client: &mut ClientHandle,
+        clickhouse_config: &ClickHouseConfig,
+        seq: u32,
+        status: ChangeStatus,
+        error: Option<String>,
+    ) -> Result<(), JournalError> {
+        if let Some(entry) = self.entries.iter_mut().find(|entry| entry.seq == seq) {
+            *entry = entry.with_status(status, error);
+            if !self.journaled {
+                return Ok(());
+            }
+            insert_journal_entries(client, clickhouse_config, std::slice::from_ref(entry)).await?;
+        }
+        Ok(())
+    }
+
+    /**
+     * Releases the lock, once the changes are applied or when the deployment stops before
+     * applying any of them.
+     */
+    pub async fn finish(
+        &self,
+        client: &mut ClientHandle,
+        clickhouse_config: &ClickHouseConfig,
+    ) -> Result<(), JournalError> {
+        if self.journaled {
+            store_lock(client, clickhouse_config, &self.id, false).await?;
+        }
+        Ok(())
+    }
+}
+
+fn journal_entries(deployment_id: &str, changes: &InfraChanges) -> Vec<JournalEntry> {
+    journaled_changes(changes)
+        .into_iter()
+        .enumerate()
+        .map(|(seq, change)| JournalEntry {
+            deployment_id: deployment_id.to_string(),
+            seq: seq as u32,
+            change,
+            status: ChangeStatus::Pending,
+            error: None,
+        })
+        .collect()
+}
+
+fn journaled_changes(changes: &InfraChanges) -> Vec<InfraChange> {
+    changes
+        .olap_changes
+        .iter()
+        .cloned()
+        .map(InfraChange::Olap)
+        .chain(
+            changes
+                .streaming_engine_changes
+                .iter()
+                .cloned()
+                .map(InfraChange::Streaming),
+        )
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::framework::core::infrastructure_map::fixtures::topic;
+    use crate::framework::core::infrastructure_map::{Change, StreamingChange};
+
+    use super::*;
+
+    fn topic_change(name: &str) -> StreamingChange {
+        StreamingChange::Topic(Change::Added(topic(name)))
+    }
+
+    #[test]
+    fn test_resume_requires_same_changes() {
+        let changes = InfraChanges {
+            streaming_engine_changes: vec![topic_change("User"), topic_change("Event")],
+            ..Default::default()
+        };
+        let deployment = Deployment::new(&changes);
+        assert_eq!(deployment.entries.len(), 2);
+        assert!(deployment.check_resumable_with(&changes).is_ok());
+
+        let other_changes = InfraChanges {
+            streaming_engine_changes: vec![topic_change("User")],
+            ..Default::default()
+        };
+        assert!(matches!(
+            deployment.check_resumable_with(&other_changes),
+            Err(JournalError::PlanMismatch { .. })
+        ));
+    }
+}
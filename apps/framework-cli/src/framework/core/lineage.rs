@@ -0,0 +1,365 @@
+//! Lineage of the data through the infrastructure of the project, from the ingest endpoints to
+//! the consumption APIs.
+//!
+//! The edges between topics, tables, endpoints and processes come from their `DataLineage`.
+//! Aggregations, blocks and consumption APIs are each run by a single process and their queries
+//! are not parsed, so they are considered to read from every table.
+
+use std::collections::{HashSet, VecDeque};
+
+use serde::Serialize;
+
+use super::infrastructure::{DataLineage, InfrastructureSignature};
+use super::infrastructure_map::{InfrastructureMap, PrimitiveSignature, PrimitiveTypes};
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum LineageFormat {
+    Dot,
+    Mermaid,
+    Json,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LineageNode {
+    pub signature: InfrastructureSignature,
+    pub label: String,
+    // None for the processes shared by all the primitives
+    pub source_primitive: Option<PrimitiveSignature>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LineageEdge {
+    pub from: InfrastructureSignature,
+    pub to: InfrastructureSignature,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LineageGraph {
+    pub nodes: Vec<LineageNode>,
+    pub edges: Vec<LineageEdge>,
+}
+
+// Processes refer to topics and tables either by id or by name
+fn resolve(
+    infra_map: &InfrastructureMap,
+    signature: InfrastructureSignature,
+) -> InfrastructureSignature {
+    match signature {
+        InfrastructureSignature::Topic { id } if !infra_map.topics.contains_key(&id) => {
+            match infra_map.topics.values().find(|topic| topic.name == id) {
+                Some(topic) => InfrastructureSignature::Topic { id: topic.id() },
+                None => InfrastructureSignature::Topic { id },
+            }
+        }
+        InfrastructureSignature::Table { id } if !infra_map.tables.contains_key(&id) => {
+            match infra_map.tables.values().find(|table| table.name == id) {
+                Some(table) => InfrastructureSignature::Table { id: table.id() },
+                None => InfrastructureSignature::Table { id },
+            }
+        }
+        signature => signature,
+    }
+}
+
+fn sorted<'a, T>(values: impl Iterator<Item = &'a T>, id: impl Fn(&T) -> String) -> Vec<&'a T> {
+    let mut values: Vec<&T> = values.collect();
+    values.sort_by_key(|value| id(value));
+    values
+}
+
+impl LineageGraph {
+    pub fn new(infra_map: &InfrastructureMap) -> LineageGraph {
+        let mut graph = LineageGraph::default();
+
+        for topic in sorted(infra_map.topics.values(), |topic| topic.id()) {
+            graph.add_node(
+                InfrastructureSignature::Topic { id: topic.id() },
+                format!("Topic {}", topic.id()),
+                Some(topic.source_primitive.clone()),
+            );
+        }
+        for table in sorted(infra_map.tables.values(), |table| table.id()) {
+            graph.add_node(
+                InfrastructureSignature::Table { id: table.id() },
+                format!("Table {}", table.name),
+                Some(table.source_primitive.clone()),
+            );
+        }
+        for endpoint in sorted(infra_map.api_endpoints.values(), |endpoint| endpoint.id()) {
+            graph.add_node(
+                InfrastructureSignature::ApiEndpoint { id: endpoint.id() },
+                format!("{:?} /{}", endpoint.method, endpoint.path.display()),
+                Some(endpoint.source_primitive.clone()),
+            );
+        }
+        for sync in sorted(infra_map.topic_to_table_sync_processes.values(), |sync| {
+            sync.id()
+        }) {
+            graph.add_node(
+                InfrastructureSignature::TopicToTableSyncProcess { id: sync.id() },
+                format!("Sync to {}", sync.target_table_id),
+                Some(sync.source_primitive.clone()),
+            );
+        }
+        for function in sorted(infra_map.function_processes.values(), |function| {
+            function.id()
+        }) {
+            graph.add_node(
+                InfrastructureSignature::FunctionProcess { id: function.id() },
+                format!("Function {}", function.name),
+                Some(function.source_primitive.clone()),
+            );
+        }
+
+        let olap_process = InfrastructureSignature::OlapProcess {
+            id: infra_map.block_db_processes.id(),
+        };
+        let consumption = InfrastructureSignature::ConsumptionApiWebServer {};
+        graph.add_node(
+            olap_process.clone(),
+            "Aggregations and blocks".to_string(),
+            None,
+        );
+        graph.add_node(consumption.clone(), "Consumption APIs".to_string(), None);
+
+        for endpoint in infra_map.api_endpoints.values() {
+            let signature = InfrastructureSignature::ApiEndpoint { id: endpoint.id() };
+            graph.add_lineage(infra_map, signature, endpoint);
+        }
+        for sync in infra_map.topic_to_table_sync_processes.values() {
+            let signature = InfrastructureSignature::TopicToTableSyncProcess { id: sync.id() };
+            graph.add_lineage(infra_map, signature, sync);
+        }
+        for function in infra_map.function_processes.values() {
+            let signature = InfrastructureSignature::FunctionProcess { id: function.id() };
+            graph.add_lineage(infra_map, signature, function);
+        }
+        for table in infra_map.tables.values() {
+            let signature = InfrastructureSignature::Table { id: table.id() };
+            graph.add_edge(signature.clone(), olap_process.clone());
+            graph.add_edge(signature, consumption.clone());
+        }
+        graph.add_edge(olap_process, consumption);
+
+        graph
+            .edges
+            .sort_by(|a, b| (&a.from, &a.to).cmp(&(&b.from, &b.to)));
+        graph
+    }
+
+    fn add_node(
+        &mut self,
+        signature: InfrastructureSignature,
+        label: String,
+        source_primitive: Option<PrimitiveSignature>,
+    ) {
+        self.nodes.push(LineageNode {
+            signature,
+            label,
+            source_primitive,
+        });
+    }
+
+    fn position(&self, signature: &InfrastructureSignature) -> Option<usize> {
+        self.nodes
+            .iter()
+            .position(|node| node.signature == *signature)
+    }
+
+    // Edges to infrastructure that isn't in the map are left out
+    fn add_edge(&mut self, from: InfrastructureSignature, to: InfrastructureSignature) {
+        let edge = LineageEdge { from, to };
+        if self.position(&edge.from).is_some()
+            && self.position(&edge.to).is_some()
+            && !self.edges.contains(&edge)
+        {
+            self.edges.push(edge);
+        }
+    }
+
+    fn add_lineage(
+        &mut self,
+        infra_map: &InfrastructureMap,
+        signature: InfrastructureSignature,
+        component: &dyn DataLineage,
+    ) {
+        for source in component
+            .receives_data_from()
+            .into_iter()
+            .chain(component.pulls_data_from())
+        {
+            self.add_edge(resolve(infra_map, source), signature.clone());
+        }
+        for target in component.pushes_data_to() {
+            self.add_edge(signature.clone(), resolve(infra_map, target));
+        }
+    }
+
+    /**
+     * The infrastructure of the data model and everything downstream of it, which is what may
+     * break when the data model changes.
+     */
+    pub fn impacted_by(&self, data_model: &str) -> Vec<&LineageNode> {
+        let mut impacted: HashSet<usize> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| {
+                node.source_primitive.as_ref().is_some_and(|primitive| {
+                    primitive.name == data_model
+                        && primitive.primitive_type == PrimitiveTypes::DataModel
+                })
+            })
+            .map(|(i, _)| i)
+            .collect();
+
+        let mut to_visit: VecDeque<usize> = impacted.iter().copied().collect();
+        while let Some(i) = to_visit.pop_front() {
+            for edge in self
+                .edges
+                .iter()
+                .filter(|edge| edge.from == self.nodes[i].signature)
+            {
+                if let Some(j) = self.position(&edge.to) {
+                    if impacted.insert(j) {
+                        to_visit.push_back(j);
+                    }
+                }
+            }
+        }
+
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| impacted.contains(i))
+            .map(|(_, node)| node)
+            .collect()
+    }
+
+    pub fn render(&self, format: LineageFormat) -> Result<String, serde_json::Error> {
+        match format {
+            LineageFormat::Dot => Ok(self.to_dot()),
+            LineageFormat::Mermaid => Ok(self.to_mermaid()),
+            LineageFormat::Json => serde_json::to_string_pretty(self),
+        }
+    }
+
+    fn edge_positions(&self) -> Vec<(usize, usize)> {
+        self.edges
+            .iter()
+            .filter_map(|edge| Some((self.position(&edge.from)?, self.position(&edge.to)?)))
+            .collect()
+    }
+
+    fn to_dot(&self) -> String {
+        let mut dot = "digraph lineage {\n    rankdir=LR;\n".to_string();
+        for (i, node) in self.nodes.iter().enumerate() {
+            let shape = match node.signature {
+                InfrastructureSignature::Table { .. } => "cylinder",
+                InfrastructureSignature::Topic { .. } => "box",
+                InfrastructureSignature::ApiEndpoint { .. }
+                | InfrastructureSignature::ConsumptionApiWebServer {} => "ellipse",
+                _ => "component",
+            };
+            dot.push_str(&format!(
+                "    n{} [label=\"{}\", shape={}];\n",
+                i,
+                node.label.replace('"', "\\\""),
+                shape
+            ));
+        }
+        for (from, to) in self.edge_positions() {
+            dot.push_str(&format!("    n{} -> n{};\n", from, to));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn to_mermaid(&self) -> String {
+        let mut mermaid = "flowchart LR\n".to_string();
+        for (i, node) in self.nodes.iter().enumerate() {
+            mermaid.push_str(&format!(
+                "    n{}[\"{}\"]\n",
+                i,
+                node.label.replace('"', "#quot;")
+            ));
+        }
+        for (from, to) in self.edge_positions() {
+            mermaid.push_str(&format!("    n{} --> n{}\n", from, to));
+        }
+        mermaid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::framework::core::infrastructure::function_process::{
+        FunctionProcess, FunctionTarget,
+    };
+    use crate::framework::core::infrastructure::topic_to_table_sync_process::TopicToTableSyncProcess;
+    use crate::framework::core::infrastructure_map::fixtures::{table, topic};
+    use crate::framework::core::primitive_map::PrimitiveMap;
+
+    use super::*;
+
+    #[test]
+    fn test_impacted_by() {
+        let mut infra_map = InfrastructureMap::new(PrimitiveMap::default());
+        let user_topic = topic("User");
+        let event_topic = topic("Event");
+        let event_table = table("Event", vec![]);
+        let sync = TopicToTableSyncProcess::new(&event_topic, &event_table);
+        let function = FunctionProcess {
+            name: "UserToEvent".to_string(),
+            source_topic: user_topic.name.clone(),
+            source_columns: vec![],
+            targets: vec![FunctionTarget {
+                data_model: "Event".to_string(),
+                topic: event_topic.name.clone(),
+                columns: vec![],
+            }],
+            target_topic_config: HashMap::new(),
+            executable: "functions/User__Event.ts".into(),
+            version: "0.0".to_string(),
+            source_primitive: PrimitiveSignature {
+                name: "UserToEvent".to_string(),
+                primitive_type: PrimitiveTypes::Function,
+            },
+            parallelism: 1,
+            stateful: false,
+            window: None,
+        };
+
+        infra_map.topics.insert(user_topic.id(), user_topic);
+        infra_map.topics.insert(event_topic.id(), event_topic);
+        infra_map.tables.insert(event_table.id(), event_table);
+        infra_map
+            .topic_to_table_sync_processes
+            .insert(sync.id(), sync);
+        infra_map.function_processes.insert(function.id(), function);
+
+        let graph = LineageGraph::new(&infra_map);
+        let labels = |nodes: Vec<&LineageNode>| {
+            nodes
+                .iter()
+                .map(|node| node.label.clone())
+                .collect::<Vec<String>>()
+        };
+
+        assert_eq!(
+            labels(graph.impacted_by("User")),
+            vec![
+                "Topic Event_0_0",
+                "Topic User_0_0",
+                "Table Event_0_0",
+                "Sync to Event_0_0",
+                "Function UserToEvent",
+                "Aggregations and blocks",
+                "Consumption APIs",
+            ]
+        );
+        assert!(graph.to_mermaid().contains("n1 --> n4"));
+    }
+}
@@ -1,11 +1,15 @@
-use clickhouse_rs::ClientHandle;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    infrastructure::olap::clickhouse_alt_client::{retrieve_infrastructure_map, StateStorageError},
-    project::Project,
+    infrastructure::olap::clickhouse_alt_client::StateStorageError,
+    infrastructure::state_storage::StateStorage, project::Project,
 };
 
 use super::{
+    drift::{detect_drift, refresh, Drift, DriftError, LiveInfrastructure},
+    history::{find_record, InfraMapRecord},
     infrastructure_map::{InfraChanges, InfrastructureMap},
     primitive_map::PrimitiveMap,
 };
@@ -20,32 +24,190 @@ pub enum PlanningError {
 
     #[error("Failed to connect to state storage")]
     Clickhouse(#[from] clickhouse_rs::errors::Error),
+
+    #[error("Failed to read or write the plan file")]
+    PlanFile(#[from] std::io::Error),
+
+    #[error("Failed to serialize or deserialize the plan")]
+    Serialization(#[from] serde_json::Error),
+
+    #[error("Failed to read the live infrastructure")]
+    LiveInfrastructure(#[from] DriftError),
+
+    #[error("No deployment {id} in the history, see `moose history`")]
+    UnknownDeployment { id: String },
+
+    #[error("The infrastructure changed since the plan was made, please plan again")]
+    Drift {
+        planned: Option<String>,
+        current: Option<String>,
+    },
 }
 
+#[derive(Debug, Serialize, Deserialize)]
 pub struct InfraPlan {
-    // pub current_infra_map: Option<InfrastructureMap>,
+    // Hash of the stored infrastructure map the plan was made from, None if there was none
+    pub current_infra_map_hash: Option<String>,
+
     pub target_infra_map: InfrastructureMap,
 
     pub changes: InfraChanges,
 }
 
+impl InfraPlan {
+    pub fn load(path: &Path) -> Result<InfraPlan, PlanningError> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), PlanningError> {
+        let content = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, content)?;
+        Ok(())
+    }
+}
+
 pub async fn plan_changes(
-    client: &mut ClientHandle,
+    storage: &dyn StateStorage,
+    project: &Project,
+) -> Result<InfraPlan, PlanningError> {
+    let current_infra_map = storage.load_infrastructure_map().await?;
+    plan_changes_from(
+        project,
+        current_infra_map.as_ref(),
+        current_infra_map.as_ref(),
+    )
+    .await
+}
+
+/**
+ * Plans the changes from the stored infrastructure map reconciled with the live infrastructure,
+ * so that the tables and topics that went missing are created again. Returns the drift found
+ * along with the plan.
+ */
+pub async fn plan_refreshed_changes(
+    storage: &dyn StateStorage,
     project: &Project,
+) -> Result<(InfraPlan, Vec<Drift>), PlanningError> {
+    let stored_infra_map = storage.load_infrastructure_map().await?;
+
+    let (refreshed_infra_map, drifts) = match &stored_infra_map {
+        Some(stored_infra_map) => {
+            let live = LiveInfrastructure::fetch(project).await?;
+            let drifts = detect_drift(
+                stored_infra_map,
+                &live,
+                project.redpanda_config.retention_ms,
+            )?;
+            let mut refreshed_infra_map = stored_infra_map.clone();
+            refresh(&mut refreshed_infra_map, &drifts);
+            (Some(refreshed_infra_map), drifts)
+        }
+        None => (None, vec![]),
+    };
+
+    let plan = plan_changes_from(
+        project,
+        stored_infra_map.as_ref(),
+        refreshed_infra_map.as_ref(),
+    )
+    .await?;
+    Ok((plan, drifts))
+}
+
+// The plan is bound to the stored map, which `moose prod --plan` checks before applying it
+async fn plan_changes_from(
+    project: &Project,
+    stored_infra_map: Option<&InfrastructureMap>,
+    current_infra_map: Option<&InfrastructureMap>,
 ) -> Result<InfraPlan, PlanningError> {
     let primitive_map = PrimitiveMap::load(project).await?;
     let target_infra_map = InfrastructureMap::new(primitive_map);
 
-    let current_infra_map = retrieve_infrastructure_map(client, &project.clickhouse_config).await?;
-
-    let changes = match &current_infra_map {
+    let changes = match current_infra_map {
         Some(current_infra_map) => current_infra_map.diff(&target_infra_map),
         None => target_infra_map.init(),
     };
 
     Ok(InfraPlan {
-        // current_infra_map,
+        current_infra_map_hash: stored_infra_map.map(InfrastructureMap::hash).transpose()?,
         target_infra_map,
         changes,
     })
 }
+
+async fn load_record(
+    storage: &dyn StateStorage,
+    id: &str,
+) -> Result<InfraMapRecord, PlanningError> {
+    find_record(storage, id)
+        .await?
+        .ok_or_else(|| PlanningError::UnknownDeployment { id: id.to_string() })
+}
+
+/**
+ * Plans the changes from the stored infrastructure map back to the one of a previous
+ * deployment. The plan can be applied with `moose prod --plan`.
+ */
+pub async fn plan_rollback(
+    storage: &dyn StateStorage,
+    id: &str,
+) -> Result<InfraPlan, PlanningError> {
+    let current_infra_map = storage.load_infrastructure_map().await?;
+    let record = load_record(storage, id).await?;
+
+    let changes = match &current_infra_map {
+        Some(current_infra_map) => current_infra_map.diff(&record.infra_map),
+        None => record.infra_map.init(),
+    };
+
+    Ok(InfraPlan {
+        current_infra_map_hash: current_infra_map
+            .as_ref()
+            .map(InfrastructureMap::hash)
+            .transpose()?,
+        target_infra_map: record.infra_map,
+        changes,
+    })
+}
+
+// The changes between two deployments of the history, from the first to the second
+pub async fn plan_between(
+    storage: &dyn StateStorage,
+    from_id: &str,
+    to_id: &str,
+) -> Result<InfraPlan, PlanningError> {
+    let from = load_record(storage, from_id).await?;
+    let to = load_record(storage, to_id).await?;
+
+    Ok(InfraPlan {
+        current_infra_map_hash: Some(from.infra_map.hash()?),
+        changes: from.infra_map.diff(&to.infra_map),
+        target_infra_map: to.infra_map,
+    })
+}
+
+/**
+ * Checks that the stored infrastructure map is still the one the plan was made from,
+ * otherwise the changes of the plan may not apply anymore.
+ */
+pub async fn check_plan_is_current(
+    storage: &dyn StateStorage,
+    plan: &InfraPlan,
+) -> Result<(), PlanningError> {
+    let current_infra_map_hash = storage
+        .load_infrastructure_map()
+        .await?
+        .as_ref()
+        .map(InfrastructureMap::hash)
+        .transpose()?;
+
+    if current_infra_map_hash != plan.current_infra_map_hash {
+        return Err(PlanningError::Drift {
+            planned: plan.current_infra_map_hash.clone(),
+            current: current_infra_map_hash,
+        });
+    }
+
+    Ok(())
+}
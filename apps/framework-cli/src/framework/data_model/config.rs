@@ -37,6 +37,9 @@ pub struct StorageConfig {
     pub enabled: bool,
     #[serde(default)]
     pub order_by_fields: Vec<String>,
+    // New field name -> previous field name, to rename the columns of the table in place
+    #[serde(default)]
+    pub renamed_fields: HashMap<String, String>,
 }
 const fn _true() -> bool {
     true
@@ -47,16 +50,25 @@ impl Default for StorageConfig {
         Self {
             enabled: true,
             order_by_fields: vec![],
+            renamed_fields: HashMap::new(),
         }
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Default)]
+pub struct EgressConfig {
+    #[serde(default)]
+    pub enabled: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Default)]
 pub struct DataModelConfig {
     #[serde(default)]
     pub ingestion: IngestionConfig,
     #[serde(default)]
     pub storage: StorageConfig,
+    #[serde(default)]
+    pub egress: EgressConfig,
 }
 
 #[derive(Debug, thiserror::Error)]
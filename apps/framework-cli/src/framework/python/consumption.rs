@@ -11,6 +11,7 @@ use super::executor;
 pub fn run(
     clickhouse_config: ClickHouseConfig,
     consumption_path: &Path,
+    versions_path: &Path,
 ) -> Result<Child, ConsumptionError> {
     let args = vec![
         consumption_path.to_str().unwrap().to_string(),
@@ -20,6 +21,7 @@ pub fn run(
         clickhouse_config.user,
         clickhouse_config.password,
         clickhouse_config.use_ssl.to_string(),
+        versions_path.to_str().unwrap().to_string(),
     ];
 
     let mut aggregation_process =
@@ -44,6 +44,14 @@ pub static CONSUMPTION_RUNNER: &str = include_str!("scripts/consumption_runner.p
 
 /// Executes a Python program in a subprocess
 pub fn run_python_program(program: PythonProgram) -> Result<Child, std::io::Error> {
+    run_python_program_with_env(program, &[])
+}
+
+/// Executes a Python program in a subprocess, with additional environment variables
+pub fn run_python_program_with_env(
+    program: PythonProgram,
+    env: &[(&str, String)],
+) -> Result<Child, std::io::Error> {
     let get_args = match program.clone() {
         PythonProgram::StreamingFunctionRunner { args } => args,
         PythonProgram::AggregationsRunner { args } => args,
@@ -63,6 +71,7 @@ pub fn run_python_program(program: PythonProgram) -> Result<Child, std::io::Erro
         .arg("-c")
         .arg(program_string)
         .args(get_args)
+        .envs(env.iter().cloned())
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
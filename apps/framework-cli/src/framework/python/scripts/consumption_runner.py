@@ -1,14 +1,21 @@
 import asyncio
-from dataclasses import dataclass
+import dataclasses
 from clickhouse_connect import get_client
 from string import Formatter
+from contextlib import contextmanager
 from importlib import import_module
-from clickhouse_connect.driver.client import Client
+import importlib.machinery
+import importlib.util
+from typing import Any, get_args, get_origin, get_type_hints, Union
 import argparse
+import datetime
+import decimal
+import enum
+import json
 import os
 import sys
-import json
-import datetime
+import traceback
+import uuid
 
 from http.server import HTTPServer, BaseHTTPRequestHandler
 from urllib.parse import urlparse, parse_qs
@@ -26,6 +33,8 @@ parser.add_argument('clickhouse_username', type=str,
 parser.add_argument('clickhouse_password', type=str,
                     help='Clickhouse password')
 parser.add_argument('clickhouse_use_ssl', type=str, help='Clickhouse use SSL')
+parser.add_argument('consumption_versions_dir_path', type=str, nargs='?', default=None,
+                    help='Path to the directory holding the old versions of the apis')
 
 
 args = parser.parse_args()
@@ -37,77 +46,315 @@ db = args.clickhouse_db
 user = args.clickhouse_username
 password = args.clickhouse_password
 consumption_dir_path = args.consumption_dir_path
+consumption_versions_dir_path = args.consumption_versions_dir_path
+
+# Set by the moose webserver when the request targets an old version of the apis
+VERSION_HEADER = 'x-moose-api-version'
 
 sys.path.append(consumption_dir_path)
 
-class DateTimeEncoder(json.JSONEncoder):
+
+class ConsumptionEncoder(json.JSONEncoder):
+    """Serializes the python types clickhouse_connect returns for ClickHouse columns."""
+
     def default(self, obj):
-        if isinstance(obj, datetime.datetime):
+        if isinstance(obj, (datetime.datetime, datetime.date, datetime.time)):
             return obj.isoformat()
+        if isinstance(obj, decimal.Decimal):
+            # Same as ClickHouse's JSON output, keeps the precision of the column
+            return str(obj)
+        if isinstance(obj, enum.Enum):
+            return obj.value
+        if isinstance(obj, uuid.UUID):
+            return str(obj)
+        if isinstance(obj, (bytes, bytearray)):
+            return obj.decode('utf-8', errors='replace')
+        if isinstance(obj, (set, frozenset, tuple)):
+            return list(obj)
+        if dataclasses.is_dataclass(obj):
+            return dataclasses.asdict(obj)
         return super().default(obj)
 
+
+class ApiError(Exception):
+    """
+    Raised by the runner to return a specific HTTP status. Apis can raise any exception
+    with an integer `status` attribute to get the same behaviour.
+    """
+
+    def __init__(self, status, message):
+        super().__init__(message)
+        self.status = status
+
+
+class BadRequestError(ApiError):
+    def __init__(self, message):
+        super().__init__(400, message)
+
+
+class NotFoundError(ApiError):
+    def __init__(self, message):
+        super().__init__(404, message)
+
+
+@dataclasses.dataclass
+class Identifier:
+    """Wraps a table or column name so that it is bound as an identifier and not a string literal."""
+    value: str
+
+
+def column(value: str) -> Identifier:
+    return Identifier(value)
+
+
+def table(value: str) -> Identifier:
+    return Identifier(value)
+
+
+def clickhouse_param_type(value: Any) -> str:
+    # bool needs to be checked before int since bool is a subclass of int
+    if isinstance(value, Identifier):
+        return 'Identifier'
+    if isinstance(value, bool):
+        return 'Bool'
+    if isinstance(value, int):
+        return 'Int64'
+    if isinstance(value, float):
+        return 'Float64'
+    if isinstance(value, decimal.Decimal):
+        return 'Decimal128(9)'
+    if isinstance(value, datetime.datetime):
+        return 'DateTime'
+    if isinstance(value, datetime.date):
+        return 'Date'
+    if isinstance(value, (list, tuple)):
+        inner = clickhouse_param_type(value[0]) if len(value) > 0 else 'String'
+        return f'Array({inner})'
+    return 'String'
+
+
+def clickhouse_param_value(value: Any) -> Any:
+    if isinstance(value, Identifier):
+        return value.value
+    if isinstance(value, enum.Enum):
+        return value.value
+    return value
+
+
 class MooseClient:
+    """
+    Query helper handed to consumption apis.
+
+    Queries use python format placeholders, e.g. `SELECT * FROM {table} WHERE id = {id}`,
+    which are turned into ClickHouse query parameters typed from the python value.
+    Table and column names need to be wrapped with `client.column()`/`client.table()`.
+    """
+
     def __init__(self, ch_client):
         self.ch_client = ch_client
 
+    @staticmethod
+    def column(value: str) -> Identifier:
+        return column(value)
+
+    @staticmethod
+    def table(value: str) -> Identifier:
+        return table(value)
+
     def query(self, input, variables):
         fieldnames = [fname for _, fname, _, _ in Formatter().parse(input) if fname]
 
-        # Create a dictionary with keys as fieldnames and values as the corresponding string format
-        params = {fname: f'{{p{i}: String}}' for i, fname in enumerate(fieldnames)}
+        # Typed QueryParams can be passed directly, their lists are kept as is
+        typed = dataclasses.is_dataclass(variables)
+        if typed:
+            variables = {f.name: getattr(variables, f.name) for f in dataclasses.fields(variables)}
+
+        params = {}
+        values = {}
+        for i, fname in enumerate(fieldnames):
+            if fname not in variables:
+                raise BadRequestError(f"Missing value for query parameter: {fname}")
+
+            value = variables[fname]
+            # parse_qs returns lists, single values are unwrapped to keep old apis working
+            if not typed and isinstance(value, list) and len(value) == 1:
+                value = value[0]
+
+            params[fname] = f'{{p{i}: {clickhouse_param_type(value)}}}'
+            values[f'p{i}'] = clickhouse_param_value(value)
 
-        values = {f'p{i}': variables[fname][0] if isinstance(variables[fname], list) and len(variables[fname]) == 1 else variables[fname] for i, fname in enumerate(fieldnames) if fname in variables}
         clickhouse_query = input.format_map(params)
-       
-        val = self.ch_client.query(clickhouse_query, values)
-        return val.result_rows
 
-def handler_with_client(ch_client):
+        val = self.ch_client.query(clickhouse_query, values)
+        return [dict(zip(val.column_names, row)) for row in val.result_rows]
+
+
+def parse_scalar(name: str, raw: str, target: Any) -> Any:
+    try:
+        if target is bool:
+            if raw.lower() in ('true', '1', 'yes'):
+                return True
+            if raw.lower() in ('false', '0', 'no'):
+                return False
+            raise ValueError(raw)
+        if target is int:
+            return int(raw)
+        if target is float:
+            return float(raw)
+        if target is decimal.Decimal:
+            return decimal.Decimal(raw)
+        if target is datetime.datetime:
+            return datetime.datetime.fromisoformat(raw)
+        if target is datetime.date:
+            return datetime.date.fromisoformat(raw)
+        if isinstance(target, type) and issubclass(target, enum.Enum):
+            return target(raw)
+    except (ValueError, decimal.InvalidOperation):
+        raise BadRequestError(f"Invalid value for query parameter {name}: {raw}")
+    return raw
+
+
+def parse_param(name: str, raw_values: list, target: Any) -> Any:
+    origin = get_origin(target)
+
+    if origin is Union:
+        # Optional[T] is Union[T, None]
+        inner = [t for t in get_args(target) if t is not type(None)]
+        return parse_param(name, raw_values, inner[0]) if len(inner) == 1 else raw_values[0]
+
+    if origin is list:
+        inner = get_args(target)
+        inner_type = inner[0] if len(inner) > 0 else str
+        return [parse_scalar(name, raw, inner_type) for raw in raw_values]
+
+    return parse_scalar(name, raw_values[0], target)
+
+
+def parse_params(module, query_params: dict) -> Any:
+    """
+    Builds the typed parameters for an api. If the module defines a `QueryParams` dataclass,
+    the query string is validated and converted to it, otherwise the raw `parse_qs` dict is returned.
+    """
+    params_type = getattr(module, 'QueryParams', None)
+    if params_type is None or not dataclasses.is_dataclass(params_type):
+        return query_params
+
+    hints = get_type_hints(params_type)
+    kwargs = {}
+    for field in dataclasses.fields(params_type):
+        if field.name in query_params:
+            kwargs[field.name] = parse_param(field.name, query_params[field.name], hints.get(field.name, str))
+        elif field.default is dataclasses.MISSING and field.default_factory is dataclasses.MISSING:
+            raise BadRequestError(f"Missing required query parameter: {field.name}")
+
+    return params_type(**kwargs)
+
+
+def load_api_module(module_name: str, version: str = None):
+    if version is None:
+        module_file = os.path.join(consumption_dir_path, *module_name.split('.')) + '.py'
+        if not os.path.exists(module_file):
+            raise NotFoundError(f"Consumption API {module_name} not found")
+        return import_module(module_name)
+
+    if consumption_versions_dir_path is None or not all(c.isalnum() or c in '._-' for c in version):
+        raise BadRequestError(f"Invalid version: {version}")
+
+    # The old versions are snapshots of the app directory, holding the apis in the same directory
+    apis_dir = os.path.basename(os.path.normpath(consumption_dir_path))
+    module_file = os.path.join(consumption_versions_dir_path, version, apis_dir, *module_name.split('.')) + '.py'
+    if not os.path.exists(module_file):
+        raise NotFoundError(f"Consumption API {module_name} not found for version {version}")
+
+    # Old versions are loaded under their own package so they don't clash with the current version
+    package_name = version_package(version)
+    with app_package(package_name):
+        return import_module(f"{package_name}.{apis_dir}.{module_name}")
+
+
+def version_package(version: str) -> str:
+    """
+    Registers the package of the snapshot of the app directory of an old version, which
+    resolves the relative imports of its apis.
+    """
+    package_name = f"moose_api_v{version.replace('.', '_').replace('-', '_')}"
+    if package_name not in sys.modules:
+        spec = importlib.machinery.ModuleSpec(package_name, None, is_package=True)
+        spec.submodule_search_locations = [os.path.join(consumption_versions_dir_path, version)]
+        sys.modules[package_name] = importlib.util.module_from_spec(spec)
+    return package_name
+
+
+# The `app` modules imported by the apis of each old version, by package name
+versioned_app_modules = {}
+
+
+def is_app_module(name: str) -> bool:
+    return name == 'app' or name.startswith('app.')
+
+
+@contextmanager
+def app_package(package_name: str):
+    """
+    Resolves the absolute `app.` imports to the snapshot of an old version while its apis are
+    loaded or run, the modules of the current version are restored afterwards.
+    """
+    current = {name: module for name, module in sys.modules.items() if is_app_module(name)}
+    for name in current:
+        del sys.modules[name]
+    sys.modules.update(versioned_app_modules.get(package_name, {'app': sys.modules[package_name]}))
+    try:
+        yield
+    finally:
+        versioned = {name: module for name, module in sys.modules.items() if is_app_module(name)}
+        for name in versioned:
+            del sys.modules[name]
+        versioned_app_modules[package_name] = versioned
+        sys.modules.update(current)
+
+
+def handler_with_client(moose_client):
     class SimpleHTTPRequestHandler(BaseHTTPRequestHandler):
+        def send_json(self, status, body):
+            response_message = bytes(json.dumps(body, cls=ConsumptionEncoder), 'utf-8')
+            self.send_response(status)
+            self.send_header('Content-Type', 'application/json')
+            self.end_headers()
+            self.wfile.write(response_message)
+
         def do_GET(self):
             parsed_path = urlparse(self.path)
-            module_name = parsed_path.path.lstrip('/')
+            module_name = parsed_path.path.strip('/')
             if module_name.startswith('consumption/'):
                 module_name = module_name[len('consumption/'):]
-            try:
-                module = import_module(module_name)
+            module_name = module_name.replace('/', '.')
 
-                # get the flow definition
+            try:
+                version = self.headers.get(VERSION_HEADER)
+                module = load_api_module(module_name, version)
+                params = parse_params(module, parse_qs(parsed_path.query))
 
-                print(module_name)
-                
-                query_params = parse_qs(parsed_path.query)
+                if version is None:
+                    response = module.run(moose_client, params)
+                else:
+                    with app_package(version_package(version)):
+                        response = module.run(moose_client, params)
 
+                # Older apis return an object holding the response in `message`
+                if hasattr(response, 'message') and not isinstance(response, (dict, list)):
+                    response = response.message
 
-                response = module.run(ch_client, query_params)
-                response_message = bytes(json.dumps(response.message, cls=DateTimeEncoder), 'utf-8')
-                self.send_response(200)
-                self.end_headers()
-                self.wfile.write(response_message)
+                self.send_json(200, response)
             except Exception as e:
-                self.send_response(500)
-                self.end_headers()
-                self.wfile.write(str(e).encode())
+                status = getattr(e, 'status', None)
+                if isinstance(status, int) and 400 <= status < 600:
+                    self.send_json(status, {'error': str(e)})
+                else:
+                    traceback.print_exc()
+                    self.send_json(500, {'error': str(e)})
     return SimpleHTTPRequestHandler
 
 
-class DependencyError(Exception):
-    pass
-
-
-def get_file_name(path):
-    return os.path.splitext(os.path.basename(path))[0]
-
-def walk_dir(dir, file_extension):
-    file_list = []
-
-    for root, dirs, files in os.walk(dir):
-        for file in files:
-            if file.endswith(file_extension):
-                file_list.append(os.path.join(root, file))
-
-    return file_list
-
 async def main():
     print(f"Connecting to Clickhouse at {interface}://{host}:{port}")
 
@@ -123,8 +370,4 @@ async def main():
     httpd.serve_forever()
 
 
-
-
-
-
-asyncio.run(main())
\ No newline at end of file
+asyncio.run(main())
@@ -1,10 +1,42 @@
 import argparse
 import dataclasses
-from datetime import datetime
+from datetime import datetime, timezone
 from importlib import import_module
+import inspect
 import json
+import os
 import sys
-from kafka import KafkaConsumer, KafkaProducer
+import time
+import typing
+try:
+    from confluent_kafka import OFFSET_BEGINNING, Consumer, KafkaError, KafkaException, Producer, TopicPartition
+except ImportError:
+    # Projects created before the functions ran in Kafka transactions depend on kafka-python-ng
+    print(
+        "The streaming functions need confluent-kafka: replace kafka-python-ng with confluent-kafka==2.4.0 "
+        "in the install_requires of setup.py, then run `pip install .`",
+        file=sys.stderr,
+    )
+    sys.exit(1)
+
+# Spans are emitted when moose exports traces and the OpenTelemetry SDK is installed,
+# otherwise the trace context is forwarded as is.
+TRACE_HEADERS = ['traceparent', 'tracestate']
+tracer = None
+if os.environ.get('OTEL_EXPORTER_OTLP_TRACES_ENDPOINT'):
+    try:
+        from opentelemetry import propagate, trace
+        from opentelemetry.exporter.otlp.proto.http.trace_exporter import OTLPSpanExporter
+        from opentelemetry.sdk.resources import Resource
+        from opentelemetry.sdk.trace import TracerProvider
+        from opentelemetry.sdk.trace.export import BatchSpanProcessor
+
+        tracer_provider = TracerProvider(resource=Resource.create({'service.name': 'moose-streaming-function'}))
+        tracer_provider.add_span_processor(BatchSpanProcessor(OTLPSpanExporter()))
+        trace.set_tracer_provider(tracer_provider)
+        tracer = trace.get_tracer('moose')
+    except ImportError:
+        print("Install opentelemetry-sdk and opentelemetry-exporter-otlp-proto-http to trace streaming functions")
 class EnhancedJSONEncoder(json.JSONEncoder):
     def default(self, o):
         if isinstance(o, datetime):
@@ -17,43 +49,65 @@ class EnhancedJSONEncoder(json.JSONEncoder):
 parser = argparse.ArgumentParser(description='Run a streaming function')
 
 parser.add_argument('source_topic', type=str, help='The source topic for the streaming function')
-parser.add_argument('target_topic', type=str, help='The target topic for the streaming function')
+parser.add_argument('targets', type=str, help='The topic of each target data model, as a JSON object')
 parser.add_argument('target_topic_config', type=str, help='The streaming server config for target topic')
 parser.add_argument('function_file_dir', type=str, help='The dir of the streaming function file')
 parser.add_argument('function_file_name', type=str, help='The file name of the streaming function without the .py extension')
-parser.add_argument('broker', type=str, help='The broker to use for the streaming function')
-# The following arguments are optional
-parser.add_argument('--sasl_username', type=str, help='The SASL username to use for the streaming function')
-parser.add_argument('--sasl_password', type=str, help='The SASL password to use for the streaming function')
-parser.add_argument('--sasl_mechanism', type=str, help='The SASL mechanism to use for the streaming function')
-parser.add_argument('--security_protocol', type=str, help='The security protocol to use for the streaming function')
+parser.add_argument('--state_topic', type=str, help='The changelog topic of the state of a stateful streaming function')
+parser.add_argument('--window', type=str, help='The window spec of a windowed streaming function, as JSON')
+parser.add_argument('--worker', type=int, default=0, help='The index of the worker, which identifies its Kafka transactions')
 
 args = parser.parse_args()
 
 source_topic = args.source_topic
-target_topic = args.target_topic
+targets = json.loads(args.targets)
+target_topics = list(targets.values())
 target_topic_config = args.target_topic_config
 function_file_dir = args.function_file_dir
 function_file_name = args.function_file_name
-broker = args.broker
-sasl_mechanism = args.sasl_mechanism
+
+# In test mode, used by `moose function test`, the records are read from the standard input and
+# the outputs written to the standard output instead of Kafka
+test_mode = os.environ.get('MOOSE_FUNCTION_TEST') == 'true'
+TEST_OUTPUT_PREFIX = 'moose-function-test-output:'
+# In replay mode, used by `moose function replay`, a range of the source topic is consumed in a
+# separate consumer group, and the runner exits at the end of the range
+replay = json.loads(os.environ['MOOSE_FUNCTION_REPLAY']) if os.environ.get('MOOSE_FUNCTION_REPLAY') else None
+
+# The connection config, passed by moose in the environment
+kafka_config = json.loads(os.environ.get('MOOSE_KAFKA_CONFIG', '{}'))
+if kafka_config.get('broker') is None and not test_mode:
+    raise Exception("Missing broker in MOOSE_KAFKA_CONFIG")
+
+# The settings shared by the consumer and the producer
+kafka_client_config = {
+    'bootstrap.servers': kafka_config.get('broker', ''),
+    'client.id': kafka_config.get('client_id') or 'python_flow_consumer',
+}
 
 # Setup SASL config w/ supported mechanisms
-if args.sasl_mechanism is not None:
-    if args.sasl_mechanism not in ['PLAIN', 'SCRAM-SHA-256']:
-        raise Exception(f"Unsupported SASL mechanism: {args.sasl_mechanism}")
-    if args.sasl_username is None or args.sasl_password is None:
+sasl_mechanism = kafka_config.get('sasl_mechanism')
+if sasl_mechanism is not None:
+    sasl_mechanism = sasl_mechanism.upper()
+    if sasl_mechanism not in ['PLAIN', 'SCRAM-SHA-256', 'SCRAM-SHA-512']:
+        raise Exception(f"Unsupported SASL mechanism: {sasl_mechanism}")
+    if kafka_config.get('sasl_username') is None or kafka_config.get('sasl_password') is None:
         raise Exception("SASL username and password must be provided if a SASL mechanism is specified")
-    if args.security_protocol is None:
+    if kafka_config.get('security_protocol') is None:
         raise Exception("Security protocol must be provided if a SASL mechanism is specified")
+    kafka_client_config.update({
+        'sasl.mechanism': sasl_mechanism,
+        'sasl.username': kafka_config['sasl_username'],
+        'sasl.password': kafka_config['sasl_password'],
+    })
 
-sasl_config = {
-    'username': args.sasl_username,
-    'password': args.sasl_password,
-    'mechanism': args.sasl_mechanism
-}
+if kafka_config.get('security_protocol') is not None:
+    kafka_client_config['security.protocol'] = kafka_config['security_protocol'].upper()
+for setting, option in [('ssl_ca_location', 'ssl.ca.location'), ('ssl_certificate_location', 'ssl.certificate.location'), ('ssl_key_location', 'ssl.key.location')]:
+    if kafka_config.get(setting) is not None:
+        kafka_client_config[option] = kafka_config[setting]
 
-log_prefix = f"{args.source_topic} -> {args.target_topic}"
+log_prefix = f"{args.source_topic} -> {', '.join(target_topics)}"
 def log(msg):
     print(f"{log_prefix}: {msg}")
 
@@ -106,70 +160,528 @@ flow_run = flow_def.run
 # get run input type that doesn't rely on the name of the input parameter
 run_input_type = flow_run.__annotations__[list(flow_run.__annotations__.keys())[0]]
 
+window_spec = json.loads(args.window) if args.window is not None else None
+# Windowed flows take a list of records
+if window_spec is not None and typing.get_args(run_input_type):
+    run_input_type = typing.get_args(run_input_type)[0]
+
 # parse json into the input type
 def parse_input(json_input):
     return run_input_type(**json_input)
 
-flow_id = f'flow-{source_topic} -> {target_topic}'
+flow_id = f'flow-{source_topic} -> {", ".join(target_topics)}'
 max_message_size = get_max_message_size(target_topic_config)
 
-if sasl_config['mechanism'] is not None:
-    consumer = KafkaConsumer(
-        source_topic,
-        client_id= "python_flow_consumer",
-        group_id=flow_id,
-        bootstrap_servers=broker,
-        sasl_plain_username=sasl_config['username'],
-        sasl_plain_password=sasl_config['password'],
-        sasl_mechanism=sasl_config['mechanism'],
-        security_protocol=args.security_protocol,
-        # consumer_timeout_ms=10000,
-        value_deserializer=lambda m: json.loads(m.decode('utf-8'))
-    )
-else:
-    print("No sasl mechanism specified. Using default consumer.")
-    consumer = KafkaConsumer(
-        source_topic,
-        client_id= "python_flow_consumer",
-        group_id=flow_id,
-        bootstrap_servers=broker,
-        # consumer_timeout_ms=10000,
-        value_deserializer=lambda m: json.loads(m.decode('utf-8'))
-    )
+group_id = flow_id if replay is None else replay['group_id']
+# The maximum number of records processed in a single transaction
+BATCH_SIZE = 500
 
-# Doesn't look like python producers can be idempotent
-if sasl_config['mechanism'] is not None:
-    producer = KafkaProducer(
-        bootstrap_servers=broker,
-        sasl_plain_username=sasl_config['username'],
-        sasl_plain_password=sasl_config['password'],
-        sasl_mechanism=sasl_config['mechanism'],
-        security_protocol=args.security_protocol,
-        max_request_size=max_message_size
-    )
-else:
-    producer = KafkaProducer(
-        bootstrap_servers=broker,
-        max_in_flight_requests_per_connection=1,
-        max_request_size=max_message_size
-    )
+consumer = None
+producer = None
+if not test_mode:
+    consumer = Consumer({
+        **kafka_client_config,
+        'group.id': group_id,
+        # The offsets are committed by the transaction of each batch
+        'enable.auto.commit': False,
+        'isolation.level': 'read_committed',
+        # The end of each replayed partition is detected even when it ends with a control record
+        'enable.partition.eof': replay is not None,
+    })
+
+    # The outputs of a batch and its offsets are committed in a single transaction, so a batch
+    # that is retried after a failure doesn't produce its records twice. Each worker has its own
+    # transactional id, which fences off the previous process of the worker when it restarts.
+    producer = Producer({
+        **kafka_client_config,
+        'transactional.id': f'{group_id}-{args.worker}',
+        'message.max.bytes': max_message_size,
+    })
+    producer.init_transactions()
+
+    # The replayed partitions are assigned when the replay starts
+    if replay is None:
+        consumer.subscribe([source_topic])
+
+# The keys of the state topic which aren't keys of the state
+RESERVED_KEY_PREFIX = '__moose_'
+# The keys of the state topic logging the open windows
+WINDOWS_KEY_PREFIX = f'{RESERVED_KEY_PREFIX}windows/'
+
+# Reads the committed changes of the state topic up to its current end
+def read_state_topic(topic, apply):
+    # The offsets are never committed, so the state is always read from the beginning
+    restore_consumer = Consumer({
+        **kafka_client_config,
+        'group.id': f'{flow_id}-state',
+        'enable.auto.commit': False,
+        'isolation.level': 'read_committed',
+        'enable.partition.eof': True,
+    })
+    topic_metadata = restore_consumer.list_topics(topic, timeout=10).topics.get(topic)
+    partitions = list(topic_metadata.partitions) if topic_metadata is not None else []
+    restore_consumer.assign([TopicPartition(topic, partition, OFFSET_BEGINNING) for partition in partitions])
+
+    remaining = set(partitions)
+    while remaining:
+        message = restore_consumer.poll(1.0)
+        if message is None:
+            continue
+        if message.error() is not None:
+            if message.error().code() == KafkaError._PARTITION_EOF:
+                remaining.discard(message.partition())
+            else:
+                print(f"{log_prefix}: Failed to read {topic}: {message.error()}", file=sys.stderr)
+            continue
+        apply(message)
+    restore_consumer.close()
+
+class StateStore:
+    """
+    The per-key state of a stateful function. It is kept in memory and every change is logged to
+    the compacted state topic, from which the state is restored when the function starts.
+    Deleted keys are logged as tombstones. The changes are logged in the transaction of the batch
+    being processed, so they are committed along with its outputs. The state of a function under
+    test or replayed starts empty and is only kept in memory.
+    """
+
+    def __init__(self, topic, logged):
+        self.topic = topic
+        self.logged = logged
+        self.entries = {}
+        self.checkpointed = {}
 
-consumer.subscribe([source_topic])
+    # The state at the start of a batch, restored when the transaction of the batch is aborted
+    def checkpoint(self):
+        self.checkpointed = dict(self.entries)
 
-# This is batched under-the-hood
-for message in consumer:
-    # Parse the message into the input type
-    input_data = parse_input(message.value)
+    def rollback(self):
+        self.entries = dict(self.checkpointed)
+
+    def get(self, key):
+        entry = self.entries.get(key)
+        if entry is None:
+            return None
+        if entry.get('expiresAt') is not None and entry['expiresAt'] <= time.time() * 1000:
+            self.delete(key)
+            return None
+        return entry['value']
+
+    def put(self, key, value, ttl_seconds=None):
+        entry = {'value': value}
+        if ttl_seconds is not None:
+            entry['expiresAt'] = time.time() * 1000 + ttl_seconds * 1000
+        self.entries[key] = entry
+        if self.logged:
+            producer.produce(self.topic, key=key.encode('utf-8'), value=json.dumps(entry, cls=EnhancedJSONEncoder).encode('utf-8'))
+
+    def delete(self, key):
+        self.entries.pop(key, None)
+        if self.logged:
+            producer.produce(self.topic, key=key.encode('utf-8'), value=None)
+
+    def apply(self, message):
+        if message.key() is None:
+            return
+        key = message.key().decode('utf-8')
+        # The open windows logged to the same topic aren't part of the state
+        if key.startswith(RESERVED_KEY_PREFIX):
+            return
+        if message.value() is None:
+            self.entries.pop(key, None)
+            return
+        entry = json.loads(message.value().decode('utf-8'))
+        if entry.get('expiresAt') is None or entry['expiresAt'] > time.time() * 1000:
+            self.entries[key] = entry
+        else:
+            self.entries.pop(key, None)
+
+    def restore(self):
+        read_state_topic(self.topic, self.apply)
+        log(f"Restored {len(self.entries)} key(s) from {self.topic}")
+
+state_store = None
+if args.state_topic is not None:
+    state_store = StateStore(args.state_topic, logged=not test_mode and replay is None)
+    if state_store.logged:
+        state_store.restore()
+
+# Stateful flows take the state store as a last parameter, after the window for windowed flows
+flow_takes_state = len(inspect.signature(flow_run).parameters) > (2 if window_spec is not None else 1)
+
+# Each element of a list returned by the flow is a record, None is dropped
+def to_records(topic, output_data):
+    items = output_data if isinstance(output_data, list) else [output_data]
+    return [(topic, item) for item in items if item is not None]
+
+# A flow with several targets returns a dict with the records of each target data model,
+# keyed by data model name
+def route(output_data):
+    if len(target_topics) == 1:
+        return to_records(target_topics[0], output_data)
+
+    if output_data is None:
+        return []
+    if not isinstance(output_data, dict):
+        print(f"{log_prefix}: Expected a dict keyed by target data model: {', '.join(targets.keys())}", file=sys.stderr)
+        return []
+
+    records = []
+    for data_model, data_model_output in output_data.items():
+        if data_model not in targets:
+            print(f"{log_prefix}: Unknown target data model {data_model}, skipping...", file=sys.stderr)
+            continue
+        records.extend(to_records(targets[data_model], data_model_output))
+    return records
+
+def run_flow(record):
+    # Parse the record into the input type
+    input_data = parse_input(record)
 
     # Run the flow
-    output_data = flow_run(input_data)
+    output_data = flow_run(input_data, state_store) if flow_takes_state else flow_run(input_data)
+
+    return route(output_data)
+
+@dataclasses.dataclass
+class Window:
+    key: str
+    start: datetime
+    end: datetime
+
+def event_time(value):
+    if isinstance(value, (int, float)):
+        return value
+    if isinstance(value, str):
+        try:
+            return datetime.fromisoformat(value.replace('Z', '+00:00')).timestamp() * 1000
+        except ValueError:
+            return None
+    return None
+
+def copy_windows(windows):
+    return {window_id: {**window, 'records': list(window['records'])} for window_id, window in windows.items()}
+
+class Windows:
+    """
+    The open windows of a windowed flow. The watermark is the latest event time received minus
+    the allowed lateness: the windows ending before it are closed, and the records that would
+    fall in a closed window are dropped.
+
+    Since the offsets of the records are committed as they arrive, the windows changed by a batch
+    and the watermark are logged to the state topic in the transaction of the batch, and restored
+    when the function starts. Each worker restores the windows it logged. The windows of a
+    function under test or replayed are only kept in memory.
+    """
+
+    def __init__(self, spec, topic, logged):
+        self.spec = spec
+        self.topic = topic
+        self.logged = logged and topic is not None
+        self.open = {}
+        self.watermark = float('-inf')
+        self.checkpointed = ({}, float('-inf'))
+        # The ids of the windows opened, changed or closed since the checkpoint
+        self.changed = set()
+        self.key_prefix = f'{WINDOWS_KEY_PREFIX}{args.worker}'
+
+    # The windows at the start of a batch, restored when the transaction of the batch is aborted
+    def checkpoint(self):
+        self.checkpointed = (copy_windows(self.open), self.watermark)
+        self.changed = set()
+
+    def rollback(self):
+        self.open = copy_windows(self.checkpointed[0])
+        self.watermark = self.checkpointed[1]
+        self.changed = set()
+
+    # Logs the windows changed since the checkpoint, closed ones as tombstones, and the watermark
+    def log_changes(self):
+        if not self.logged:
+            return
+        for window_id in self.changed:
+            key, start = window_id
+            window = self.open.get(window_id)
+            value = json.dumps(window, cls=EnhancedJSONEncoder).encode('utf-8') if window is not None else None
+            producer.produce(self.topic, key=f'{self.key_prefix}/{key}/{start}'.encode('utf-8'), value=value)
+        producer.produce(self.topic, key=self.key_prefix.encode('utf-8'), value=json.dumps(self.watermark).encode('utf-8'))
+        self.changed = set()
+
+    def apply(self, message):
+        key = message.key().decode('utf-8') if message.key() is not None else ''
+        if key == self.key_prefix:
+            self.watermark = json.loads(message.value().decode('utf-8'))
+        elif key.startswith(self.key_prefix + '/'):
+            window = json.loads(message.value().decode('utf-8')) if message.value() is not None else None
+            # The key of a window can contain slashes, its start can't
+            window_key, start = key[len(self.key_prefix) + 1:].rsplit('/', 1)
+            self.open.pop((window_key, start), None)
+            if window is not None:
+                self.open[(window_key, start)] = window
+
+    def restore(self):
+        read_state_topic(self.topic, self.apply)
+        # The windows are keyed by their start as a number once restored
+        self.open = {(window['key'], window['start']): window for window in self.open.values()}
+        log(f"Restored {len(self.open)} open window(s) from {self.topic}")
+
+    # Returns False when the record is dropped
+    def add(self, record):
+        time_ms = event_time(record.get(self.spec['time_column']))
+        if time_ms is None:
+            print(f"{log_prefix}: Record without a valid {self.spec['time_column']}, skipping...", file=sys.stderr)
+            return False
+        key = str(record.get(self.spec['key']))
+
+        if self.spec['type'] == 'session':
+            added = self.add_to_session(key, time_ms, record)
+        else:
+            size = self.spec['size_secs'] * 1000
+            advance = self.spec['advance_secs'] * 1000 if self.spec['type'] == 'hopping' else size
+            added = False
+            start = (time_ms // advance) * advance
+            while start > time_ms - size:
+                if start + size > self.watermark:
+                    window = self.open.setdefault((key, start), {'key': key, 'start': start, 'end': start + size, 'records': []})
+                    window['records'].append(record)
+                    self.changed.add((key, start))
+                    added = True
+                start -= advance
+
+        self.watermark = max(self.watermark, time_ms - self.spec.get('allowed_lateness_secs', 0) * 1000)
+        return added
+
+    # The sessions of a key closer than the gap to the record are merged with it
+    def add_to_session(self, key, time_ms, record):
+        gap = self.spec['gap_secs'] * 1000
+        if time_ms + gap <= self.watermark:
+            return False
+
+        session = {'key': key, 'start': time_ms, 'end': time_ms + gap, 'records': [record]}
+        for window_id, window in list(self.open.items()):
+            if window['key'] == key and window['start'] - gap <= time_ms < window['end']:
+                session['start'] = min(session['start'], window['start'])
+                session['end'] = max(session['end'], window['end'])
+                session['records'] = window['records'] + session['records']
+                del self.open[window_id]
+                self.changed.add(window_id)
+        self.open[(key, session['start'])] = session
+        self.changed.add((key, session['start']))
+        return True
+
+    # Removes the windows closed by the watermark, in the order they ended
+    def closed(self):
+        closed = [window_id for window_id, window in self.open.items() if window['end'] <= self.watermark]
+        self.changed.update(closed)
+        return sorted((self.open.pop(window_id) for window_id in closed), key=lambda window: window['end'])
+
+    # Closes all the open windows, once all the records are received
+    def close_all(self):
+        self.watermark = float('inf')
+        return self.closed()
+
+def run_windows(record):
+    if not windows.add(record):
+        print(f"{log_prefix}: Dropped a record arriving after its window closed", file=sys.stderr)
+
+    return aggregate_windows(windows.closed())
+
+# Number of records or windows the flow failed on, which are skipped and fail the test mode
+failures = 0
+
+# Calls the flow with the records of each closed window
+def aggregate_windows(closed_windows):
+    global failures
+    output_data_list = []
+    for window in closed_windows:
+        flow_window = Window(
+            key=window['key'],
+            start=datetime.fromtimestamp(window['start'] / 1000, tz=timezone.utc),
+            end=datetime.fromtimestamp(window['end'] / 1000, tz=timezone.utc),
+        )
+        records = [parse_input(record) for record in window['records']]
+        try:
+            output_data = flow_run(records, flow_window, state_store) if flow_takes_state else flow_run(records, flow_window)
+        except Exception as e:
+            failures += 1
+            print(f"{log_prefix}: Failed to aggregate window {window['key']} {flow_window.start}: {e}", file=sys.stderr)
+            continue
+        output_data_list.extend(route(output_data))
+    return output_data_list
+
+windows = None
+if window_spec is not None:
+    windows = Windows(window_spec, args.state_topic, logged=not test_mode and replay is None)
+    if windows.logged:
+        windows.restore()
+
+# Runs the flow on the records of the standard input, one JSON record per line, and writes each
+# output record to the standard output, on a line starting with TEST_OUTPUT_PREFIX. The open
+# windows are closed once all the records are read. Exits with 1 if the flow failed on any record.
+def run_test():
+    global failures
+    output_data_list = []
+    for line in sys.stdin:
+        if not line.strip():
+            continue
+        record = json.loads(line)
+        try:
+            output_data_list.extend(run_windows(record) if windows is not None else run_flow(record))
+        except Exception as e:
+            print(f"{log_prefix}: Failed to transform data: {e}", file=sys.stderr)
+            failures += 1
+    if windows is not None:
+        output_data_list.extend(aggregate_windows(windows.close_all()))
+
+    for topic, item in output_data_list:
+        print(TEST_OUTPUT_PREFIX + json.dumps({'topic': topic, 'record': item}, cls=EnhancedJSONEncoder))
+    sys.exit(1 if failures > 0 else 0)
+
+def produce(topic, item, headers=None):
+    producer.produce(topic, json.dumps(item, cls=EnhancedJSONEncoder).encode('utf-8'), headers=headers)
+
+# The output records of the message, with the trace headers to send them with
+def transform(message):
+    record = json.loads(message.value().decode('utf-8'))
+
+    if windows is not None:
+        return run_windows(record), None
+
+    headers = [(key, value) for key, value in (message.headers() or []) if key in TRACE_HEADERS]
+
+    if tracer is None:
+        return run_flow(record), headers
+
+    parent_context = propagate.extract({key: value.decode('utf-8') for key, value in headers})
+    with tracer.start_as_current_span(
+        'streaming_function',
+        context=parent_context,
+        kind=trace.SpanKind.CONSUMER,
+        attributes={'messaging.source.name': source_topic, 'messaging.destination.name': ','.join(target_topics)},
+    ):
+        output_data_list = run_flow(record)
+
+        carrier = {}
+        propagate.inject(carrier)
+        return output_data_list, [(key, value.encode('utf-8')) for key, value in carrier.items()]
+
+# Produces the outputs of the message in the transaction of its batch. A record the flow fails
+# on is logged and skipped, only the Kafka errors fail the batch.
+def process_message(message):
+    global failures
+    if message.value() is None:
+        log("Received message with no value, skipping...")
+        return
+
+    try:
+        output_data_list, headers = transform(message)
+    except Exception as e:
+        failures += 1
+        print(f"{log_prefix}: Failed to transform data: {e}", file=sys.stderr)
+        return
+
+    for topic, item in output_data_list:
+        produce(topic, item, headers)
+
+# The offset of the next message of each partition of the batch, committed with its outputs
+def next_offsets(messages):
+    offsets = {}
+    for message in messages:
+        offsets[message.partition()] = message.offset() + 1
+    return [TopicPartition(source_topic, partition, offset) for partition, offset in offsets.items()]
+
+# Seeks back to the first message of each partition of the batch, to consume it again
+def rewind(messages):
+    first_offsets = {}
+    for message in messages:
+        first_offsets.setdefault(message.partition(), message.offset())
+    for partition, offset in first_offsets.items():
+        consumer.seek(TopicPartition(source_topic, partition, offset))
+
+# The end offset, excluded, of each partition left to replay
+replay_ends = {
+    int(partition): end
+    for partition, (start, end) in (replay['partitions'].items() if replay is not None else [])
+    if start < end
+}
+
+# The records past the end of a replayed partition are left alone
+def in_range(message):
+    return replay is None or message.offset() < replay_ends.get(message.partition(), -1)
+
+def send_batch(messages):
+    producer.begin_transaction()
+    if state_store is not None:
+        state_store.checkpoint()
+    if windows is not None:
+        windows.checkpoint()
+    try:
+        for message in messages:
+            if in_range(message):
+                process_message(message)
+        if windows is not None:
+            windows.log_changes()
+        producer.send_offsets_to_transaction(next_offsets(messages), consumer.consumer_group_metadata())
+        producer.commit_transaction()
+    except (KafkaException, BufferError):
+        producer.abort_transaction()
+        if state_store is not None:
+            state_store.rollback()
+        if windows is not None:
+            windows.rollback()
+        # The batch is consumed again from its first message
+        rewind(messages)
+        raise
+
+# Consumes the source topic in batches, or the replayed range until its end
+def run_consumer():
+    while replay is None or replay_ends:
+        messages = []
+        for message in consumer.consume(num_messages=BATCH_SIZE, timeout=1.0):
+            if message.error() is None:
+                messages.append(message)
+            elif message.error().code() == KafkaError._PARTITION_EOF:
+                # The offset of the end of the partition, past any control record
+                if message.offset() >= replay_ends.get(message.partition(), -1):
+                    replay_ends.pop(message.partition(), None)
+            else:
+                print(f"{log_prefix}: Failed to consume {source_topic}: {message.error()}", file=sys.stderr)
+
+        if messages:
+            try:
+                send_batch(messages)
+            except (KafkaException, BufferError) as e:
+                print(f"{log_prefix}: Failed to send the outputs of {len(messages)} message(s), retrying: {e}", file=sys.stderr)
+                continue
+
+        if replay is not None:
+            partitions = consumer.position([TopicPartition(source_topic, partition) for partition in replay_ends])
+            for topic_partition in partitions:
+                if topic_partition.offset >= replay_ends[topic_partition.partition]:
+                    replay_ends.pop(topic_partition.partition)
+
+# Consumes the replayed range of each partition, then closes the open windows and exits
+def run_replay():
+    consumer.assign([
+        TopicPartition(source_topic, partition, replay['partitions'][str(partition)][0])
+        for partition in replay_ends
+    ])
+    run_consumer()
+
+    if windows is not None:
+        producer.begin_transaction()
+        for topic, item in aggregate_windows(windows.close_all()):
+            produce(topic, item)
+        producer.commit_transaction()
+    # Leaves the consumer group of the replay, which moose deletes
+    consumer.close()
+    log("Replay is done")
+    sys.exit(0)
+
+if test_mode:
+    run_test()
 
-    # Handle flow function returning an array or a single object
-    output_data_list = output_data if isinstance(output_data, list) else [output_data]
+if replay is not None:
+    run_replay()
 
-    for item in output_data_list:
-        # Ignore flow function returning null
-        if item is not None:
-            # send() is asynchronous. When called it adds the record to a buffer of pending record sends 
-            # and immediately returns. This allows the producer to batch together individual records
-            producer.send(target_topic, json.dumps(item, cls=EnhancedJSONEncoder).encode('utf-8'))
+run_consumer()
@@ -2,17 +2,28 @@ use std::path::Path;
 
 use tokio::process::Child;
 
-use crate::infrastructure::stream::redpanda::RedpandaConfig;
-use tokio::io::AsyncBufReadExt;
+use crate::cli::tracing;
+use crate::framework::streaming::model::RunnerMode;
+use crate::infrastructure::stream::redpanda::{RedpandaConfig, KAFKA_CONFIG_ENV};
 
 use super::executor;
 
+// The stdout and stderr of the process are left to the caller, which supervises the function
+#[allow(clippy::too_many_arguments)]
 pub fn run(
     redpanda_config: &RedpandaConfig,
     source_topic: &str,
-    target_topic: &str,
+    // The topic of each target data model, as a JSON object
+    targets: &str,
     target_topic_config: &str,
     function_path: &Path,
+    // The changelog topic of the stateful and windowed functions
+    state_topic: Option<&str>,
+    // The window spec of the windowed functions, as JSON
+    window: Option<&str>,
+    // The index of the worker, which identifies its Kafka transactions
+    worker: u16,
+    mode: &RunnerMode,
 ) -> Result<Child, std::io::Error> {
     let dir = function_path
         .parent()
@@ -28,43 +39,34 @@ pub fn run(
         .unwrap()
         .to_string();
 
-    let mut streaming_function_process =
-        executor::run_python_program(executor::PythonProgram::StreamingFunctionRunner {
-            args: vec![
-                source_topic.to_string(),
-                target_topic.to_string(),
-                target_topic_config.to_string(),
-                dir,
-                module_name,
-                redpanda_config.broker.clone(),
-            ],
-        })?;
-
-    let stdout = streaming_function_process
-        .stdout
-        .take()
-        .expect("Streaming process did not have a handle to stdout");
-    let stderr = streaming_function_process
-        .stderr
-        .take()
-        .expect("Streaming process did not have a handle to stderr");
-
-    let mut stdout_reader = tokio::io::BufReader::new(stdout).lines();
-    let mut stderr_reader = tokio::io::BufReader::new(stderr).lines();
-
-    tokio::spawn(async move {
-        while let Ok(Some(line)) = stdout_reader.next_line().await {
-            log::info!("{}", line);
-        }
-    });
-
-    tokio::spawn(async move {
-        while let Ok(Some(line)) = stderr_reader.next_line().await {
-            log::error!("{}", line);
-        }
-    });
-
-    Ok(streaming_function_process)
+    let kafka_config = redpanda_config.runner_config_json(&format!("moose-{}", module_name));
+
+    let mut args = vec![
+        source_topic.to_string(),
+        targets.to_string(),
+        target_topic_config.to_string(),
+        dir,
+        module_name,
+    ];
+    if let Some(state_topic) = state_topic {
+        args.push("--state_topic".to_string());
+        args.push(state_topic.to_string());
+    }
+    if let Some(window) = window {
+        args.push("--window".to_string());
+        args.push(window.to_string());
+    }
+    args.push("--worker".to_string());
+    args.push(worker.to_string());
+
+    let mut env = vec![(KAFKA_CONFIG_ENV, kafka_config)];
+    env.extend(mode.env());
+    env.extend(tracing::runner_env());
+
+    executor::run_python_program_with_env(
+        executor::PythonProgram::StreamingFunctionRunner { args },
+        &env,
+    )
 }
 
 // tests
@@ -89,7 +91,7 @@ mod tests {
         // You can then run a moose instance and send data to the endpoints
         let redpanda_config = RedpandaConfig::default();
         let source_topic = "UserActivity_0_0";
-        let target_topic = "ParsedActivity_0_0";
+        let targets = r#"{"ParsedActivity": "ParsedActivity_0_0"}"#;
         let target_topic_config = "{}";
         let flow_path = Path::new(
             "/Users/timdelisle/Dev/igloo-stack/apps/framework-cli/tests/python/flows/valid",
@@ -98,9 +100,13 @@ mod tests {
         let child = run(
             &redpanda_config,
             source_topic,
-            target_topic,
+            targets,
             target_topic_config,
             flow_path,
+            None,
+            None,
+            0,
+            &RunnerMode::Streaming,
         )
         .unwrap();
 
@@ -116,7 +122,7 @@ mod tests {
     async fn test_run_with_invalid_flow_file() {
         let redpanda_config = RedpandaConfig::default();
         let source_topic = "source";
-        let target_topic = "target";
+        let targets = r#"{"target": "target"}"#;
         let target_topic_config = "{}";
         let flow_path = Path::new(
             "/Users/timdelisle/Dev/igloo-stack/apps/framework-cli/tests/python/flows/invalid",
@@ -125,9 +131,13 @@ mod tests {
         let child = run(
             &redpanda_config,
             source_topic,
-            target_topic,
+            targets,
             target_topic_config,
             flow_path,
+            None,
+            None,
+            0,
+            &RunnerMode::Streaming,
         )
         .unwrap();
 
@@ -148,6 +148,47 @@ setup_queries = [
 block = Blocks(teardown=teardown_queries, setup=setup_queries)
 "#;
 
+pub static PYTHON_BASE_APIS_SAMPLE_TEMPLATE: &str = r#"
+# Here is a sample api configuration that creates an API which serves the daily active users materialized view
+from dataclasses import dataclass
+
+# Query parameters are converted to the types of this dataclass, invalid or missing
+# parameters are answered with a 400 error.
+@dataclass
+class QueryParams:
+    limit: int = 10
+    minDailyActiveUsers: int = 0
+
+def run(client, params: QueryParams):
+    return client.query(
+        """
+        SELECT
+            date,
+            uniqMerge(dailyActiveUsers) as dailyActiveUsers
+        FROM DailyActiveUsers
+        GROUP BY date
+        HAVING dailyActiveUsers >= {minDailyActiveUsers}
+        ORDER BY date
+        LIMIT {limit}
+        """,
+        params,
+    )
+"#;
+
+pub static PYTHON_BASE_CONSUMPTION_TEMPLATE: &str = r#"
+# This file is where you can define your API templates for consuming your data
+# Query parameters are converted to the types declared in QueryParams and are used
+# within the query placeholders to parameterize your queries
+from dataclasses import dataclass
+
+@dataclass
+class QueryParams:
+    pass
+
+def run(client, params: QueryParams):
+    return client.query("", params)
+"#;
+
 pub fn render_setup_py(project: PythonProject) -> Result<String, PythonRenderingError> {
     let reg = Handlebars::new();
 
@@ -0,0 +1,380 @@
+//! Runs a streaming function on fixture records through its runner, or in process for the
+//! WebAssembly functions, without Kafka, and checks its outputs. Used by `moose function test`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Map, Value};
+use tokio::io::AsyncWriteExt;
+
+use crate::framework::core::infrastructure::function_process::FunctionProcess;
+use crate::framework::core::infrastructure::table::{Column, ColumnType, EnumValue};
+use crate::framework::streaming::model::RunnerMode;
+use crate::framework::wasm::streaming::{transform, WasmFunction, WasmFunctionError};
+use crate::infrastructure::processes::functions_registry::{spawn_runner, FunctionRegistryError};
+use crate::infrastructure::processes::kafka_clickhouse_sync::record_errors;
+use crate::infrastructure::stream::redpanda::RedpandaConfig;
+
+// The runners write each output on a line starting with this prefix, the other lines are logs
+const TEST_OUTPUT_PREFIX: &str = "moose-function-test-output:";
+
+#[derive(Debug, thiserror::Error)]
+pub enum FunctionTestError {
+    #[error("Failed to read {path}")]
+    ReadFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("Invalid records in {path}")]
+    InvalidRecords {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[error("Failed to start the function runner")]
+    Runner(#[from] FunctionRegistryError),
+
+    #[error("Failed to read the outputs of the function runner")]
+    IO(#[from] std::io::Error),
+
+    #[error("Failed to load the WebAssembly function")]
+    Wasm(#[from] WasmFunctionError),
+}
+
+// The output records of a function, in order, by target data model
+pub type FunctionOutputs = BTreeMap<String, Vec<Value>>;
+
+pub struct FunctionTestRun {
+    pub outputs: FunctionOutputs,
+    // The lines logged by the runner and the function
+    pub logs: Vec<String>,
+    // Whether the function failed on any record
+    pub failed: bool,
+}
+
+fn read_file(path: &Path) -> Result<String, FunctionTestError> {
+    std::fs::read_to_string(path).map_err(|source| FunctionTestError::ReadFile {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+// A JSON array of records, or one JSON record per line
+fn parse_records(content: &str) -> Result<Vec<Value>, serde_json::Error> {
+    if content.trim_start().starts_with('[') {
+        serde_json::from_str(content)
+    } else {
+        content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect()
+    }
+}
+
+pub fn read_records(path: &Path) -> Result<Vec<Value>, FunctionTestError> {
+    parse_records(&read_file(path)?).map_err(|source| FunctionTestError::InvalidRecords {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/**
+ * Reads the expected outputs of the function: its output records, or for a function with several
+ * target data models, an object with the output records of each target data model.
+ */
+pub fn read_expected_outputs(
+    path: &Path,
+    function_process: &FunctionProcess,
+) -> Result<FunctionOutputs, FunctionTestError> {
+    let content = read_file(path)?;
+    let expected = match function_process.targets.as_slice() {
+        [target] => parse_records(&content)
+            .map(|records| BTreeMap::from([(target.data_model.clone(), records)])),
+        _ => serde_json::from_str(&content),
+    };
+    expected.map_err(|source| FunctionTestError::InvalidRecords {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/**
+ * Generates records of a data model with sample values of the type of each column. The values
+ * vary from one record to the next, so that the records don't all have the same key.
+ */
+pub fn sample_records(columns: &[Column], count: usize) -> Vec<Value> {
+    (0..count).map(|i| sample_record(columns, i)).collect()
+}
+
+fn sample_record(columns: &[Column], i: usize) -> Value {
+    let record: Map<String, Value> = columns
+        .iter()
+        .map(|column| {
+            (
+                column.name.clone(),
+                sample_value(&column.data_type, &column.name, i),
+            )
+        })
+        .collect();
+    Value::Object(record)
+}
+
+fn sample_value(column_type: &ColumnType, name: &str, i: usize) -> Value {
+    match column_type {
+        ColumnType::String => json!(format!("{}-{}", name, i)),
+        ColumnType::Boolean => json!(i.is_multiple_of(2)),
+        ColumnType::Int | ColumnType::BigInt => json!(i),
+        ColumnType::Float | ColumnType::Decimal => json!(i as f64 + 0.5),
+        ColumnType::DateTime => json!(format!("2024-01-01T{:02}:{:02}:00Z", (i / 60) % 24, i % 60)),
+        ColumnType::Enum(data_enum) => match data_enum
+            .values
+            .get(i % data_enum.values.len().max(1))
+            .map(|member| &member.value)
+        {
+            Some(EnumValue::Int(value)) => json!(value),
+            Some(EnumValue::String(value)) => json!(value),
+            None => Value::Null,
+        },
+        ColumnType::Array(inner) => json!([sample_value(inner, name, i)]),
+        ColumnType::Nested(nested) => sample_record(&nested.columns, i),
+        ColumnType::Json => json!({}),
+        ColumnType::Bytes => json!([]),
+    }
+}
+
+// Calls the WebAssembly function on each record, as the moose process runs it
+fn run_wasm_function(
+    function_process: &FunctionProcess,
+    records: &[Value],
+) -> Result<FunctionTestRun, FunctionTestError> {
+    let mut function = WasmFunction::load(&function_process.executable)?;
+
+    let mut outputs = FunctionOutputs::new();
+    let mut logs = Vec::new();
+    let mut failed = false;
+    for record in records {
+        let routed = transform(
+            &mut function,
+            &function_process.targets,
+            record.to_string().as_bytes(),
+        );
+        logs.extend(function.take_logs());
+        match routed {
+            Ok(routed) => {
+                for (target, output) in routed {
+                    outputs
+                        .entry(target.data_model.clone())
+                        .or_default()
+                        .push(output);
+                }
+            }
+            Err(e) => {
+                failed = true;
+                logs.push(format!("Failed to transform {}: {:?}", record, e));
+            }
+        }
+    }
+
+    Ok(FunctionTestRun {
+        outputs,
+        logs,
+        failed,
+    })
+}
+
+/**
+ * Runs the function on the records with the runner the function process registry starts, in test
+ * mode: the records are written to its stdin and its outputs read from its stdout.
+ */
+pub async fn run_function(
+    kafka_config: &RedpandaConfig,
+    function_process: &FunctionProcess,
+    records: &[Value],
+) -> Result<FunctionTestRun, FunctionTestError> {
+    if function_process.is_wasm_function_process() {
+        return run_wasm_function(function_process, records);
+    }
+
+    let mut child = spawn_runner(kafka_config, function_process, 0, &RunnerMode::Test)?;
+
+    let input: String = records
+        .iter()
+        .map(|record| format!("{}\n", record))
+        .collect();
+    // Written while the outputs are read, so that the runner doesn't block on a full stdout
+    let writer = child.stdin.take().map(|mut stdin| {
+        tokio::spawn(async move {
+            // the runner closing its stdin early is reported by its exit status
+            let _ = stdin.write_all(input.as_bytes()).await;
+        })
+    });
+
+    let output = child.wait_with_output().await?;
+    if let Some(writer) = writer {
+        let _ = writer.await;
+    }
+
+    let mut outputs = FunctionOutputs::new();
+    let mut logs = Vec::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let targeted = line
+            .strip_prefix(TEST_OUTPUT_PREFIX)
+            .and_then(|output| serde_json::from_str::<Value>(output).ok());
+        match targeted {
+            Some(targeted) => {
+                let data_model = function_process
+                    .targets
+                    .iter()
+                    .find(|target| Some(target.topic.as_str()) == targeted["topic"].as_str())
+                    .map(|target| target.data_model.clone())
+                    .unwrap_or_default();
+                outputs
+                    .entry(data_model)
+                    .or_default()
+                    .push(targeted["record"].clone());
+            }
+            None => logs.push(line.to_string()),
+        }
+    }
+    logs.extend(
+        String::from_utf8_lossy(&output.stderr)
+            .lines()
+            .map(str::to_string),
+    );
+
+    Ok(FunctionTestRun {
+        outputs,
+        logs,
+        failed: !output.status.success(),
+    })
+}
+
+// Numbers are compared by value, since the runners may write 1.0 as 1
+fn values_match(actual: &Value, expected: &Value) -> bool {
+    match (actual, expected) {
+        (Value::Number(actual), Value::Number(expected)) => actual.as_f64() == expected.as_f64(),
+        (Value::Array(actual), Value::Array(expected)) => {
+            actual.len() == expected.len()
+                && actual
+                    .iter()
+                    .zip(expected)
+                    .all(|(actual, expected)| values_match(actual, expected))
+        }
+        (Value::Object(actual), Value::Object(expected)) => {
+            actual.len() == expected.len()
+                && actual.iter().all(|(key, actual)| {
+                    expected
+                        .get(key)
+                        .is_some_and(|expected| values_match(actual, expected))
+                })
+        }
+        (actual, expected) => actual == expected,
+    }
+}
+
+/**
+ * The problems with the outputs of the function: records that don't fit the columns of their
+ * target data model, and the differences with the expected outputs, if any.
+ */
+pub fn check_outputs(
+    function_process: &FunctionProcess,
+    outputs: &FunctionOutputs,
+    expected: Option<&FunctionOutputs>,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for target in function_process.targets.iter() {
+        for (i, record) in outputs
+            .get(&target.data_model)
+            .into_iter()
+            .flatten()
+            .enumerate()
+        {
+            for error in record_errors(&target.columns, record) {
+                problems.push(format!("{}[{}]: {}", target.data_model, i, error));
+            }
+        }
+    }
+
+    if let Some(expected) = expected {
+        let no_records = Vec::new();
+        let data_models: BTreeSet<&String> = outputs.keys().chain(expected.keys()).collect();
+        for data_model in data_models {
+            let actual_records = outputs.get(data_model).unwrap_or(&no_records);
+            let expected_records = expected.get(data_model).unwrap_or(&no_records);
+            for i in 0..actual_records.len().max(expected_records.len()) {
+                match (actual_records.get(i), expected_records.get(i)) {
+                    (Some(actual), Some(expected)) if !values_match(actual, expected) => problems
+                        .push(format!(
+                            "{}[{}]: expected {}, got {}",
+                            data_model, i, expected, actual
+                        )),
+                    (Some(actual), None) => problems.push(format!(
+                        "{}[{}]: unexpected record {}",
+                        data_model, i, actual
+                    )),
+                    (None, Some(expected)) => problems.push(format!(
+                        "{}[{}]: missing record {}",
+                        data_model, i, expected
+                    )),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framework::core::infrastructure_map::fixtures::column;
+
+    #[test]
+    fn test_parse_records_from_json_array_or_ndjson() {
+        let array = parse_records(r#"[{"id": 1}, {"id": 2}]"#).unwrap();
+        let ndjson = parse_records("{\"id\": 1}\n\n{\"id\": 2}\n").unwrap();
+        assert_eq!(array, vec![json!({"id": 1}), json!({"id": 2})]);
+        assert_eq!(array, ndjson);
+    }
+
+    #[test]
+    fn test_sample_records_fit_their_columns() {
+        let columns = vec![
+            column("id", ColumnType::String, true),
+            column("count", ColumnType::Int, true),
+            column("timestamp", ColumnType::DateTime, true),
+            column(
+                "scores",
+                ColumnType::Array(Box::new(ColumnType::Float)),
+                true,
+            ),
+        ];
+        let records = sample_records(&columns, 3);
+
+        assert_eq!(records.len(), 3);
+        assert_ne!(records[0]["id"], records[1]["id"]);
+        for record in records.iter() {
+            assert!(record_errors(&columns, record).is_empty());
+        }
+        assert_eq!(
+            record_errors(&columns, &json!({"id": 1, "timestamp": "now"})).len(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_numbers_match_by_value() {
+        assert!(values_match(
+            &json!({"total": 1, "items": [2.0]}),
+            &json!({"total": 1.0, "items": [2]})
+        ));
+        assert!(!values_match(
+            &json!({"total": 1}),
+            &json!({"total": 1, "items": []})
+        ));
+    }
+}
@@ -1,5 +1,7 @@
 use super::model::{FunctionError, StreamingFunction};
-use crate::utilities::constants::PYTHON_INIT_FILE;
+use crate::utilities::constants::{
+    PYTHON_FILE_EXTENSION, PYTHON_INIT_FILE, TYPESCRIPT_FILE_EXTENSION, WASM_FILE_EXTENSION,
+};
 use crate::{
     framework::data_model::model::DataModelSet,
     project::Project,
@@ -22,25 +24,95 @@ pub async fn get_all_current_streaming_functions(
     data_models: &DataModelSet,
 ) -> Result<Vec<StreamingFunction>, FunctionError> {
     let functions_path = project.streaming_func_dir();
-    get_all_streaming_functions(data_models, project.cur_version(), &functions_path).await
+    let mut functions =
+        get_all_streaming_functions(data_models, project.cur_version(), &functions_path).await?;
+
+    for function in functions.iter_mut() {
+        let name = Path::new(&function.name)
+            .with_extension("")
+            .to_string_lossy()
+            .to_string();
+        if let Some(parallelism) = project.streaming_function_config.parallelism.get(&name) {
+            function.parallelism = (*parallelism).max(1);
+        }
+
+        if let Some(window) = project.streaming_function_config.windows.get(&name) {
+            window
+                .validate(&function.source_data_model)
+                .map_err(|message| FunctionError::InvalidWindow {
+                    function: name.clone(),
+                    message,
+                })?;
+            function.window = Some(window.clone());
+        }
+
+        // The state and the open windows of a function are held in the memory of its runner, so
+        // they can't be split between several workers
+        let stateful = project.streaming_function_config.stateful.contains(&name);
+        if function.is_wasm() && (stateful || function.window.is_some()) {
+            return Err(FunctionError::WasmStateOrWindow { function: name });
+        }
+        if stateful || function.window.is_some() {
+            function.stateful = stateful;
+            if function.parallelism > 1 {
+                warn!(
+                    "Stateful or windowed streaming function {} runs a single worker, ignoring its parallelism",
+                    name
+                );
+                function.parallelism = 1;
+            }
+        }
+    }
+
+    Ok(functions)
 }
 
-pub fn parse_streaming_function(file_name_no_extension: &str) -> Option<(&str, &str)> {
+/**
+ * Splits the name of a function file, `Source__Target` or `Source__TargetA__TargetB` for the
+ * functions producing to several data models, into its source and target data model names.
+ */
+pub fn parse_streaming_function(file_name_no_extension: &str) -> Option<(&str, Vec<&str>)> {
+    // Migration functions also use `__` separators
+    if Regex::new(MIGRATION_REGEX)
+        .unwrap()
+        .is_match(file_name_no_extension)
+    {
+        return None;
+    }
+
     let split: Vec<&str> = file_name_no_extension.split("__").collect();
-    if split.len() == 2 {
-        let source_data_model_name = split[0];
-        let target_data_model_name = split[1];
-        Some((source_data_model_name, target_data_model_name))
+    if split.len() >= 2 && split.iter().all(|name| !name.is_empty()) {
+        Some((split[0], split[1..].to_vec()))
     } else {
         None
     }
 }
 
+// The names of the target data models of the function defined in the file
+pub fn target_data_model_names(file_name_no_extension: &str) -> Vec<&str> {
+    match Regex::new(MIGRATION_REGEX)
+        .unwrap()
+        .captures(file_name_no_extension)
+    {
+        Some(caps) => caps
+            .get(4)
+            .or(caps.get(1))
+            .map(|name| name.as_str())
+            .into_iter()
+            .collect(),
+        None => parse_streaming_function(file_name_no_extension)
+            .map(|(_, targets)| targets)
+            .unwrap_or_default(),
+    }
+}
+
 pub fn extension_supported_in_streaming_function(path: &Path) -> bool {
     path.extension().is_some_and(|extension_os_str| {
-        extension_os_str
-            .to_str()
-            .is_some_and(|extension| extension == "ts" || extension == "py")
+        extension_os_str.to_str().is_some_and(|extension| {
+            extension == TYPESCRIPT_FILE_EXTENSION
+                || extension == PYTHON_FILE_EXTENSION
+                || extension == WASM_FILE_EXTENSION
+        })
     })
 }
 
@@ -85,7 +157,7 @@ async fn get_all_streaming_functions(
 
                 match migration_regex.captures(potential_function_file_name) {
                     None => {
-                        if let Some((source_data_model_name, target_data_model_name)) =
+                        if let Some((source_data_model_name, target_data_model_names)) =
                             parse_streaming_function(potential_function_file_name)
                         {
                             let source_data_model = if let Some(source_data_model) =
@@ -100,24 +172,27 @@ async fn get_all_streaming_functions(
                                 continue;
                             };
 
-                            let target_data_model = if let Some(target_data_model) =
-                                data_models.get(target_data_model_name, current_version)
+                            if let Some(missing) = target_data_model_names
+                                .iter()
+                                .find(|name| data_models.get(name, current_version).is_none())
                             {
-                                target_data_model
-                            } else {
-                                warn!(
-                                    "Data model {} not found in the data model set",
-                                    target_data_model_name
-                                );
+                                warn!("Data model {} not found in the data model set", missing);
                                 continue;
-                            };
+                            }
+                            let target_data_models = target_data_model_names
+                                .iter()
+                                .filter_map(|name| data_models.get(name, current_version).cloned())
+                                .collect();
 
                             let function = StreamingFunction {
                                 name: potential_function_file_name.clone(),
                                 source_data_model: source_data_model.clone(),
-                                target_data_model: target_data_model.clone(),
+                                target_data_models,
                                 executable: source.path(),
                                 version: current_version.to_string(),
+                                parallelism: 1,
+                                stateful: false,
+                                window: None,
                             };
                             functions.push(function);
                         } else {
@@ -184,9 +259,12 @@ async fn get_all_streaming_functions(
                     let func = StreamingFunction {
                         name: file_name.clone(),
                         source_data_model: source_data_model.clone(),
-                        target_data_model: target_data_model.clone(),
+                        target_data_models: vec![target_data_model.clone()],
                         executable: function_file.path().to_path_buf(),
                         version: current_version.to_string(),
+                        parallelism: 1,
+                        stateful: false,
+                        window: None,
                     };
                     functions.push(func);
 
@@ -223,8 +301,31 @@ fn build_migration_function(
     StreamingFunction {
         name: file_name.to_string(),
         source_data_model: source_data_model.clone(),
-        target_data_model: target_data_model.clone(),
+        target_data_models: vec![target_data_model.clone()],
         executable: executable.to_path_buf(),
         version: current_version.to_string(),
+        parallelism: 1,
+        stateful: false,
+        window: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_streaming_function() {
+        assert_eq!(
+            parse_streaming_function("User__Event"),
+            Some(("User", vec!["Event"]))
+        );
+        assert_eq!(
+            parse_streaming_function("Order__LineItem__Invoice"),
+            Some(("Order", vec!["LineItem", "Invoice"]))
+        );
+        assert_eq!(parse_streaming_function("User"), None);
+        assert_eq!(parse_streaming_function("User__"), None);
+        assert_eq!(parse_streaming_function("User_migrate__0_0__0_1"), None);
     }
 }
@@ -1,9 +1,15 @@
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
 
+use serde::{Deserialize, Serialize};
+
 use crate::{
     framework::data_model::model::DataModel,
     utilities::{
-        constants::{PYTHON_FILE_EXTENSION, SQL_FILE_EXTENSION, TYPESCRIPT_FILE_EXTENSION},
+        constants::{
+            PYTHON_FILE_EXTENSION, SQL_FILE_EXTENSION, TYPESCRIPT_FILE_EXTENSION,
+            WASM_FILE_EXTENSION,
+        },
         system::KillProcessError,
     },
 };
@@ -22,6 +28,145 @@ pub enum FunctionError {
 
     #[error("Kill process Error")]
     KillProcessError(#[from] KillProcessError),
+
+    #[error("Invalid window of streaming function {function}: {message}")]
+    InvalidWindow { function: String, message: String },
+
+    #[error("The WebAssembly streaming function {function} can't keep state or use windows")]
+    WasmStateOrWindow { function: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum WindowKind {
+    // Consecutive windows of `size_secs`
+    Tumbling { size_secs: u64 },
+    // Windows of `size_secs` starting every `advance_secs`, a record can be in several windows
+    Hopping { size_secs: u64, advance_secs: u64 },
+    // Windows of the records of a key separated by less than `gap_secs`
+    Session { gap_secs: u64 },
+}
+
+/**
+ * Groups the records of the source topic by key and event time window. The function is called
+ * with the records of each window once the window is closed, i.e. once records later than the
+ * end of the window plus the allowed lateness are received.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WindowSpec {
+    #[serde(flatten)]
+    pub kind: WindowKind,
+    // The field of the source records the windows are computed for
+    pub key: String,
+    // The field holding the event time of the source records
+    pub time_column: String,
+    #[serde(default)]
+    pub allowed_lateness_secs: u64,
+}
+
+impl WindowSpec {
+    pub fn validate(&self, source_data_model: &DataModel) -> Result<(), String> {
+        match self.kind {
+            WindowKind::Tumbling { size_secs: 0 }
+            | WindowKind::Hopping { size_secs: 0, .. }
+            | WindowKind::Session { gap_secs: 0 } => {
+                return Err("the window size can't be 0".to_string())
+            }
+            WindowKind::Hopping {
+                size_secs,
+                advance_secs,
+            } if advance_secs == 0 || advance_secs > size_secs => {
+                return Err("the advance must be between 1 and the window size".to_string())
+            }
+            _ => {}
+        }
+
+        for column in [&self.key, &self.time_column] {
+            if !source_data_model
+                .columns
+                .iter()
+                .any(|source_column| &source_column.name == column)
+            {
+                return Err(format!(
+                    "{} is not a column of {}",
+                    column, source_data_model.name
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StreamingFunctionConfig {
+    // Functions keeping state across messages, by function name. It comes before the tables,
+    // which TOML can't have values after
+    #[serde(default)]
+    pub stateful: Vec<String>,
+    // Number of workers of each function, by function name. The functions not listed run a
+    // single worker
+    #[serde(default)]
+    pub parallelism: HashMap<String, u16>,
+    // Functions aggregating windows of records, by function name
+    #[serde(default)]
+    pub windows: HashMap<String, WindowSpec>,
+}
+
+// Set to "true" to start the function runners in test mode
+pub const FUNCTION_TEST_ENV: &str = "MOOSE_FUNCTION_TEST";
+// The range of the source topic replayed by the function runners in replay mode, as JSON
+pub const FUNCTION_REPLAY_ENV: &str = "MOOSE_FUNCTION_REPLAY";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayRange {
+    // The consumer group of the replay, separate from the one of the function
+    pub group_id: String,
+    // The first offset and the end offset, excluded, of each partition of the source topic
+    pub partitions: BTreeMap<i32, (i64, i64)>,
+}
+
+#[derive(Debug, Clone)]
+pub enum RunnerMode {
+    // Consumes the source topic in the consumer group of the function
+    Streaming,
+    // Reads the source records from stdin and writes the outputs to stdout
+    Test,
+    // Consumes a range of the source topic in its own consumer group, then exits
+    Replay(ReplayRange),
+}
+
+impl RunnerMode {
+    // The environment variables starting a runner in this mode
+    pub fn env(&self) -> Vec<(&'static str, String)> {
+        match self {
+            RunnerMode::Streaming => vec![],
+            RunnerMode::Test => vec![(FUNCTION_TEST_ENV, "true".to_string())],
+            RunnerMode::Replay(range) => {
+                vec![(FUNCTION_REPLAY_ENV, serde_json::to_string(range).unwrap())]
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FunctionState {
+    Running,
+    Restarting,
+    Failed,
+    Stopped,
+}
+
+// The state of a worker of a function, as reported by its supervisor
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionStatus {
+    pub function: String,
+    pub worker: u16,
+    pub state: FunctionState,
+    pub restarts: u32,
+    pub last_exit_code: Option<i32>,
+    pub last_stderr: Vec<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -33,22 +178,42 @@ pub struct StreamingFunction {
     pub name: String,
 
     pub source_data_model: DataModel,
-    pub target_data_model: DataModel,
+    // Functions declared as `Source__TargetA__TargetB` produce to several data models
+    pub target_data_models: Vec<DataModel>,
 
     pub executable: PathBuf,
 
     pub version: String,
+
+    // Number of runners of the function consuming its source topic together
+    pub parallelism: u16,
+
+    // Whether the function keeps state in a changelog topic
+    pub stateful: bool,
+
+    pub window: Option<WindowSpec>,
 }
 
 impl StreamingFunction {
     // Should the version of the data models be included in the id?
     pub fn id(&self) -> String {
+        let target_names = self
+            .target_data_models
+            .iter()
+            .map(|data_model| data_model.name.as_str())
+            .collect::<Vec<&str>>()
+            .join("_");
         format!(
             "{}_{}_{}_{}",
-            self.name, self.source_data_model.name, self.target_data_model.name, self.version
+            self.name, self.source_data_model.name, target_names, self.version
         )
     }
 
+    // The first target data model, the only one of the migration functions
+    pub fn target_data_model(&self) -> &DataModel {
+        &self.target_data_models[0]
+    }
+
     pub fn is_ts(&self) -> bool {
         self.executable.extension().unwrap().to_str().unwrap() == TYPESCRIPT_FILE_EXTENSION
     }
@@ -57,8 +222,67 @@ impl StreamingFunction {
         self.executable.extension().unwrap().to_str().unwrap() == PYTHON_FILE_EXTENSION
     }
 
+    pub fn is_wasm(&self) -> bool {
+        self.executable.extension().unwrap().to_str().unwrap() == WASM_FILE_EXTENSION
+    }
+
     pub fn is_migration(&self) -> bool {
-        self.source_data_model.version != self.target_data_model.version
+        self.source_data_model.version != self.target_data_model().version
             && self.executable.extension().unwrap().to_str().unwrap() != SQL_FILE_EXTENSION
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::framework::core::infrastructure::table::{Column, ColumnType};
+    use crate::framework::data_model::config::DataModelConfig;
+
+    use super::*;
+
+    #[test]
+    fn test_window_spec_from_config() {
+        let config: StreamingFunctionConfig = toml::from_str(
+            r#"
+            [windows.Click__ClickStats]
+            type = "hopping"
+            size_secs = 60
+            advance_secs = 120
+            key = "userId"
+            time_column = "timestamp"
+            "#,
+        )
+        .unwrap();
+        let window = &config.windows["Click__ClickStats"];
+        assert_eq!(
+            window.kind,
+            WindowKind::Hopping {
+                size_secs: 60,
+                advance_secs: 120
+            }
+        );
+        assert_eq!(window.allowed_lateness_secs, 0);
+
+        let column = |name: &str| Column {
+            name: name.to_string(),
+            data_type: ColumnType::String,
+            required: true,
+            unique: false,
+            primary_key: false,
+            default: None,
+        };
+        let click = DataModel {
+            columns: vec![column("userId"), column("timestamp")],
+            name: "Click".to_string(),
+            config: DataModelConfig::default(),
+            abs_file_path: PathBuf::new(),
+            version: "0.0".to_string(),
+        };
+        assert!(window.validate(&click).is_err());
+
+        let tumbling = WindowSpec {
+            kind: WindowKind::Tumbling { size_secs: 60 },
+            ..window.clone()
+        };
+        assert!(tumbling.validate(&click).is_ok());
+    }
+}
@@ -15,6 +15,7 @@ const CONSUMPTION_RUNNER_WRAPPER: &str = include_str!("ts_scripts/consumption-ap
 pub fn run(
     clickhouse_config: ClickHouseConfig,
     consumption_path: &Path,
+    versions_path: &Path,
 ) -> Result<Child, ConsumptionError> {
     let host_port = clickhouse_config.host_port.to_string();
     let use_ssl = clickhouse_config.use_ssl.to_string();
@@ -26,6 +27,7 @@ pub fn run(
         &clickhouse_config.user,
         &clickhouse_config.password,
         &use_ssl,
+        versions_path.to_str().unwrap(),
     ];
 
     let mut consumption_process = ts_node::run(CONSUMPTION_RUNNER_WRAPPER, &args)?;
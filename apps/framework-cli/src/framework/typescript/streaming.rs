@@ -1,9 +1,10 @@
-use log::{error, info};
+use log::info;
 use std::path::Path;
-use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::process::Child;
 
-use crate::infrastructure::stream::redpanda::RedpandaConfig;
+use crate::cli::tracing;
+use crate::framework::streaming::model::RunnerMode;
+use crate::infrastructure::stream::redpanda::{RedpandaConfig, KAFKA_CONFIG_ENV};
 
 use super::ts_node;
 
@@ -13,69 +14,54 @@ const FUNCTION_RUNNER_WRAPPER: &str = include_str!("ts_scripts/streaming-functio
 // abstract this to other type of streaming engine, we will need to be able to abstract this away.
 // TODO: compilation errors are not proxied to the user in dev mode. We need to fix it
 // so that they can have some feedback when they mess up the typescript
+// The stdout and stderr of the process are left to the caller, which supervises the function
+#[allow(clippy::too_many_arguments)]
 pub fn run(
     redpanda_config: &RedpandaConfig,
     source_topic: &str,
-    target_topic: &str,
+    // The topic of each target data model, as a JSON object
+    targets: &str,
     target_topic_config: &str,
     streaming_function_file: &Path,
     // TODO Remove the anyhow type here
+    // The changelog topic of the stateful and windowed functions
+    state_topic: Option<&str>,
+    // The window spec of the windowed functions, as JSON
+    window: Option<&str>,
+    // The index of the worker, which identifies its Kafka transactions
+    worker: u16,
+    mode: &RunnerMode,
 ) -> Result<Child, std::io::Error> {
+    let worker = worker.to_string();
     let mut args = vec![
         source_topic,
-        target_topic,
+        targets,
         target_topic_config,
         streaming_function_file.to_str().unwrap(),
-        &redpanda_config.broker,
     ];
+    // The optional arguments are positional, the missing ones are passed empty
+    args.push(state_topic.unwrap_or(""));
+    args.push(window.unwrap_or(""));
+    args.push(&worker);
 
     info!(
-        "Starting a streaming function with the following public arguments: {:#?}",
+        "Starting a streaming function with the following arguments: {:#?}",
         args
     );
 
-    if redpanda_config.sasl_username.is_some() {
-        args.push(redpanda_config.sasl_username.as_ref().unwrap());
-    }
-
-    if redpanda_config.sasl_password.is_some() {
-        args.push(redpanda_config.sasl_password.as_ref().unwrap());
-    }
-
-    if redpanda_config.sasl_mechanism.is_some() {
-        args.push(redpanda_config.sasl_mechanism.as_ref().unwrap());
-    }
-
-    if redpanda_config.security_protocol.is_some() {
-        args.push(redpanda_config.security_protocol.as_ref().unwrap());
-    }
-
-    let mut streaming_function_process = ts_node::run(FUNCTION_RUNNER_WRAPPER, &args)?;
-
-    let stdout = streaming_function_process
-        .stdout
-        .take()
-        .expect("Streaming process did not have a handle to stdout");
-
-    let stderr = streaming_function_process
-        .stderr
-        .take()
-        .expect("Streaming process did not have a handle to stderr");
-
-    let mut stdout_reader = BufReader::new(stdout).lines();
-    let mut stderr_reader = BufReader::new(stderr).lines();
-
-    tokio::spawn(async move {
-        while let Ok(Some(line)) = stdout_reader.next_line().await {
-            info!("{}", line);
-        }
-    });
+    let client_id = format!(
+        "moose-{}",
+        streaming_function_file
+            .with_extension("")
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+    );
+    let kafka_config = redpanda_config.runner_config_json(&client_id);
 
-    tokio::spawn(async move {
-        while let Ok(Some(line)) = stderr_reader.next_line().await {
-            error!("{}", line);
-        }
-    });
+    let mut env = vec![(KAFKA_CONFIG_ENV, kafka_config)];
+    env.extend(mode.env());
+    env.extend(tracing::runner_env());
 
-    Ok(streaming_function_process)
+    ts_node::run_with_env(FUNCTION_RUNNER_WRAPPER, &args, &env)
 }
@@ -12,6 +12,14 @@ pub struct CliMessage {
 }
 
 pub fn run(script: &str, args: &[&str]) -> Result<Child, std::io::Error> {
+    run_with_env(script, args, &[])
+}
+
+pub fn run_with_env(
+    script: &str,
+    args: &[&str],
+    env: &[(&str, String)],
+) -> Result<Child, std::io::Error> {
     let mut command = Command::new("npx");
 
     command
@@ -27,6 +35,8 @@ pub fn run(script: &str, args: &[&str]) -> Result<Child, std::io::Error> {
     }
 
     command
+        .envs(env.iter().cloned())
+        .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn()
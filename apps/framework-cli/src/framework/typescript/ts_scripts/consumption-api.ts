@@ -1,4 +1,5 @@
 import http from "http";
+import { basename } from "node:path";
 import process from "node:process";
 import { getClickhouseClient, MooseClient, sql } from "@514labs/moose-lib";
 
@@ -16,8 +17,12 @@ const [
   CLICKHOUSE_USERNAME,
   CLICKHOUSE_PASSWORD,
   CLICKHOUSE_USE_SSL,
+  CONSUMPTION_VERSIONS_DIR_PATH,
 ] = process.argv;
 
+// Set by the moose webserver when the request targets an old version of the apis
+const VERSION_HEADER = "x-moose-api-version";
+
 const clickhouseConfig = {
   username: CLICKHOUSE_USERNAME,
   password: CLICKHOUSE_PASSWORD,
@@ -27,7 +32,14 @@ const clickhouseConfig = {
   port: CLICKHOUSE_PORT,
 };
 
-const createPath = (path: string) => `${CONSUMPTION_DIR_PATH}${path}.ts`;
+// The old versions are snapshots of the app directory, their apis are in the same directory as
+// the current ones for their relative imports to resolve in the snapshot
+const createPath = (path: string, version?: string) =>
+  version === undefined
+    ? `${CONSUMPTION_DIR_PATH}${path}.ts`
+    : `${CONSUMPTION_VERSIONS_DIR_PATH}/${version}/${basename(CONSUMPTION_DIR_PATH)}${path}.ts`;
+
+const isValidVersion = (version: string) => /^[0-9A-Za-z_.-]+$/.test(version);
 
 const apiHandler = async (
   req: http.IncomingMessage,
@@ -37,7 +49,17 @@ const apiHandler = async (
     const url = new URL(req.url || "", "https://localhost");
     const fileName = url.pathname;
 
-    const pathName = createPath(fileName);
+    const versionHeader = req.headers[VERSION_HEADER];
+    const version = Array.isArray(versionHeader)
+      ? versionHeader[0]
+      : versionHeader;
+    if (version !== undefined && !isValidVersion(version)) {
+      res.writeHead(400, { "Content-Type": "application/json" });
+      res.end(JSON.stringify({ error: `Invalid version: ${version}` }));
+      return;
+    }
+
+    const pathName = createPath(fileName, version);
 
     const paramsObject = Array.from(url.searchParams.entries()).reduce(
       (obj: { [key: string]: any }, [key, value]) => {
@@ -1,22 +1,92 @@
-import { Consumer, Kafka, KafkaMessage, Producer, SASLOptions } from "kafkajs";
+import {
+  Consumer,
+  Kafka,
+  KafkaMessage,
+  Producer,
+  RecordMetadata,
+  SASLOptions,
+  KafkaConfig,
+  Sender,
+} from "kafkajs";
 import { Buffer } from "node:buffer";
+import { readFileSync } from "node:fs";
 import process from "node:process";
-import { cliLog } from "@514labs/moose-lib";
+import { createInterface } from "node:readline";
+import {
+  cliLog,
+  setupTracing,
+  shutdownTracing,
+  startKafkaSpan,
+} from "@514labs/moose-lib";
 
 const SOURCE_TOPIC = process.argv[1];
-const TARGET_TOPIC = process.argv[2];
+// The topic of each target data model
+const TARGETS: Record<string, string> = JSON.parse(process.argv[2] || "{}");
+const TARGET_TOPICS = Object.values(TARGETS);
 const TARGET_TOPIC_CONFIG = process.argv[3];
 const FUNCTION_FILE_PATH = process.argv[4];
-const BROKER = process.argv[5];
-const SASL_USERNAME = process.argv[6];
-const SASL_PASSWORD = process.argv[7];
-const SASL_MECHANISM = process.argv[8];
-const SECURITY_PROTOCOL = process.argv[9];
+// The changelog topic of the stateful functions
+const STATE_TOPIC: string | undefined = process.argv[5] || undefined;
+// The window spec of the windowed functions
+const WINDOW: WindowSpec | undefined = process.argv[6]
+  ? JSON.parse(process.argv[6])
+  : undefined;
+// The index of the worker among the workers of the function
+const WORKER = process.argv[7] || "0";
+// In test mode, used by `moose function test`, the records are read from the standard input and
+// the outputs written to the standard output instead of Kafka
+const TEST_MODE = process.env.MOOSE_FUNCTION_TEST === "true";
+const TEST_OUTPUT_PREFIX = "moose-function-test-output:";
+// In replay mode, used by `moose function replay`, a range of the source topic is consumed in a
+// separate consumer group, and the runner exits at the end of the range
+type ReplayRange = {
+  group_id: string;
+  // The first offset and the end offset, excluded, of each partition
+  partitions: Record<string, [number, number]>;
+};
+const REPLAY: ReplayRange | undefined = process.env.MOOSE_FUNCTION_REPLAY
+  ? JSON.parse(process.env.MOOSE_FUNCTION_REPLAY)
+  : undefined;
+
+// The connection config, passed by moose in the environment
+type RunnerKafkaConfig = {
+  broker?: string;
+  client_id?: string;
+  sasl_username?: string | null;
+  sasl_password?: string | null;
+  sasl_mechanism?: string | null;
+  security_protocol?: string | null;
+  ssl_ca_location?: string | null;
+  ssl_certificate_location?: string | null;
+  ssl_key_location?: string | null;
+};
+const KAFKA_CONFIG: RunnerKafkaConfig = JSON.parse(
+  process.env.MOOSE_KAFKA_CONFIG || "{}",
+);
 
-type StreamingFunction = (data: unknown) => unknown | Promise<unknown>;
-type SlimKafkaMessage = { value: string };
+type StreamingFunction = (
+  data: unknown,
+  state?: StateStore,
+) => unknown | Promise<unknown>;
+type WindowSpec = {
+  type: "tumbling" | "hopping" | "session";
+  size_secs?: number;
+  advance_secs?: number;
+  gap_secs?: number;
+  key: string;
+  time_column: string;
+  allowed_lateness_secs: number;
+};
+type Window = { key: string; start: Date; end: Date };
+type WindowedFunction = (
+  records: unknown[],
+  window: Window,
+  state?: StateStore,
+) => unknown | Promise<unknown>;
+type SlimKafkaMessage = { value: string; headers?: Record<string, string> };
+type TargetedMessage = { topic: string; message: SlimKafkaMessage };
 
-const logPrefix = `${SOURCE_TOPIC} -> ${TARGET_TOPIC}`;
+const logPrefix = `${SOURCE_TOPIC} -> ${TARGET_TOPICS.join(", ")}`;
 const log = (message: string): void => {
   console.log(`${logPrefix}: ${message}`);
 };
@@ -35,7 +105,7 @@ if (SOURCE_TOPIC === undefined) {
   process.exit(1);
 }
 
-if (TARGET_TOPIC === undefined) {
+if (TARGET_TOPICS.length === 0) {
   error("Missing target topic");
   process.exit(1);
 }
@@ -45,47 +115,53 @@ if (FUNCTION_FILE_PATH === undefined) {
   process.exit(1);
 }
 
-if (BROKER === undefined) {
-  error("Missing argument: BROKER");
+if (KAFKA_CONFIG.broker === undefined) {
+  error("Missing broker in MOOSE_KAFKA_CONFIG");
   process.exit(1);
 }
 
-// TODO - when having optional arguments, we should use a proper
-// command line parser to handle this. As is a user would not be able to
-// only provide a SASL_USERNAME and not a SASL_PASSWORD.
-if (SASL_USERNAME === undefined) {
-  warn("No argument: SASL_USERNAME");
-}
-
-if (SASL_PASSWORD === undefined) {
-  warn("No argument: SASL_USERNAME");
-}
-
-if (SASL_MECHANISM === undefined) {
-  warn("No argument: SASL_MECHANISM");
-}
-
-if (SECURITY_PROTOCOL === undefined) {
-  warn("No argument: SECURITY_PROTOCOL");
-}
-
 log(`Streaming function configuration loaded with file ${FUNCTION_FILE_PATH}`);
 
 const getSaslConfig = (): SASLOptions | undefined => {
-  const mechanism = SASL_MECHANISM ? SASL_MECHANISM.toLowerCase() : "";
+  if (!KAFKA_CONFIG.sasl_mechanism) {
+    return undefined;
+  }
+
+  const mechanism = KAFKA_CONFIG.sasl_mechanism.toLowerCase();
   switch (mechanism) {
     case "plain":
     case "scram-sha-256":
     case "scram-sha-512":
       return {
         mechanism: mechanism,
-        username: SASL_USERNAME || "",
-        password: SASL_PASSWORD || "",
+        username: KAFKA_CONFIG.sasl_username || "",
+        password: KAFKA_CONFIG.sasl_password || "",
       };
     default:
-      log(`Unsupported SASL mechanism: ${SASL_MECHANISM}`);
-      return undefined;
+      error(`Unsupported SASL mechanism: ${KAFKA_CONFIG.sasl_mechanism}`);
+      process.exit(1);
+  }
+};
+
+const getSslConfig = (): KafkaConfig["ssl"] => {
+  const protocol = (KAFKA_CONFIG.security_protocol || "").toUpperCase();
+  if (protocol !== "SSL" && protocol !== "SASL_SSL") {
+    return false;
   }
+
+  const { ssl_ca_location, ssl_certificate_location, ssl_key_location } =
+    KAFKA_CONFIG;
+  if (!ssl_ca_location && !ssl_certificate_location && !ssl_key_location) {
+    return true;
+  }
+
+  return {
+    ca: ssl_ca_location ? [readFileSync(ssl_ca_location, "utf8")] : undefined,
+    cert: ssl_certificate_location
+      ? readFileSync(ssl_certificate_location, "utf8")
+      : undefined,
+    key: ssl_key_location ? readFileSync(ssl_key_location, "utf8") : undefined,
+  };
 };
 
 const jsonDateReviver = (key: string, value: unknown): unknown => {
@@ -100,17 +176,198 @@ const jsonDateReviver = (key: string, value: unknown): unknown => {
 };
 
 const kafka = new Kafka({
-  clientId: "streaming-function-consumer",
-  brokers: [BROKER],
-  ssl: SECURITY_PROTOCOL === "SASL_SSL",
+  clientId: KAFKA_CONFIG.client_id || "streaming-function-consumer",
+  brokers: KAFKA_CONFIG.broker.split(","),
+  ssl: getSslConfig(),
   sasl: getSaslConfig(),
 });
 
-const streamingFuncId = `flow-${SOURCE_TOPIC}-${TARGET_TOPIC}`;
+const streamingFuncId = `flow-${SOURCE_TOPIC}-${TARGET_TOPICS.join("-")}`;
+const consumerGroupId =
+  REPLAY === undefined ? streamingFuncId : REPLAY.group_id;
 const consumer: Consumer = kafka.consumer({
-  groupId: streamingFuncId,
+  groupId: consumerGroupId,
 });
-const producer: Producer = kafka.producer({ transactionalId: streamingFuncId });
+// The outputs of a batch and its offsets are committed in a single transaction, so a batch that
+// is retried after a failure doesn't produce its records twice. Each worker has its own
+// transactional id, which fences off the previous process of the worker when it restarts.
+const producer: Producer = kafka.producer({
+  transactionalId: `${consumerGroupId}-${WORKER}`,
+  idempotent: true,
+  maxInFlightRequests: 1,
+});
+
+type StateEntry = { value: unknown; expiresAt?: number };
+
+// The keys of the state topic which aren't keys of the state
+const RESERVED_KEY_PREFIX = "__moose_";
+// The keys of the state topic logging the open windows
+const WINDOWS_KEY_PREFIX = `${RESERVED_KEY_PREFIX}windows/`;
+
+/**
+ * Reads the committed changes of the state topic up to its current end. The changes are
+ * committed in transactions, whose control records aren't passed to the consumer, so the end of
+ * each partition is marked with a tombstone committed before reading it.
+ */
+const readStateTopic = async (
+  topic: string,
+  apply: (message: KafkaMessage) => void,
+): Promise<void> => {
+  const admin = kafka.admin();
+  await admin.connect();
+  const offsets = await admin.fetchTopicOffsets(topic);
+  await admin.disconnect();
+
+  const transaction = await producer.transaction();
+  let markers: RecordMetadata[] = [];
+  try {
+    markers = await transaction.send({
+      topic,
+      messages: offsets.map(({ partition }) => ({
+        key: `${RESERVED_KEY_PREFIX}restore/${WORKER}`,
+        value: null,
+        partition,
+      })),
+    });
+    await transaction.commit();
+  } catch (e) {
+    await transaction.abort();
+    throw e;
+  }
+  // The offset of the marker of each partition left to read
+  const remaining = new Map<number, number>(
+    markers.map(({ partition, baseOffset }) => [
+      partition,
+      Number(baseOffset),
+    ]),
+  );
+
+  // The offsets are never committed, so the state is always read from the beginning. Each
+  // worker reads all the partitions, in its own group.
+  const restoreConsumer = kafka.consumer({
+    groupId: `${streamingFuncId}-state-${WORKER}`,
+  });
+  await restoreConsumer.connect();
+  await restoreConsumer.subscribe({
+    topics: [topic],
+    fromBeginning: true,
+  });
+  await new Promise<void>((resolve, reject) => {
+    restoreConsumer
+      .run({
+        autoCommit: false,
+        eachMessage: async ({ partition, message }) => {
+          apply(message);
+          const marker = remaining.get(partition);
+          if (marker !== undefined && Number(message.offset) >= marker) {
+            remaining.delete(partition);
+            if (remaining.size === 0) {
+              resolve();
+            }
+          }
+        },
+      })
+      .catch(reject);
+  });
+  await restoreConsumer.disconnect();
+};
+
+/**
+ * The per-key state of a stateful function. It is kept in memory and every change is logged to
+ * the compacted state topic, from which the state is restored when the function starts.
+ * Deleted keys are logged as tombstones. The changes are logged in the transaction of the batch
+ * being processed, so they are committed along with its outputs.
+ */
+class StateStore {
+  private entries = new Map<string, StateEntry>();
+  private sender: Sender = producer;
+  private checkpointed = new Map<string, StateEntry>();
+
+  constructor(private topic: string) {}
+
+  useSender(sender: Sender): void {
+    this.sender = sender;
+  }
+
+  // The state at the start of a batch, restored when the transaction of the batch is aborted
+  checkpoint(): void {
+    this.checkpointed = new Map(this.entries);
+  }
+
+  rollback(): void {
+    this.entries = new Map(this.checkpointed);
+  }
+
+  get(key: string): unknown {
+    const entry = this.entries.get(key);
+    if (entry === undefined) {
+      return undefined;
+    }
+    // Expired entries are dropped when the state is restored, they aren't logged as deleted
+    if (entry.expiresAt !== undefined && entry.expiresAt <= Date.now()) {
+      this.entries.delete(key);
+      return undefined;
+    }
+    return entry.value;
+  }
+
+  async put(key: string, value: unknown, ttlSeconds?: number): Promise<void> {
+    const entry: StateEntry = {
+      value,
+      expiresAt:
+        ttlSeconds === undefined ? undefined : Date.now() + ttlSeconds * 1000,
+    };
+    this.entries.set(key, entry);
+    await this.sender.send({
+      topic: this.topic,
+      messages: [{ key, value: JSON.stringify(entry) }],
+    });
+  }
+
+  async delete(key: string): Promise<void> {
+    this.entries.delete(key);
+    await this.sender.send({
+      topic: this.topic,
+      messages: [{ key, value: null }],
+    });
+  }
+
+  private apply(message: KafkaMessage): void {
+    if (message.key === null || message.key === undefined) {
+      return;
+    }
+    const key = message.key.toString();
+    // The open windows and the markers logged to the same topic aren't part of the state
+    if (key.startsWith(RESERVED_KEY_PREFIX)) {
+      return;
+    }
+
+    if (message.value === null || message.value === undefined) {
+      this.entries.delete(key);
+      return;
+    }
+    const entry = JSON.parse(message.value.toString()) as StateEntry;
+    if (entry.expiresAt === undefined || entry.expiresAt > Date.now()) {
+      this.entries.set(key, entry);
+    } else {
+      this.entries.delete(key);
+    }
+  }
+
+  async restore(): Promise<void> {
+    await readStateTopic(this.topic, (message) => this.apply(message));
+    log(`Restored ${this.entries.size} key(s) from ${this.topic}`);
+  }
+}
+
+const stateStore =
+  STATE_TOPIC === undefined ? undefined : new StateStore(STATE_TOPIC);
+
+// The state of a function under test or replayed starts empty and is only kept in memory
+const unloggedSender: Sender = {
+  send: async () => [],
+  sendBatch: async () => [],
+};
 
 const startProducer = async (): Promise<void> => {
   await producer.connect();
@@ -127,29 +384,365 @@ const stopConsumer = async (): Promise<void> => {
   log("Consumer is shutting down...");
 };
 
+/**
+ * Each element of an array returned by the function is a record, null and undefined are
+ * dropped.
+ */
+const toMessages = (
+  topic: string,
+  output: unknown,
+  headers: Record<string, string>,
+): TargetedMessage[] =>
+  (Array.isArray(output) ? output : [output])
+    .filter((item) => item !== null && item !== undefined)
+    .map((item) => ({
+      topic,
+      message: { value: JSON.stringify(item), headers },
+    }));
+
+/**
+ * A function with several targets returns an object with the records of each target data
+ * model, keyed by data model name.
+ */
+const route = (
+  output: unknown,
+  headers: Record<string, string>,
+): TargetedMessage[] => {
+  if (TARGET_TOPICS.length === 1) {
+    return toMessages(TARGET_TOPICS[0], output, headers);
+  }
+
+  if (typeof output !== "object" || Array.isArray(output)) {
+    error(
+      `Expected an object keyed by target data model: ${Object.keys(TARGETS).join(", ")}`,
+    );
+    return [];
+  }
+
+  return Object.entries(output as Record<string, unknown>).flatMap(
+    ([dataModel, records]) => {
+      const topic = TARGETS[dataModel];
+      if (topic === undefined) {
+        error(`Unknown target data model ${dataModel}, skipping...`);
+        return [];
+      }
+      return toMessages(topic, records, headers);
+    },
+  );
+};
+
+type OpenWindow = {
+  key: string;
+  start: number;
+  end: number;
+  records: unknown[];
+};
+
+const eventTime = (value: unknown): number | undefined => {
+  let time = NaN;
+  if (value instanceof Date) {
+    time = value.getTime();
+  } else if (typeof value === "number") {
+    time = value;
+  } else if (typeof value === "string") {
+    time = Date.parse(value);
+  }
+  return isNaN(time) ? undefined : time;
+};
+
+const copyWindows = (
+  windows: Map<string, OpenWindow>,
+): Map<string, OpenWindow> =>
+  new Map(
+    [...windows].map(([id, window]) => [
+      id,
+      { ...window, records: [...window.records] },
+    ]),
+  );
+
+/**
+ * The open windows of a windowed function. The watermark is the latest event time received
+ * minus the allowed lateness: the windows ending before it are closed, and the records that
+ * would fall in a closed window are dropped.
+ *
+ * Since the offsets of the records are committed as they arrive, the windows changed by a batch
+ * and the watermark are logged to the state topic in the transaction of the batch, and restored
+ * when the function starts. Each worker restores the windows it logged. The windows of a
+ * function under test or replayed are only kept in memory.
+ */
+class Windows {
+  private open = new Map<string, OpenWindow>();
+  private watermark = -Infinity;
+  private checkpointed: [Map<string, OpenWindow>, number] = [
+    new Map(),
+    -Infinity,
+  ];
+  // The ids of the windows opened, changed or closed since the checkpoint
+  private changed = new Set<string>();
+  private sender: Sender = unloggedSender;
+  private keyPrefix = `${WINDOWS_KEY_PREFIX}${WORKER}`;
+
+  constructor(
+    private spec: WindowSpec,
+    private topic?: string,
+  ) {}
+
+  useSender(sender: Sender): void {
+    this.sender = sender;
+  }
+
+  // The windows at the start of a batch, restored when the transaction of the batch is aborted
+  checkpoint(): void {
+    this.checkpointed = [copyWindows(this.open), this.watermark];
+    this.changed.clear();
+  }
+
+  rollback(): void {
+    this.open = copyWindows(this.checkpointed[0]);
+    this.watermark = this.checkpointed[1];
+    this.changed.clear();
+  }
+
+  // Logs the windows changed since the checkpoint, closed ones as tombstones, and the watermark
+  async logChanges(): Promise<void> {
+    if (this.topic === undefined) {
+      return;
+    }
+    const messages = [...this.changed].map((id) => {
+      const window = this.open.get(id);
+      return {
+        key: `${this.keyPrefix}/${id}`,
+        value: window === undefined ? null : JSON.stringify(window),
+      };
+    });
+    messages.push({
+      key: this.keyPrefix,
+      value: JSON.stringify(this.watermark),
+    });
+    await this.sender.send({ topic: this.topic, messages });
+    this.changed.clear();
+  }
+
+  private apply(message: KafkaMessage): void {
+    const key = message.key?.toString();
+    if (key === this.keyPrefix && message.value) {
+      // -Infinity is logged as null
+      this.watermark = JSON.parse(message.value.toString()) ?? -Infinity;
+    } else if (key?.startsWith(`${this.keyPrefix}/`)) {
+      const id = key.substring(this.keyPrefix.length + 1);
+      if (message.value) {
+        this.open.set(
+          id,
+          JSON.parse(message.value.toString(), jsonDateReviver),
+        );
+      } else {
+        this.open.delete(id);
+      }
+    }
+  }
+
+  async restore(): Promise<void> {
+    if (this.topic === undefined) {
+      return;
+    }
+    await readStateTopic(this.topic, (message) => this.apply(message));
+    log(`Restored ${this.open.size} open window(s) from ${this.topic}`);
+  }
+
+  // Returns false when the record is dropped
+  add(record: Record<string, unknown>): boolean {
+    const time = eventTime(record[this.spec.time_column]);
+    if (time === undefined) {
+      error(`Record without a valid ${this.spec.time_column}, skipping...`);
+      return false;
+    }
+    const key = String(record[this.spec.key]);
+
+    let added = false;
+    if (this.spec.type === "session") {
+      added = this.addToSession(key, time, record);
+    } else {
+      const size = (this.spec.size_secs || 0) * 1000;
+      const advance =
+        this.spec.type === "hopping"
+          ? (this.spec.advance_secs || 0) * 1000
+          : size;
+      for (
+        let start = Math.floor(time / advance) * advance;
+        start > time - size;
+        start -= advance
+      ) {
+        if (start + size <= this.watermark) {
+          continue;
+        }
+        const id = `${key}/${start}`;
+        const window = this.open.get(id) || {
+          key,
+          start,
+          end: start + size,
+          records: [],
+        };
+        window.records.push(record);
+        this.open.set(id, window);
+        this.changed.add(id);
+        added = true;
+      }
+    }
+
+    this.watermark = Math.max(
+      this.watermark,
+      time - this.spec.allowed_lateness_secs * 1000,
+    );
+    return added;
+  }
+
+  // The sessions of a key closer than the gap to the record are merged with it
+  private addToSession(
+    key: string,
+    time: number,
+    record: Record<string, unknown>,
+  ): boolean {
+    const gap = (this.spec.gap_secs || 0) * 1000;
+    if (time + gap <= this.watermark) {
+      return false;
+    }
+
+    const session: OpenWindow = {
+      key,
+      start: time,
+      end: time + gap,
+      records: [record],
+    };
+    for (const [id, window] of this.open) {
+      if (
+        window.key === key &&
+        window.start - gap <= time &&
+        time < window.end
+      ) {
+        session.start = Math.min(session.start, window.start);
+        session.end = Math.max(session.end, window.end);
+        session.records = window.records.concat(session.records);
+        this.open.delete(id);
+        this.changed.add(id);
+      }
+    }
+    this.open.set(`${key}/${session.start}`, session);
+    this.changed.add(`${key}/${session.start}`);
+    return true;
+  }
+
+  // Removes the windows closed by the watermark, in the order they ended
+  closed(): OpenWindow[] {
+    const closed = [...this.open.entries()].filter(
+      ([, window]) => window.end <= this.watermark,
+    );
+    for (const [id] of closed) {
+      this.open.delete(id);
+      this.changed.add(id);
+    }
+    return closed
+      .map(([, window]) => window)
+      .sort((a, b) => a.end - b.end);
+  }
+
+  // Closes all the open windows, once all the records are received
+  closeAll(): OpenWindow[] {
+    this.watermark = Infinity;
+    return this.closed();
+  }
+}
+
+const windows =
+  WINDOW === undefined ? undefined : new Windows(WINDOW, STATE_TOPIC);
+
+// Number of records or windows the function failed on, which fail the test mode
+let failures = 0;
+
+/**
+ * Adds the records of the batch to their windows, and calls the function with the records of
+ * each window closed by the batch.
+ */
+const handleWindowedBatch = async (
+  windowedFunction: WindowedFunction,
+  openWindows: Windows,
+  messages: KafkaMessage[],
+): Promise<TargetedMessage[]> => {
+  let late = 0;
+  for (const message of messages) {
+    if (message.value === undefined || message.value === null) {
+      continue;
+    }
+    const record = JSON.parse(message.value.toString(), jsonDateReviver);
+    if (!openWindows.add(record)) {
+      late++;
+    }
+  }
+  if (late > 0) {
+    warn(`Dropped ${late} record(s) arriving after their window closed`);
+  }
+
+  return aggregateWindows(windowedFunction, openWindows.closed());
+};
+
+// Calls the function with the records of each closed window
+const aggregateWindows = async (
+  windowedFunction: WindowedFunction,
+  closedWindows: OpenWindow[],
+): Promise<TargetedMessage[]> => {
+  const results: TargetedMessage[] = [];
+  for (const window of closedWindows) {
+    try {
+      const output = await windowedFunction(
+        window.records,
+        {
+          key: window.key,
+          start: new Date(window.start),
+          end: new Date(window.end),
+        },
+        stateStore,
+      );
+      if (output) {
+        results.push(...route(output, {}));
+      }
+    } catch (e) {
+      failures++;
+      error(`Failed to aggregate window ${window.key} ${window.start}`);
+      if (e instanceof Error) {
+        error(e.message);
+      }
+    }
+  }
+  return results;
+};
+
 const handleMessage = async (
   streamingFunction: StreamingFunction,
   message: KafkaMessage,
-): Promise<SlimKafkaMessage[] | null> => {
+): Promise<TargetedMessage[] | null> => {
   if (message.value === undefined || message.value === null) {
     log(`Received message with no value, skipping...`);
     return null;
   }
 
+  const span = startKafkaSpan("streaming_function", message.headers, {
+    "messaging.source.name": SOURCE_TOPIC,
+    "messaging.destination.name": TARGET_TOPICS.join(","),
+  });
+
   try {
     const transformedData = await streamingFunction(
       JSON.parse(message.value.toString(), jsonDateReviver),
+      stateStore,
     );
+    span.end();
 
     if (transformedData) {
-      if (Array.isArray(transformedData)) {
-        return transformedData.map((item) => ({ value: JSON.stringify(item) }));
-      } else {
-        return [{ value: JSON.stringify(transformedData) }];
-      }
+      return route(transformedData, span.headers);
     }
   } catch (e) {
+    span.end(e);
     // TODO: Track failure rate
+    failures++;
     error(`Failed to transform data`);
     if (e instanceof Error) {
       error(e.message);
@@ -160,6 +753,7 @@ const handleMessage = async (
 };
 
 const sendMessages = async (
+  sender: Sender,
   targetTopic: string,
   messages: SlimKafkaMessage[],
   maxMessageSize: number,
@@ -173,7 +767,7 @@ const sendMessages = async (
 
       if (chunkSize + messageSize > maxMessageSize) {
         // Send the current chunk before adding the new message
-        await producer.send({ topic: targetTopic, messages: chunks });
+        await sender.send({ topic: targetTopic, messages: chunks });
         log(`Sent ${chunks.length} transformed data to ${targetTopic}`);
 
         // Start a new chunk
@@ -188,7 +782,7 @@ const sendMessages = async (
 
     // Send the last chunk
     if (chunks.length > 0) {
-      await producer.send({ topic: targetTopic, messages: chunks });
+      await sender.send({ topic: targetTopic, messages: chunks });
       log(`Sent final ${chunks.length} transformed data to ${targetTopic}`);
     }
   } catch (e) {
@@ -196,56 +790,189 @@ const sendMessages = async (
     if (e instanceof Error) {
       error(e.message);
     }
+    // Aborts the transaction of the batch
+    throw e;
+  }
+};
+
+const sendResults = async (
+  sender: Sender,
+  results: (TargetedMessage | null)[],
+  maxMessageSize: number,
+): Promise<void> => {
+  const messagesByTopic = new Map<string, SlimKafkaMessage[]>();
+  for (const targeted of results) {
+    if (targeted !== null) {
+      const topicMessages = messagesByTopic.get(targeted.topic) || [];
+      topicMessages.push(targeted.message);
+      messagesByTopic.set(targeted.topic, topicMessages);
+    }
+  }
+
+  for (const [topic, topicMessages] of messagesByTopic) {
+    await sendMessages(sender, topic, topicMessages, maxMessageSize);
   }
 };
 
+// The end offset, excluded, of each partition left to replay. Moose ends the ranges after a
+// committed record, which reaches the consumer unlike the control records of transactions.
+const replayEnds = new Map<number, number>(
+  Object.entries(REPLAY?.partitions || {})
+    .filter(([, [start, end]]) => start < end)
+    .map(([partition, [, end]]) => [Number(partition), end]),
+);
+
+// Closes the open windows once the whole range is replayed, and exits
+const finishReplay = async (
+  windowedFunction: WindowedFunction,
+  maxMessageSize: number,
+): Promise<void> => {
+  if (windows !== undefined) {
+    const transaction = await producer.transaction();
+    try {
+      const results = await aggregateWindows(
+        windowedFunction,
+        windows.closeAll(),
+      );
+      await sendResults(transaction, results, maxMessageSize);
+      await transaction.commit();
+    } catch (e) {
+      await transaction.abort();
+      throw e;
+    }
+  }
+  log("Replay is done");
+  await stopConsumer();
+  await stopProducer();
+  await shutdownTracing();
+  process.exit(failures > 0 ? 1 : 0);
+};
+
 const startConsumer = async (
   sourceTopic: string,
-  targetTopic: string,
   maxMessageSize: number,
 ): Promise<void> => {
   await consumer.connect();
 
   log(
-    `Starting consumer group '${streamingFuncId}' with source topic: ${sourceTopic} and target topic: ${targetTopic}`,
+    `Starting consumer group '${consumerGroupId}' with source topic: ${sourceTopic} and target topics: ${TARGET_TOPICS.join(", ")}`,
   );
 
   const streamingFunctionImport = await import(
     FUNCTION_FILE_PATH.substring(0, FUNCTION_FILE_PATH.length - 3)
   );
   const streamingFunction: StreamingFunction = streamingFunctionImport.default;
+  const windowedFunction = streamingFunctionImport.default as WindowedFunction;
+
+  if (REPLAY !== undefined && replayEnds.size === 0) {
+    return finishReplay(windowedFunction, maxMessageSize);
+  }
 
   await consumer.subscribe({
     topics: [sourceTopic],
     fromBeginning: false,
   });
   await consumer.run({
+    // The offsets are committed by the transaction of each batch
+    autoCommit: false,
     eachBatchAutoResolve: true,
     eachBatch: async ({ batch }) => {
+      // The records past the end of a replayed partition are left alone
+      const replayEnd = replayEnds.get(batch.partition);
+      if (REPLAY !== undefined && replayEnd === undefined) {
+        return;
+      }
+      const batchMessages =
+        replayEnd === undefined
+          ? batch.messages
+          : batch.messages.filter(
+              (message) => Number(message.offset) < replayEnd,
+            );
+
       cliLog({
         action: "Received",
-        message: `${logPrefix} ${batch.messages.length} message(s)`,
+        message: `${logPrefix} ${batchMessages.length} message(s)`,
       });
-      const messages = (
-        await Promise.all(
-          batch.messages.map((message) =>
-            handleMessage(streamingFunction, message),
-          ),
-        )
-      ).flat();
-
-      const filteredMessages = messages.filter((msg) => msg !== null);
-
-      if (filteredMessages.length > 0) {
-        await sendMessages(
-          targetTopic,
-          filteredMessages as SlimKafkaMessage[],
-          maxMessageSize,
-        );
+      const transaction = await producer.transaction();
+      stateStore?.useSender(
+        REPLAY === undefined ? transaction : unloggedSender,
+      );
+      windows?.useSender(REPLAY === undefined ? transaction : unloggedSender);
+      stateStore?.checkpoint();
+      windows?.checkpoint();
+      try {
+        // The messages of a stateful function are handled in order, since they can update the
+        // state of the same key
+        let results: (TargetedMessage[] | null)[] = [];
+        if (windows !== undefined) {
+          results = [
+            await handleWindowedBatch(windowedFunction, windows, batchMessages),
+          ];
+        } else if (stateStore === undefined) {
+          results = await Promise.all(
+            batchMessages.map((message) =>
+              handleMessage(streamingFunction, message),
+            ),
+          );
+        } else {
+          for (const message of batchMessages) {
+            results.push(await handleMessage(streamingFunction, message));
+          }
+        }
+
+        await sendResults(transaction, results.flat(), maxMessageSize);
+        await windows?.logChanges();
+        await transaction.sendOffsets({
+          consumerGroupId,
+          topics: [
+            {
+              topic: batch.topic,
+              partitions: [
+                {
+                  partition: batch.partition,
+                  offset: (Number(batch.lastOffset()) + 1).toString(),
+                },
+              ],
+            },
+          ],
+        });
+        await transaction.commit();
+      } catch (e) {
+        await transaction.abort();
+        stateStore?.rollback();
+        windows?.rollback();
+        // The batch is consumed again from its first message
+        throw e;
+      }
+
+      if (
+        replayEnd !== undefined &&
+        Number(batch.lastOffset()) + 1 >= replayEnd
+      ) {
+        replayEnds.delete(batch.partition);
+        if (replayEnds.size === 0) {
+          // The consumer can't be stopped from within one of its batches
+          setImmediate(
+            () => void finishReplay(windowedFunction, maxMessageSize),
+          );
+        }
       }
     },
   });
 
+  // The replayed partitions are consumed from the start of their range
+  for (const [partition, [start, end]] of Object.entries(
+    REPLAY?.partitions || {},
+  )) {
+    if (start < end) {
+      consumer.seek({
+        topic: sourceTopic,
+        partition: Number(partition),
+        offset: start.toString(),
+      });
+    }
+  }
+
   log("Consumer is running...");
 };
 
@@ -270,11 +997,14 @@ const getMaxMessageSize = (config: Record<string, unknown>): number => {
 
 const startStreaming = async (
   sourceTopic: string,
-  targetTopic: string,
   targetTopicConfigJson: string,
 ): Promise<void> => {
   try {
     await startProducer();
+    if (REPLAY === undefined) {
+      await stateStore?.restore();
+      await windows?.restore();
+    }
 
     try {
       const targetTopicConfig = JSON.parse(targetTopicConfigJson) as Record<
@@ -283,7 +1013,7 @@ const startStreaming = async (
       >;
       const maxMessageSize = getMaxMessageSize(targetTopicConfig);
 
-      await startConsumer(sourceTopic, targetTopic, maxMessageSize);
+      await startConsumer(sourceTopic, maxMessageSize);
     } catch (e) {
       error("Failed to start kafka consumer: ");
       if (e instanceof Error) {
@@ -302,7 +1032,57 @@ process.on("SIGTERM", async () => {
   log("Received SIGTERM, shutting down...");
   await stopConsumer();
   await stopProducer();
+  await shutdownTracing();
   process.exit(0);
 });
 
-startStreaming(SOURCE_TOPIC, TARGET_TOPIC, TARGET_TOPIC_CONFIG);
+/**
+ * Runs the function on the records of the standard input, one JSON record per line, and writes
+ * each output record to the standard output, on a line starting with TEST_OUTPUT_PREFIX. The
+ * open windows are closed once all the records are read. Exits with 1 if the function failed
+ * on any record.
+ */
+const runTest = async (): Promise<void> => {
+  const streamingFunctionImport = await import(
+    FUNCTION_FILE_PATH.substring(0, FUNCTION_FILE_PATH.length - 3)
+  );
+  stateStore?.useSender(unloggedSender);
+
+  const messages: KafkaMessage[] = [];
+  for await (const line of createInterface({ input: process.stdin })) {
+    if (line.trim() !== "") {
+      messages.push({ value: Buffer.from(line), headers: {} } as KafkaMessage);
+    }
+  }
+
+  const results: TargetedMessage[] = [];
+  if (windows !== undefined) {
+    const windowedFunction =
+      streamingFunctionImport.default as WindowedFunction;
+    results.push(
+      ...(await handleWindowedBatch(windowedFunction, windows, messages)),
+      ...(await aggregateWindows(windowedFunction, windows.closeAll())),
+    );
+  } else {
+    for (const message of messages) {
+      const outputs = await handleMessage(
+        streamingFunctionImport.default as StreamingFunction,
+        message,
+      );
+      results.push(...(outputs || []));
+    }
+  }
+
+  for (const { topic, message } of results) {
+    const output = { topic, record: JSON.parse(message.value) };
+    process.stdout.write(`${TEST_OUTPUT_PREFIX}${JSON.stringify(output)}\n`);
+  }
+  process.exit(failures > 0 ? 1 : 0);
+};
+
+if (TEST_MODE) {
+  runTest();
+} else {
+  setupTracing("moose-streaming-function");
+  startStreaming(SOURCE_TOPIC, TARGET_TOPIC_CONFIG);
+}
@@ -0,0 +1,254 @@
+//! Generates the Rust types of the data models of a WebAssembly streaming function, with the
+//! glue code exporting the function to the moose runtime.
+
+use std::collections::BTreeMap;
+
+use convert_case::{Case, Casing};
+
+use crate::framework::core::infrastructure::table::{Column, ColumnType, DataEnum, EnumValue};
+use crate::framework::data_model::model::DataModel;
+
+use super::templates::WASM_BINDINGS_RUNTIME;
+
+// The strict and reserved keywords, up to the 2024 edition
+const RUST_KEYWORDS: [&str; 52] = [
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl",
+    "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "Self", "static", "struct", "super", "trait", "true", "try", "type",
+    "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+// The name of the struct of the records a function with several targets returns
+const OUTPUTS_STRUCT: &str = "Outputs";
+
+// The keywords which can't be raw identifiers either, the fields get a trailing underscore
+const PATH_KEYWORDS: [&str; 4] = ["crate", "self", "Self", "super"];
+
+fn field_name(name: &str) -> String {
+    let snake = name.to_case(Case::Snake);
+    if PATH_KEYWORDS.contains(&snake.as_str()) {
+        format!("{}_", snake)
+    } else if RUST_KEYWORDS.contains(&snake.as_str()) {
+        format!("r#{}", snake)
+    } else {
+        snake
+    }
+}
+
+// The serde attribute keeping the name of the column in the records, if it isn't the field name
+fn rename_attribute(field: &str, name: &str) -> String {
+    if field.trim_start_matches("r#") == name {
+        String::new()
+    } else {
+        format!("    #[serde(rename = \"{}\")]\n", name)
+    }
+}
+
+// The type definitions, by name
+type Definitions = BTreeMap<String, String>;
+
+fn rust_type(column_type: &ColumnType, definitions: &mut Definitions) -> String {
+    match column_type {
+        ColumnType::String => "String".to_string(),
+        ColumnType::Boolean => "bool".to_string(),
+        ColumnType::Int => "i64".to_string(),
+        ColumnType::BigInt => "i128".to_string(),
+        ColumnType::Float | ColumnType::Decimal => "f64".to_string(),
+        // RFC 3339 date times, as they are sent to the ingest endpoints
+        ColumnType::DateTime => "String".to_string(),
+        ColumnType::Enum(data_enum) => {
+            define_enum(data_enum, definitions);
+            data_enum.name.clone()
+        }
+        ColumnType::Array(inner) => format!("Vec<{}>", rust_type(inner, definitions)),
+        ColumnType::Nested(nested) => {
+            define_struct(&nested.name, &nested.columns, definitions);
+            nested.name.clone()
+        }
+        ColumnType::Json => "serde_json::Value".to_string(),
+        ColumnType::Bytes => "Vec<u8>".to_string(),
+    }
+}
+
+fn define_struct(name: &str, columns: &[Column], definitions: &mut Definitions) {
+    if definitions.contains_key(name) {
+        return;
+    }
+
+    let mut fields = String::new();
+    for column in columns {
+        let field = field_name(&column.name);
+        let data_type = rust_type(&column.data_type, definitions);
+        let data_type = if column.required {
+            data_type
+        } else {
+            format!("Option<{}>", data_type)
+        };
+        fields.push_str(&rename_attribute(&field, &column.name));
+        fields.push_str(&format!("    pub {}: {},\n", field, data_type));
+    }
+
+    definitions.insert(
+        name.to_string(),
+        format!(
+            "#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]\npub struct {} {{\n{}}}\n",
+            name, fields
+        ),
+    );
+}
+
+// Enums of strings are Rust enums, enums of integers their integer type with a constant for each
+// member
+fn define_enum(data_enum: &DataEnum, definitions: &mut Definitions) {
+    if definitions.contains_key(&data_enum.name) {
+        return;
+    }
+
+    let string_enum = data_enum
+        .values
+        .iter()
+        .all(|member| matches!(member.value, EnumValue::String(_)));
+    let definition = if string_enum {
+        let mut variants = String::new();
+        for member in data_enum.values.iter() {
+            if let EnumValue::String(value) = &member.value {
+                let mut variant = member.name.to_case(Case::Pascal);
+                if variant == "Self" {
+                    variant.push('_');
+                }
+                if &variant != value {
+                    variants.push_str(&format!("    #[serde(rename = \"{}\")]\n", value));
+                }
+                variants.push_str(&format!("    {},\n", variant));
+            }
+        }
+        format!(
+            "#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]\npub enum {} {{\n{}}}\n",
+            data_enum.name, variants
+        )
+    } else {
+        let mut constants = format!("pub type {} = u8;\n", data_enum.name);
+        for member in data_enum.values.iter() {
+            if let EnumValue::Int(value) = &member.value {
+                constants.push_str(&format!(
+                    "pub const {}_{}: {} = {};\n",
+                    data_enum.name.to_case(Case::UpperSnake),
+                    member.name.to_case(Case::UpperSnake),
+                    data_enum.name,
+                    value
+                ));
+            }
+        }
+        constants
+    };
+
+    definitions.insert(data_enum.name.clone(), definition);
+}
+
+/**
+ * Generates the `moose.rs` module of a WebAssembly streaming function: a struct for each of its
+ * data models, with the types of their columns, and for a function with several target data
+ * models, the `Outputs` struct of the records of each target.
+ */
+pub fn generate_bindings(source: &DataModel, targets: &[DataModel]) -> String {
+    let mut definitions = Definitions::new();
+    for data_model in std::iter::once(source).chain(targets) {
+        define_struct(&data_model.name, &data_model.columns, &mut definitions);
+    }
+
+    if targets.len() > 1 {
+        let mut fields = String::new();
+        for target in targets {
+            let field = field_name(&target.name);
+            fields.push_str(&rename_attribute(&field, &target.name));
+            fields.push_str(&format!("    pub {}: Vec<{}>,\n", field, target.name));
+        }
+        definitions.insert(
+            OUTPUTS_STRUCT.to_string(),
+            format!(
+                "#[derive(Debug, Clone, Default, Serialize)]\npub struct {} {{\n{}}}\n",
+                OUTPUTS_STRUCT, fields
+            ),
+        );
+    }
+
+    let mut code = String::from(
+        "// Generated by `moose function build` from the data models of the function, do not edit\n\n#![allow(dead_code)]\n\nuse serde::{Deserialize, Serialize};\n",
+    );
+    for definition in definitions.values() {
+        code.push('\n');
+        code.push_str(definition);
+    }
+    code.push_str(WASM_BINDINGS_RUNTIME);
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framework::core::infrastructure::table::EnumMember;
+    use crate::framework::data_model::config::DataModelConfig;
+    use std::path::PathBuf;
+
+    fn column(name: &str, data_type: ColumnType, required: bool) -> Column {
+        Column {
+            name: name.to_string(),
+            data_type,
+            required,
+            unique: false,
+            primary_key: false,
+            default: None,
+        }
+    }
+
+    fn data_model(name: &str, columns: Vec<Column>) -> DataModel {
+        DataModel {
+            columns,
+            name: name.to_string(),
+            config: DataModelConfig::default(),
+            abs_file_path: PathBuf::new(),
+            version: "0.0".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_generate_bindings() {
+        let status = DataEnum {
+            name: "Status".to_string(),
+            values: vec![EnumMember {
+                name: "active".to_string(),
+                value: EnumValue::String("active".to_string()),
+            }],
+        };
+        let source = data_model(
+            "Order",
+            vec![
+                column("orderId", ColumnType::String, true),
+                column("type", ColumnType::Enum(status), true),
+                column("self", ColumnType::String, true),
+                column("final", ColumnType::Boolean, true),
+                column(
+                    "amounts",
+                    ColumnType::Array(Box::new(ColumnType::Float)),
+                    false,
+                ),
+            ],
+        );
+        let line_item = data_model("LineItem", vec![column("sku", ColumnType::String, true)]);
+        let invoice = data_model("Invoice", vec![column("total", ColumnType::Int, true)]);
+
+        let code = generate_bindings(&source, &[line_item, invoice]);
+
+        assert!(code.contains(
+            "pub struct Order {\n    #[serde(rename = \"orderId\")]\n    pub order_id: String,\n    pub r#type: Status,\n    #[serde(rename = \"self\")]\n    pub self_: String,\n    pub r#final: bool,\n    pub amounts: Option<Vec<f64>>,\n}"
+        ));
+        assert!(
+            code.contains("pub enum Status {\n    #[serde(rename = \"active\")]\n    Active,\n}")
+        );
+        assert!(code.contains(
+            "pub struct Outputs {\n    #[serde(rename = \"LineItem\")]\n    pub line_item: Vec<LineItem>,\n    #[serde(rename = \"Invoice\")]\n    pub invoice: Vec<Invoice>,\n}"
+        ));
+        assert!(code.contains("macro_rules! streaming_function"));
+    }
+}
@@ -0,0 +1,522 @@
+//! Runs the streaming functions compiled to WebAssembly inside the moose process, instead of in
+//! a node or python runner process.
+//!
+//! The module exports its `memory`, `moose_alloc(len) -> ptr` and `moose_dealloc(ptr, len)` to
+//! manage the buffers passed to it, and `moose_transform(ptr, len) -> ptr << 32 | len`, called
+//! with each source record as JSON and returning its output as JSON. It can import
+//! `moose.log(ptr, len)` to write to the moose logs, and `moose.fail(ptr, len)` to fail the
+//! record with an error message, in which case its output is ignored.
+//!
+//! Each call gets a deadline, so a function stuck in a loop traps instead of holding its worker,
+//! and the memory of the module is capped. The records are transformed on the blocking threads,
+//! off the tokio workers.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use log::{error, info};
+use rdkafka::consumer::Consumer;
+use rdkafka::error::KafkaError;
+use rdkafka::topic_partition_list::{Offset, TopicPartitionList};
+use rdkafka::Message;
+use serde_json::Value;
+use wasmtime::{
+    Caller, Config, Engine, Extern, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder,
+    TypedFunc,
+};
+
+use crate::framework::core::infrastructure::function_process::{FunctionProcess, FunctionTarget};
+use crate::framework::streaming::model::ReplayRange;
+use crate::infrastructure::processes::kafka_clickhouse_sync::{
+    receive_batch, rewind, send_in_transaction, TransactionRecord,
+};
+use crate::infrastructure::stream::redpanda::{
+    create_transactional_producer, create_transactional_subscriber, RedpandaConfig,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum WasmFunctionError {
+    #[error("Failed to load the WebAssembly module {path}")]
+    Load {
+        path: PathBuf,
+        source: anyhow::Error,
+    },
+
+    #[error("The WebAssembly function failed")]
+    Call(#[from] anyhow::Error),
+
+    #[error("The WebAssembly function failed: {0}")]
+    Failed(String),
+
+    #[error("The WebAssembly function returned invalid JSON")]
+    InvalidOutput(#[from] serde_json::Error),
+
+    #[error("Expected an object keyed by target data model: {0}")]
+    UnroutableOutput(String),
+
+    #[error("Unknown target data model {0}")]
+    UnknownTarget(String),
+
+    #[error("The function failed on {0} record(s)")]
+    Failures(usize),
+
+    #[error("Failed to consume or produce to Kafka")]
+    Kafka(#[from] KafkaError),
+}
+
+struct FunctionData {
+    // The lines written by the function with moose.log, until they are taken
+    logs: Vec<String>,
+    // The error the function failed the record it is called on with, with moose.fail
+    failure: Option<String>,
+    limits: StoreLimits,
+}
+
+// The engine ticks an epoch every EPOCH_TICK, and a call traps once its deadline is reached
+const EPOCH_TICK: Duration = Duration::from_millis(100);
+const CALL_DEADLINE_TICKS: u64 = 50;
+
+// Growing the memory of the module past this size fails
+const MAX_MEMORY_BYTES: usize = 256 * 1024 * 1024;
+
+lazy_static! {
+    static ref ENGINE: Engine = {
+        let mut config = Config::new();
+        config.epoch_interruption(true);
+        let engine = Engine::new(&config).unwrap();
+
+        let ticking = engine.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(EPOCH_TICK);
+            ticking.increment_epoch();
+        });
+        engine
+    };
+}
+
+struct Exports {
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    dealloc: TypedFunc<(i32, i32), ()>,
+    transform: TypedFunc<(i32, i32), i64>,
+}
+
+pub struct WasmFunction {
+    module: Module,
+    linker: Linker<FunctionData>,
+    store: Store<FunctionData>,
+    exports: Exports,
+}
+
+// Reads a string the function passed to an import
+fn read_string(caller: &mut Caller<'_, FunctionData>, ptr: i32, len: i32) -> Option<String> {
+    let memory = caller.get_export("memory").and_then(Extern::into_memory)?;
+    let start = ptr as u32 as usize;
+    memory
+        .data(&caller)
+        .get(start..start + len as u32 as usize)
+        .map(|bytes| String::from_utf8_lossy(bytes).to_string())
+}
+
+fn log_from_function(mut caller: Caller<'_, FunctionData>, ptr: i32, len: i32) {
+    if let Some(line) = read_string(&mut caller, ptr, len) {
+        caller.data_mut().logs.push(line);
+    }
+}
+
+fn fail_from_function(mut caller: Caller<'_, FunctionData>, ptr: i32, len: i32) {
+    let message = read_string(&mut caller, ptr, len);
+    caller.data_mut().failure = Some(message.unwrap_or_default());
+}
+
+fn instantiate(
+    linker: &Linker<FunctionData>,
+    module: &Module,
+) -> anyhow::Result<(Store<FunctionData>, Exports)> {
+    let data = FunctionData {
+        logs: Vec::new(),
+        failure: None,
+        limits: StoreLimitsBuilder::new()
+            .memory_size(MAX_MEMORY_BYTES)
+            .build(),
+    };
+    let mut store = Store::new(module.engine(), data);
+    store.limiter(|data| &mut data.limits);
+    // Covers the start function of the module
+    store.set_epoch_deadline(CALL_DEADLINE_TICKS);
+    let instance = linker.instantiate(&mut store, module)?;
+    let exports = Exports {
+        memory: instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| anyhow::anyhow!("The module doesn't export its memory"))?,
+        alloc: instance.get_typed_func(&mut store, "moose_alloc")?,
+        dealloc: instance.get_typed_func(&mut store, "moose_dealloc")?,
+        transform: instance.get_typed_func(&mut store, "moose_transform")?,
+    };
+    Ok((store, exports))
+}
+
+impl WasmFunction {
+    pub fn load(path: &Path) -> Result<Self, WasmFunctionError> {
+        Module::from_file(&ENGINE, path)
+            .and_then(Self::from_module)
+            .map_err(|source| WasmFunctionError::Load {
+                path: path.to_path_buf(),
+                source,
+            })
+    }
+
+    fn from_module(module: Module) -> anyhow::Result<Self> {
+        let mut linker = Linker::new(&ENGINE);
+        linker.func_wrap("moose", "log", log_from_function)?;
+        linker.func_wrap("moose", "fail", fail_from_function)?;
+        let (store, exports) = instantiate(&linker, &module)?;
+        Ok(WasmFunction {
+            module,
+            linker,
+            store,
+            exports,
+        })
+    }
+
+    // Calls the function on a source record, as JSON, and returns its output
+    pub fn call(&mut self, record: &[u8]) -> Result<Value, WasmFunctionError> {
+        let output = self.call_exports(record);
+
+        if let Err(WasmFunctionError::Call(_)) = output {
+            // A trap can leave the memory of the instance in any state, so it starts over
+            let logs = self.take_logs();
+            let (store, exports) = instantiate(&self.linker, &self.module)?;
+            self.store = store;
+            self.exports = exports;
+            self.store.data_mut().logs = logs;
+        }
+        output
+    }
+
+    fn call_exports(&mut self, record: &[u8]) -> Result<Value, WasmFunctionError> {
+        let store = &mut self.store;
+        let exports = &self.exports;
+        store.set_epoch_deadline(CALL_DEADLINE_TICKS);
+
+        let len = record.len() as i32;
+        let ptr = exports.alloc.call(&mut *store, len)?;
+        exports
+            .memory
+            .write(&mut *store, ptr as u32 as usize, record)
+            .map_err(anyhow::Error::from)?;
+        store.data_mut().failure = None;
+        let packed = exports.transform.call(&mut *store, (ptr, len))? as u64;
+        exports.dealloc.call(&mut *store, (ptr, len))?;
+        if let Some(failure) = store.data_mut().failure.take() {
+            return Err(WasmFunctionError::Failed(failure));
+        }
+
+        let (output_ptr, output_len) = ((packed >> 32) as u32, packed as u32);
+        if output_ptr as usize + output_len as usize > exports.memory.data_size(&*store) {
+            return Err(WasmFunctionError::Call(anyhow::anyhow!(
+                "The output of the function is out of its memory"
+            )));
+        }
+        let mut output = vec![0; output_len as usize];
+        exports
+            .memory
+            .read(&*store, output_ptr as usize, &mut output)
+            .map_err(anyhow::Error::from)?;
+        exports
+            .dealloc
+            .call(&mut *store, (output_ptr as i32, output_len as i32))?;
+
+        Ok(serde_json::from_slice(&output)?)
+    }
+
+    pub fn take_logs(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.store.data_mut().logs)
+    }
+}
+
+// Each element of an array returned by the function is a record, nulls are dropped
+fn records(output: Value) -> Vec<Value> {
+    let records = match output {
+        Value::Array(records) => records,
+        record => vec![record],
+    };
+    records
+        .into_iter()
+        .filter(|record| !record.is_null())
+        .collect()
+}
+
+/**
+ * Matches the records of the output of the function with their target data model. A function
+ * with several targets returns an object with the records of each target data model, keyed by
+ * data model name.
+ */
+pub fn route(
+    targets: &[FunctionTarget],
+    output: Value,
+) -> Result<Vec<(&FunctionTarget, Value)>, WasmFunctionError> {
+    if let [target] = targets {
+        return Ok(records(output)
+            .into_iter()
+            .map(|record| (target, record))
+            .collect());
+    }
+
+    let outputs = match output {
+        Value::Object(outputs) => outputs,
+        Value::Null => return Ok(vec![]),
+        _ => {
+            let data_models: Vec<&str> = targets
+                .iter()
+                .map(|target| target.data_model.as_str())
+                .collect();
+            return Err(WasmFunctionError::UnroutableOutput(data_models.join(", ")));
+        }
+    };
+
+    let mut routed = Vec::new();
+    for (data_model, output) in outputs {
+        let target = targets
+            .iter()
+            .find(|target| target.data_model == data_model)
+            .ok_or(WasmFunctionError::UnknownTarget(data_model))?;
+        routed.extend(records(output).into_iter().map(|record| (target, record)));
+    }
+    Ok(routed)
+}
+
+// Calls the function on the record and routes its outputs
+pub fn transform<'a>(
+    function: &mut WasmFunction,
+    targets: &'a [FunctionTarget],
+    record: &[u8],
+) -> Result<Vec<(&'a FunctionTarget, Value)>, WasmFunctionError> {
+    route(targets, function.call(record)?)
+}
+
+/**
+ * Runs a worker of the function, like the runner processes of the other languages do: it
+ * consumes the source topic in the consumer group of the function, or the replayed range in the
+ * group of the replay, and sends the outputs of each batch of records in a transaction which
+ * also commits its offsets. A replay returns once the whole range is consumed.
+ */
+pub async fn run(
+    kafka_config: RedpandaConfig,
+    function_process: FunctionProcess,
+    worker: u16,
+    replay: Option<ReplayRange>,
+) -> Result<(), WasmFunctionError> {
+    let mut function = WasmFunction::load(&function_process.executable)?;
+    let source_topic = &function_process.source_topic;
+    let group_id = match &replay {
+        Some(replay) => replay.group_id.clone(),
+        None => function_process.consumer_group_id(),
+    };
+
+    let subscriber = Arc::new(create_transactional_subscriber(
+        &kafka_config,
+        &group_id,
+        source_topic,
+    )?);
+    // The end offset, excluded, of each partition left to replay
+    let mut replay_ends: HashMap<i32, i64> = HashMap::new();
+    if let Some(replay) = &replay {
+        subscriber.unsubscribe();
+        let mut partitions = TopicPartitionList::new();
+        for (partition, (start, end)) in replay.partitions.iter() {
+            if start < end {
+                partitions.add_partition_offset(
+                    source_topic,
+                    *partition,
+                    Offset::Offset(*start),
+                )?;
+                replay_ends.insert(*partition, *end);
+            }
+        }
+        if replay_ends.is_empty() {
+            return Ok(());
+        }
+        subscriber.assign(&partitions)?;
+    }
+    let producer =
+        create_transactional_producer(&kafka_config, &format!("{}-{}", group_id, worker)).await?;
+
+    let mut failures = 0;
+    loop {
+        let batch = receive_batch(&subscriber, source_topic).await;
+
+        if !batch.is_empty() {
+            // The records past the end of a replayed partition are left alone
+            let payloads: Vec<(usize, Vec<u8>)> = batch
+                .iter()
+                .enumerate()
+                .filter(|(_, message)| {
+                    replay.is_none()
+                        || replay_ends
+                            .get(&message.partition())
+                            .is_some_and(|end| message.offset() < *end)
+                })
+                .filter_map(|(i, message)| message.payload().map(|payload| (i, payload.to_vec())))
+                .collect();
+
+            // The function is moved to a blocking thread for the batch, and handed back with the
+            // outputs of each record
+            let targets = function_process.targets.clone();
+            let (returned, transformed) = tokio::task::spawn_blocking(move || {
+                let transformed: Vec<_> = payloads
+                    .into_iter()
+                    .map(|(i, payload)| {
+                        let routed = transform(&mut function, &targets, &payload).map(|routed| {
+                            routed
+                                .into_iter()
+                                .map(|(target, record)| (target.topic.clone(), record))
+                                .collect::<Vec<_>>()
+                        });
+                        (i, routed, function.take_logs())
+                    })
+                    .collect();
+                (function, transformed)
+            })
+            .await
+            .map_err(|_| KafkaError::Canceled)?;
+            function = returned;
+
+            let mut outputs: Vec<TransactionRecord> = Vec::new();
+            for (i, routed, logs) in transformed {
+                for line in logs {
+                    info!("{}", line);
+                }
+                match routed {
+                    Ok(routed) => outputs.extend(routed.into_iter().map(|(topic, record)| {
+                        TransactionRecord {
+                            topic,
+                            payload: record.to_string().into_bytes(),
+                            headers: batch[i].headers().cloned(),
+                        }
+                    })),
+                    Err(e) => {
+                        failures += 1;
+                        error!("Failed to transform data: {:?}", e);
+                    }
+                }
+            }
+
+            if let Err(e) = send_in_transaction(&subscriber, &producer, outputs).await {
+                error!(
+                    "Failed to send the outputs of {} records from {}, retrying: {}",
+                    batch.len(),
+                    source_topic,
+                    e
+                );
+                rewind(&subscriber, &batch).await?;
+            }
+        }
+
+        if replay.is_some() {
+            let positions = subscriber.position()?;
+            replay_ends.retain(|partition, end| {
+                match positions
+                    .find_partition(source_topic, *partition)
+                    .map(|position| position.offset())
+                {
+                    Some(Offset::Offset(position)) => position < *end,
+                    _ => true,
+                }
+            });
+            if replay_ends.is_empty() {
+                return match failures {
+                    0 => Ok(()),
+                    failures => Err(WasmFunctionError::Failures(failures)),
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn target(data_model: &str) -> FunctionTarget {
+        FunctionTarget {
+            data_model: data_model.to_string(),
+            topic: format!("{}_0_0", data_model),
+            columns: vec![],
+        }
+    }
+
+    #[test]
+    fn test_route_outputs_to_targets() {
+        let single = vec![target("Invoice")];
+        assert_eq!(route(&single, Value::Null).unwrap().len(), 0);
+        assert_eq!(
+            route(&single, json!([{"total": 1}, null, {"total": 2}]))
+                .unwrap()
+                .len(),
+            2
+        );
+
+        let several = vec![target("LineItem"), target("Invoice")];
+        let routed = route(
+            &several,
+            json!({"LineItem": [{"sku": "a"}, {"sku": "b"}], "Invoice": {"total": 1}}),
+        )
+        .unwrap();
+        let mut topics: Vec<&str> = routed
+            .iter()
+            .map(|(target, _)| target.topic.as_str())
+            .collect();
+        topics.sort();
+        assert_eq!(topics, vec!["Invoice_0_0", "LineItem_0_0", "LineItem_0_0"]);
+
+        assert!(route(&several, json!([{"sku": "a"}])).is_err());
+        assert!(route(&several, json!({"Order": {}})).is_err());
+    }
+
+    // Fails records of 1 byte, echoes records of 2 bytes, fails the records of 4 bytes if its
+    // memory can't grow past the limit and returns an output out of its memory otherwise
+    const MODULE: &str = r#"
+        (module
+          (import "moose" "fail" (func $fail (param i32 i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "bad record")
+          (func (export "moose_alloc") (param i32) (result i32) i32.const 1024)
+          (func (export "moose_dealloc") (param i32 i32))
+          (func (export "moose_transform") (param $ptr i32) (param $len i32) (result i64)
+            (if (i32.eq (local.get $len) (i32.const 1))
+              (then (call $fail (i32.const 0) (i32.const 10)) (return (i64.const 0))))
+            (if (i32.eq (local.get $len) (i32.const 2))
+              (then (return (i64.const 0x0000040000000002))))
+            (if (i32.eq (local.get $len) (i32.const 4))
+              (then
+                (if (i32.eq (memory.grow (i32.const 5000)) (i32.const -1))
+                  (then (call $fail (i32.const 0) (i32.const 10)) (return (i64.const 0))))))
+            (i64.const 0x0000ffff00000010)))
+    "#;
+
+    #[test]
+    fn test_calls_are_checked() {
+        let module = Module::new(&ENGINE, MODULE).unwrap();
+        let mut function = WasmFunction::from_module(module).unwrap();
+
+        assert!(matches!(
+            function.call(b"1"),
+            Err(WasmFunctionError::Failed(message)) if message == "bad record"
+        ));
+        assert_eq!(function.call(b"{}").unwrap(), json!({}));
+        assert!(matches!(
+            function.call(b"1234"),
+            Err(WasmFunctionError::Failed(_))
+        ));
+        assert!(matches!(
+            function.call(b"123"),
+            Err(WasmFunctionError::Call(_))
+        ));
+        assert_eq!(function.call(b"{}").unwrap(), json!({}));
+    }
+}
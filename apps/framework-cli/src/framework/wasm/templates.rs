@@ -0,0 +1,109 @@
+pub static WASM_FUNCTION_CARGO_TEMPLATE: &str = r#"[package]
+name = "{{crate_name}}"
+version = "0.0.0"
+edition = "2021"
+
+# Built on its own, not as a member of an enclosing workspace
+[workspace]
+
+[lib]
+name = "moose_function"
+crate-type = ["cdylib"]
+
+[dependencies]
+serde = { version = "1.0", features = ["derive"] }
+serde_json = "1.0"
+
+[profile.release]
+opt-level = "s"
+lto = true
+"#;
+
+pub static WASM_FUNCTION_GITIGNORE_TEMPLATE: &str = r#"target
+"#;
+
+pub static WASM_STREAMING_FUNCTION_TEMPLATE: &str = r#"// The types of the data models are generated in moose.rs by `moose function build`
+mod moose;
+
+use moose::{{{source}}, {{destination}}};
+
+// The 'transform' function transforms {{source}} data to {{destination}} format, or returns None
+// to skip the record. Build it with `moose function build {{source}}__{{destination}}`.
+// For more details on how Moose streaming functions work, see: https://docs.moosejs.com
+fn transform(source: {{source}}) -> Option<{{destination}}> {
+    moose::log(&format!("{:?}", source));
+    None
+}
+
+streaming_function!(transform);
+"#;
+
+// The glue between the moose runtime and the function, appended to the generated data model types
+pub static WASM_BINDINGS_RUNTIME: &str = r#"
+#[link(wasm_import_module = "moose")]
+extern "C" {
+    #[link_name = "log"]
+    fn moose_log(ptr: i32, len: i32);
+    #[link_name = "fail"]
+    fn moose_fail(ptr: i32, len: i32);
+}
+
+/// Writes a line to the moose logs
+pub fn log(message: &str) {
+    unsafe { moose_log(message.as_ptr() as i32, message.len() as i32) }
+}
+
+// Fails the record with the message, the output returned with it is ignored
+fn fail(message: &str) -> i64 {
+    unsafe { moose_fail(message.as_ptr() as i32, message.len() as i32) };
+    0
+}
+
+fn buffer_layout(len: i32) -> std::alloc::Layout {
+    std::alloc::Layout::array::<u8>((len as usize).max(1)).unwrap()
+}
+
+#[no_mangle]
+pub extern "C" fn moose_alloc(len: i32) -> i32 {
+    unsafe { std::alloc::alloc(buffer_layout(len)) as i32 }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn moose_dealloc(ptr: i32, len: i32) {
+    std::alloc::dealloc(ptr as *mut u8, buffer_layout(len))
+}
+
+#[doc(hidden)]
+pub fn __transform<I, O>(ptr: i32, len: i32, function: fn(I) -> O) -> i64
+where
+    I: serde::de::DeserializeOwned,
+    O: Serialize,
+{
+    let input = unsafe { std::slice::from_raw_parts(ptr as *const u8, len as usize) };
+    let record: I = match serde_json::from_slice(input) {
+        Ok(record) => record,
+        Err(e) => return fail(&format!("Invalid source record: {}", e)),
+    };
+    let output = match serde_json::to_vec(&function(record)) {
+        Ok(output) => output,
+        Err(e) => return fail(&format!("Invalid output records: {}", e)),
+    };
+
+    // The output buffer is freed by the runtime with moose_dealloc
+    let output = std::mem::ManuallyDrop::new(output.into_boxed_slice());
+    ((output.as_ptr() as u32 as i64) << 32) | output.len() as i64
+}
+
+/// Exports the function called with each source record. It returns the records to send: an
+/// Option or a Vec of records of the destination data model, or for a function with several
+/// destination data models, Outputs.
+#[macro_export]
+macro_rules! streaming_function {
+    ($function:path) => {
+        #[no_mangle]
+        pub extern "C" fn moose_transform(ptr: i32, len: i32) -> i64 {
+            $crate::moose::__transform(ptr, len, $function)
+        }
+    };
+}
+"#;
@@ -18,4 +18,5 @@ pub mod api;
 pub mod ingest;
 pub mod olap;
 pub mod processes;
+pub mod state_storage;
 pub mod stream;
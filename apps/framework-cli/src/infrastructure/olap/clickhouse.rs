@@ -3,9 +3,14 @@ use crypto_hash::{hex_digest, Algorithm};
 use errors::ClickhouseError;
 use log::{debug, info};
 use mapper::std_table_to_clickhouse_table;
-use queries::{create_table_query, drop_table_query, ClickhouseEngine};
+use migration::{
+    backup_table_queries, copy_migration_queries, plan_table_migration, TableMigration,
+};
+use queries::{create_table_query, drop_table_query, engine_of_engine_full, ClickhouseEngine};
 use serde::{Deserialize, Serialize};
 
+use crate::framework::core::guardrails::destructive_column_changes;
+use crate::framework::core::infrastructure::table::Table;
 use crate::framework::core::infrastructure_map::{Change, OlapChange};
 use crate::infrastructure::olap::clickhouse::model::{ClickHouseSystemTableRow, ClickHouseTable};
 use crate::project::Project;
@@ -15,9 +20,12 @@ use self::model::ClickHouseSystemTable;
 
 pub mod client;
 pub mod config;
+pub mod egress;
 pub mod errors;
+pub mod graphql;
 pub mod inserter;
 pub mod mapper;
+pub mod migration;
 pub mod model;
 pub mod queries;
 pub mod version_sync;
@@ -34,6 +42,9 @@ pub enum ClickhouseChangesError {
 
     #[error("Not Supported {0}")]
     NotSupported(String),
+
+    #[error("{0} holds the data of a migration that failed midway, it has to be copied back to its table before migrating again")]
+    FailedMigration(String),
 }
 
 pub async fn execute_changes(
@@ -59,36 +70,65 @@ pub async fn execute_changes(
             }
             OlapChange::Table(Change::Removed(table)) => {
                 log::info!("Removing table: {:?}", table.id());
+                if project.is_production {
+                    backup_table(db_name, table, &configured_client).await?;
+                }
 
                 let clickhouse_table = std_table_to_clickhouse_table(table)?;
                 let drop_query = drop_table_query(db_name, clickhouse_table)?;
                 run_query(&drop_query, &configured_client).await?;
             }
             OlapChange::Table(Change::Updated { before, after }) => {
-                // In dev - we drop and re-create the table
-                if !project.is_production {
-                    log::info!(
-                        "Replacing table: {:?} and replacing it with {:?}",
-                        before,
-                        after
-                    );
-                    let table_to_drop = std_table_to_clickhouse_table(before)?;
-                    let drop_query = drop_table_query(db_name, table_to_drop)?;
-                    run_query(&drop_query, &configured_client).await?;
-
-                    let table_to_create = std_table_to_clickhouse_table(after)?;
-                    let create_data_table_query =
-                        create_table_query(db_name, table_to_create, ClickhouseEngine::MergeTree)?;
-                    run_query(&create_data_table_query, &configured_client).await?;
-                } else {
-                    // In production - ideally we would run an alter statement if possible. Current functionlity is that
-                    // this should not happen - so we error out. This is to prevent accidental data loss
-                    // We should complain at the planning level an actually never get here.
-
-                    return Err(ClickhouseChangesError::NotSupported(format!(
-                        "Table update to {} are not supported in production",
-                        after.id()
-                    )));
+                let diff = before.diff(after);
+                if project.is_production && !destructive_column_changes(before, after).is_empty() {
+                    backup_table(db_name, before, &configured_client).await?;
+                }
+
+                match plan_table_migration(db_name, before, &diff)? {
+                    TableMigration::Alter(queries) => {
+                        log::info!("Altering table: {:?}", after.id());
+                        for query in queries.iter() {
+                            run_query(query, &configured_client).await?;
+                        }
+                    }
+                    // In dev - we drop and re-create the table rather than copying the data
+                    TableMigration::Copy if !project.is_production => {
+                        log::info!(
+                            "Replacing table: {:?} and replacing it with {:?}",
+                            before,
+                            after
+                        );
+                        let engine =
+                            table_engine(db_name, &before.name, &configured_client).await?;
+                        let table_to_drop = std_table_to_clickhouse_table(before)?;
+                        let drop_query = drop_table_query(db_name, table_to_drop)?;
+                        run_query(&drop_query, &configured_client).await?;
+
+                        let table_to_create = std_table_to_clickhouse_table(after)?;
+                        let create_data_table_query =
+                            create_table_query(db_name, table_to_create, engine)?;
+                        run_query(&create_data_table_query, &configured_client).await?;
+                    }
+                    TableMigration::Copy => {
+                        log::info!("Migrating table: {:?} to a copy", after.id());
+                        // After the exchange, the migration table holds the data until it is
+                        // copied back, it isn't dropped as a leftover then
+                        let migration_table_name = format!("{}_migration", after.name);
+                        if table_rows(db_name, &migration_table_name, &configured_client).await? > 0
+                        {
+                            return Err(ClickhouseChangesError::FailedMigration(
+                                migration_table_name,
+                            ));
+                        }
+
+                        let engine =
+                            table_engine(db_name, &before.name, &configured_client).await?;
+                        for query in
+                            copy_migration_queries(db_name, before, after, &diff, engine)?.iter()
+                        {
+                            run_query(query, &configured_client).await?;
+                        }
+                    }
                 }
             }
         }
@@ -97,6 +137,25 @@ pub async fn execute_changes(
     Ok(())
 }
 
+async fn backup_table(
+    db_name: &str,
+    table: &Table,
+    configured_client: &ConfiguredDBClient,
+) -> Result<(), ClickhouseChangesError> {
+    let (backup_table_name, queries) = backup_table_queries(db_name, &table.name)?;
+    log::info!(
+        "Backing up table: {:?} to {:?}",
+        table.id(),
+        backup_table_name
+    );
+
+    for query in queries.iter() {
+        run_query(query, configured_client).await?;
+    }
+
+    Ok(())
+}
+
 pub struct ConfiguredDBClient {
     pub client: Client,
     pub config: ClickHouseConfig,
@@ -240,6 +299,43 @@ pub async fn get_engine(
     Ok(cursor.next().await?)
 }
 
+// The engine of the table with its arguments, MergeTree if the table doesn't exist
+async fn table_engine(
+    db_name: &str,
+    name: &str,
+    configured_client: &ConfiguredDBClient,
+) -> Result<ClickhouseEngine, clickhouse::error::Error> {
+    let engines = configured_client
+        .client
+        .query("SELECT engine_full FROM system.tables WHERE database = ? AND name = ?")
+        .bind(db_name)
+        .bind(name)
+        .fetch_all::<String>()
+        .await?;
+
+    Ok(match engines.first() {
+        Some(engine_full) => ClickhouseEngine::Existing(engine_of_engine_full(engine_full)),
+        None => ClickhouseEngine::MergeTree,
+    })
+}
+
+// The number of rows of the table, 0 if it doesn't exist
+async fn table_rows(
+    db_name: &str,
+    name: &str,
+    configured_client: &ConfiguredDBClient,
+) -> Result<u64, clickhouse::error::Error> {
+    let rows = configured_client
+        .client
+        .query("SELECT ifNull(total_rows, 0) FROM system.tables WHERE database = ? AND name = ?")
+        .bind(db_name)
+        .bind(name)
+        .fetch_all::<u64>()
+        .await?;
+
+    Ok(rows.into_iter().sum())
+}
+
 pub async fn check_is_table_new(
     table: &ClickHouseTable,
     configured_client: &ConfiguredDBClient,
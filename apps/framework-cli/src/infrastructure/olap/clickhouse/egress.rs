@@ -0,0 +1,738 @@
+//! # Egress
+//! Builds the ClickHouse queries behind the egress APIs that the moose webserver serves
+//! directly from a data model's table, without a consumption API handler.
+//!
+//! The query string of the request drives the query:
+//! - `fields=a,b` - projection, defaults to every column of the table
+//! - `<column>=<value>` or `<column>__<op>=<value>` - filters, `op` is one of
+//!   `eq`, `ne`, `gt`, `gte`, `lt`, `lte`. Repeating an `eq`/`ne` filter turns it into `IN`/`NOT IN`
+//! - `order_by=a,-b` - ordering, `-` for descending. Defaults to the table's `order_by`. The key
+//!   columns of the table, or all its comparable columns if it has none, are added to break ties
+//! - `limit` and either `offset` or `cursor` for pagination
+
+use base64::prelude::*;
+use serde_json::{json, Map, Value};
+
+use crate::framework::core::infrastructure::table::{Column, ColumnType, EnumValue, Table};
+
+use super::mapper::std_field_type_to_clickhouse_type_mapper;
+use super::model::{sanitize_column_name, ClickHouseColumnType, ClickHouseValue};
+
+pub const DEFAULT_LIMIT: u64 = 100;
+pub const MAX_LIMIT: u64 = 10_000;
+
+const FIELDS_PARAM: &str = "fields";
+const ORDER_BY_PARAM: &str = "order_by";
+const LIMIT_PARAM: &str = "limit";
+const OFFSET_PARAM: &str = "offset";
+const CURSOR_PARAM: &str = "cursor";
+
+const OPERATOR_SEPARATOR: &str = "__";
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum EgressQueryError {
+    #[error("Unknown column: {0}")]
+    UnknownColumn(String),
+
+    #[error("Unknown filter operator: {0}")]
+    UnknownOperator(String),
+
+    #[error("Column {0} cannot be used to filter or order results")]
+    UnsupportedColumn(String),
+
+    #[error("Invalid value for {column}: {value}")]
+    InvalidValue { column: String, value: String },
+
+    #[error("Invalid {param} parameter: {value}")]
+    InvalidParameter { param: String, value: String },
+
+    #[error("Cursor pagination cannot be combined with an offset")]
+    CursorWithOffset,
+
+    #[error("Cursor pagination requires the results to be ordered in a single direction")]
+    UnsupportedCursorOrdering,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterOperator {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+}
+
+impl FilterOperator {
+    fn parse(operator: &str) -> Result<Self, EgressQueryError> {
+        match operator {
+            "eq" => Ok(FilterOperator::Eq),
+            "ne" => Ok(FilterOperator::Ne),
+            "gt" => Ok(FilterOperator::Gt),
+            "gte" => Ok(FilterOperator::Gte),
+            "lt" => Ok(FilterOperator::Lt),
+            "lte" => Ok(FilterOperator::Lte),
+            _ => Err(EgressQueryError::UnknownOperator(operator.to_string())),
+        }
+    }
+
+    fn sql(&self) -> &'static str {
+        match self {
+            FilterOperator::Eq => "=",
+            FilterOperator::Ne => "!=",
+            FilterOperator::Gt => ">",
+            FilterOperator::Gte => ">=",
+            FilterOperator::Lt => "<",
+            FilterOperator::Lte => "<=",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub column: String,
+    pub operator: FilterOperator,
+    // Literals are already validated and escaped for the type of the column
+    pub values: Vec<String>,
+}
+
+impl Filter {
+    fn to_sql(&self) -> String {
+        let column = quote_identifier(&self.column);
+        match (self.operator, self.values.as_slice()) {
+            (FilterOperator::Eq, [_, _, ..]) => {
+                format!("{} IN ({})", column, self.values.join(", "))
+            }
+            (FilterOperator::Ne, [_, _, ..]) => {
+                format!("{} NOT IN ({})", column, self.values.join(", "))
+            }
+            _ => self
+                .values
+                .iter()
+                .map(|value| format!("{} {} {}", column, self.operator.sql(), value))
+                .collect::<Vec<String>>()
+                .join(" AND "),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pagination {
+    Offset(u64),
+    // Literals of the values of the order by columns of the last row of the previous page
+    Cursor(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EgressQuery {
+    pub projection: Vec<String>,
+    pub filters: Vec<Filter>,
+    pub order_by: Vec<(String, SortDirection)>,
+    pub limit: u64,
+    pub pagination: Pagination,
+}
+
+impl EgressQuery {
+    pub fn from_query_params(
+        table: &Table,
+        params: &[(String, String)],
+    ) -> Result<Self, EgressQueryError> {
+        let mut projection: Option<Vec<String>> = None;
+        let mut order_by: Option<Vec<(String, SortDirection)>> = None;
+        let mut limit = DEFAULT_LIMIT;
+        let mut offset: Option<u64> = None;
+        let mut cursor: Option<String> = None;
+        let mut filters: Vec<Filter> = Vec::new();
+
+        for (key, value) in params {
+            match key.as_str() {
+                FIELDS_PARAM => {
+                    projection = Some(
+                        split_list(value)
+                            .map(|field| Ok(find_column(table, field)?.name.clone()))
+                            .collect::<Result<Vec<String>, EgressQueryError>>()?,
+                    );
+                }
+                ORDER_BY_PARAM => {
+                    order_by = Some(
+                        split_list(value)
+                            .map(|field| {
+                                let (field, direction) = match field.strip_prefix('-') {
+                                    Some(field) => (field, SortDirection::Desc),
+                                    None => (field, SortDirection::Asc),
+                                };
+                                let column = find_sortable_column(table, field)?;
+                                Ok((column.name.clone(), direction))
+                            })
+                            .collect::<Result<Vec<_>, EgressQueryError>>()?,
+                    );
+                }
+                LIMIT_PARAM => {
+                    limit = value
+                        .parse::<u64>()
+                        .ok()
+                        .filter(|limit| (1..=MAX_LIMIT).contains(limit))
+                        .ok_or_else(|| invalid_parameter(LIMIT_PARAM, value))?;
+                }
+                OFFSET_PARAM => {
+                    offset = Some(
+                        value
+                            .parse::<u64>()
+                            .map_err(|_| invalid_parameter(OFFSET_PARAM, value))?,
+                    );
+                }
+                CURSOR_PARAM => cursor = Some(value.clone()),
+                _ => {
+                    let (field, operator) = match key.rsplit_once(OPERATOR_SEPARATOR) {
+                        Some((field, operator)) if find_column(table, key).is_err() => {
+                            (field, FilterOperator::parse(operator)?)
+                        }
+                        _ => (key.as_str(), FilterOperator::Eq),
+                    };
+
+                    let column = find_column(table, field)?;
+                    let literal = to_literal(column, value)?;
+
+                    match filters.iter_mut().find(|f| {
+                        f.column == column.name
+                            && f.operator == operator
+                            && matches!(operator, FilterOperator::Eq | FilterOperator::Ne)
+                    }) {
+                        Some(filter) => filter.values.push(literal),
+                        None => filters.push(Filter {
+                            column: column.name.clone(),
+                            operator,
+                            values: vec![literal],
+                        }),
+                    }
+                }
+            }
+        }
+
+        let order_by = match order_by {
+            Some(order_by) => order_by,
+            None => table
+                .order_by
+                .iter()
+                .map(|field| {
+                    Ok((
+                        find_sortable_column(table, field)?.name.clone(),
+                        SortDirection::Asc,
+                    ))
+                })
+                .collect::<Result<Vec<_>, EgressQueryError>>()?,
+        };
+        let order_by = with_tiebreaker(table, order_by);
+
+        let pagination = match (offset, cursor) {
+            (Some(_), Some(_)) => return Err(EgressQueryError::CursorWithOffset),
+            (_, Some(cursor)) => {
+                if cursor_direction(&order_by).is_none() {
+                    return Err(EgressQueryError::UnsupportedCursorOrdering);
+                }
+                Pagination::Cursor(decode_cursor(table, &order_by, &cursor)?)
+            }
+            (offset, None) => Pagination::Offset(offset.unwrap_or(0)),
+        };
+
+        Ok(EgressQuery {
+            projection: projection
+                .unwrap_or_else(|| table.columns.iter().map(|c| c.name.clone()).collect()),
+            filters,
+            order_by,
+            limit,
+            pagination,
+        })
+    }
+
+    /// Columns selected by the query, the order by columns are added at the end when they
+    /// are not part of the projection since they are needed to compute the next cursor.
+    pub fn selected_columns<'a>(&self, table: &'a Table) -> Vec<&'a Column> {
+        let mut selected: Vec<&str> = self.projection.iter().map(|c| c.as_str()).collect();
+        for (column, _) in &self.order_by {
+            if !selected.contains(&column.as_str()) {
+                selected.push(column);
+            }
+        }
+
+        selected
+            .into_iter()
+            .filter_map(|name| table.columns.iter().find(|c| c.name == name))
+            .collect()
+    }
+
+    /// ClickHouse types of the selected columns, in the select order. Columns that are
+    /// serialized to JSON strings by the query are reported as strings.
+    pub fn selected_column_types(&self, table: &Table) -> Vec<ClickHouseColumnType> {
+        self.selected_columns(table)
+            .into_iter()
+            .map(|column| {
+                if is_json_encoded(&column.data_type) {
+                    ClickHouseColumnType::String
+                } else {
+                    std_field_type_to_clickhouse_type_mapper(column.data_type.clone())
+                        .unwrap_or(ClickHouseColumnType::String)
+                }
+            })
+            .collect()
+    }
+
+    pub fn to_sql(&self, table: &Table) -> String {
+        let select = self
+            .selected_columns(table)
+            .into_iter()
+            .map(|column| {
+                let name = quote_identifier(&column.name);
+                if is_json_encoded(&column.data_type) {
+                    format!("toJSONString({}) AS {}", name, name)
+                } else {
+                    name
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        let mut conditions: Vec<String> = self.filters.iter().map(|f| f.to_sql()).collect();
+        if let Pagination::Cursor(values) = &self.pagination {
+            let operator = match cursor_direction(&self.order_by) {
+                Some(SortDirection::Desc) => "<",
+                _ => ">",
+            };
+            conditions.push(format!(
+                "({}) {} ({})",
+                self.order_by
+                    .iter()
+                    .map(|(column, _)| quote_identifier(column))
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                operator,
+                values.join(", ")
+            ));
+        }
+
+        let mut sql = format!("SELECT {} FROM {}", select, quote_identifier(&table.name));
+
+        if !conditions.is_empty() {
+            sql.push_str(&format!(" WHERE {}", conditions.join(" AND ")));
+        }
+
+        if !self.order_by.is_empty() {
+            sql.push_str(&format!(
+                " ORDER BY {}",
+                self.order_by
+                    .iter()
+                    .map(|(column, direction)| match direction {
+                        SortDirection::Asc => format!("{} ASC", quote_identifier(column)),
+                        SortDirection::Desc => format!("{} DESC", quote_identifier(column)),
+                    })
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ));
+        }
+
+        sql.push_str(&format!(" LIMIT {}", self.limit));
+
+        if let Pagination::Offset(offset) = self.pagination {
+            if offset > 0 {
+                sql.push_str(&format!(" OFFSET {}", offset));
+            }
+        }
+
+        sql
+    }
+
+    /// Builds the response body from the rows returned by ClickHouse: decodes the columns
+    /// that were serialized to JSON, computes the next cursor and drops the columns
+    /// that were only selected for the cursor.
+    pub fn to_response(&self, table: &Table, rows: Vec<Value>) -> Value {
+        let mut rows = self.decode_rows(table, rows);
+
+        let next_cursor = match rows.last() {
+            Some(last)
+                if rows.len() as u64 == self.limit
+                    && cursor_direction(&self.order_by).is_some() =>
+            {
+                let values: Vec<Value> = self
+                    .order_by
+                    .iter()
+                    .map(|(column, _)| {
+                        last.get(&sanitize_column_name(column.clone()))
+                            .cloned()
+                            .unwrap_or(Value::Null)
+                    })
+                    .collect();
+                Some(BASE64_URL_SAFE_NO_PAD.encode(serde_json::to_vec(&values).unwrap()))
+            }
+            _ => None,
+        };
+
+        let projection: Vec<String> = self
+            .projection
+            .iter()
+            .map(|column| sanitize_column_name(column.clone()))
+            .collect();
+        for row in rows.iter_mut() {
+            row.retain(|column, _| projection.contains(column));
+        }
+
+        json!({
+            "data": rows,
+            "next_cursor": next_cursor,
+        })
+    }
+
+    /// Decodes the columns that were serialized to JSON by the query. The rows are keyed by the
+    /// ClickHouse column names, i.e. the sanitized names of the columns.
+    pub fn decode_rows(&self, table: &Table, rows: Vec<Value>) -> Vec<Map<String, Value>> {
+        let json_encoded: Vec<String> = self
+            .selected_columns(table)
+            .into_iter()
+            .filter(|c| is_json_encoded(&c.data_type))
+            .map(|c| sanitize_column_name(c.name.clone()))
+            .collect();
+
+        rows.into_iter()
+            .filter_map(|row| match row {
+                Value::Object(mut row) => {
+                    for column in &json_encoded {
+                        if let Some(Value::String(encoded)) = row.get(column) {
+                            if let Ok(decoded) = serde_json::from_str::<Value>(encoded) {
+                                row.insert(column.clone(), decoded);
+                            }
+                        }
+                    }
+                    Some(row)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value.split(',').map(|v| v.trim()).filter(|v| !v.is_empty())
+}
+
+fn invalid_parameter(param: &str, value: &str) -> EgressQueryError {
+    EgressQueryError::InvalidParameter {
+        param: param.to_string(),
+        value: value.to_string(),
+    }
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!(
+        "`{}`",
+        sanitize_column_name(name.to_string()).replace('`', "\\`")
+    )
+}
+
+fn find_column<'a>(table: &'a Table, name: &str) -> Result<&'a Column, EgressQueryError> {
+    table
+        .columns
+        .iter()
+        .find(|c| c.name == name || sanitize_column_name(c.name.clone()) == name)
+        .ok_or_else(|| EgressQueryError::UnknownColumn(name.to_string()))
+}
+
+fn find_sortable_column<'a>(table: &'a Table, name: &str) -> Result<&'a Column, EgressQueryError> {
+    let column = find_column(table, name)?;
+    if is_comparable(&column.data_type) {
+        Ok(column)
+    } else {
+        Err(EgressQueryError::UnsupportedColumn(column.name.clone()))
+    }
+}
+
+/// Whether the column can be used to filter or order results.
+pub fn is_comparable(column_type: &ColumnType) -> bool {
+    !matches!(
+        column_type,
+        ColumnType::Array(_) | ColumnType::Nested(_) | ColumnType::Json | ColumnType::Bytes
+    )
+}
+
+/// Nested and JSON columns are returned as JSON strings by ClickHouse and decoded in the response.
+fn is_json_encoded(column_type: &ColumnType) -> bool {
+    match column_type {
+        ColumnType::Nested(_) | ColumnType::Json => true,
+        ColumnType::Array(inner) => is_json_encoded(inner),
+        _ => false,
+    }
+}
+
+/// Cursors only work when every order by column is sorted in the same direction
+/// since they rely on tuple comparison.
+fn cursor_direction(order_by: &[(String, SortDirection)]) -> Option<SortDirection> {
+    let (_, direction) = order_by.first()?;
+    if order_by.iter().all(|(_, d)| d == direction) {
+        Some(*direction)
+    } else {
+        None
+    }
+}
+
+/// Keyset pagination skips the rows tied with the last row of a page on every order by column, so
+/// the key columns, or all the comparable columns of a table without a key, are appended to the
+/// order by in its direction.
+fn with_tiebreaker(
+    table: &Table,
+    mut order_by: Vec<(String, SortDirection)>,
+) -> Vec<(String, SortDirection)> {
+    let direction = match cursor_direction(&order_by) {
+        Some(direction) => direction,
+        None if order_by.is_empty() => SortDirection::Asc,
+        // Cursors aren't supported on mixed directions
+        None => return order_by,
+    };
+
+    let key: Vec<&Column> = table.columns.iter().filter(|c| c.primary_key).collect();
+    let tiebreaker = if key.is_empty() {
+        table.columns.iter().collect()
+    } else {
+        key
+    };
+
+    for column in tiebreaker {
+        if is_comparable(&column.data_type) && !order_by.iter().any(|(c, _)| c == &column.name) {
+            order_by.push((column.name.clone(), direction));
+        }
+    }
+    order_by
+}
+
+fn decode_cursor(
+    table: &Table,
+    order_by: &[(String, SortDirection)],
+    cursor: &str,
+) -> Result<Vec<String>, EgressQueryError> {
+    let values: Vec<Value> = BASE64_URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .filter(|values: &Vec<Value>| values.len() == order_by.len())
+        .ok_or_else(|| invalid_parameter(CURSOR_PARAM, cursor))?;
+
+    order_by
+        .iter()
+        .zip(values.iter())
+        .map(|((column, _), value)| {
+            let column = find_column(table, column)?;
+            let raw = match value {
+                Value::String(s) => s.clone(),
+                Value::Number(n) => n.to_string(),
+                Value::Bool(b) => b.to_string(),
+                _ => return Err(invalid_parameter(CURSOR_PARAM, cursor)),
+            };
+            to_literal(column, &raw).map_err(|_| invalid_parameter(CURSOR_PARAM, cursor))
+        })
+        .collect()
+}
+
+/// Validates a raw value from the query string against the type of the column
+/// and renders it as an escaped ClickHouse literal.
+pub fn to_literal(column: &Column, raw: &str) -> Result<String, EgressQueryError> {
+    let invalid = || EgressQueryError::InvalidValue {
+        column: column.name.clone(),
+        value: raw.to_string(),
+    };
+
+    match &column.data_type {
+        ColumnType::String => {
+            Ok(ClickHouseValue::new_string(raw.to_string()).clickhouse_to_string())
+        }
+        ColumnType::Boolean => raw
+            .parse::<bool>()
+            .map(|v| ClickHouseValue::new_boolean(v).clickhouse_to_string())
+            .map_err(|_| invalid()),
+        ColumnType::Int | ColumnType::BigInt => raw
+            .parse::<i64>()
+            .map(|v| ClickHouseValue::new_int_64(v).clickhouse_to_string())
+            .map_err(|_| invalid()),
+        // Passed as a string for ClickHouse to parse it at the precision of the column
+        ColumnType::Decimal => is_decimal(raw)
+            .then(|| ClickHouseValue::new_string(raw.to_string()).clickhouse_to_string())
+            .ok_or_else(invalid),
+        ColumnType::Float => raw
+            .parse::<f64>()
+            .ok()
+            .filter(|v| v.is_finite())
+            .map(|v| ClickHouseValue::new_float_64(v).clickhouse_to_string())
+            .ok_or_else(invalid),
+        ColumnType::DateTime => chrono::DateTime::parse_from_rfc3339(raw)
+            .map(|v| {
+                format!(
+                    "parseDateTimeBestEffort({})",
+                    ClickHouseValue::new_date_time(v).clickhouse_to_string()
+                )
+            })
+            .map_err(|_| invalid()),
+        ColumnType::Enum(data_enum) => data_enum
+            .values
+            .iter()
+            .find_map(|member| match &member.value {
+                EnumValue::Int(i) if member.name == raw || i.to_string() == raw => {
+                    Some(member.name.clone())
+                }
+                EnumValue::String(s) if s == raw => Some(s.clone()),
+                _ => None,
+            })
+            .map(|v| ClickHouseValue::new_string(v).clickhouse_to_string())
+            .ok_or_else(invalid),
+        ColumnType::Array(_) | ColumnType::Nested(_) | ColumnType::Json | ColumnType::Bytes => {
+            Err(EgressQueryError::UnsupportedColumn(column.name.clone()))
+        }
+    }
+}
+
+pub fn is_decimal(raw: &str) -> bool {
+    let digits = raw.strip_prefix('-').unwrap_or(raw);
+    let (integer, fraction) = digits.split_once('.').unwrap_or((digits, "0"));
+    [integer, fraction]
+        .iter()
+        .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framework::core::infrastructure_map::fixtures::{column, table};
+
+    fn user_activity() -> Table {
+        let mut event_id = column("eventId", ColumnType::String, true);
+        event_id.primary_key = true;
+        table(
+            "UserActivity",
+            vec![
+                event_id,
+                column("timestamp", ColumnType::DateTime, true),
+                column("count", ColumnType::Int, true),
+                column(
+                    "tags",
+                    ColumnType::Array(Box::new(ColumnType::String)),
+                    true,
+                ),
+            ],
+        )
+    }
+
+    fn params(query: &str) -> Vec<(String, String)> {
+        serde_urlencoded::from_str(query).unwrap()
+    }
+
+    #[test]
+    fn test_default_query() {
+        let table = user_activity();
+        let query = EgressQuery::from_query_params(&table, &[]).unwrap();
+
+        assert_eq!(
+            query.to_sql(&table),
+            "SELECT `eventId`, `timestamp`, `count`, `tags` FROM `UserActivity_0_0` ORDER BY `eventId` ASC LIMIT 100"
+        );
+    }
+
+    #[test]
+    fn test_filters_projection_and_ordering() {
+        let table = user_activity();
+        let query = EgressQuery::from_query_params(
+            &table,
+            &params("fields=count&eventId=a&eventId=b'c&count__gte=3&order_by=-count&limit=10&offset=20"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            query.to_sql(&table),
+            "SELECT `count`, `eventId` FROM `UserActivity_0_0` WHERE `eventId` IN ('a', 'b\\'c') AND `count` >= 3 ORDER BY `count` DESC, `eventId` DESC LIMIT 10 OFFSET 20"
+        );
+    }
+
+    #[test]
+    fn test_invalid_queries() {
+        let table = user_activity();
+
+        for query in [
+            "unknown=1",
+            "count=abc",
+            "count__like=1",
+            "tags=a",
+            "order_by=tags",
+            "limit=0",
+            "offset=1&cursor=abc",
+            "timestamp=yesterday",
+        ] {
+            assert!(
+                EgressQuery::from_query_params(&table, &params(query)).is_err(),
+                "{} should be rejected",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let table = user_activity();
+        let query =
+            EgressQuery::from_query_params(&table, &params("fields=count&limit=1")).unwrap();
+
+        let response = query.to_response(&table, vec![json!({"eventId": "abc", "count": 1})]);
+        assert_eq!(response["data"], json!([{"count": 1}]));
+
+        let cursor = response["next_cursor"].as_str().unwrap();
+        let next_query = EgressQuery::from_query_params(
+            &table,
+            &params(&format!("fields=count&limit=1&cursor={}", cursor)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            next_query.to_sql(&table),
+            "SELECT `count`, `eventId` FROM `UserActivity_0_0` WHERE (`eventId`) > ('abc') ORDER BY `eventId` ASC LIMIT 1"
+        );
+    }
+
+    #[test]
+    fn test_sanitized_decimal_column_with_tiebreaker() {
+        let mut table = user_activity();
+        table
+            .columns
+            .push(column("unit price", ColumnType::Decimal, true));
+
+        assert!(EgressQuery::from_query_params(&table, &params("unit_price=1e5")).is_err());
+
+        let query = EgressQuery::from_query_params(
+            &table,
+            &params("fields=unit_price&unit_price__gte=1.50&order_by=-unit_price&limit=1"),
+        )
+        .unwrap();
+        assert_eq!(
+            query.to_sql(&table),
+            "SELECT `unit_price`, `eventId` FROM `UserActivity_0_0` WHERE `unit_price` >= '1.50' ORDER BY `unit_price` DESC, `eventId` DESC LIMIT 1"
+        );
+
+        let response = query.to_response(
+            &table,
+            vec![json!({"unit_price": "2.50", "eventId": "abc"})],
+        );
+        assert_eq!(response["data"], json!([{"unit_price": "2.50"}]));
+
+        let cursor = response["next_cursor"].as_str().unwrap();
+        let next_query = EgressQuery::from_query_params(
+            &table,
+            &params(&format!(
+                "fields=unit_price&order_by=-unit_price&limit=1&cursor={}",
+                cursor
+            )),
+        )
+        .unwrap();
+        assert!(next_query
+            .to_sql(&table)
+            .contains("WHERE (`unit_price`, `eventId`) < ('2.50', 'abc')"));
+    }
+}
@@ -0,0 +1,727 @@
+//! # GraphQL
+//! Generates a GraphQL schema from the tables of the infrastructure map. Every table gets a
+//! query field returning its rows, with filters, ordering and pagination. Queries are compiled
+//! to ClickHouse SQL with the same query builder as the egress APIs.
+//!
+//! `Int` columns are 64 bits integers, beyond the range of the GraphQL `Int`. They are exposed as
+//! the `Int64` scalar, serialized as strings.
+//!
+//! ```graphql
+//! {
+//!   UserActivity_0_0(
+//!     where: { userId: { in: ["a", "b"] }, timestamp: { gte: "2024-01-01T00:00:00Z" } }
+//!     orderBy: [{ field: timestamp, direction: DESC }]
+//!     limit: 10
+//!   ) {
+//!     eventId
+//!     timestamp
+//!   }
+//! }
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use async_graphql::dynamic::{
+    Enum, Field, FieldFuture, FieldValue, InputObject, InputValue, Object, ResolverContext, Scalar,
+    Schema, SchemaError, Type, TypeRef,
+};
+use async_graphql::Value as GraphQLValue;
+use log::debug;
+use serde_json::{Map, Value};
+
+use crate::framework::core::infrastructure::table::{
+    Column, ColumnType, EnumValue, Nested, Table, TableType,
+};
+use crate::infrastructure::olap::clickhouse_alt_client::select_as_json;
+
+use super::egress::{
+    is_comparable, is_decimal, to_literal, EgressQuery, Filter, FilterOperator, Pagination,
+    SortDirection, DEFAULT_LIMIT, MAX_LIMIT,
+};
+use super::model::sanitize_column_name;
+
+const QUERY_TYPE: &str = "Query";
+const SORT_DIRECTION_TYPE: &str = "SortDirection";
+const BIG_INT_SCALAR: &str = "BigInt";
+const INT64_SCALAR: &str = "Int64";
+const DECIMAL_SCALAR: &str = "Decimal";
+const DATE_TIME_SCALAR: &str = "DateTime";
+const JSON_SCALAR: &str = "JSON";
+
+const WHERE_ARG: &str = "where";
+const ORDER_BY_ARG: &str = "orderBy";
+const LIMIT_ARG: &str = "limit";
+const OFFSET_ARG: &str = "offset";
+
+// Bounds of the queries accepted, each field selected counts 1 towards the complexity
+const MAX_DEPTH: usize = 16;
+const MAX_COMPLEXITY: usize = 1000;
+
+const ORDER_BY_FIELD: &str = "field";
+const ORDER_BY_DIRECTION: &str = "direction";
+const ASC: &str = "ASC";
+const DESC: &str = "DESC";
+
+const IN_OPERATOR: &str = "in";
+const NOT_IN_OPERATOR: &str = "notIn";
+
+const COMPARISON_OPERATORS: [(&str, FilterOperator); 6] = [
+    ("eq", FilterOperator::Eq),
+    ("ne", FilterOperator::Ne),
+    ("gt", FilterOperator::Gt),
+    ("gte", FilterOperator::Gte),
+    ("lt", FilterOperator::Lt),
+    ("lte", FilterOperator::Lte),
+];
+
+// The types defined by GraphQL
+const BUILT_IN_TYPES: [&str; 5] = [
+    TypeRef::STRING,
+    TypeRef::INT,
+    TypeRef::FLOAT,
+    TypeRef::BOOLEAN,
+    TypeRef::ID,
+];
+
+// Scalars that have a filter input type, e.g. `StringFilter`
+const FILTERABLE_SCALARS: [&str; 7] = [
+    TypeRef::STRING,
+    INT64_SCALAR,
+    TypeRef::FLOAT,
+    DECIMAL_SCALAR,
+    TypeRef::BOOLEAN,
+    BIG_INT_SCALAR,
+    DATE_TIME_SCALAR,
+];
+
+/// Builds the schema for the given tables. There is nothing to query without tables,
+/// in which case no schema is returned. Tables or columns whose GraphQL names collide are
+/// rejected.
+pub fn build_schema(
+    tables: &[Table],
+    clickhouse_pool: clickhouse_rs::Pool,
+) -> Result<Option<Schema>, SchemaError> {
+    let mut tables: Vec<&Table> = tables
+        .iter()
+        .filter(|t| t.table_type != TableType::Unsupported && !t.columns.is_empty())
+        .collect();
+    tables.sort_by(|a, b| a.name.cmp(&b.name));
+
+    if tables.is_empty() {
+        return Ok(None);
+    }
+
+    let mut types: Vec<Type> = vec![
+        Scalar::new(INT64_SCALAR)
+            .description("64 bits integer, serialized as a string")
+            .validator(|value| match value {
+                GraphQLValue::String(s) => s.parse::<i64>().is_ok(),
+                GraphQLValue::Number(n) => n.is_i64(),
+                _ => false,
+            })
+            .into(),
+        Scalar::new(DECIMAL_SCALAR)
+            .description("Decimal number, serialized as a string to keep its precision")
+            .validator(|value| match value {
+                GraphQLValue::String(s) => is_decimal(s),
+                GraphQLValue::Number(_) => true,
+                _ => false,
+            })
+            .into(),
+        Scalar::new(BIG_INT_SCALAR).into(),
+        Scalar::new(DATE_TIME_SCALAR).into(),
+        Scalar::new(JSON_SCALAR).into(),
+        Enum::new(SORT_DIRECTION_TYPE).items([ASC, DESC]).into(),
+    ];
+    types.extend(
+        FILTERABLE_SCALARS
+            .iter()
+            .map(|scalar| filter_input(scalar).into()),
+    );
+
+    // The names of the types defined, to reject the tables and columns whose names collide
+    let mut type_names: HashSet<String> = BUILT_IN_TYPES
+        .iter()
+        .chain(
+            [
+                QUERY_TYPE,
+                SORT_DIRECTION_TYPE,
+                INT64_SCALAR,
+                DECIMAL_SCALAR,
+                BIG_INT_SCALAR,
+                DATE_TIME_SCALAR,
+                JSON_SCALAR,
+            ]
+            .iter(),
+        )
+        .map(|name| name.to_string())
+        .chain(
+            FILTERABLE_SCALARS
+                .iter()
+                .map(|scalar| format!("{}Filter", scalar)),
+        )
+        .collect();
+    let mut query = Object::new(QUERY_TYPE);
+
+    for table in tables {
+        let table_schema = Arc::new(TableSchema::new(table));
+        let type_name = graphql_name(&table.name);
+
+        // The nested types of the table, a nested type used by several columns is defined once
+        let mut registered: HashSet<String> = HashSet::new();
+        types.extend(object_types(
+            &type_name,
+            &type_name,
+            &table.columns,
+            &mut registered,
+            &mut type_names,
+        )?);
+
+        let mut field = Field::new(
+            type_name.clone(),
+            TypeRef::named_nn_list_nn(type_name.clone()),
+            move |ctx| {
+                let table_schema = table_schema.clone();
+                FieldFuture::new(async move { table_schema.resolve(ctx).await })
+            },
+        )
+        .argument(
+            InputValue::new(LIMIT_ARG, TypeRef::named(TypeRef::INT))
+                .default_value(DEFAULT_LIMIT as i64),
+        )
+        .argument(InputValue::new(OFFSET_ARG, TypeRef::named(TypeRef::INT)).default_value(0));
+
+        let comparable: Vec<(String, &Column)> = table
+            .columns
+            .iter()
+            .filter(|c| is_comparable(&c.data_type))
+            .map(|c| (graphql_name(&c.name), c))
+            .collect();
+
+        if !comparable.is_empty() {
+            let filter_name = format!("{}Filter", type_name);
+            let column_enum_name = format!("{}Column", type_name);
+            let order_by_name = format!("{}OrderBy", type_name);
+            for name in [&filter_name, &column_enum_name, &order_by_name] {
+                define_type(&mut type_names, name)?;
+            }
+
+            types.push(
+                comparable
+                    .iter()
+                    .fold(InputObject::new(&filter_name), |input, (name, column)| {
+                        input.field(InputValue::new(
+                            name,
+                            TypeRef::named(format!("{}Filter", scalar_name(&column.data_type))),
+                        ))
+                    })
+                    .into(),
+            );
+            types.push(
+                Enum::new(&column_enum_name)
+                    .items(comparable.iter().map(|(name, _)| name.clone()))
+                    .into(),
+            );
+            types.push(
+                InputObject::new(&order_by_name)
+                    .field(InputValue::new(
+                        ORDER_BY_FIELD,
+                        TypeRef::named_nn(column_enum_name),
+                    ))
+                    .field(InputValue::new(
+                        ORDER_BY_DIRECTION,
+                        TypeRef::named(SORT_DIRECTION_TYPE),
+                    ))
+                    .into(),
+            );
+
+            field = field
+                .argument(InputValue::new(WHERE_ARG, TypeRef::named(filter_name)))
+                .argument(InputValue::new(
+                    ORDER_BY_ARG,
+                    TypeRef::named_nn_list(order_by_name),
+                ));
+        }
+
+        query = query.field(field);
+    }
+
+    let schema = types
+        .into_iter()
+        .fold(Schema::build(QUERY_TYPE, None, None), |builder, t| {
+            builder.register(t)
+        })
+        .register(query)
+        .data(clickhouse_pool)
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish()?;
+
+    Ok(Some(schema))
+}
+
+struct TableSchema {
+    table: Table,
+    // Columns by GraphQL field name
+    fields: HashMap<String, Column>,
+}
+
+impl TableSchema {
+    fn new(table: &Table) -> Self {
+        Self {
+            table: table.clone(),
+            fields: table
+                .columns
+                .iter()
+                .map(|c| (graphql_name(&c.name), c.clone()))
+                .collect(),
+        }
+    }
+
+    fn field(&self, name: &str) -> async_graphql::Result<&Column> {
+        self.fields
+            .get(name)
+            .ok_or_else(|| format!("Unknown field: {}", name).into())
+    }
+
+    async fn resolve<'a>(
+        &self,
+        ctx: ResolverContext<'a>,
+    ) -> async_graphql::Result<Option<FieldValue<'a>>> {
+        let query = self.compile(&ctx)?;
+        let sql = query.to_sql(&self.table);
+        debug!("GraphQL query: {}", sql);
+
+        let pool = ctx.data::<clickhouse_rs::Pool>()?;
+        let mut client = pool.get_handle().await?;
+        let rows =
+            select_as_json(&mut client, &sql, &query.selected_column_types(&self.table)).await?;
+
+        Ok(Some(FieldValue::list(
+            query
+                .decode_rows(&self.table, rows)
+                .into_iter()
+                .map(FieldValue::owned_any),
+        )))
+    }
+
+    fn compile(&self, ctx: &ResolverContext) -> async_graphql::Result<EgressQuery> {
+        // Only the selected columns are queried
+        let mut projection: Vec<String> = Vec::new();
+        for selection in ctx.ctx.field().selection_set() {
+            if let Some(column) = self.fields.get(selection.name()) {
+                if !projection.contains(&column.name) {
+                    projection.push(column.name.clone());
+                }
+            }
+        }
+        if projection.is_empty() {
+            // e.g. only `__typename` is selected
+            projection.push(self.table.columns[0].name.clone());
+        }
+
+        let mut filters: Vec<Filter> = Vec::new();
+        if let Some(where_arg) = ctx.args.get(WHERE_ARG) {
+            for (name, column_filter) in where_arg.object()?.iter() {
+                let column = self.field(name.as_str())?;
+
+                for (operator, value) in column_filter.object()?.iter() {
+                    if value.is_null() {
+                        continue;
+                    }
+
+                    let (operator, values) = match operator.as_str() {
+                        IN_OPERATOR | NOT_IN_OPERATOR => {
+                            let values = value
+                                .list()?
+                                .iter()
+                                .map(|v| literal(column, v.as_value()))
+                                .collect::<async_graphql::Result<Vec<String>>>()?;
+                            if values.is_empty() {
+                                return Err(format!(
+                                    "{} on {} requires at least one value",
+                                    operator.as_str(),
+                                    name.as_str()
+                                )
+                                .into());
+                            }
+
+                            match operator.as_str() {
+                                IN_OPERATOR => (FilterOperator::Eq, values),
+                                _ => (FilterOperator::Ne, values),
+                            }
+                        }
+                        operator => {
+                            let operator = COMPARISON_OPERATORS
+                                .iter()
+                                .find(|(name, _)| *name == operator)
+                                .map(|(_, operator)| *operator)
+                                .ok_or_else(|| format!("Unknown filter operator: {}", operator))?;
+                            (operator, vec![literal(column, value.as_value())?])
+                        }
+                    };
+
+                    filters.push(Filter {
+                        column: column.name.clone(),
+                        operator,
+                        values,
+                    });
+                }
+            }
+        }
+
+        let order_by = match ctx.args.get(ORDER_BY_ARG) {
+            Some(order_by) => order_by
+                .list()?
+                .iter()
+                .map(|item| {
+                    let item = item.object()?;
+                    let field = item.try_get(ORDER_BY_FIELD)?;
+                    let column = self.field(field.enum_name()?)?;
+
+                    let direction = match item.get(ORDER_BY_DIRECTION) {
+                        Some(direction) if !direction.is_null() => match direction.enum_name()? {
+                            DESC => SortDirection::Desc,
+                            _ => SortDirection::Asc,
+                        },
+                        _ => SortDirection::Asc,
+                    };
+                    Ok((column.name.clone(), direction))
+                })
+                .collect::<async_graphql::Result<Vec<_>>>()?,
+            // Same default as the egress APIs, the table's order by
+            None => self
+                .table
+                .order_by
+                .iter()
+                .filter_map(|name| self.table.columns.iter().find(|c| &c.name == name))
+                .filter(|c| is_comparable(&c.data_type))
+                .map(|c| (c.name.clone(), SortDirection::Asc))
+                .collect(),
+        };
+
+        let limit = match ctx.args.get(LIMIT_ARG) {
+            Some(limit) if !limit.is_null() => limit.u64()?,
+            _ => DEFAULT_LIMIT,
+        };
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err(format!("{} must be between 1 and {}", LIMIT_ARG, MAX_LIMIT).into());
+        }
+
+        let offset = match ctx.args.get(OFFSET_ARG) {
+            Some(offset) if !offset.is_null() => offset.u64()?,
+            _ => 0,
+        };
+
+        Ok(EgressQuery {
+            projection,
+            filters,
+            order_by,
+            limit,
+            pagination: Pagination::Offset(offset),
+        })
+    }
+}
+
+/// Renders a GraphQL argument as a ClickHouse literal for the column.
+fn literal(column: &Column, value: &GraphQLValue) -> async_graphql::Result<String> {
+    let raw = match value {
+        GraphQLValue::String(s) => s.clone(),
+        GraphQLValue::Number(n) => n.to_string(),
+        GraphQLValue::Boolean(b) => b.to_string(),
+        GraphQLValue::Enum(name) => name.to_string(),
+        _ => return Err(format!("Invalid value for {}: {}", column.name, value).into()),
+    };
+
+    Ok(to_literal(column, &raw)?)
+}
+
+fn define_type(type_names: &mut HashSet<String>, name: &str) -> Result<(), SchemaError> {
+    if type_names.insert(name.to_string()) {
+        Ok(())
+    } else {
+        Err(SchemaError(format!(
+            "The GraphQL type {} is defined twice, rename the tables or the nested columns whose GraphQL names collide",
+            name
+        )))
+    }
+}
+
+/// Object types for the columns of a table or a nested column, nested columns get their own
+/// types prefixed with the name of the table type.
+fn object_types(
+    type_name: &str,
+    table_type_name: &str,
+    columns: &[Column],
+    registered: &mut HashSet<String>,
+    type_names: &mut HashSet<String>,
+) -> Result<Vec<Type>, SchemaError> {
+    let mut types: Vec<Type> = Vec::new();
+    if !registered.insert(type_name.to_string()) {
+        return Ok(types);
+    }
+    define_type(type_names, type_name)?;
+
+    let mut field_names: HashSet<String> = HashSet::new();
+    let mut object = Object::new(type_name);
+    for column in columns {
+        if !field_names.insert(graphql_name(&column.name)) {
+            return Err(SchemaError(format!(
+                "Several columns of {} have the GraphQL name {}",
+                type_name,
+                graphql_name(&column.name)
+            )));
+        }
+
+        if let Some(nested) = nested_column(&column.data_type) {
+            let nested_type_name = nested_type_name(table_type_name, &nested.name);
+            types.extend(object_types(
+                &nested_type_name,
+                table_type_name,
+                &nested.columns,
+                registered,
+                type_names,
+            )?);
+        }
+
+        // Top level rows are keyed by the ClickHouse column names, nested objects by the
+        // names of their members
+        let key = if type_name == table_type_name {
+            sanitize_column_name(column.name.clone())
+        } else {
+            column.name.clone()
+        };
+        let column_type = column.data_type.clone();
+
+        object = object.field(Field::new(
+            graphql_name(&column.name),
+            output_type(table_type_name, &column.data_type, column.required),
+            move |ctx| {
+                let key = key.clone();
+                let column_type = column_type.clone();
+                FieldFuture::new(async move {
+                    let row = ctx.parent_value.try_downcast_ref::<Map<String, Value>>()?;
+                    to_field_value(row.get(&key), &column_type)
+                })
+            },
+        ));
+    }
+
+    types.push(object.into());
+    Ok(types)
+}
+
+fn nested_column(column_type: &ColumnType) -> Option<&Nested> {
+    match column_type {
+        ColumnType::Nested(nested) => Some(nested),
+        ColumnType::Array(inner) => nested_column(inner),
+        _ => None,
+    }
+}
+
+fn nested_type_name(table_type_name: &str, nested_name: &str) -> String {
+    format!("{}_{}", table_type_name, graphql_name(nested_name))
+}
+
+fn output_type(table_type_name: &str, column_type: &ColumnType, required: bool) -> TypeRef {
+    let type_ref = match column_type {
+        ColumnType::Array(inner) => {
+            TypeRef::List(Box::new(output_type(table_type_name, inner, false)))
+        }
+        ColumnType::Nested(nested) => {
+            TypeRef::named(nested_type_name(table_type_name, &nested.name))
+        }
+        _ => TypeRef::named(scalar_name(column_type)),
+    };
+
+    if required {
+        TypeRef::NonNull(Box::new(type_ref))
+    } else {
+        type_ref
+    }
+}
+
+fn scalar_name(column_type: &ColumnType) -> &'static str {
+    match column_type {
+        ColumnType::Boolean => TypeRef::BOOLEAN,
+        ColumnType::Int => INT64_SCALAR,
+        ColumnType::BigInt => BIG_INT_SCALAR,
+        ColumnType::Float => TypeRef::FLOAT,
+        ColumnType::Decimal => DECIMAL_SCALAR,
+        ColumnType::DateTime => DATE_TIME_SCALAR,
+        ColumnType::Json | ColumnType::Nested(_) | ColumnType::Array(_) => JSON_SCALAR,
+        ColumnType::String | ColumnType::Enum(_) | ColumnType::Bytes => TypeRef::STRING,
+    }
+}
+
+fn filter_input(scalar: &str) -> InputObject {
+    let operators: Vec<&str> = match scalar {
+        TypeRef::BOOLEAN => vec!["eq", "ne"],
+        _ => COMPARISON_OPERATORS.iter().map(|(name, _)| *name).collect(),
+    };
+
+    operators
+        .into_iter()
+        .fold(
+            InputObject::new(format!("{}Filter", scalar)),
+            |input, operator| input.field(InputValue::new(operator, TypeRef::named(scalar))),
+        )
+        .field(InputValue::new(IN_OPERATOR, TypeRef::named_nn_list(scalar)))
+        .field(InputValue::new(
+            NOT_IN_OPERATOR,
+            TypeRef::named_nn_list(scalar),
+        ))
+}
+
+fn to_field_value<'a>(
+    value: Option<&Value>,
+    column_type: &ColumnType,
+) -> async_graphql::Result<Option<FieldValue<'a>>> {
+    let value = match value {
+        None | Some(Value::Null) => return Ok(None),
+        Some(value) => value,
+    };
+
+    match column_type {
+        ColumnType::Nested(_) => {
+            // Nested columns are stored as arrays of tuples while the data model holds one object
+            let object = match value {
+                Value::Array(values) => values.first(),
+                value => Some(value),
+            };
+            Ok(match object {
+                Some(Value::Object(object)) => Some(FieldValue::owned_any(object.clone())),
+                _ => None,
+            })
+        }
+        ColumnType::Array(inner) => match value {
+            Value::Array(values) => {
+                let values = values
+                    .iter()
+                    .map(|v| Ok(to_field_value(Some(v), inner)?.unwrap_or(FieldValue::NULL)))
+                    .collect::<async_graphql::Result<Vec<FieldValue>>>()?;
+                Ok(Some(FieldValue::list(values)))
+            }
+            _ => Err(format!("Expected a list, got {}", value).into()),
+        },
+        // Enums with int values are returned as numbers, they are exposed with the member names
+        ColumnType::Enum(data_enum) => Ok(Some(FieldValue::value(
+            data_enum
+                .values
+                .iter()
+                .find(|member| match (&member.value, value) {
+                    (EnumValue::Int(i), Value::Number(n)) => n.as_u64() == Some(*i as u64),
+                    _ => false,
+                })
+                .map(|member| member.name.clone())
+                .unwrap_or_else(|| match value {
+                    Value::String(s) => s.clone(),
+                    value => value.to_string(),
+                }),
+        ))),
+        ColumnType::Int | ColumnType::Decimal => Ok(Some(FieldValue::value(match value {
+            Value::Number(n) => GraphQLValue::String(n.to_string()),
+            value => GraphQLValue::from_json(value.clone())?,
+        }))),
+        _ => Ok(Some(FieldValue::value(GraphQLValue::from_json(
+            value.clone(),
+        )?))),
+    }
+}
+
+/// GraphQL names only allow `[_A-Za-z][_0-9A-Za-z]*`
+fn graphql_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+
+    match name.chars().next() {
+        Some(c) if c.is_ascii_digit() => format!("_{}", name),
+        _ => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framework::core::infrastructure_map::fixtures::{column, table};
+
+    fn user_activity() -> Table {
+        table(
+            "UserActivity",
+            vec![
+                column("eventId", ColumnType::String, true),
+                column("timestamp", ColumnType::DateTime, true),
+                column("amount", ColumnType::Decimal, true),
+                column(
+                    "location",
+                    ColumnType::Nested(Nested {
+                        name: "Location".to_string(),
+                        columns: vec![column("city", ColumnType::String, true)],
+                    }),
+                    true,
+                ),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_graphql_name() {
+        assert_eq!(graphql_name("UserActivity_0_0"), "UserActivity_0_0");
+        assert_eq!(graphql_name("user id"), "user_id");
+        assert_eq!(graphql_name("1st-place"), "_1st_place");
+    }
+
+    #[test]
+    fn test_schema_from_tables() {
+        let pool = clickhouse_rs::Pool::new("tcp://localhost:9000");
+        let schema = build_schema(&[user_activity()], pool).unwrap().unwrap();
+        let sdl = schema.sdl();
+
+        assert!(sdl.contains("type UserActivity_0_0 {"));
+        assert!(sdl.contains("type UserActivity_0_0_Location {"));
+        assert!(sdl.contains("input UserActivity_0_0Filter {"));
+        assert!(sdl.contains("enum UserActivity_0_0Column {"));
+        assert!(sdl.contains("input DateTimeFilter {"));
+        assert!(sdl.contains("scalar Int64"));
+        assert!(sdl.contains("amount: Decimal!"));
+        assert!(sdl.contains("input DecimalFilter {"));
+    }
+
+    #[test]
+    fn test_decimals_are_returned_as_strings() {
+        let value = to_field_value(Some(&serde_json::json!(10.25)), &ColumnType::Decimal)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            value.as_value(),
+            Some(&GraphQLValue::String("10.25".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_colliding_names_are_rejected() {
+        let pool = clickhouse_rs::Pool::new("tcp://localhost:9000");
+
+        let mut columns = user_activity();
+        columns
+            .columns
+            .push(column("event id", ColumnType::String, true));
+        columns
+            .columns
+            .push(column("event_id", ColumnType::String, true));
+        assert!(build_schema(&[columns], pool.clone()).is_err());
+
+        let mut nested = user_activity();
+        nested.name = "UserActivity_0_0_Location".to_string();
+        assert!(build_schema(&[user_activity(), nested], pool).is_err());
+    }
+
+    #[test]
+    fn test_no_schema_without_tables() {
+        let pool = clickhouse_rs::Pool::new("tcp://localhost:9000");
+        assert!(build_schema(&[], pool).unwrap().is_none());
+    }
+}
@@ -1,8 +1,12 @@
 use crate::infrastructure::olap::clickhouse::client::ClickHouseClient;
 use crate::infrastructure::olap::clickhouse::model::ClickHouseRecord;
-use std::time::Duration;
+use crate::metrics::{Metrics, MetricsMessage};
+use crate::utilities::constants::TRACER_NAME;
+use std::time::{Duration, Instant};
 
 use log::{debug, error};
+use opentelemetry::trace::{Span, SpanContext, SpanKind, Status, TraceContextExt, Tracer};
+use opentelemetry::{global, Context, KeyValue};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use tokio::time;
@@ -11,8 +15,17 @@ use super::config::ClickHouseConfig;
 
 const MAX_FLUSH_INTERVAL_SECONDS: u64 = 1;
 const MAX_BATCH_SIZE: usize = 100000;
+// Bounds the number of spans emitted for a single insert
+const MAX_TRACES_PER_BATCH: usize = 128;
 
-pub type BatchRecords = Arc<Mutex<Vec<ClickHouseRecord>>>;
+#[derive(Default)]
+pub struct Batch {
+    records: Vec<ClickHouseRecord>,
+    // Distinct traces of the records, each of them gets a span for the insert
+    traces: Vec<SpanContext>,
+}
+
+pub type BatchRecords = Arc<Mutex<Batch>>;
 
 pub struct Inserter {
     buffer: BatchRecords,
@@ -20,23 +33,38 @@ pub struct Inserter {
 
 // TODO Add at least once delivery guarantees
 impl Inserter {
-    pub fn new(clickhouse_config: ClickHouseConfig, table: &str, columns: Vec<String>) -> Self {
-        let buffer = Arc::new(Mutex::new(Vec::new()));
+    pub fn new(
+        clickhouse_config: ClickHouseConfig,
+        table: &str,
+        columns: Vec<String>,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        let buffer = Arc::new(Mutex::new(Batch::default()));
 
         tokio::spawn(flush(
             clickhouse_config,
             buffer.clone(),
             table.to_string(),
             columns,
+            metrics,
         ));
 
         Self { buffer }
     }
 
-    pub async fn insert(&self, record: ClickHouseRecord) -> anyhow::Result<()> {
-        let mut buffer: tokio::sync::MutexGuard<'_, Vec<ClickHouseRecord>> =
-            self.buffer.lock().await;
-        buffer.push(record);
+    pub async fn insert(
+        &self,
+        record: ClickHouseRecord,
+        span_context: SpanContext,
+    ) -> anyhow::Result<()> {
+        let mut buffer: tokio::sync::MutexGuard<'_, Batch> = self.buffer.lock().await;
+        buffer.records.push(record);
+        if span_context.is_valid()
+            && buffer.traces.len() < MAX_TRACES_PER_BATCH
+            && !buffer.traces.contains(&span_context)
+        {
+            buffer.traces.push(span_context);
+        }
         Ok(())
     }
 }
@@ -46,34 +74,83 @@ async fn flush(
     buffer: BatchRecords,
     table: String,
     columns: Vec<String>,
+    metrics: Arc<Metrics>,
 ) {
     let mut interval = time::interval(Duration::from_secs(MAX_FLUSH_INTERVAL_SECONDS));
 
     let client = ClickHouseClient::new(&clickhouse_config).unwrap();
+    let tracer = global::tracer(TRACER_NAME);
 
     loop {
         interval.tick().await;
-        let mut buffer_owned = buffer.lock().await;
-        if buffer_owned.is_empty() {
-            drop(buffer_owned);
+        // The batch is taken out of the buffer so that the records keep being buffered, and the
+        // metrics sent, without holding the lock
+        let mut batch = std::mem::take(&mut *buffer.lock().await);
+        metrics
+            .send_metric(MetricsMessage::InserterBufferDepth {
+                table: table.clone(),
+                depth: batch.records.len() as i64,
+            })
+            .await;
+        if batch.records.is_empty() {
             continue;
         }
 
+        // The records of a batch come from many traces, the insert shows up in each of them
+        let attributes = vec![
+            KeyValue::new("db.system", "clickhouse"),
+            KeyValue::new("db.sql.table", table.clone()),
+            KeyValue::new("db.record_count", batch.records.len() as i64),
+        ];
+        let mut spans: Vec<_> = batch
+            .traces
+            .drain(..)
+            .map(|span_context| {
+                tracer
+                    .span_builder("clickhouse.insert")
+                    .with_kind(SpanKind::Client)
+                    .with_attributes(attributes.clone())
+                    .start_with_context(
+                        &tracer,
+                        &Context::new().with_remote_span_context(span_context),
+                    )
+            })
+            .collect();
+
+        let flush_start = Instant::now();
         // in case the buffer is too big, we slice it and then we flush it
-        for chunk in buffer_owned.chunks(MAX_BATCH_SIZE) {
+        for chunk in batch.records.chunks(MAX_BATCH_SIZE) {
             match client.insert(&table, &columns, chunk).await {
                 Ok(_) => {
                     debug!("Inserted {} records", chunk.len());
+                    metrics
+                        .send_metric(MetricsMessage::InsertedRows {
+                            table: table.clone(),
+                            rows: chunk.len() as u64,
+                        })
+                        .await;
                 }
                 Err(e) => {
+                    metrics
+                        .send_metric(MetricsMessage::FailedRows {
+                            table: table.clone(),
+                            rows: chunk.len() as u64,
+                        })
+                        .await;
                     error!("Error inserting records to {}: {:?}", table, e);
                     debug!("Failed batch {:?}", chunk);
+                    spans
+                        .iter_mut()
+                        .for_each(|span| span.set_status(Status::error(e.to_string())));
                 }
             }
         }
-
-        buffer_owned.clear();
-
-        drop(buffer_owned);
+        spans.iter_mut().for_each(|span| span.end());
+        metrics
+            .send_metric(MetricsMessage::InserterFlushDuration {
+                table: table.clone(),
+                duration: flush_start.elapsed(),
+            })
+            .await;
     }
 }
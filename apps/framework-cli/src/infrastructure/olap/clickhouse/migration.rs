@@ -0,0 +1,313 @@
+//! Migrations of the tables whose schema changed.
+//!
+//! Most column changes are applied in place with `ALTER TABLE` statements. The changes Clickhouse
+//! can't apply in place, such as a change of the sorting key or a type that isn't a widening of
+//! the previous one, are applied by copying the data to a new table that replaces the current one.
+
+use crate::framework::core::infrastructure::table::{ColumnChange, Table, TableDiff};
+
+use super::errors::ClickhouseError;
+use super::mapper::{std_column_to_clickhouse_column, std_table_to_clickhouse_table};
+use super::model::sanitize_column_name;
+use super::queries::{
+    add_column_query, copy_table_data_query, create_table_as_query, create_table_query,
+    drop_column_query, drop_table_query, exchange_tables_query, insert_all_query,
+    modify_column_query, rename_column_query, ClickhouseEngine,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TableMigration {
+    // The queries altering the table in place
+    Alter(Vec<String>),
+    // The changes require the data to be copied to a new table
+    Copy,
+}
+
+pub fn plan_table_migration(
+    db_name: &str,
+    before: &Table,
+    diff: &TableDiff,
+) -> Result<TableMigration, ClickhouseError> {
+    if diff.order_by_change.is_some() {
+        return Ok(TableMigration::Copy);
+    }
+
+    // Clickhouse doesn't allow the columns of the sorting key to be altered
+    let is_key_column = |name: &str| {
+        before.order_by.iter().any(|key| key == name)
+            || before
+                .columns
+                .iter()
+                .any(|column| column.name == name && column.primary_key)
+    };
+
+    let mut queries = Vec::new();
+    for change in diff.column_changes.iter() {
+        match change {
+            ColumnChange::Added {
+                column,
+                after_column,
+            } => {
+                if column.primary_key {
+                    return Ok(TableMigration::Copy);
+                }
+                queries.push(add_column_query(
+                    db_name,
+                    &before.name,
+                    &std_column_to_clickhouse_column(column.clone())?,
+                    after_column.clone().map(sanitize_column_name).as_deref(),
+                )?);
+            }
+            ColumnChange::Removed(column) => {
+                if is_key_column(&column.name) {
+                    return Ok(TableMigration::Copy);
+                }
+                queries.push(drop_column_query(
+                    db_name,
+                    &before.name,
+                    &sanitize_column_name(column.name.clone()),
+                )?);
+            }
+            ColumnChange::Renamed {
+                before: before_column,
+                after,
+            } => {
+                if is_key_column(&before_column.name) || !before_column.is_widened_to(after) {
+                    return Ok(TableMigration::Copy);
+                }
+                queries.push(rename_column_query(
+                    db_name,
+                    &before.name,
+                    &sanitize_column_name(before_column.name.clone()),
+                    &sanitize_column_name(after.name.clone()),
+                )?);
+                if before_column.data_type != after.data_type
+                    || before_column.required != after.required
+                {
+                    queries.push(modify_column_query(
+                        db_name,
+                        &before.name,
+                        &std_column_to_clickhouse_column(after.clone())?,
+                    )?);
+                }
+            }
+            ColumnChange::Updated {
+                before: before_column,
+                after,
+            } => {
+                if is_key_column(&before_column.name) || !before_column.is_widened_to(after) {
+                    return Ok(TableMigration::Copy);
+                }
+                queries.push(modify_column_query(
+                    db_name,
+                    &before.name,
+                    &std_column_to_clickhouse_column(after.clone())?,
+                )?);
+            }
+        }
+    }
+
+    Ok(TableMigration::Alter(queries))
+}
+
+/**
+ * Creates a table with the new schema and the engine of the current one next to it, swaps them
+ * and copies the data of the columns the two tables have in common back to the table. The table
+ * is swapped before the copy for the rows inserted during the copy to land in the new table. The
+ * values are converted to the new types by Clickhouse, the columns that didn't exist get their
+ * default value.
+ */
+pub fn copy_migration_queries(
+    db_name: &str,
+    before: &Table,
+    after: &Table,
+    diff: &TableDiff,
+    engine: ClickhouseEngine,
+) -> Result<Vec<String>, ClickhouseError> {
+    let mut migration_table = std_table_to_clickhouse_table(after)?;
+    migration_table.name = format!("{}_migration", after.name);
+    let migration_table_name = migration_table.name.clone();
+
+    let (from_fields, to_fields): (Vec<String>, Vec<String>) = after
+        .columns
+        .iter()
+        .filter_map(|column| {
+            let source_name = diff
+                .column_changes
+                .iter()
+                .find_map(|change| match change {
+                    ColumnChange::Renamed {
+                        before: before_column,
+                        after: after_column,
+                    } if after_column.name == column.name => Some(before_column.name.clone()),
+                    _ => None,
+                })
+                .unwrap_or_else(|| column.name.clone());
+
+            before
+                .columns
+                .iter()
+                .any(|before_column| before_column.name == source_name)
+                .then(|| {
+                    (
+                        sanitize_column_name(source_name),
+                        sanitize_column_name(column.name.clone()),
+                    )
+                })
+        })
+        .unzip();
+
+    let mut queries = vec![
+        // leftover of a migration that failed before the exchange, it is empty
+        drop_table_query(db_name, migration_table.clone())?,
+        create_table_query(db_name, migration_table.clone(), engine)?,
+        exchange_tables_query(db_name, &before.name, &migration_table_name)?,
+    ];
+    // after the exchange, the migration table holds the previous data
+    if !from_fields.is_empty() {
+        queries.push(copy_table_data_query(
+            db_name,
+            &migration_table_name,
+            &before.name,
+            &from_fields,
+            &to_fields,
+        )?);
+    }
+    queries.push(drop_table_query(db_name, migration_table)?);
+
+    Ok(queries)
+}
+
+/**
+ * Copies the table to a new table named after the table and the current time, before its data
+ * is dropped. Returns the name of the backup table along with the queries.
+ */
+pub fn backup_table_queries(
+    db_name: &str,
+    table_name: &str,
+) -> Result<(String, Vec<String>), ClickhouseError> {
+    let backup_table_name = format!(
+        "{}_backup_{}",
+        table_name,
+        chrono::Utc::now().format("%Y%m%d%H%M%S")
+    );
+
+    let queries = vec![
+        create_table_as_query(db_name, &backup_table_name, table_name)?,
+        insert_all_query(db_name, table_name, &backup_table_name)?,
+    ];
+
+    Ok((backup_table_name, queries))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::framework::core::infrastructure::table::ColumnType;
+    use crate::framework::core::infrastructure_map::fixtures::{column, table};
+
+    use super::*;
+
+    #[test]
+    fn test_alter_migration() {
+        let before = table(
+            "User",
+            vec![
+                column("id", ColumnType::String, true),
+                column("name", ColumnType::String, true),
+                column("age", ColumnType::Int, true),
+                column("legacy", ColumnType::String, false),
+            ],
+        );
+        let mut after = table(
+            "User",
+            vec![
+                column("id", ColumnType::String, true),
+                column("full_name", ColumnType::String, true),
+                column("age", ColumnType::Int, false),
+                column("email", ColumnType::String, false),
+            ],
+        );
+        after
+            .renamed_columns
+            .insert("full_name".to_string(), "name".to_string());
+
+        let diff = before.diff(&after);
+        assert_eq!(
+            diff.column_changes,
+            vec![
+                ColumnChange::Removed(before.columns[3].clone()),
+                ColumnChange::Renamed {
+                    before: before.columns[1].clone(),
+                    after: after.columns[1].clone(),
+                },
+                ColumnChange::Updated {
+                    before: before.columns[2].clone(),
+                    after: after.columns[2].clone(),
+                },
+                ColumnChange::Added {
+                    column: after.columns[3].clone(),
+                    after_column: Some("age".to_string()),
+                },
+            ]
+        );
+
+        let queries = match plan_table_migration("local", &before, &diff).unwrap() {
+            TableMigration::Alter(queries) => queries,
+            TableMigration::Copy => panic!("Expected an in place migration"),
+        };
+        let queries: Vec<&str> = queries.iter().map(|query| query.trim()).collect();
+        assert_eq!(
+            queries,
+            vec![
+                "ALTER TABLE local.User_0_0 DROP COLUMN IF EXISTS legacy",
+                "ALTER TABLE local.User_0_0 RENAME COLUMN IF EXISTS name TO full_name",
+                "ALTER TABLE local.User_0_0 MODIFY COLUMN IF EXISTS age Int64 NULL",
+                "ALTER TABLE local.User_0_0 ADD COLUMN IF NOT EXISTS email String NULL AFTER age",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_copy_migration() {
+        let before = table(
+            "User",
+            vec![
+                column("id", ColumnType::String, true),
+                column("age", ColumnType::String, true),
+            ],
+        );
+        let mut after = table(
+            "User",
+            vec![
+                column("id", ColumnType::String, true),
+                column("age", ColumnType::Int, true),
+            ],
+        );
+        after.order_by.push("age".to_string());
+
+        let diff = before.diff(&after);
+        assert_eq!(
+            plan_table_migration("local", &before, &diff).unwrap(),
+            TableMigration::Copy
+        );
+
+        let queries = copy_migration_queries(
+            "local",
+            &before,
+            &after,
+            &diff,
+            ClickhouseEngine::Existing("ReplacingMergeTree".to_string()),
+        )
+        .unwrap();
+        assert_eq!(queries.len(), 5);
+        assert!(queries[1].contains("ENGINE = ReplacingMergeTree"));
+        assert_eq!(
+            queries[2].trim(),
+            "EXCHANGE TABLES local.User_0_0 AND local.User_0_0_migration"
+        );
+        assert_eq!(
+            queries[3].trim(),
+            "INSERT INTO local.User_0_0 (id, age)\nSELECT id, age\nFROM local.User_0_0_migration"
+        );
+    }
+}
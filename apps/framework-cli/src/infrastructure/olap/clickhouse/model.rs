@@ -101,6 +101,35 @@ impl ClickHouseColumn {
     pub fn is_array(&self) -> bool {
         matches!(&self.column_type, ClickHouseColumnType::Array(_))
     }
+
+    /**
+     * The type of the column as listed in `system.columns`, None when Clickhouse lists it
+     * differently from how it was declared, such as enums or decimals, which get their
+     * parameters expanded.
+     */
+    pub fn system_type(&self) -> Option<String> {
+        fn base_type(column_type: &ClickHouseColumnType) -> Option<String> {
+            match column_type {
+                ClickHouseColumnType::String => Some("String".to_string()),
+                ClickHouseColumnType::Boolean => Some("Bool".to_string()),
+                ClickHouseColumnType::ClickhouseInt(int) => Some(int.to_string()),
+                ClickHouseColumnType::ClickhouseFloat(float) => Some(float.to_string()),
+                ClickHouseColumnType::DateTime => Some("DateTime('UTC')".to_string()),
+                ClickHouseColumnType::Array(inner) => {
+                    base_type(inner).map(|inner| format!("Array({})", inner))
+                }
+                _ => None,
+            }
+        }
+
+        let column_type = base_type(&self.column_type)?;
+        // Clickhouse doesn't allow array fields to be nullable
+        if self.required || self.is_array() {
+            Some(column_type)
+        } else {
+            Some(format!("Nullable({})", column_type))
+        }
+    }
 }
 
 pub enum ClickHouseRuntimeEnum {
@@ -63,6 +63,30 @@ ENGINE = {{engine}}
 
 pub enum ClickhouseEngine {
     MergeTree,
+    // The engine of an existing table, with its arguments, see `engine_of_engine_full`
+    Existing(String),
+}
+
+/// The engine with its arguments at the start of the `engine_full` of a table in `system.tables`,
+/// e.g. `ReplacingMergeTree(version)` out of `ReplacingMergeTree(version) ORDER BY id SETTINGS ..`.
+pub fn engine_of_engine_full(engine_full: &str) -> String {
+    let mut depth = 0;
+    let mut quoted = false;
+    for (i, c) in engine_full.char_indices() {
+        match c {
+            '\'' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => {
+                depth -= 1;
+                if depth == 0 {
+                    return engine_full[..=i].to_string();
+                }
+            }
+            ' ' if !quoted && depth == 0 => return engine_full[..i].to_string(),
+            _ => {}
+        }
+    }
+    engine_full.to_string()
 }
 
 pub fn create_table_query(
@@ -74,6 +98,7 @@ pub fn create_table_query(
 
     let (engine, ignore_primary_key) = match engine {
         ClickhouseEngine::MergeTree => ("MergeTree".to_string(), false),
+        ClickhouseEngine::Existing(engine) => (engine, false),
     };
 
     let primary_key = if ignore_primary_key {
@@ -184,6 +209,174 @@ pub fn drop_table_query(db_name: &str, table: ClickHouseTable) -> Result<String,
     Ok(reg.render_template(DROP_TABLE_TEMPLATE, &context)?)
 }
 
+static ADD_COLUMN_TEMPLATE: &str = r#"
+ALTER TABLE {{db_name}}.{{table_name}} ADD COLUMN IF NOT EXISTS {{field_name}} {{{field_type}}} {{field_nullable}} {{#if after_column}}AFTER {{after_column}}{{else}}FIRST{{/if}}
+"#;
+
+pub fn add_column_query(
+    db_name: &str,
+    table_name: &str,
+    column: &ClickHouseColumn,
+    after_column: Option<&str>,
+) -> Result<String, ClickhouseError> {
+    let reg = Handlebars::new();
+
+    let mut context = field_context(column)?;
+    context["db_name"] = json!(db_name);
+    context["table_name"] = json!(table_name);
+    context["after_column"] = json!(after_column);
+
+    Ok(reg.render_template(ADD_COLUMN_TEMPLATE, &context)?)
+}
+
+static DROP_COLUMN_TEMPLATE: &str = r#"
+ALTER TABLE {{db_name}}.{{table_name}} DROP COLUMN IF EXISTS {{column_name}}
+"#;
+
+pub fn drop_column_query(
+    db_name: &str,
+    table_name: &str,
+    column_name: &str,
+) -> Result<String, ClickhouseError> {
+    let reg = Handlebars::new();
+
+    let context = json!({
+        "db_name": db_name,
+        "table_name": table_name,
+        "column_name": column_name,
+    });
+
+    Ok(reg.render_template(DROP_COLUMN_TEMPLATE, &context)?)
+}
+
+static RENAME_COLUMN_TEMPLATE: &str = r#"
+ALTER TABLE {{db_name}}.{{table_name}} RENAME COLUMN IF EXISTS {{column_name}} TO {{new_column_name}}
+"#;
+
+pub fn rename_column_query(
+    db_name: &str,
+    table_name: &str,
+    column_name: &str,
+    new_column_name: &str,
+) -> Result<String, ClickhouseError> {
+    let reg = Handlebars::new();
+
+    let context = json!({
+        "db_name": db_name,
+        "table_name": table_name,
+        "column_name": column_name,
+        "new_column_name": new_column_name,
+    });
+
+    Ok(reg.render_template(RENAME_COLUMN_TEMPLATE, &context)?)
+}
+
+static MODIFY_COLUMN_TEMPLATE: &str = r#"
+ALTER TABLE {{db_name}}.{{table_name}} MODIFY COLUMN IF EXISTS {{field_name}} {{{field_type}}} {{field_nullable}}
+"#;
+
+pub fn modify_column_query(
+    db_name: &str,
+    table_name: &str,
+    column: &ClickHouseColumn,
+) -> Result<String, ClickhouseError> {
+    let reg = Handlebars::new();
+
+    let mut context = field_context(column)?;
+    context["db_name"] = json!(db_name);
+    context["table_name"] = json!(table_name);
+
+    Ok(reg.render_template(MODIFY_COLUMN_TEMPLATE, &context)?)
+}
+
+static COPY_TABLE_DATA_TEMPLATE: &str = r#"
+INSERT INTO {{db_name}}.{{dest_table_name}} ({{#each to_fields}}{{this}}{{#unless @last}}, {{/unless}}{{/each}})
+SELECT {{#each from_fields}}{{this}}{{#unless @last}}, {{/unless}}{{/each}}
+FROM {{db_name}}.{{source_table_name}}
+"#;
+
+// Copies the values of the `from_fields` of the source table to the `to_fields` of the destination
+pub fn copy_table_data_query(
+    db_name: &str,
+    source_table_name: &str,
+    dest_table_name: &str,
+    from_fields: &[String],
+    to_fields: &[String],
+) -> Result<String, ClickhouseError> {
+    let reg = Handlebars::new();
+
+    let context = json!({
+        "db_name": db_name,
+        "source_table_name": source_table_name,
+        "dest_table_name": dest_table_name,
+        "from_fields": from_fields,
+        "to_fields": to_fields,
+    });
+
+    Ok(reg.render_template(COPY_TABLE_DATA_TEMPLATE, &context)?)
+}
+
+static EXCHANGE_TABLES_TEMPLATE: &str = r#"
+EXCHANGE TABLES {{db_name}}.{{table_name}} AND {{db_name}}.{{other_table_name}}
+"#;
+
+pub fn exchange_tables_query(
+    db_name: &str,
+    table_name: &str,
+    other_table_name: &str,
+) -> Result<String, ClickhouseError> {
+    let reg = Handlebars::new();
+
+    let context = json!({
+        "db_name": db_name,
+        "table_name": table_name,
+        "other_table_name": other_table_name,
+    });
+
+    Ok(reg.render_template(EXCHANGE_TABLES_TEMPLATE, &context)?)
+}
+
+static CREATE_TABLE_AS_TEMPLATE: &str = r#"
+CREATE TABLE IF NOT EXISTS {{db_name}}.{{table_name}} AS {{db_name}}.{{source_table_name}}
+"#;
+
+// Creates an empty table with the same schema and engine as the source table
+pub fn create_table_as_query(
+    db_name: &str,
+    table_name: &str,
+    source_table_name: &str,
+) -> Result<String, ClickhouseError> {
+    let reg = Handlebars::new();
+
+    let context = json!({
+        "db_name": db_name,
+        "table_name": table_name,
+        "source_table_name": source_table_name,
+    });
+
+    Ok(reg.render_template(CREATE_TABLE_AS_TEMPLATE, &context)?)
+}
+
+static INSERT_ALL_TEMPLATE: &str = r#"
+INSERT INTO {{db_name}}.{{dest_table_name}} SELECT * FROM {{db_name}}.{{source_table_name}}
+"#;
+
+pub fn insert_all_query(
+    db_name: &str,
+    source_table_name: &str,
+    dest_table_name: &str,
+) -> Result<String, ClickhouseError> {
+    let reg = Handlebars::new();
+
+    let context = json!({
+        "db_name": db_name,
+        "source_table_name": source_table_name,
+        "dest_table_name": dest_table_name,
+    });
+
+    Ok(reg.render_template(INSERT_ALL_TEMPLATE, &context)?)
+}
+
 fn basic_field_type_to_string(
     field_type: &ClickHouseColumnType,
 ) -> Result<String, ClickhouseError> {
@@ -252,23 +445,25 @@ fn basic_field_type_to_string(
     }
 }
 
+fn field_context(column: &ClickHouseColumn) -> Result<Value, ClickhouseError> {
+    let field_type = basic_field_type_to_string(&column.column_type)?;
+
+    Ok(json!({
+        "field_name": column.name,
+        "field_type": field_type,
+        // Clickhouse doesn't allow array fields to be nullable
+        "field_nullable": if column.required || column.is_array() {
+            "NOT NULL".to_string()
+        } else {
+            "NULL".to_string()
+        },
+    }))
+}
+
 fn builds_field_context(columns: &[ClickHouseColumn]) -> Result<Vec<Value>, ClickhouseError> {
     columns
         .iter()
-        .map(|column| {
-            let field_type = basic_field_type_to_string(&column.column_type)?;
-
-            Ok(json!({
-                "field_name": column.name,
-                "field_type": field_type,
-                // Clickhouse doesn't allow array fields to be nullable
-                "field_nullable": if column.required || column.is_array() {
-                    "NOT NULL".to_string()
-                } else {
-                    "NULL".to_string()
-                },
-            }))
-        })
+        .map(field_context)
         .collect::<Result<Vec<Value>, ClickhouseError>>()
 }
 
@@ -281,6 +476,20 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_engine_of_engine_full() {
+        assert_eq!(
+            engine_of_engine_full(
+                "MergeTree PRIMARY KEY id ORDER BY id SETTINGS index_granularity = 8192"
+            ),
+            "MergeTree"
+        );
+        assert_eq!(
+            engine_of_engine_full("ReplicatedReplacingMergeTree('/tables/{shard}/t (1)', '{replica}', version) ORDER BY id"),
+            "ReplicatedReplacingMergeTree('/tables/{shard}/t (1)', '{replica}', version)"
+        );
+    }
+
     #[test]
     fn test_nested_query_generator() {
         let complete_nest_type = ClickHouseColumnType::Nested(vec![
@@ -13,13 +13,14 @@ use futures::stream::BoxStream;
 use futures::StreamExt;
 use itertools::Either;
 use log::{info, warn};
-use serde::__private::from_utf8_lossy;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Map, Value};
 
 use crate::framework::core::code_loader::FrameworkObjectVersions;
+use crate::framework::core::history::InfraMapRecord;
 use crate::framework::core::infrastructure::table::EnumValue;
 use crate::framework::core::infrastructure_map::InfrastructureMap;
+use crate::framework::core::journal::JournalEntry;
 use crate::framework::data_model::model::DataModel;
 use crate::infrastructure::olap::clickhouse::config::ClickHouseConfig;
 use crate::infrastructure::olap::clickhouse::model::{ClickHouseColumnType, ClickHouseTable};
@@ -70,7 +71,7 @@ fn value_to_json(
         ValueRef::Int64(v) => json!(v),
         // TODO: base64 encode if type is Bytes (probably Uint8Array in TS)
         // In clickhouse the String type means arbitrary bytes
-        ValueRef::String(v) => json!(from_utf8_lossy(v)),
+        ValueRef::String(v) => json!(String::from_utf8_lossy(v)),
         ValueRef::Float32(v) => json!(v),
         ValueRef::Float64(v) => json!(v),
         ValueRef::Date(v) => {
@@ -233,6 +234,25 @@ pub async fn select_all_as_json<'a>(
     Ok(Box::pin(stream))
 }
 
+/// Runs the query and converts every returned row to a JSON object.
+/// `column_types` are the types of the selected columns, in the select order.
+pub async fn select_as_json(
+    client: &mut ClientHandle,
+    query: &str,
+    column_types: &[ClickHouseColumnType],
+) -> Result<Vec<Value>, clickhouse_rs::errors::Error> {
+    let enum_mapping: Vec<Option<Vec<&str>>> = column_types
+        .iter()
+        .map(column_type_to_enum_mapping)
+        .collect();
+
+    let block = client.query(query).fetch_all().await?;
+    block
+        .rows()
+        .map(|row| row_to_json(&row, &enum_mapping))
+        .collect()
+}
+
 async fn create_state_table(
     client: &mut ClientHandle,
     click_house_config: &ClickHouseConfig,
@@ -251,33 +271,19 @@ ORDER BY timestamp"#,
 
 pub async fn store_current_state(
     client: &mut ClientHandle,
-    framework_object_versions: &FrameworkObjectVersions,
-    aggregations: &HashSet<String>,
+    application_state: &ApplicationState,
     click_house_config: &ClickHouseConfig,
 ) -> Result<(), StateStorageError> {
     create_state_table(client, click_house_config).await?;
 
-    let data = clickhouse_rs::Block::new().column(
-        "state",
-        vec![serde_json::to_string(&ApplicationState::from((
-            framework_object_versions,
-            aggregations,
-        )))?],
-    );
+    let data = clickhouse_rs::Block::new()
+        .column("state", vec![serde_json::to_string(application_state)?]);
     client
         .insert(format!("{}._MOOSE_STATE", click_house_config.db_name), data)
         .await?;
     Ok(())
 }
 
-pub async fn get_state(
-    click_house_config: &ClickHouseConfig,
-) -> Result<Option<ApplicationState>, StateStorageError> {
-    let pool = get_pool(click_house_config);
-    let mut client = pool.get_handle().await?;
-    retrieve_current_state(&mut client, click_house_config).await
-}
-
 pub async fn retrieve_current_state(
     client: &mut ClientHandle,
     click_house_config: &ClickHouseConfig,
@@ -309,6 +315,12 @@ pub enum StateStorageError {
     SerdeError(#[from] serde_json::Error),
     #[error("Clickhouse error")]
     ClickhouseError(#[from] clickhouse_rs::errors::Error),
+    #[error("Failed to read or write the state file")]
+    IoError(#[from] std::io::Error),
+    #[error("Redpanda error")]
+    RedpandaError(#[from] rdkafka::error::KafkaError),
+    #[error("Failed to create the state topic")]
+    Other(#[from] anyhow::Error),
 }
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ApplicationState {
@@ -354,6 +366,8 @@ pub async fn store_infrastructure_map(
     clickhouse_config: &ClickHouseConfig,
     infrastructure_map: &InfrastructureMap,
 ) -> Result<(), StateStorageError> {
+    create_infrastructure_map_table(client, clickhouse_config).await?;
+
     let data = clickhouse_rs::Block::new().column(
         "infra_map",
         vec![serde_json::to_string(infrastructure_map)?],
@@ -408,3 +422,225 @@ pub async fn retrieve_infrastructure_map(
         len => panic!("LIMIT 1 but got {} rows: {:?}", len, inframap_string),
     }
 }
+
+async fn create_infra_map_history_table(
+    client: &mut ClientHandle,
+    click_house_config: &ClickHouseConfig,
+) -> Result<(), clickhouse_rs::errors::Error> {
+    let sql = format!(
+        r#"CREATE TABLE IF NOT EXISTS {}._MOOSE_STATE_HISTORY (
+            timestamp DateTime64(6, 'UTC') DEFAULT now64(6),
+            id String,
+            record String
+        ) ENGINE = MergeTree
+        ORDER BY timestamp"#,
+        click_house_config.db_name
+    );
+    client.execute(sql).await
+}
+
+pub async fn insert_infra_map_record(
+    client: &mut ClientHandle,
+    click_house_config: &ClickHouseConfig,
+    record: &InfraMapRecord,
+) -> Result<(), StateStorageError> {
+    create_infra_map_history_table(client, click_house_config).await?;
+
+    let data = clickhouse_rs::Block::new()
+        .column("id", vec![record.id.clone()])
+        .column("record", vec![serde_json::to_string(record)?]);
+    client
+        .insert(
+            format!("{}._MOOSE_STATE_HISTORY", click_house_config.db_name),
+            data,
+        )
+        .await?;
+    Ok(())
+}
+
+// The records of the stored infrastructure maps, oldest first
+pub async fn retrieve_infra_map_records(
+    client: &mut ClientHandle,
+    click_house_config: &ClickHouseConfig,
+) -> Result<Vec<InfraMapRecord>, StateStorageError> {
+    create_infra_map_history_table(client, click_house_config).await?;
+
+    let block = client
+        .query(format!(
+            "SELECT record FROM {}._MOOSE_STATE_HISTORY ORDER BY timestamp",
+            click_house_config.db_name
+        ))
+        .fetch_all()
+        .await?;
+
+    let mut records = Vec::new();
+    for row in block.rows() {
+        let record: String = row.get(0)?;
+        records.push(serde_json::from_str(&record)?);
+    }
+    Ok(records)
+}
+
+async fn create_journal_table(
+    client: &mut ClientHandle,
+    click_house_config: &ClickHouseConfig,
+) -> Result<(), clickhouse_rs::errors::Error> {
+    let sql = format!(
+        r#"CREATE TABLE IF NOT EXISTS {}._MOOSE_CHANGE_JOURNAL (
+            timestamp DateTime64(6, 'UTC') DEFAULT now64(6),
+            deployment_id String,
+            seq UInt32,
+            change String,
+            status String,
+            error String
+        ) ENGINE = MergeTree
+        ORDER BY (deployment_id, seq, timestamp)"#,
+        click_house_config.db_name
+    );
+    client.execute(sql).await
+}
+
+// The journal is append only, the last row of a change holds its current status
+pub async fn insert_journal_entries(
+    client: &mut ClientHandle,
+    click_house_config: &ClickHouseConfig,
+    entries: &[JournalEntry],
+) -> Result<(), StateStorageError> {
+    create_journal_table(client, click_house_config).await?;
+
+    let mut changes = Vec::new();
+    let mut statuses = Vec::new();
+    for entry in entries.iter() {
+        changes.push(serde_json::to_string(&entry.change)?);
+        statuses.push(match serde_json::to_value(entry.status)? {
+            Value::String(status) => status,
+            status => status.to_string(),
+        });
+    }
+
+    let data = clickhouse_rs::Block::new()
+        .column(
+            "deployment_id",
+            entries
+                .iter()
+                .map(|entry| entry.deployment_id.clone())
+                .collect::<Vec<String>>(),
+        )
+        .column(
+            "seq",
+            entries.iter().map(|entry| entry.seq).collect::<Vec<u32>>(),
+        )
+        .column("change", changes)
+        .column("status", statuses)
+        .column(
+            "error",
+            entries
+                .iter()
+                .map(|entry| entry.error.clone().unwrap_or_default())
+                .collect::<Vec<String>>(),
+        );
+    client
+        .insert(
+            format!("{}._MOOSE_CHANGE_JOURNAL", click_house_config.db_name),
+            data,
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn retrieve_deployment_journal(
+    client: &mut ClientHandle,
+    click_house_config: &ClickHouseConfig,
+    deployment_id: &str,
+) -> Result<Vec<JournalEntry>, StateStorageError> {
+    create_journal_table(client, click_house_config).await?;
+
+    let block = client
+        .query(format!(
+            r#"SELECT seq, argMax(change, timestamp), argMax(status, timestamp), argMax(error, timestamp)
+            FROM {}._MOOSE_CHANGE_JOURNAL
+            WHERE deployment_id = '{}'
+            GROUP BY seq
+            ORDER BY seq"#,
+            click_house_config.db_name,
+            deployment_id.replace('\'', "")
+        ))
+        .fetch_all()
+        .await?;
+
+    let mut entries = Vec::new();
+    for row in block.rows() {
+        let change: String = row.get(1)?;
+        let status: String = row.get(2)?;
+        let error: String = row.get(3)?;
+        entries.push(JournalEntry {
+            deployment_id: deployment_id.to_string(),
+            seq: row.get(0)?,
+            change: serde_json::from_str(&change)?,
+            status: serde_json::from_value(Value::String(status))?,
+            error: (!error.is_empty()).then_some(error),
+        });
+    }
+    Ok(entries)
+}
+
+async fn create_lock_table(
+    client: &mut ClientHandle,
+    click_house_config: &ClickHouseConfig,
+) -> Result<(), clickhouse_rs::errors::Error> {
+    let sql = format!(
+        r#"CREATE TABLE IF NOT EXISTS {}._MOOSE_LOCK (
+            timestamp DateTime64(6, 'UTC') DEFAULT now64(6),
+            owner String,
+            locked UInt8
+        ) ENGINE = MergeTree
+        ORDER BY timestamp"#,
+        click_house_config.db_name
+    );
+    client.execute(sql).await
+}
+
+pub async fn store_lock(
+    client: &mut ClientHandle,
+    click_house_config: &ClickHouseConfig,
+    owner: &str,
+    locked: bool,
+) -> Result<(), StateStorageError> {
+    create_lock_table(client, click_house_config).await?;
+
+    let data = clickhouse_rs::Block::new()
+        .column("owner", vec![owner.to_string()])
+        .column("locked", vec![locked as u8]);
+    client
+        .insert(format!("{}._MOOSE_LOCK", click_house_config.db_name), data)
+        .await?;
+    Ok(())
+}
+
+/**
+ * The owner of the lock if it is held. Deployments take the lock by appending a row, so several
+ * of them can take it at the same time: the lock belongs to the first one to take it after it
+ * was last released, the others see it is held by that one.
+ */
+pub async fn retrieve_lock_owner(
+    client: &mut ClientHandle,
+    click_house_config: &ClickHouseConfig,
+) -> Result<Option<String>, StateStorageError> {
+    create_lock_table(client, click_house_config).await?;
+
+    let block = client
+        .query(format!(
+            "SELECT owner FROM {db}._MOOSE_LOCK \
+             WHERE locked = 1 \
+             AND timestamp > (SELECT max(timestamp) FROM {db}._MOOSE_LOCK WHERE locked = 0) \
+             ORDER BY timestamp, owner LIMIT 1",
+            db = click_house_config.db_name
+        ))
+        .fetch_all()
+        .await?;
+
+    match block.rows().next() {
+        Some(row) => Ok(Some(row.get(0)?)),
+        None => Ok(None),
+    }
+}
@@ -1,3 +1,5 @@
+use std::collections::HashSet;
+
 use consumption_registry::ConsumptionError;
 use kafka_clickhouse_sync::SyncingProcessesRegistry;
 use process_registry::ProcessRegistries;
@@ -37,6 +39,20 @@ pub async fn execute_changes(
     process_registry: &mut ProcessRegistries,
     changes: &[ProcessChange],
 ) -> Result<(), SyncProcessChangesError> {
+    // A function whose version or targets change is removed and added again under a new id, the
+    // added function restores the state the removed one leaves
+    let kept_state_topics: HashSet<String> = changes
+        .iter()
+        .filter_map(|change| match change {
+            ProcessChange::FunctionProcess(Change::Added(function_process))
+            | ProcessChange::FunctionProcess(Change::Updated {
+                after: function_process,
+                ..
+            }) => function_process.state_topic(),
+            _ => None,
+        })
+        .collect();
+
     for change in changes.iter() {
         match change {
             ProcessChange::TopicToTableSyncProcess(Change::Added(sync)) => {
@@ -68,16 +84,22 @@ pub async fn execute_changes(
             }
             ProcessChange::FunctionProcess(Change::Added(function_process)) => {
                 log::info!("Starting Function process: {:?}", function_process.id());
-                process_registry.functions.start(function_process)?;
+                process_registry.functions.start(function_process).await?;
             }
             ProcessChange::FunctionProcess(Change::Removed(function_process)) => {
                 log::info!("Stoping Function process: {:?}", function_process.id());
-                process_registry.functions.stop(function_process).await?;
+                let delete_state = function_process
+                    .state_topic()
+                    .is_some_and(|topic| !kept_state_topics.contains(&topic));
+                process_registry
+                    .functions
+                    .remove(function_process, delete_state)
+                    .await?;
             }
             ProcessChange::FunctionProcess(Change::Updated { before, after }) => {
                 log::info!("Updating Function process: {:?}", before.id());
                 process_registry.functions.stop(before).await?;
-                process_registry.functions.start(after)?;
+                process_registry.functions.start(after).await?;
             }
             ProcessChange::OlapProcess(Change::Added(olap_process)) => {
                 log::info!("Starting Aggregation process: {:?}", olap_process.id());
@@ -23,6 +23,8 @@ pub struct ConsumptionProcessRegistry {
     api_process: Option<Child>,
     clickhouse_config: ClickHouseConfig,
     dir: PathBuf,
+    // Holds one directory per supported old version of the apis
+    versions_dir: PathBuf,
     language: SupportedLanguages,
 }
 
@@ -31,11 +33,13 @@ impl ConsumptionProcessRegistry {
         language: SupportedLanguages,
         clickhouse_config: ClickHouseConfig,
         dir: PathBuf,
+        versions_dir: PathBuf,
     ) -> Self {
         Self {
             api_process: Option::None,
             language,
             dir,
+            versions_dir,
             clickhouse_config,
         }
     }
@@ -44,12 +48,16 @@ impl ConsumptionProcessRegistry {
         info!("Starting consumption API...");
 
         let child = match self.language {
-            SupportedLanguages::Python => {
-                python::consumption::run(self.clickhouse_config.clone(), &self.dir)
-            }
-            SupportedLanguages::Typescript => {
-                typescript::consumption::run(self.clickhouse_config.clone(), &self.dir)
-            }
+            SupportedLanguages::Python => python::consumption::run(
+                self.clickhouse_config.clone(),
+                &self.dir,
+                &self.versions_dir,
+            ),
+            SupportedLanguages::Typescript => typescript::consumption::run(
+                self.clickhouse_config.clone(),
+                &self.dir,
+                &self.versions_dir,
+            ),
         }?;
 
         self.api_process = Some(child);
@@ -1,19 +1,38 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use log::info;
+use futures::future::join_all;
+use log::{error, info, warn};
+use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::process::Child;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
 
 use crate::{
     framework::{
         core::infrastructure::{function_process::FunctionProcess, topic::Topic},
         python,
-        streaming::model::StreamingFunction,
+        streaming::model::{FunctionState, FunctionStatus, RunnerMode, StreamingFunction},
         typescript,
+        wasm::{self, streaming::WasmFunctionError},
     },
-    infrastructure::stream::redpanda::RedpandaConfig,
+    infrastructure::stream::redpanda::{self, RedpandaConfig},
+    metrics::{Metrics, MetricsMessage},
     utilities::system::{kill_child, KillProcessError},
 };
 
+// Delay before the first restart of a function, doubled at every consecutive crash
+const RESTART_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const RESTART_MAX_BACKOFF: Duration = Duration::from_secs(60);
+// A function that crashes this many times in a row is not restarted anymore
+const CRASH_LOOP_THRESHOLD: u32 = 5;
+// A function that ran this long before exiting is not considered in a crash loop
+const STABLE_RUN_DURATION: Duration = Duration::from_secs(60);
+const STDERR_LINES_KEPT: usize = 20;
+// How often the number of workers of a function is matched to the partitions of its source topic
+const SCALING_INTERVAL: Duration = Duration::from_secs(30);
+
 #[derive(Debug, thiserror::Error)]
 pub enum FunctionRegistryError {
     #[error("Failed to load function_process files")]
@@ -24,60 +43,441 @@ pub enum FunctionRegistryError {
 
     #[error("Cannot run function_process {file_name}. Unsupported function_process type")]
     UnsupportedFunctionLanguage { file_name: String },
+
+    #[error("Failed to create or delete the state topic {topic}")]
+    StateTopic {
+        topic: String,
+        source: anyhow::Error,
+    },
+}
+
+// A task running until it is told to stop: a worker supervisor or a scaler
+struct SupervisedFunction {
+    stop: oneshot::Sender<()>,
+    supervisor: JoinHandle<()>,
+}
+
+type Workers = Arc<tokio::sync::Mutex<Vec<SupervisedFunction>>>;
+
+// The workers of a function, all consuming the source topic in the same consumer group
+struct WorkerGroup {
+    workers: Workers,
+    scaler: Option<SupervisedFunction>,
 }
 
 pub struct FunctionProcessRegistry {
-    registry: HashMap<String, Child>,
+    registry: HashMap<String, WorkerGroup>,
+    kafka_config: RedpandaConfig,
+    metrics: Arc<Metrics>,
+}
+
+// A running worker of a function: a runner process, or a task running a WebAssembly function
+// inside the moose process
+enum Runner {
+    Process(Child),
+    Wasm(JoinHandle<Result<(), WasmFunctionError>>),
+}
+
+impl Runner {
+    // Waits for the worker to exit, and returns its exit code
+    async fn wait(&mut self, last_stderr: &Mutex<VecDeque<String>>) -> Option<i32> {
+        match self {
+            Runner::Process(child) => child.wait().await.ok().and_then(|status| status.code()),
+            Runner::Wasm(task) => match task.await {
+                Ok(Ok(())) => Some(0),
+                Ok(Err(e)) => {
+                    error!("{:?}", e);
+                    keep_stderr_line(last_stderr, e.to_string());
+                    Some(1)
+                }
+                Err(_) => None,
+            },
+        }
+    }
+
+    async fn kill(&mut self) -> Result<(), KillProcessError> {
+        match self {
+            Runner::Process(child) => {
+                kill_child(child).await?;
+                let _ = child.wait().await;
+            }
+            Runner::Wasm(task) => {
+                task.abort();
+                let _ = task.await;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn spawn_function(
+    kafka_config: &RedpandaConfig,
+    function_process: &FunctionProcess,
+    worker: u16,
+) -> Result<Runner, FunctionRegistryError> {
+    if function_process.is_wasm_function_process() {
+        return Ok(Runner::Wasm(tokio::spawn(wasm::streaming::run(
+            kafka_config.clone(),
+            function_process.clone(),
+            worker,
+            None,
+        ))));
+    }
+
+    let mut child = spawn_runner(
+        kafka_config,
+        function_process,
+        worker,
+        &RunnerMode::Streaming,
+    )?;
+
+    if let Some(stdout) = child.stdout.take() {
+        let mut stdout_reader = BufReader::new(stdout).lines();
+        tokio::spawn(async move {
+            while let Ok(Some(line)) = stdout_reader.next_line().await {
+                info!("{}", line);
+            }
+        });
+    }
+
+    Ok(Runner::Process(child))
+}
+
+// Starts the runner process of the function in its language
+pub fn spawn_runner(
+    kafka_config: &RedpandaConfig,
+    function_process: &FunctionProcess,
+    worker: u16,
+    mode: &RunnerMode,
+) -> Result<Child, FunctionRegistryError> {
+    if function_process.is_py_function_process() {
+        Ok(python::streaming::run(
+            kafka_config,
+            &function_process.source_topic,
+            &function_process.targets_json(),
+            &function_process.target_topic_config_json(),
+            &function_process.executable,
+            function_process.state_topic().as_deref(),
+            function_process.window_json().as_deref(),
+            worker,
+            mode,
+        )?)
+    } else if function_process.is_ts_function_process() {
+        Ok(typescript::streaming::run(
+            kafka_config,
+            &function_process.source_topic,
+            &function_process.targets_json(),
+            &function_process.target_topic_config_json(),
+            &function_process.executable,
+            function_process.state_topic().as_deref(),
+            function_process.window_json().as_deref(),
+            worker,
+            mode,
+        )?)
+    } else {
+        Err(FunctionRegistryError::UnsupportedFunctionLanguage {
+            file_name: function_process
+                .executable
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .to_string(),
+        })
+    }
+}
+
+fn keep_stderr_line(last_stderr: &Mutex<VecDeque<String>>, line: String) {
+    let mut last_stderr = last_stderr.lock().unwrap();
+    if last_stderr.len() == STDERR_LINES_KEPT {
+        last_stderr.pop_front();
+    }
+    last_stderr.push_back(line);
+}
+
+// Logs the errors of the function and keeps the last lines for its status
+fn follow_stderr(runner: &mut Runner, last_stderr: Arc<Mutex<VecDeque<String>>>) {
+    let stderr = match runner {
+        Runner::Process(child) => child.stderr.take(),
+        // the errors of a WebAssembly function are returned by its task
+        Runner::Wasm(_) => None,
+    };
+    if let Some(stderr) = stderr {
+        let mut stderr_reader = BufReader::new(stderr).lines();
+        tokio::spawn(async move {
+            while let Ok(Some(line)) = stderr_reader.next_line().await {
+                error!("{}", line);
+                keep_stderr_line(&last_stderr, line);
+            }
+        });
+    }
+}
+
+struct Supervisor {
+    kafka_config: RedpandaConfig,
+    function_process: FunctionProcess,
+    worker: u16,
+    metrics: Arc<Metrics>,
+    restarts: u32,
+    last_exit_code: Option<i32>,
+    last_stderr: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl Supervisor {
+    async fn report(&self, state: FunctionState) {
+        let status = FunctionStatus {
+            function: self.function_process.id(),
+            worker: self.worker,
+            state,
+            restarts: self.restarts,
+            last_exit_code: self.last_exit_code,
+            last_stderr: self.last_stderr.lock().unwrap().iter().cloned().collect(),
+        };
+        self.metrics
+            .send_metric(MetricsMessage::StreamingFunctionStatus(status))
+            .await;
+    }
+
+    /**
+     * Restarts the function every time it exits, after a delay growing exponentially with the
+     * number of consecutive crashes, until it is stopped or crashes too many times in a row.
+     */
+    async fn run(mut self, mut runner: Runner, mut stop: oneshot::Receiver<()>) {
+        let id = format!("{} (worker {})", self.function_process.id(), self.worker);
+        let mut consecutive_crashes = 0;
+        let mut backoff = RESTART_INITIAL_BACKOFF;
+
+        loop {
+            follow_stderr(&mut runner, self.last_stderr.clone());
+            self.report(FunctionState::Running).await;
+            let started_at = Instant::now();
+
+            let exit_code = tokio::select! {
+                _ = &mut stop => {
+                    if let Err(e) = runner.kill().await {
+                        warn!("Failed to stop function process {}: {:?}", id, e);
+                    }
+                    self.report(FunctionState::Stopped).await;
+                    return;
+                }
+                exit_code = runner.wait(&self.last_stderr) => exit_code,
+            };
+
+            self.last_exit_code = exit_code;
+            if started_at.elapsed() >= STABLE_RUN_DURATION {
+                consecutive_crashes = 0;
+                backoff = RESTART_INITIAL_BACKOFF;
+            }
+            consecutive_crashes += 1;
+
+            if consecutive_crashes >= CRASH_LOOP_THRESHOLD {
+                error!(
+                    "Function process {} crashed {} times in a row, it will not be restarted",
+                    id, consecutive_crashes
+                );
+                self.report(FunctionState::Failed).await;
+                return;
+            }
+
+            warn!(
+                "Function process {} exited with code {:?}, restarting in {:?}",
+                id, self.last_exit_code, backoff
+            );
+            self.report(FunctionState::Restarting).await;
+            tokio::select! {
+                _ = &mut stop => {
+                    self.report(FunctionState::Stopped).await;
+                    return;
+                }
+                _ = tokio::time::sleep(backoff) => {}
+            }
+            backoff = (backoff * 2).min(RESTART_MAX_BACKOFF);
+
+            runner = match spawn_function(&self.kafka_config, &self.function_process, self.worker) {
+                Ok(runner) => runner,
+                Err(e) => {
+                    error!("Failed to restart function process {}: {:?}", id, e);
+                    self.report(FunctionState::Failed).await;
+                    return;
+                }
+            };
+            self.restarts += 1;
+            self.metrics
+                .send_metric(MetricsMessage::StreamingFunctionRestart {
+                    function: self.function_process.id(),
+                })
+                .await;
+        }
+    }
+}
+
+fn spawn_worker(
+    kafka_config: &RedpandaConfig,
+    function_process: &FunctionProcess,
+    metrics: &Arc<Metrics>,
+    worker: u16,
+) -> Result<SupervisedFunction, FunctionRegistryError> {
+    let runner = spawn_function(kafka_config, function_process, worker)?;
+
+    let (stop, stopped) = oneshot::channel();
+    let supervisor = Supervisor {
+        kafka_config: kafka_config.clone(),
+        function_process: function_process.clone(),
+        worker,
+        metrics: metrics.clone(),
+        restarts: 0,
+        last_exit_code: None,
+        last_stderr: Arc::new(Mutex::new(VecDeque::new())),
+    };
+
+    Ok(SupervisedFunction {
+        stop,
+        supervisor: tokio::spawn(supervisor.run(runner, stopped)),
+    })
+}
+
+// Number of workers to run for a function given the partitions of its source topic
+fn worker_count(parallelism: u16, partitions: usize) -> usize {
+    (parallelism as usize).min(partitions).max(1)
+}
+
+struct Scaler {
     kafka_config: RedpandaConfig,
+    function_process: FunctionProcess,
+    metrics: Arc<Metrics>,
+    workers: Workers,
+}
+
+impl Scaler {
+    async fn run(self, mut stop: oneshot::Receiver<()>) {
+        let id = self.function_process.id();
+        let metadata_client = match redpanda::MetadataClient::new(&self.kafka_config) {
+            Ok(metadata_client) => metadata_client,
+            Err(e) => {
+                error!(
+                    "Failed to create the client scaling function process {}: {:?}",
+                    id, e
+                );
+                return;
+            }
+        };
+        let mut interval = tokio::time::interval(SCALING_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = &mut stop => return,
+                _ = interval.tick() => {}
+            }
+
+            let partitions = match metadata_client
+                .partition_count(&self.function_process.source_topic)
+                .await
+            {
+                Ok(partitions) => partitions,
+                Err(e) => {
+                    warn!(
+                        "Failed to get the partitions of topic {} for function process {}: {:?}",
+                        self.function_process.source_topic, id, e
+                    );
+                    continue;
+                }
+            };
+            let wanted = worker_count(self.function_process.parallelism, partitions);
+
+            let mut workers = self.workers.lock().await;
+            if workers.len() != wanted {
+                info!(
+                    "Scaling function process {} from {} to {} workers",
+                    id,
+                    workers.len(),
+                    wanted
+                );
+            }
+            while workers.len() < wanted {
+                match spawn_worker(
+                    &self.kafka_config,
+                    &self.function_process,
+                    &self.metrics,
+                    workers.len() as u16,
+                ) {
+                    Ok(worker) => workers.push(worker),
+                    Err(e) => {
+                        error!(
+                            "Failed to start a worker of function process {}: {:?}",
+                            id, e
+                        );
+                        break;
+                    }
+                }
+            }
+            while workers.len() > wanted {
+                if let Some(worker) = workers.pop() {
+                    stop_supervised(worker).await;
+                }
+            }
+        }
+    }
 }
 
 impl FunctionProcessRegistry {
-    pub fn new(kafka_config: RedpandaConfig) -> Self {
+    pub fn new(kafka_config: RedpandaConfig, metrics: Arc<Metrics>) -> Self {
         Self {
             registry: HashMap::new(),
             kafka_config,
+            metrics,
         }
     }
 
-    pub fn start(
+    /**
+     * Starts the workers of the function. Functions with a parallelism above one get a scaler
+     * keeping their number of workers at most the number of partitions of the source topic,
+     * since the extra consumers of the group would not be assigned any partition.
+     */
+    pub async fn start(
         &mut self,
         function_process: &FunctionProcess,
     ) -> Result<(), FunctionRegistryError> {
-        let child = if function_process.is_py_function_process() {
-            Ok(python::streaming::run(
-                &self.kafka_config,
-                &function_process.source_topic,
-                &function_process.target_topic,
-                &function_process.target_topic_config_json(),
-                &function_process.executable,
-            )?)
-        } else if function_process.is_ts_function_process() {
-            Ok(typescript::streaming::run(
+        if let Some(topic) = function_process.state_topic() {
+            redpanda::create_compacted_topic(&self.kafka_config, &topic)
+                .await
+                .map_err(|source| FunctionRegistryError::StateTopic { topic, source })?;
+        }
+
+        let mut workers = Vec::new();
+        for worker in 0..function_process.parallelism.max(1) {
+            workers.push(spawn_worker(
                 &self.kafka_config,
-                &function_process.source_topic,
-                &function_process.target_topic,
-                &function_process.target_topic_config_json(),
-                &function_process.executable,
-            )?)
-        } else {
-            Err(FunctionRegistryError::UnsupportedFunctionLanguage {
-                file_name: function_process
-                    .executable
-                    .file_name()
-                    .unwrap()
-                    .to_string_lossy()
-                    .to_string(),
+                function_process,
+                &self.metrics,
+                worker,
+            )?);
+        }
+        let workers: Workers = Arc::new(tokio::sync::Mutex::new(workers));
+
+        let scaler = if function_process.parallelism > 1 {
+            let (stop, stopped) = oneshot::channel();
+            let scaler = Scaler {
+                kafka_config: self.kafka_config.clone(),
+                function_process: function_process.clone(),
+                metrics: self.metrics.clone(),
+                workers: workers.clone(),
+            };
+            Some(SupervisedFunction {
+                stop,
+                supervisor: tokio::spawn(scaler.run(stopped)),
             })
-        }?;
+        } else {
+            None
+        };
 
-        self.registry.insert(function_process.id(), child);
+        self.registry
+            .insert(function_process.id(), WorkerGroup { workers, scaler });
 
         Ok(())
     }
 
     // This is a legacy method that takes in directly a streaming function
     // to accommodate the current way of spinning up streaming functions
-    pub fn start_all(
+    pub async fn start_all(
         &mut self,
         functions: &[StreamingFunction],
         topics: &[String],
@@ -91,9 +491,10 @@ impl FunctionProcessRegistry {
                     &source_topic,
                     &target_topic,
                 );
-                self.start(&function_process)?;
+                self.start(&function_process).await?;
             } else {
-                self.start(&FunctionProcess::from_function(streaming_function, topics))?;
+                self.start(&FunctionProcess::from_function(streaming_function, topics))
+                    .await?;
             }
         }
 
@@ -106,23 +507,66 @@ impl FunctionProcessRegistry {
     ) -> Result<(), FunctionRegistryError> {
         info!("Stopping function process {:?}...", function_process.id());
 
-        let id = &function_process.id();
-        if let Some(running_function_process) = self.registry.get_mut(id) {
-            kill_child(running_function_process).await?;
-            self.registry.remove(id);
+        if let Some(group) = self.registry.remove(&function_process.id()) {
+            stop_group(group).await;
+        }
+
+        Ok(())
+    }
+
+    // Stops the function and deletes its state, unless another function of the project restores
+    // it
+    pub async fn remove(
+        &mut self,
+        function_process: &FunctionProcess,
+        delete_state: bool,
+    ) -> Result<(), FunctionRegistryError> {
+        self.stop(function_process).await?;
+
+        if let Some(topic) = function_process.state_topic().filter(|_| delete_state) {
+            redpanda::delete_topics(&self.kafka_config, vec![topic.clone()])
+                .await
+                .map_err(|source| FunctionRegistryError::StateTopic { topic, source })?;
         }
 
         Ok(())
     }
 
     pub async fn stop_all(&mut self) -> Result<(), FunctionRegistryError> {
-        for (id, running_function_process) in self.registry.iter_mut() {
+        for (id, group) in self.registry.drain() {
             info!("Stopping function_process {:?}...", id);
-            kill_child(running_function_process).await?;
+            stop_group(group).await;
         }
 
-        self.registry.clear();
-
         Ok(())
     }
 }
+
+// The supervisor kills the process when it is told to stop, or has already exited if the
+// function failed
+async fn stop_supervised(supervised: SupervisedFunction) {
+    let _ = supervised.stop.send(());
+    let _ = supervised.supervisor.await;
+}
+
+// The scaler is stopped first so that it doesn't start workers while the group is stopping
+async fn stop_group(group: WorkerGroup) {
+    if let Some(scaler) = group.scaler {
+        stop_supervised(scaler).await;
+    }
+    let workers = std::mem::take(&mut *group.workers.lock().await);
+    join_all(workers.into_iter().map(stop_supervised)).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_worker_count_is_bounded_by_partitions() {
+        assert_eq!(worker_count(4, 8), 4);
+        assert_eq!(worker_count(4, 2), 2);
+        assert_eq!(worker_count(4, 0), 1);
+        assert_eq!(worker_count(1, 8), 1);
+    }
+}
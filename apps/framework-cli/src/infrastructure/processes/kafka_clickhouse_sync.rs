@@ -1,17 +1,23 @@
 use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
 use std::collections::HashSet;
-use std::collections::{HashMap, VecDeque};
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use log::debug;
 use log::error;
 use log::info;
-use rdkafka::consumer::StreamConsumer;
-use rdkafka::producer::{DeliveryFuture, FutureProducer};
+use opentelemetry::trace::TraceContextExt;
+use opentelemetry::Context;
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::error::KafkaError;
+use rdkafka::message::{OwnedHeaders, OwnedMessage};
+use rdkafka::producer::{FutureProducer, FutureRecord, Producer};
+use rdkafka::topic_partition_list::{Offset, TopicPartitionList};
 use rdkafka::Message;
 use serde_json::Value;
-use tokio::sync::Mutex;
 use tokio::task::JoinHandle;
 
 use crate::framework::core::code_loader::FrameworkObjectVersions;
@@ -24,13 +30,24 @@ use crate::infrastructure::olap::clickhouse::model::{
     ClickHouseColumn, ClickHouseRecord, ClickHouseRuntimeEnum, ClickHouseValue,
 };
 use crate::infrastructure::olap::clickhouse::version_sync::{VersionSync, VersionSyncType};
+use crate::infrastructure::stream::redpanda::consumer_lag;
 use crate::infrastructure::stream::redpanda::create_subscriber;
 use crate::infrastructure::stream::redpanda::fetch_topics;
+use crate::infrastructure::stream::redpanda::trace_context;
 use crate::infrastructure::stream::redpanda::RedpandaConfig;
-use crate::infrastructure::stream::redpanda::{create_producer, send_with_back_pressure};
+use crate::infrastructure::stream::redpanda::{
+    create_transactional_producer, create_transactional_subscriber,
+};
+use crate::metrics::{Metrics, MetricsMessage};
 
 const TABLE_SYNC_GROUP_ID: &str = "clickhouse_sync";
 const VERSION_SYNC_GROUP_ID: &str = "version_sync_flow_sync";
+const CONSUMER_LAG_INTERVAL_SECONDS: u64 = 10;
+
+// Messages copied in a single transaction, and the longest a transaction waits for messages
+const TRANSACTION_MAX_MESSAGES: usize = 1000;
+const TRANSACTION_MAX_WAIT: Duration = Duration::from_millis(100);
+const TRANSACTION_TIMEOUT: Duration = Duration::from_secs(30);
 
 struct TableSyncingProcess {
     process: JoinHandle<anyhow::Result<()>>,
@@ -50,15 +67,21 @@ pub struct SyncingProcessesRegistry {
     to_topic_registry: HashMap<String, JoinHandle<()>>,
     kafka_config: RedpandaConfig,
     clickhouse_config: ClickHouseConfig,
+    metrics: Arc<Metrics>,
 }
 
 impl SyncingProcessesRegistry {
-    pub fn new(kafka_config: RedpandaConfig, clickhouse_config: ClickHouseConfig) -> Self {
+    pub fn new(
+        kafka_config: RedpandaConfig,
+        clickhouse_config: ClickHouseConfig,
+        metrics: Arc<Metrics>,
+    ) -> Self {
         Self {
             to_table_registry: HashMap::new(),
             to_topic_registry: HashMap::new(),
             kafka_config,
             clickhouse_config,
+            metrics,
         }
     }
 
@@ -88,6 +111,7 @@ impl SyncingProcessesRegistry {
 
         let kafka_config = self.kafka_config.clone();
         let clickhouse_config = self.clickhouse_config.clone();
+        let metrics = self.metrics.clone();
 
         let available_topics: HashSet<String, RandomState> =
             HashSet::from_iter(fetch_topics(&kafka_config).await?);
@@ -118,6 +142,7 @@ impl SyncingProcessesRegistry {
                         .map(spawn_sync_process(
                             kafka_config.clone(),
                             clickhouse_config.clone(),
+                            metrics.clone(),
                         ))
                 });
 
@@ -129,6 +154,7 @@ impl SyncingProcessesRegistry {
                     Some(spawn_sync_process_core(
                         kafka_config.clone(),
                         clickhouse_config.clone(),
+                        metrics.clone(),
                         output_topic,
                         vs.dest_data_model.columns.clone(),
                         vs.dest_table.name.clone(),
@@ -151,6 +177,7 @@ impl SyncingProcessesRegistry {
             if available_topics.contains(&input_topic) && available_topics.contains(&source_topic) {
                 self.insert_topic_sync(spawn_kafka_to_kafka_process(
                     kafka_config.clone(),
+                    metrics.clone(),
                     source_topic,
                     input_topic,
                 ));
@@ -181,6 +208,7 @@ impl SyncingProcessesRegistry {
         let syncing_process = spawn_sync_process_core(
             self.kafka_config.clone(),
             self.clickhouse_config.clone(),
+            self.metrics.clone(),
             source_topic_name,
             source_topic_columns,
             target_table_name,
@@ -204,6 +232,7 @@ type FnSyncProcess =
 fn spawn_sync_process(
     kafka_config: RedpandaConfig,
     clickhouse_config: ClickHouseConfig,
+    metrics: Arc<Metrics>,
 ) -> FnSyncProcess {
     Box::new(
         move |(
@@ -219,6 +248,7 @@ fn spawn_sync_process(
             spawn_sync_process_core(
                 kafka_config.clone(),
                 clickhouse_config.clone(),
+                metrics.clone(),
                 source_topic_name,
                 source_topic_columns,
                 target_table_name,
@@ -231,6 +261,7 @@ fn spawn_sync_process(
 fn spawn_sync_process_core(
     kafka_config: RedpandaConfig,
     clickhouse_config: ClickHouseConfig,
+    metrics: Arc<Metrics>,
     source_topic_name: String,
     source_topic_columns: Vec<Column>,
     target_table_name: String,
@@ -239,6 +270,7 @@ fn spawn_sync_process_core(
     let syncing_process = tokio::spawn(sync_kafka_to_clickhouse(
         kafka_config,
         clickhouse_config,
+        metrics,
         source_topic_name.clone(),
         source_topic_columns,
         target_table_name.clone(),
@@ -254,11 +286,13 @@ fn spawn_sync_process_core(
 
 fn spawn_kafka_to_kafka_process(
     kafka_config: RedpandaConfig,
+    metrics: Arc<Metrics>,
     source_topic_name: String,
     target_topic_name: String,
 ) -> TopicToTopicSyncingProcess {
     let syncing_process = tokio::spawn(sync_kafka_to_kafka(
         kafka_config,
+        metrics,
         source_topic_name.clone(),
         target_topic_name.clone(),
     ));
@@ -270,50 +304,214 @@ fn spawn_kafka_to_kafka_process(
     }
 }
 
+/**
+ * Copies the source topic to the target topic exactly once. The messages are copied in
+ * transactions which also commit the offsets of the subscriber, so that a copy that fails and
+ * is retried doesn't duplicate messages in the target topic.
+ */
 async fn sync_kafka_to_kafka(
     kafka_config: RedpandaConfig,
+    metrics: Arc<Metrics>,
     source_topic_name: String,
     target_topic_name: String,
 ) {
-    let subscriber = create_subscriber(&kafka_config, VERSION_SYNC_GROUP_ID, &source_topic_name);
-    let producer = create_producer(kafka_config.clone());
-
-    // there is no concurrency on this queue, the mutex is to satisfy the borrow checker
-    let queue: Mutex<VecDeque<DeliveryFuture>> = Mutex::new(VecDeque::new());
-    let target_topic_name = &target_topic_name;
-
-    iterate_subscriber(subscriber, source_topic_name, |payload| {
-        let producer: &FutureProducer = &producer.producer;
-        let queue = &queue;
-        Box::pin(async move {
-            send_with_back_pressure(
-                &mut *queue.lock().await,
-                producer,
-                target_topic_name,
-                payload,
-            )
+    let transactional_id = format!(
+        "{}-{}-{}",
+        VERSION_SYNC_GROUP_ID, source_topic_name, target_topic_name
+    );
+    let clients = match create_transactional_subscriber(
+        &kafka_config,
+        VERSION_SYNC_GROUP_ID,
+        &source_topic_name,
+    ) {
+        Ok(subscriber) => create_transactional_producer(&kafka_config, &transactional_id)
             .await
-        })
+            .map(|producer| (subscriber, producer)),
+        Err(e) => Err(e),
+    };
+    let (subscriber, producer) = match clients {
+        Ok((subscriber, producer)) => (Arc::new(subscriber), producer),
+        Err(e) => {
+            error!(
+                "Failed to start copying {} to {}: {}",
+                source_topic_name, target_topic_name, e
+            );
+            return;
+        }
+    };
+
+    let mut last_lag_report = Instant::now();
+
+    loop {
+        let batch = receive_batch(&subscriber, &source_topic_name).await;
+
+        if !batch.is_empty() {
+            // the trace headers are copied along, for the traces to continue in the target topic
+            let records: Vec<TransactionRecord> = batch
+                .iter()
+                .filter_map(|message| {
+                    message.payload().map(|payload| TransactionRecord {
+                        topic: target_topic_name.clone(),
+                        payload: payload.to_vec(),
+                        headers: message.headers().cloned(),
+                    })
+                })
+                .collect();
+            if let Err(e) = send_in_transaction(&subscriber, &producer, records).await {
+                error!(
+                    "Failed to copy {} messages from {} to {}, retrying: {}",
+                    batch.len(),
+                    source_topic_name,
+                    target_topic_name,
+                    e
+                );
+                if let Err(e) = rewind(&subscriber, &batch).await {
+                    error!("Failed to rewind {}: {}", source_topic_name, e);
+                }
+            }
+        }
+
+        if last_lag_report.elapsed() >= Duration::from_secs(CONSUMER_LAG_INTERVAL_SECONDS) {
+            last_lag_report = Instant::now();
+            report_consumer_lag(
+                subscriber.clone(),
+                &source_topic_name,
+                VERSION_SYNC_GROUP_ID,
+                metrics.clone(),
+            );
+        }
+    }
+}
+
+// Receives the messages available within TRANSACTION_MAX_WAIT, up to TRANSACTION_MAX_MESSAGES
+pub async fn receive_batch(
+    subscriber: &StreamConsumer,
+    source_topic_name: &str,
+) -> Vec<OwnedMessage> {
+    let mut batch = Vec::new();
+    let deadline = tokio::time::Instant::now() + TRANSACTION_MAX_WAIT;
+
+    while batch.len() < TRANSACTION_MAX_MESSAGES {
+        match tokio::time::timeout_at(deadline, subscriber.recv()).await {
+            Err(_) => break,
+            Ok(Ok(message)) => batch.push(message.detach()),
+            Ok(Err(e)) => debug!("Error receiving message from {}: {}", source_topic_name, e),
+        }
+    }
+
+    batch
+}
+
+/// A message sent in a transaction
+pub struct TransactionRecord {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub headers: Option<OwnedHeaders>,
+}
+
+/**
+ * Sends the records in a transaction which also commits the position of the subscriber. The
+ * transaction is aborted if any record fails to be sent. The calls to the transaction
+ * coordinator block for up to TRANSACTION_TIMEOUT, so they are made outside of the async runtime.
+ */
+pub async fn send_in_transaction(
+    subscriber: &Arc<StreamConsumer>,
+    producer: &FutureProducer,
+    records: Vec<TransactionRecord>,
+) -> anyhow::Result<()> {
+    let subscriber = subscriber.clone();
+    let producer = producer.clone();
+
+    tokio::task::spawn_blocking(move || {
+        producer.begin_transaction()?;
+
+        let sent = (|| -> anyhow::Result<()> {
+            for record in records.iter() {
+                let mut future_record = FutureRecord::to(&record.topic)
+                    .key(record.topic.as_str())
+                    .payload(record.payload.as_slice());
+                if let Some(headers) = &record.headers {
+                    future_record = future_record.headers(headers.clone());
+                }
+                // the deliveries are awaited by the commit of the transaction
+                producer.send_result(future_record).map_err(|(e, _)| e)?;
+            }
+
+            let group_metadata = subscriber
+                .group_metadata()
+                .ok_or_else(|| anyhow::anyhow!("The subscriber has no consumer group metadata"))?;
+            producer.send_offsets_to_transaction(
+                &subscriber.position()?,
+                &group_metadata,
+                TRANSACTION_TIMEOUT,
+            )?;
+            producer.commit_transaction(TRANSACTION_TIMEOUT)?;
+            Ok(())
+        })();
+
+        if sent.is_err() {
+            if let Err(e) = producer.abort_transaction(TRANSACTION_TIMEOUT) {
+                error!("Failed to abort the transaction: {}", e);
+            }
+        }
+        sent
+    })
+    .await?
+}
+
+// Moves the subscriber back to the first message of the batch of each partition, so that the
+// messages of an aborted transaction are sent again
+pub async fn rewind(
+    subscriber: &Arc<StreamConsumer>,
+    batch: &[OwnedMessage],
+) -> Result<(), KafkaError> {
+    let mut first_offsets: HashMap<(String, i32), i64> = HashMap::new();
+    for message in batch {
+        let offset = first_offsets
+            .entry((message.topic().to_string(), message.partition()))
+            .or_insert(message.offset());
+        *offset = (*offset).min(message.offset());
+    }
+
+    let mut partitions = TopicPartitionList::new();
+    for ((topic, partition), offset) in first_offsets {
+        partitions.add_partition_offset(&topic, partition, Offset::Offset(offset))?;
+    }
+    let subscriber = subscriber.clone();
+    tokio::task::spawn_blocking(move || {
+        subscriber.seek_partitions(partitions, TRANSACTION_TIMEOUT)?;
+        Ok(())
     })
     .await
+    .map_err(|_| KafkaError::Canceled)?
 }
 
 async fn sync_kafka_to_clickhouse(
     kafka_config: RedpandaConfig,
     clickhouse_config: ClickHouseConfig,
+    metrics: Arc<Metrics>,
     source_topic_name: String,
     source_topic_columns: Vec<Column>,
     target_table_name: String,
     target_table_columns: Vec<ClickHouseColumn>,
 ) -> anyhow::Result<()> {
-    let subscriber = create_subscriber(&kafka_config, TABLE_SYNC_GROUP_ID, &source_topic_name);
+    let subscriber = Arc::new(create_subscriber(
+        &kafka_config,
+        TABLE_SYNC_GROUP_ID,
+        &source_topic_name,
+    ));
 
     let clickhouse_columns = target_table_columns
         .iter()
         .map(|column| column.name.clone())
         .collect();
 
-    let inserter = Inserter::new(clickhouse_config, &target_table_name, clickhouse_columns);
+    let inserter = Inserter::new(
+        clickhouse_config,
+        &target_table_name,
+        clickhouse_columns,
+        metrics.clone(),
+    );
 
     // WARNING: the code below is very performance sensitive
     // it is run for every message that needs to be written to clickhouse. As such we should
@@ -325,36 +523,98 @@ async fn sync_kafka_to_clickhouse(
 
     // This should also not be broken, otherwise, the subscriber will stop receiving messages
 
-    iterate_subscriber(subscriber, source_topic_name, |payload_str| {
-        // allow the async block to move the borrows
-        let inserter = &inserter;
-        let source_topic_columns = &source_topic_columns;
-
-        Box::pin(async move {
-            if let Ok(json_value) = serde_json::from_str(payload_str.as_str()) {
-                if let Ok(clickhouse_record) =
-                    mapper_json_to_clickhouse_record(source_topic_columns, json_value)
-                {
-                    let res = inserter.insert(clickhouse_record).await;
-
-                    if let Err(e) = res {
-                        error!("Error adding records to the queue to be inserted: {}", e);
+    iterate_subscriber(
+        subscriber,
+        TABLE_SYNC_GROUP_ID,
+        source_topic_name,
+        metrics.clone(),
+        |payload_str, cx| {
+            // allow the async block to move the borrows
+            let inserter = &inserter;
+            let source_topic_columns = &source_topic_columns;
+
+            Box::pin(async move {
+                if let Ok(json_value) = serde_json::from_str(payload_str.as_str()) {
+                    if let Ok(clickhouse_record) =
+                        mapper_json_to_clickhouse_record(source_topic_columns, json_value)
+                    {
+                        let res = inserter
+                            .insert(clickhouse_record, cx.span().span_context().clone())
+                            .await;
+
+                        if let Err(e) = res {
+                            error!("Error adding records to the queue to be inserted: {}", e);
+                        }
                     }
                 }
-            }
-        })
-    })
+            })
+        },
+    )
     .await;
     Ok(())
 }
 
-async fn iterate_subscriber<'a, F>(subscriber: StreamConsumer, source_topic_name: String, action: F)
-where
+/**
+ * Reports the lag of the subscriber on the topic. The lag is computed from calls to the brokers
+ * which block, so it is sampled in the background rather than in the loop consuming the topic.
+ */
+fn report_consumer_lag(
+    subscriber: Arc<StreamConsumer>,
+    topic: &str,
+    consumer_group: &str,
+    metrics: Arc<Metrics>,
+) {
+    let topic = topic.to_string();
+    let consumer_group = consumer_group.to_string();
+    tokio::spawn(async move {
+        let lag = {
+            let topic = topic.clone();
+            tokio::task::spawn_blocking(move || consumer_lag(&subscriber, &topic)).await
+        };
+        match lag {
+            Ok(Ok(lag)) => {
+                metrics
+                    .send_metric(MetricsMessage::ConsumerLag {
+                        topic,
+                        consumer_group,
+                        lag,
+                    })
+                    .await
+            }
+            Ok(Err(e)) => debug!("Error fetching the lag on {}: {}", topic, e),
+            Err(e) => debug!("Error fetching the lag on {}: {}", topic, e),
+        }
+    });
+}
+
+async fn iterate_subscriber<'a, F>(
+    subscriber: Arc<StreamConsumer>,
+    group_id: &str,
+    source_topic_name: String,
+    metrics: Arc<Metrics>,
+    action: F,
+) where
     // we shouldn't need the boxing, but i can't make the borrow checker happy
-    F: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>>,
+    F: Fn(String, Context) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>>,
 {
+    let mut lag_interval =
+        tokio::time::interval(Duration::from_secs(CONSUMER_LAG_INTERVAL_SECONDS));
+
     loop {
-        match subscriber.recv().await {
+        let received = tokio::select! {
+            received = subscriber.recv() => received,
+            _ = lag_interval.tick() => {
+                report_consumer_lag(
+                    subscriber.clone(),
+                    &source_topic_name,
+                    group_id,
+                    metrics.clone(),
+                );
+                continue;
+            }
+        };
+
+        match received {
             Err(e) => {
                 debug!("Error receiving message from {}: {}", source_topic_name, e);
             }
@@ -367,7 +627,7 @@ where
                             source_topic_name, payload_str
                         );
 
-                        action(payload_str.to_string()).await;
+                        action(payload_str.to_string(), trace_context(&message)).await;
                     }
                     Err(_) => {
                         error!(
@@ -387,6 +647,36 @@ where
     }
 }
 
+/**
+ * The reasons a JSON record wouldn't be inserted as is in a table with these columns: missing
+ * required columns and values that don't map to the type of their column.
+ */
+pub fn record_errors(schema_columns: &[Column], json_value: &Value) -> Vec<String> {
+    let map = match json_value.as_object() {
+        Some(map) => map,
+        None => return vec!["the record is not a JSON object".to_string()],
+    };
+
+    schema_columns
+        .iter()
+        .filter_map(|column| match map.get(&column.name) {
+            // missing arrays are inserted empty, and the columns with a default get their default
+            None | Some(Value::Null) => {
+                let has_fallback =
+                    matches!(column.data_type, ColumnType::Array(_)) || column.default.is_some();
+                if column.required && !has_fallback {
+                    Some(format!("missing required column {}", column.name))
+                } else {
+                    None
+                }
+            }
+            Some(value) => map_json_value_to_clickhouse_value(&column.data_type, value)
+                .err()
+                .map(|e| format!("{}: {}", column.name, e)),
+        })
+        .collect()
+}
+
 fn mapper_json_to_clickhouse_record(
     schema_columns: &[Column],
     json_value: Value,
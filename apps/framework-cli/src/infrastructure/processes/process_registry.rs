@@ -1,4 +1,7 @@
+use std::sync::Arc;
+
 use crate::cli::settings::Features;
+use crate::metrics::Metrics;
 use crate::project::Project;
 
 use super::aggregations_registry::AggregationProcessRegistry;
@@ -12,8 +15,8 @@ pub struct ProcessRegistries {
 }
 
 impl ProcessRegistries {
-    pub fn new(project: &Project, features: &Features) -> Self {
-        let functions = FunctionProcessRegistry::new(project.redpanda_config.clone());
+    pub fn new(project: &Project, features: &Features, metrics: Arc<Metrics>) -> Self {
+        let functions = FunctionProcessRegistry::new(project.redpanda_config.clone(), metrics);
         let aggs_dir = if features.blocks {
             project.blocks_dir()
         } else {
@@ -29,6 +32,7 @@ impl ProcessRegistries {
             project.language,
             project.clickhouse_config.clone(),
             project.consumption_dir(),
+            project.old_versions_consumption_dir(),
         );
 
         Self {
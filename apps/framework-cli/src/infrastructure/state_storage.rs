@@ -0,0 +1,454 @@
+//! # State Storage
+//! Storage of the state of moose: the infrastructure map of the new core and the application
+//! state of the previous one.
+//!
+//! The state is stored in Clickhouse by default. It can also be stored in local files, for
+//! development and CI, or in a compacted Redpanda topic, so that it doesn't depend on the
+//! database it describes. The storage is configured in the `state_config` of the project.
+//!
+//! Each storage also keeps the history of the infrastructure maps stored.
+
+use std::path::{Path, PathBuf};
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::framework::core::history::InfraMapRecord;
+use crate::framework::core::infrastructure_map::InfrastructureMap;
+use crate::infrastructure::olap::clickhouse::config::ClickHouseConfig;
+use crate::infrastructure::olap::clickhouse_alt_client::{
+    get_pool, insert_infra_map_record, retrieve_current_state, retrieve_infra_map_records,
+    retrieve_infrastructure_map, store_current_state, store_infrastructure_map, ApplicationState,
+    StateStorageError,
+};
+use crate::infrastructure::stream::redpanda::{
+    create_compacted_topic, read_latest_by_key, send_keyed, RedpandaConfig,
+};
+use crate::project::Project;
+
+const INFRA_MAP_KEY: &str = "infra_map";
+const APPLICATION_STATE_KEY: &str = "application_state";
+const HISTORY_KEY: &str = "history";
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum StateStorageKind {
+    #[default]
+    Clickhouse,
+    File,
+    Redpanda,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateConfig {
+    #[serde(default)]
+    pub storage: StateStorageKind,
+    // Directory of the state files, relative to the project, for the file storage
+    #[serde(default = "StateConfig::default_path")]
+    pub path: PathBuf,
+    // Compacted topic holding the state, for the Redpanda storage
+    #[serde(default = "StateConfig::default_topic")]
+    pub topic: String,
+}
+
+impl StateConfig {
+    fn default_path() -> PathBuf {
+        PathBuf::from(".moose/state")
+    }
+
+    fn default_topic() -> String {
+        "_moose_state".to_string()
+    }
+}
+
+impl Default for StateConfig {
+    fn default() -> Self {
+        StateConfig {
+            storage: StateStorageKind::default(),
+            path: StateConfig::default_path(),
+            topic: StateConfig::default_topic(),
+        }
+    }
+}
+
+pub trait StateStorage: Send + Sync {
+    fn store_infrastructure_map<'a>(
+        &'a self,
+        infra_map: &'a InfrastructureMap,
+    ) -> BoxFuture<'a, Result<(), StateStorageError>>;
+
+    fn load_infrastructure_map(
+        &self,
+    ) -> BoxFuture<'_, Result<Option<InfrastructureMap>, StateStorageError>>;
+
+    fn store_application_state<'a>(
+        &'a self,
+        application_state: &'a ApplicationState,
+    ) -> BoxFuture<'a, Result<(), StateStorageError>>;
+
+    fn load_application_state(
+        &self,
+    ) -> BoxFuture<'_, Result<Option<ApplicationState>, StateStorageError>>;
+
+    fn store_infra_map_record<'a>(
+        &'a self,
+        record: &'a InfraMapRecord,
+    ) -> BoxFuture<'a, Result<(), StateStorageError>>;
+
+    // The records of the infrastructure maps stored, oldest first
+    fn load_infra_map_records(
+        &self,
+    ) -> BoxFuture<'_, Result<Vec<InfraMapRecord>, StateStorageError>>;
+}
+
+// The storage configured for the project
+pub fn state_storage(project: &Project) -> Box<dyn StateStorage> {
+    state_storage_of_kind(project, project.state_config.storage)
+}
+
+pub fn state_storage_of_kind(project: &Project, kind: StateStorageKind) -> Box<dyn StateStorage> {
+    match kind {
+        StateStorageKind::Clickhouse => Box::new(ClickhouseStateStorage {
+            clickhouse_config: project.clickhouse_config.clone(),
+        }),
+        StateStorageKind::File => Box::new(FileStateStorage {
+            directory: project.project_location.join(&project.state_config.path),
+        }),
+        StateStorageKind::Redpanda => Box::new(RedpandaStateStorage {
+            redpanda_config: project.redpanda_config.clone(),
+            topic: project.state_config.topic.clone(),
+        }),
+    }
+}
+
+pub struct ClickhouseStateStorage {
+    clickhouse_config: ClickHouseConfig,
+}
+
+impl StateStorage for ClickhouseStateStorage {
+    fn store_infrastructure_map<'a>(
+        &'a self,
+        infra_map: &'a InfrastructureMap,
+    ) -> BoxFuture<'a, Result<(), StateStorageError>> {
+        async move {
+            let mut client = get_pool(&self.clickhouse_config).get_handle().await?;
+            store_infrastructure_map(&mut client, &self.clickhouse_config, infra_map).await
+        }
+        .boxed()
+    }
+
+    fn load_infrastructure_map(
+        &self,
+    ) -> BoxFuture<'_, Result<Option<InfrastructureMap>, StateStorageError>> {
+        async move {
+            let mut client = get_pool(&self.clickhouse_config).get_handle().await?;
+            retrieve_infrastructure_map(&mut client, &self.clickhouse_config).await
+        }
+        .boxed()
+    }
+
+    fn store_application_state<'a>(
+        &'a self,
+        application_state: &'a ApplicationState,
+    ) -> BoxFuture<'a, Result<(), StateStorageError>> {
+        async move {
+            let mut client = get_pool(&self.clickhouse_config).get_handle().await?;
+            store_current_state(&mut client, application_state, &self.clickhouse_config).await
+        }
+        .boxed()
+    }
+
+    fn load_application_state(
+        &self,
+    ) -> BoxFuture<'_, Result<Option<ApplicationState>, StateStorageError>> {
+        async move {
+            let mut client = get_pool(&self.clickhouse_config).get_handle().await?;
+            retrieve_current_state(&mut client, &self.clickhouse_config).await
+        }
+        .boxed()
+    }
+
+    fn store_infra_map_record<'a>(
+        &'a self,
+        record: &'a InfraMapRecord,
+    ) -> BoxFuture<'a, Result<(), StateStorageError>> {
+        async move {
+            let mut client = get_pool(&self.clickhouse_config).get_handle().await?;
+            insert_infra_map_record(&mut client, &self.clickhouse_config, record).await
+        }
+        .boxed()
+    }
+
+    fn load_infra_map_records(
+        &self,
+    ) -> BoxFuture<'_, Result<Vec<InfraMapRecord>, StateStorageError>> {
+        async move {
+            let mut client = get_pool(&self.clickhouse_config).get_handle().await?;
+            retrieve_infra_map_records(&mut client, &self.clickhouse_config).await
+        }
+        .boxed()
+    }
+}
+
+// Stores each part of the state as a JSON file of the directory
+pub struct FileStateStorage {
+    directory: PathBuf,
+}
+
+impl FileStateStorage {
+    fn file(&self, key: &str) -> PathBuf {
+        self.directory.join(format!("{}.json", key))
+    }
+
+    fn store<T: Serialize>(&self, key: &str, value: &T) -> Result<(), StateStorageError> {
+        let file = self.file(key);
+        if let Some(parent) = file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // the file is replaced at once so that a failed write doesn't lose the previous state
+        let temporary_file = file.with_extension("json.tmp");
+        std::fs::write(&temporary_file, serde_json::to_string_pretty(value)?)?;
+        std::fs::rename(&temporary_file, file)?;
+        Ok(())
+    }
+
+    fn load<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, StateStorageError> {
+        load_file(&self.file(key))
+    }
+
+    fn load_records(&self) -> Result<Vec<InfraMapRecord>, StateStorageError> {
+        let history_directory = self.directory.join(HISTORY_KEY);
+        let entries = match std::fs::read_dir(&history_directory) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut records = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+            {
+                records.extend(load_file::<InfraMapRecord>(&path)?);
+            }
+        }
+        records.sort_by_key(|record| record.timestamp);
+        Ok(records)
+    }
+}
+
+fn load_file<T: DeserializeOwned>(path: &Path) -> Result<Option<T>, StateStorageError> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(Some(serde_json::from_str(&content)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+impl StateStorage for FileStateStorage {
+    fn store_infrastructure_map<'a>(
+        &'a self,
+        infra_map: &'a InfrastructureMap,
+    ) -> BoxFuture<'a, Result<(), StateStorageError>> {
+        async move { self.store(INFRA_MAP_KEY, infra_map) }.boxed()
+    }
+
+    fn load_infrastructure_map(
+        &self,
+    ) -> BoxFuture<'_, Result<Option<InfrastructureMap>, StateStorageError>> {
+        async move { self.load(INFRA_MAP_KEY) }.boxed()
+    }
+
+    fn store_application_state<'a>(
+        &'a self,
+        application_state: &'a ApplicationState,
+    ) -> BoxFuture<'a, Result<(), StateStorageError>> {
+        async move { self.store(APPLICATION_STATE_KEY, application_state) }.boxed()
+    }
+
+    fn load_application_state(
+        &self,
+    ) -> BoxFuture<'_, Result<Option<ApplicationState>, StateStorageError>> {
+        async move { self.load(APPLICATION_STATE_KEY) }.boxed()
+    }
+
+    fn store_infra_map_record<'a>(
+        &'a self,
+        record: &'a InfraMapRecord,
+    ) -> BoxFuture<'a, Result<(), StateStorageError>> {
+        async move { self.store(&format!("{}/{}", HISTORY_KEY, record.id), record) }.boxed()
+    }
+
+    fn load_infra_map_records(
+        &self,
+    ) -> BoxFuture<'_, Result<Vec<InfraMapRecord>, StateStorageError>> {
+        async move { self.load_records() }.boxed()
+    }
+}
+
+// Stores each part of the state as the record of its key in a compacted topic
+pub struct RedpandaStateStorage {
+    redpanda_config: RedpandaConfig,
+    topic: String,
+}
+
+impl RedpandaStateStorage {
+    async fn store<T: Serialize + Sync>(
+        &self,
+        key: &str,
+        value: &T,
+    ) -> Result<(), StateStorageError> {
+        create_compacted_topic(&self.redpanda_config, &self.topic).await?;
+        let payload = serde_json::to_vec(value)?;
+        send_keyed(&self.redpanda_config, &self.topic, key, &payload).await?;
+        Ok(())
+    }
+
+    async fn load<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, StateStorageError> {
+        let latest = read_latest_by_key(&self.redpanda_config, &self.topic).await?;
+        match latest.get(key) {
+            Some(payload) => Ok(Some(serde_json::from_slice(payload)?)),
+            None => Ok(None),
+        }
+    }
+
+    // Each record has its own key, so that compaction keeps all of them
+    async fn load_records(&self) -> Result<Vec<InfraMapRecord>, StateStorageError> {
+        let latest = read_latest_by_key(&self.redpanda_config, &self.topic).await?;
+        let prefix = format!("{}/", HISTORY_KEY);
+
+        let mut records = Vec::new();
+        for (key, payload) in latest.iter() {
+            if key.starts_with(&prefix) {
+                records.push(serde_json::from_slice::<InfraMapRecord>(payload)?);
+            }
+        }
+        records.sort_by_key(|record| record.timestamp);
+        Ok(records)
+    }
+}
+
+impl StateStorage for RedpandaStateStorage {
+    fn store_infrastructure_map<'a>(
+        &'a self,
+        infra_map: &'a InfrastructureMap,
+    ) -> BoxFuture<'a, Result<(), StateStorageError>> {
+        self.store(INFRA_MAP_KEY, infra_map).boxed()
+    }
+
+    fn load_infrastructure_map(
+        &self,
+    ) -> BoxFuture<'_, Result<Option<InfrastructureMap>, StateStorageError>> {
+        self.load(INFRA_MAP_KEY).boxed()
+    }
+
+    fn store_application_state<'a>(
+        &'a self,
+        application_state: &'a ApplicationState,
+    ) -> BoxFuture<'a, Result<(), StateStorageError>> {
+        self.store(APPLICATION_STATE_KEY, application_state).boxed()
+    }
+
+    fn load_application_state(
+        &self,
+    ) -> BoxFuture<'_, Result<Option<ApplicationState>, StateStorageError>> {
+        self.load(APPLICATION_STATE_KEY).boxed()
+    }
+
+    fn store_infra_map_record<'a>(
+        &'a self,
+        record: &'a InfraMapRecord,
+    ) -> BoxFuture<'a, Result<(), StateStorageError>> {
+        async move {
+            self.store(&format!("{}/{}", HISTORY_KEY, record.id), record)
+                .await
+        }
+        .boxed()
+    }
+
+    fn load_infra_map_records(
+        &self,
+    ) -> BoxFuture<'_, Result<Vec<InfraMapRecord>, StateStorageError>> {
+        self.load_records().boxed()
+    }
+}
+
+/// The whole state, as written by `moose state export` and read by `moose state import`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StateExport {
+    pub infra_map: Option<InfrastructureMap>,
+    pub application_state: Option<ApplicationState>,
+    #[serde(default)]
+    pub history: Vec<InfraMapRecord>,
+}
+
+pub async fn export_state(storage: &dyn StateStorage) -> Result<StateExport, StateStorageError> {
+    Ok(StateExport {
+        infra_map: storage.load_infrastructure_map().await?,
+        application_state: storage.load_application_state().await?,
+        history: storage.load_infra_map_records().await?,
+    })
+}
+
+pub async fn import_state(
+    storage: &dyn StateStorage,
+    state: &StateExport,
+) -> Result<(), StateStorageError> {
+    if let Some(infra_map) = &state.infra_map {
+        storage.store_infrastructure_map(infra_map).await?;
+    }
+    if let Some(application_state) = &state.application_state {
+        storage.store_application_state(application_state).await?;
+    }
+    for record in state.history.iter() {
+        storage.store_infra_map_record(record).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::framework::core::primitive_map::PrimitiveMap;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_file_state_storage() {
+        let directory = std::env::temp_dir().join(format!("moose-state-{}", uuid::Uuid::new_v4()));
+        let storage = FileStateStorage {
+            directory: directory.clone(),
+        };
+
+        assert!(storage.load_infrastructure_map().await.unwrap().is_none());
+
+        let infra_map = InfrastructureMap::new(PrimitiveMap::default());
+        storage.store_infrastructure_map(&infra_map).await.unwrap();
+        let loaded = storage.load_infrastructure_map().await.unwrap().unwrap();
+        assert_eq!(loaded.hash().unwrap(), infra_map.hash().unwrap());
+
+        for version in ["0.1", "0.2"] {
+            let record = InfraMapRecord {
+                id: uuid::Uuid::new_v4().to_string(),
+                timestamp: chrono::Utc::now(),
+                commit: None,
+                version: version.to_string(),
+                infra_map: infra_map.clone(),
+            };
+            storage.store_infra_map_record(&record).await.unwrap();
+        }
+        let records = storage.load_infra_map_records().await.unwrap();
+        assert_eq!(
+            records
+                .iter()
+                .map(|record| record.version.as_str())
+                .collect::<Vec<_>>(),
+            vec!["0.1", "0.2"]
+        );
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
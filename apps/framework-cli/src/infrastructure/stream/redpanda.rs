@@ -1,10 +1,13 @@
 use log::{error, info, warn};
+use opentelemetry::{global, Context};
 use rdkafka::admin::ResourceSpecifier;
 use rdkafka::config::RDKafkaLogLevel;
 use rdkafka::consumer::stream_consumer::StreamConsumer;
-use rdkafka::consumer::Consumer;
+use rdkafka::consumer::{BaseConsumer, Consumer};
 use rdkafka::error::KafkaError;
+use rdkafka::message::{Header, Headers, Message, OwnedHeaders};
 use rdkafka::producer::{DeliveryFuture, FutureRecord};
+use rdkafka::topic_partition_list::{Offset, TopicPartitionList};
 use rdkafka::types::RDKafkaErrorCode;
 use rdkafka::{
     admin::{AdminClient, AdminOptions, NewTopic, TopicReplication},
@@ -12,7 +15,9 @@ use rdkafka::{
     ClientConfig,
 };
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
 
 use crate::framework::core::infrastructure_map::{Change, StreamingChange};
@@ -44,6 +49,15 @@ pub async fn execute_changes(
             }
 
             StreamingChange::Topic(Change::Removed(topic)) => {
+                if project.is_production {
+                    let snapshot = format!(
+                        "{}_backup_{}",
+                        topic.id(),
+                        chrono::Utc::now().format("%Y%m%d%H%M%S")
+                    );
+                    log::info!("Snapshotting topic: {:?} to {:?}", topic.id(), snapshot);
+                    copy_topic(&project.redpanda_config, &topic.id(), &snapshot).await?;
+                }
                 log::info!("Deleting topic: {:?}", topic.id());
                 delete_topics(&project.redpanda_config, vec![topic.id()]).await?;
             }
@@ -143,6 +157,242 @@ pub async fn delete_topics(
     Ok(())
 }
 
+/// Deletes consumer groups which aren't used anymore. The groups with members are left alone.
+pub async fn delete_consumer_groups(
+    config: &RedpandaConfig,
+    groups: &[&str],
+) -> Result<(), KafkaError> {
+    let admin_client: AdminClient<_> = config_client(config).create()?;
+    let options = AdminOptions::new().operation_timeout(Some(std::time::Duration::from_secs(5)));
+
+    for result in admin_client.delete_groups(groups, &options).await? {
+        match result {
+            Ok(group) => info!("Consumer group {} deleted successfully", group),
+            Err((group, err)) => error!("Failed to delete consumer group {}: {}", group, err),
+        }
+    }
+    Ok(())
+}
+
+/// The low and high watermarks of each partition of the topic, None if the topic doesn't exist.
+/// The calls to the brokers block, so they are made outside of the async runtime.
+async fn fetch_partition_watermarks(
+    config: &RedpandaConfig,
+    topic: &str,
+) -> Result<Option<BTreeMap<i32, (i64, i64)>>, KafkaError> {
+    let client_config = config_client(config);
+    let topic = topic.to_string();
+
+    tokio::task::spawn_blocking(move || {
+        let consumer: BaseConsumer = client_config.create()?;
+        let timeout = Duration::from_secs(5);
+        let metadata = consumer.fetch_metadata(Some(&topic), timeout)?;
+        let partitions = match metadata.topics().iter().find(|t| t.name() == topic) {
+            Some(metadata) if metadata.error().is_none() => metadata.partitions(),
+            _ => return Ok(None),
+        };
+
+        let mut watermarks = BTreeMap::new();
+        for partition in partitions {
+            let partition_watermarks =
+                consumer.fetch_watermarks(&topic, partition.id(), timeout)?;
+            watermarks.insert(partition.id(), partition_watermarks);
+        }
+        Ok(Some(watermarks))
+    })
+    .await
+    .map_err(|_| KafkaError::Canceled)?
+}
+
+/// The assignment reading the partitions with messages from their beginning, with the high
+/// watermark each of them is read up to
+fn assignment_to_end(
+    topic: &str,
+    watermarks: &BTreeMap<i32, (i64, i64)>,
+) -> Result<(TopicPartitionList, HashMap<i32, i64>), KafkaError> {
+    let mut assignment = TopicPartitionList::new();
+    let mut high_watermarks = HashMap::new();
+    for (partition, (low_watermark, high_watermark)) in watermarks {
+        if high_watermark > low_watermark {
+            assignment.add_partition_offset(topic, *partition, Offset::Offset(*low_watermark))?;
+            high_watermarks.insert(*partition, *high_watermark);
+        }
+    }
+    Ok((assignment, high_watermarks))
+}
+
+/// Drops the partition from the ones left to read once the message read is the last one before
+/// its high watermark. The partitions ending with control records of transactions or with
+/// compacted records are dropped when the consumer reaches their end instead.
+fn consumed_up_to(high_watermarks: &mut HashMap<i32, i64>, partition: i32, offset: i64) {
+    if high_watermarks
+        .get(&partition)
+        .is_some_and(|high_watermark| offset + 1 >= *high_watermark)
+    {
+        high_watermarks.remove(&partition);
+    }
+}
+
+/// Copies the committed messages currently in the topic to another topic, created if needed: to
+/// recover them after the topic is deleted, or to publish the staged outputs of a replay
+pub async fn copy_topic(
+    config: &RedpandaConfig,
+    topic: &str,
+    destination_topic: &str,
+) -> anyhow::Result<()> {
+    create_topics(config, vec![destination_topic.to_string()]).await?;
+
+    let watermarks = fetch_partition_watermarks(config, topic)
+        .await?
+        .ok_or_else(|| KafkaError::MetadataFetch(RDKafkaErrorCode::UnknownTopic))?;
+
+    let mut client_config = config_client(config);
+    client_config
+        .set("group.id", format!("{}_copy", destination_topic))
+        .set("enable.auto.commit", "false")
+        .set("enable.partition.eof", "true")
+        .set("isolation.level", "read_committed");
+    let consumer: StreamConsumer = client_config.create()?;
+
+    // the messages are copied up to the high watermark of each partition at the time of the copy
+    let (assignment, mut high_watermarks) = assignment_to_end(topic, &watermarks)?;
+    if high_watermarks.is_empty() {
+        return Ok(());
+    }
+    consumer.assign(&assignment)?;
+
+    let timeout = Duration::from_secs(5);
+    let producer = create_producer(config.clone());
+    while !high_watermarks.is_empty() {
+        let message = match consumer.recv().await {
+            Ok(message) => message,
+            Err(KafkaError::PartitionEOF(partition)) => {
+                high_watermarks.remove(&partition);
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut record = FutureRecord::<[u8], [u8]>::to(destination_topic);
+        if let Some(key) = message.key() {
+            record = record.key(key);
+        }
+        if let Some(payload) = message.payload() {
+            record = record.payload(payload);
+        }
+        if let Some(headers) = message.headers() {
+            record = record.headers(headers.detach());
+        }
+        producer
+            .producer
+            .send(record, timeout)
+            .await
+            .map_err(|(e, _)| e)?;
+
+        consumed_up_to(&mut high_watermarks, message.partition(), message.offset());
+    }
+
+    info!("Topic {} copied to {}", topic, destination_topic);
+    Ok(())
+}
+
+// Large enough for the state of the projects with many data models
+const STATE_MAX_MESSAGE_BYTES: &str = "52428800";
+
+/**
+ * Creates a compacted topic, in which only the last record of each key is kept. Does nothing
+ * if the topic already exists.
+ */
+pub async fn create_compacted_topic(config: &RedpandaConfig, topic: &str) -> anyhow::Result<()> {
+    let admin_client: AdminClient<_> = config_client(config)
+        .create()
+        .expect("Redpanda Admin Client creation failed");
+    let options = AdminOptions::new().operation_timeout(Some(std::time::Duration::from_secs(5)));
+
+    let new_topic = NewTopic::new(topic, 1, TopicReplication::Fixed(1))
+        .set("cleanup.policy", "compact")
+        .set("max.message.bytes", STATE_MAX_MESSAGE_BYTES);
+
+    for result in admin_client.create_topics(&[new_topic], &options).await? {
+        match result {
+            Ok(_) | Err((_, RDKafkaErrorCode::TopicAlreadyExists)) => {}
+            Err((topic_name, err)) => {
+                error!("Failed to create topic {}: {}", topic_name, err);
+                return Err(err.into());
+            }
+        }
+    }
+    Ok(())
+}
+
+pub async fn send_keyed(
+    config: &RedpandaConfig,
+    topic: &str,
+    key: &str,
+    payload: &[u8],
+) -> Result<(), KafkaError> {
+    let mut client_config = config_client(config);
+    client_config.set("message.max.bytes", STATE_MAX_MESSAGE_BYTES);
+    let producer: FutureProducer = client_config.create()?;
+
+    producer
+        .send(
+            FutureRecord::to(topic).key(key).payload(payload),
+            Duration::from_secs(5),
+        )
+        .await
+        .map_err(|(e, _)| e)?;
+    Ok(())
+}
+
+/**
+ * Reads a topic from its beginning and returns the last payload of each key, which is the
+ * content of a compacted topic.
+ */
+pub async fn read_latest_by_key(
+    config: &RedpandaConfig,
+    topic: &str,
+) -> Result<HashMap<String, Vec<u8>>, KafkaError> {
+    // the topic doesn't exist until the state is stored for the first time
+    let watermarks = match fetch_partition_watermarks(config, topic).await? {
+        Some(watermarks) => watermarks,
+        None => return Ok(HashMap::new()),
+    };
+
+    let mut client_config = config_client(config);
+    client_config
+        .set("group.id", format!("{}_reader", topic))
+        .set("enable.auto.commit", "false")
+        .set("enable.partition.eof", "true")
+        .set("fetch.message.max.bytes", STATE_MAX_MESSAGE_BYTES);
+    let consumer: StreamConsumer = client_config.create()?;
+
+    let mut latest = HashMap::new();
+    let (assignment, mut high_watermarks) = assignment_to_end(topic, &watermarks)?;
+    if high_watermarks.is_empty() {
+        return Ok(latest);
+    }
+    consumer.assign(&assignment)?;
+
+    while !high_watermarks.is_empty() {
+        let message = match consumer.recv().await {
+            Ok(message) => message,
+            Err(KafkaError::PartitionEOF(partition)) => {
+                high_watermarks.remove(&partition);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        if let (Some(key), Some(payload)) = (message.key(), message.payload()) {
+            latest.insert(String::from_utf8_lossy(key).to_string(), payload.to_vec());
+        }
+
+        consumed_up_to(&mut high_watermarks, message.partition(), message.offset());
+    }
+
+    Ok(latest)
+}
+
 pub async fn describe_topic_config(
     config: &RedpandaConfig,
     topic_name: &str,
@@ -194,6 +444,35 @@ pub struct RedpandaConfig {
     pub sasl_password: Option<String>,
     pub sasl_mechanism: Option<String>,
     pub security_protocol: Option<String>,
+    // PEM files used to verify the brokers and to authenticate to them over TLS
+    pub ssl_ca_location: Option<String>,
+    pub ssl_certificate_location: Option<String>,
+    pub ssl_key_location: Option<String>,
+}
+
+// Environment variable holding the connection config of the function runners
+pub const KAFKA_CONFIG_ENV: &str = "MOOSE_KAFKA_CONFIG";
+
+impl RedpandaConfig {
+    /**
+     * The connection config of the streaming function runners, as JSON. It is passed in the
+     * environment rather than as arguments so that the credentials don't show up in the list
+     * of processes.
+     */
+    pub fn runner_config_json(&self, client_id: &str) -> String {
+        serde_json::json!({
+            "broker": self.broker,
+            "client_id": client_id,
+            "sasl_username": self.sasl_username,
+            "sasl_password": self.sasl_password,
+            "sasl_mechanism": self.sasl_mechanism,
+            "security_protocol": self.security_protocol,
+            "ssl_ca_location": self.ssl_ca_location,
+            "ssl_certificate_location": self.ssl_certificate_location,
+            "ssl_key_location": self.ssl_key_location,
+        })
+        .to_string()
+    }
 }
 
 impl Default for RedpandaConfig {
@@ -206,6 +485,9 @@ impl Default for RedpandaConfig {
             sasl_password: None,
             sasl_mechanism: None,
             security_protocol: None,
+            ssl_ca_location: None,
+            ssl_certificate_location: None,
+            ssl_key_location: None,
         }
     }
 }
@@ -236,6 +518,15 @@ fn config_client(config: &RedpandaConfig) -> ClientConfig {
     if let Some(security_protocol) = config.clone().security_protocol {
         client_config.set("security.protocol", &security_protocol);
     }
+    if let Some(ca_location) = config.clone().ssl_ca_location {
+        client_config.set("ssl.ca.location", &ca_location);
+    }
+    if let Some(certificate_location) = config.clone().ssl_certificate_location {
+        client_config.set("ssl.certificate.location", &certificate_location);
+    }
+    if let Some(key_location) = config.clone().ssl_key_location {
+        client_config.set("ssl.key.location", &key_location);
+    }
     client_config
 }
 
@@ -283,6 +574,164 @@ pub async fn check_topic_size(topic: &str, config: &RedpandaConfig) -> Result<i6
     Ok(total_count)
 }
 
+/// A client reading the metadata of topics, kept by the tasks checking it periodically. Its calls
+/// block, so they are made outside of the async runtime.
+#[derive(Clone)]
+pub struct MetadataClient {
+    consumer: Arc<BaseConsumer>,
+}
+
+impl MetadataClient {
+    pub fn new(config: &RedpandaConfig) -> Result<Self, KafkaError> {
+        Ok(MetadataClient {
+            consumer: Arc::new(config_client(config).create()?),
+        })
+    }
+
+    pub async fn partition_count(&self, topic: &str) -> Result<usize, KafkaError> {
+        let consumer = self.consumer.clone();
+        let topic = topic.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            let metadata = consumer.fetch_metadata(Some(&topic), Duration::from_secs(5))?;
+            let partitions = metadata
+                .topics()
+                .iter()
+                .find(|t| t.name() == topic)
+                .ok_or_else(|| KafkaError::MetadataFetch(RDKafkaErrorCode::UnknownTopic))?
+                .partitions()
+                .len();
+            Ok(partitions)
+        })
+        .await
+        .map_err(|_| KafkaError::Canceled)?
+    }
+}
+
+/// A position in a topic: an offset in each of its partitions, or the time of the records
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TopicPosition {
+    Offset(i64),
+    // In milliseconds since the epoch
+    Timestamp(i64),
+}
+
+impl FromStr for TopicPosition {
+    type Err = String;
+
+    fn from_str(position: &str) -> Result<Self, Self::Err> {
+        if let Ok(offset) = position.parse::<i64>() {
+            return Ok(TopicPosition::Offset(offset));
+        }
+        chrono::DateTime::parse_from_rfc3339(position)
+            .map(|time| TopicPosition::Timestamp(time.timestamp_millis()))
+            .map_err(|_| format!("{} is neither an offset nor an RFC 3339 time", position))
+    }
+}
+
+/// The offset of each partition of the topic at the position. Positions past the end of a
+/// partition are at its end.
+pub async fn partition_offsets(
+    config: &RedpandaConfig,
+    topic: &str,
+    position: TopicPosition,
+) -> Result<BTreeMap<i32, i64>, KafkaError> {
+    let client: StreamConsumer<_> = config_client(config).create()?;
+    let timeout = Duration::from_secs(5);
+    let metadata = client.fetch_metadata(Some(topic), timeout)?;
+    let partitions = metadata
+        .topics()
+        .iter()
+        .find(|t| t.name() == topic)
+        .ok_or_else(|| KafkaError::MetadataFetch(RDKafkaErrorCode::UnknownTopic))?
+        .partitions();
+
+    let mut times = TopicPartitionList::new();
+    let mut offsets = BTreeMap::new();
+    for partition in partitions {
+        let (low_watermark, high_watermark) =
+            client.fetch_watermarks(topic, partition.id(), timeout)?;
+        match position {
+            TopicPosition::Offset(offset) => {
+                offsets.insert(partition.id(), offset.clamp(low_watermark, high_watermark));
+            }
+            TopicPosition::Timestamp(time) => {
+                // the partitions without records at or after the time stay at their end
+                offsets.insert(partition.id(), high_watermark);
+                times.add_partition_offset(topic, partition.id(), Offset::Offset(time))?;
+            }
+        }
+    }
+
+    if times.count() > 0 {
+        for elem in client.offsets_for_times(times, timeout)?.elements() {
+            if let Offset::Offset(offset) = elem.offset() {
+                offsets.insert(elem.partition(), offset);
+            }
+        }
+    }
+
+    Ok(offsets)
+}
+
+/**
+ * Moves the end of the range of each partition back to the offset following its last committed
+ * record, or to the start of the range when it has none. The control records of transactions
+ * and the aborted records aren't passed to the consumers, which couldn't tell that they reached
+ * the end of a range ending with them. The last committed record is searched in windows growing
+ * backwards from the end of the range.
+ */
+pub async fn committed_range_ends(
+    config: &RedpandaConfig,
+    topic: &str,
+    ranges: &BTreeMap<i32, (i64, i64)>,
+) -> Result<BTreeMap<i32, (i64, i64)>, KafkaError> {
+    let mut client_config = config_client(config);
+    client_config
+        .set("group.id", format!("{}_range_end", topic))
+        .set("enable.auto.commit", "false")
+        .set("enable.partition.eof", "true")
+        .set("isolation.level", "read_committed");
+    let consumer: StreamConsumer = client_config.create()?;
+
+    let mut committed_ranges = BTreeMap::new();
+    for (partition, (start, end)) in ranges {
+        let mut window_end = *end;
+        let mut window_size = 64;
+        let mut last_committed = None;
+        while last_committed.is_none() && window_end > *start {
+            let window_start = (window_end - window_size).max(*start);
+            let mut assignment = TopicPartitionList::new();
+            assignment.add_partition_offset(topic, *partition, Offset::Offset(window_start))?;
+            consumer.assign(&assignment)?;
+
+            // the window is read up to the record following it, or to the end of the partition
+            loop {
+                match consumer.recv().await {
+                    Ok(message) if message.partition() == *partition => {
+                        if message.offset() >= window_end {
+                            break;
+                        }
+                        last_committed = Some(message.offset());
+                    }
+                    Ok(_) => {}
+                    Err(KafkaError::PartitionEOF(eof_partition)) if eof_partition == *partition => {
+                        break
+                    }
+                    Err(KafkaError::PartitionEOF(_)) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            window_end = window_start;
+            window_size *= 2;
+        }
+
+        let committed_end = last_committed.map_or(*start, |offset| offset + 1);
+        committed_ranges.insert(*partition, (*start, committed_end));
+    }
+    Ok(committed_ranges)
+}
+
 pub async fn fetch_topics(
     config: &RedpandaConfig,
 ) -> Result<Vec<String>, rdkafka::error::KafkaError> {
@@ -320,6 +769,72 @@ pub fn create_subscriber(config: &RedpandaConfig, group_id: &str, topic: &str) -
     consumer
 }
 
+/// A subscriber for a transactional copy: its offsets are committed by the producer along with
+/// the copied messages, and it only reads the messages of committed transactions.
+pub fn create_transactional_subscriber(
+    config: &RedpandaConfig,
+    group_id: &str,
+    topic: &str,
+) -> Result<StreamConsumer, KafkaError> {
+    let mut client_config = config_client(config);
+
+    client_config
+        .set("session.timeout.ms", "6000")
+        .set("enable.partition.eof", "false")
+        .set("enable.auto.commit", "false")
+        .set("isolation.level", "read_committed")
+        .set("group.id", group_id);
+
+    let consumer: StreamConsumer = client_config.create()?;
+    consumer.subscribe(&[topic])?;
+
+    Ok(consumer)
+}
+
+/// A producer sending messages in transactions. Initializing its transactions blocks until the
+/// previous producer with the same id is fenced, so it is done outside of the async runtime.
+pub async fn create_transactional_producer(
+    config: &RedpandaConfig,
+    transactional_id: &str,
+) -> Result<FutureProducer, KafkaError> {
+    let mut client_config = config_client(config);
+
+    client_config
+        .set("message.timeout.ms", (5 * 60 * 1000).to_string())
+        .set("enable.idempotence", true.to_string())
+        .set("transactional.id", transactional_id);
+
+    tokio::task::spawn_blocking(move || {
+        let producer: FutureProducer = client_config.create()?;
+        producer.init_transactions(Duration::from_secs(30))?;
+        Ok(producer)
+    })
+    .await
+    .map_err(|_| KafkaError::Canceled)?
+}
+
+/// Number of messages of the topic that the consumer has yet to consume, summed over the
+/// partitions assigned to it
+pub fn consumer_lag(consumer: &StreamConsumer, topic: &str) -> Result<i64, KafkaError> {
+    let timeout = Duration::from_secs(1);
+    let positions = consumer.position()?;
+
+    positions
+        .elements_for_topic(topic)
+        .iter()
+        .map(|elem| {
+            let (low_watermark, high_watermark) =
+                consumer.fetch_watermarks(topic, elem.partition(), timeout)?;
+            // no position until the first message of the partition is consumed
+            let position = match elem.offset() {
+                Offset::Offset(offset) => offset,
+                _ => low_watermark,
+            };
+            Ok((high_watermark - position).max(0))
+        })
+        .sum()
+}
+
 pub async fn wait_for_delivery(topic: &str, future: DeliveryFuture) {
     match future.await {
         Ok(Ok((partition, offset))) => {
@@ -390,3 +905,49 @@ pub async fn send_with_back_pressure(
         }
     }
 }
+
+/// Kafka headers propagating the trace context to the consumers of a record
+pub fn trace_headers(cx: &Context) -> OwnedHeaders {
+    let mut carrier: HashMap<String, String> = HashMap::new();
+    global::get_text_map_propagator(|propagator| propagator.inject_context(cx, &mut carrier));
+
+    carrier
+        .iter()
+        .fold(OwnedHeaders::new(), |headers, (key, value)| {
+            headers.insert(Header {
+                key,
+                value: Some(value),
+            })
+        })
+}
+
+/// The trace context propagated in the headers of a record, empty if the record isn't traced
+pub fn trace_context<M: Message>(message: &M) -> Context {
+    let carrier: HashMap<String, String> = match message.headers() {
+        Some(headers) => headers
+            .iter()
+            .filter_map(|header| {
+                let value = std::str::from_utf8(header.value?).ok()?;
+                Some((header.key.to_string(), value.to_string()))
+            })
+            .collect(),
+        None => return Context::new(),
+    };
+
+    global::get_text_map_propagator(|propagator| propagator.extract(&carrier))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topic_position_from_offset_or_time() {
+        assert_eq!("42".parse(), Ok(TopicPosition::Offset(42)));
+        assert_eq!(
+            "2024-01-01T00:00:01Z".parse(),
+            Ok(TopicPosition::Timestamp(1_704_067_201_000))
+        );
+        assert!("yesterday".parse::<TopicPosition>().is_err());
+    }
+}
@@ -1,10 +1,14 @@
+use prometheus_client::metrics::counter::Counter;
 use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
 use prometheus_client::{
     encoding::{text::encode, EncodeLabelSet},
     metrics::histogram::Histogram,
     registry::Registry,
 };
-use std::{path::PathBuf, sync::Arc, time::Duration};
+use std::{collections::HashMap, path::PathBuf, sync::Arc, time::Duration};
+
+use crate::framework::streaming::model::{FunctionState, FunctionStatus};
 
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
@@ -15,7 +19,42 @@ pub enum MetricsErrors {
 
 pub enum MetricsMessage {
     GetMetricsRegistryAsString(tokio::sync::oneshot::Sender<String>),
+    GetStreamingFunctionStatuses(tokio::sync::oneshot::Sender<Vec<FunctionStatus>>),
     HTTPLatency((PathBuf, Duration, String)),
+    IngestedRecords {
+        data_model: String,
+        records: u64,
+        bytes: u64,
+    },
+    ProduceErrors {
+        topic: String,
+        records: u64,
+    },
+    ConsumerLag {
+        topic: String,
+        consumer_group: String,
+        lag: i64,
+    },
+    InsertedRows {
+        table: String,
+        rows: u64,
+    },
+    FailedRows {
+        table: String,
+        rows: u64,
+    },
+    InserterBufferDepth {
+        table: String,
+        depth: i64,
+    },
+    InserterFlushDuration {
+        table: String,
+        duration: Duration,
+    },
+    StreamingFunctionRestart {
+        function: String,
+    },
+    StreamingFunctionStatus(FunctionStatus),
 }
 
 #[derive(Clone)]
@@ -26,6 +65,19 @@ pub struct Metrics {
 pub struct Statistics {
     pub total_latency_histogram: Histogram,
     pub histogram_family: Family<Labels, Histogram>,
+    pub ingested_records: Family<DataModelLabels, Counter>,
+    pub ingested_bytes: Family<DataModelLabels, Counter>,
+    pub produce_errors: Family<TopicLabels, Counter>,
+    pub consumer_lag: Family<ConsumerLabels, Gauge>,
+    pub inserted_rows: Family<TableLabels, Counter>,
+    pub failed_rows: Family<TableLabels, Counter>,
+    pub inserter_buffer_depth: Family<TableLabels, Gauge>,
+    pub inserter_flush_duration: Family<TableLabels, Histogram>,
+    pub streaming_function_restarts: Family<FunctionLabels, Counter>,
+    pub streaming_function_state: Family<FunctionStateLabels, Gauge>,
+    pub streaming_function_exit_code: Family<FunctionWorkerLabels, Gauge>,
+    // Current status of the streaming function workers, by function and worker
+    pub streaming_function_statuses: HashMap<String, FunctionStatus>,
     pub registry: Option<Registry>,
 }
 
@@ -35,6 +87,54 @@ pub struct Labels {
     path: String,
 }
 
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct DataModelLabels {
+    data_model: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct TopicLabels {
+    topic: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct ConsumerLabels {
+    topic: String,
+    consumer_group: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct TableLabels {
+    table: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct FunctionLabels {
+    function: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct FunctionWorkerLabels {
+    function: String,
+    worker: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct FunctionStateLabels {
+    function: String,
+    worker: String,
+    state: String,
+}
+
+const FUNCTION_STATES: [(FunctionState, &str); 3] = [
+    (FunctionState::Running, "running"),
+    (FunctionState::Restarting, "restarting"),
+    (FunctionState::Failed, "failed"),
+];
+
+const FLUSH_DURATION_BUCKETS: [f64; 10] =
+    [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0, 10.0];
+
 impl Metrics {
     pub fn new() -> (Metrics, tokio::sync::mpsc::Receiver<MetricsMessage>) {
         let (tx, rx) = tokio::sync::mpsc::channel(32);
@@ -58,6 +158,18 @@ impl Metrics {
         Ok(resp_rx.await?)
     }
 
+    pub async fn get_streaming_function_statuses(
+        &self,
+    ) -> Result<Vec<FunctionStatus>, MetricsErrors> {
+        let (resp_tx, resp_rx) = tokio::sync::oneshot::channel();
+        let _ = self
+            .tx
+            .send(MetricsMessage::GetStreamingFunctionStatuses(resp_tx))
+            .await;
+
+        Ok(resp_rx.await?)
+    }
+
     pub async fn start_listening_to_metrics(
         self: &Arc<Metrics>,
         mut rx: tokio::sync::mpsc::Receiver<MetricsMessage>,
@@ -79,6 +191,20 @@ impl Metrics {
                     .into_iter(),
                 )
             }),
+            ingested_records: Family::default(),
+            ingested_bytes: Family::default(),
+            produce_errors: Family::default(),
+            consumer_lag: Family::default(),
+            inserted_rows: Family::default(),
+            failed_rows: Family::default(),
+            inserter_buffer_depth: Family::default(),
+            inserter_flush_duration: Family::<TableLabels, Histogram>::new_with_constructor(|| {
+                Histogram::new(FLUSH_DURATION_BUCKETS.into_iter())
+            }),
+            streaming_function_restarts: Family::default(),
+            streaming_function_state: Family::default(),
+            streaming_function_exit_code: Family::default(),
+            streaming_function_statuses: HashMap::new(),
             registry: Some(Registry::default()),
         };
         let mut new_registry = data.registry.unwrap();
@@ -92,6 +218,61 @@ impl Metrics {
             "Latency of HTTP requests",
             data.histogram_family.clone(),
         );
+        new_registry.register(
+            "ingested_records",
+            "Records received by the ingest endpoints",
+            data.ingested_records.clone(),
+        );
+        new_registry.register(
+            "ingested_bytes",
+            "Bytes received by the ingest endpoints",
+            data.ingested_bytes.clone(),
+        );
+        new_registry.register(
+            "produce_errors",
+            "Records that could not be sent to their topic",
+            data.produce_errors.clone(),
+        );
+        new_registry.register(
+            "consumer_lag",
+            "Messages left to consume in a topic by a consumer group",
+            data.consumer_lag.clone(),
+        );
+        new_registry.register(
+            "inserted_rows",
+            "Rows inserted in ClickHouse tables",
+            data.inserted_rows.clone(),
+        );
+        new_registry.register(
+            "failed_rows",
+            "Rows that could not be inserted in ClickHouse tables",
+            data.failed_rows.clone(),
+        );
+        new_registry.register(
+            "inserter_buffer_depth",
+            "Records waiting to be inserted in ClickHouse tables",
+            data.inserter_buffer_depth.clone(),
+        );
+        new_registry.register(
+            "inserter_flush_duration",
+            "Duration of the flushes of records to ClickHouse tables",
+            data.inserter_flush_duration.clone(),
+        );
+        new_registry.register(
+            "streaming_function_restarts",
+            "Restarts of streaming function processes",
+            data.streaming_function_restarts.clone(),
+        );
+        new_registry.register(
+            "streaming_function_state",
+            "State of the streaming function processes, 1 for the current state",
+            data.streaming_function_state.clone(),
+        );
+        new_registry.register(
+            "streaming_function_exit_code",
+            "Exit code of the last exit of streaming function processes",
+            data.streaming_function_exit_code.clone(),
+        );
 
         data.registry = Some(new_registry);
 
@@ -101,6 +282,12 @@ impl Metrics {
                     MetricsMessage::GetMetricsRegistryAsString(v) => {
                         let _ = v.send(formatted_registry(&data.registry.as_ref()).await);
                     }
+                    MetricsMessage::GetStreamingFunctionStatuses(v) => {
+                        let mut statuses: Vec<FunctionStatus> =
+                            data.streaming_function_statuses.values().cloned().collect();
+                        statuses.sort_by(|a, b| a.function.cmp(&b.function));
+                        let _ = v.send(statuses);
+                    }
                     MetricsMessage::HTTPLatency((path, duration, method)) => {
                         data.histogram_family
                             .get_or_create(&Labels {
@@ -110,6 +297,84 @@ impl Metrics {
                             .observe(duration.as_secs_f64());
                         data.total_latency_histogram.observe(duration.as_secs_f64())
                     }
+                    MetricsMessage::IngestedRecords {
+                        data_model,
+                        records,
+                        bytes,
+                    } => {
+                        let labels = DataModelLabels { data_model };
+                        data.ingested_records.get_or_create(&labels).inc_by(records);
+                        data.ingested_bytes.get_or_create(&labels).inc_by(bytes);
+                    }
+                    MetricsMessage::ProduceErrors { topic, records } => {
+                        data.produce_errors
+                            .get_or_create(&TopicLabels { topic })
+                            .inc_by(records);
+                    }
+                    MetricsMessage::ConsumerLag {
+                        topic,
+                        consumer_group,
+                        lag,
+                    } => {
+                        data.consumer_lag
+                            .get_or_create(&ConsumerLabels {
+                                topic,
+                                consumer_group,
+                            })
+                            .set(lag);
+                    }
+                    MetricsMessage::InsertedRows { table, rows } => {
+                        data.inserted_rows
+                            .get_or_create(&TableLabels { table })
+                            .inc_by(rows);
+                    }
+                    MetricsMessage::FailedRows { table, rows } => {
+                        data.failed_rows
+                            .get_or_create(&TableLabels { table })
+                            .inc_by(rows);
+                    }
+                    MetricsMessage::InserterBufferDepth { table, depth } => {
+                        data.inserter_buffer_depth
+                            .get_or_create(&TableLabels { table })
+                            .set(depth);
+                    }
+                    MetricsMessage::InserterFlushDuration { table, duration } => {
+                        data.inserter_flush_duration
+                            .get_or_create(&TableLabels { table })
+                            .observe(duration.as_secs_f64());
+                    }
+                    MetricsMessage::StreamingFunctionRestart { function } => {
+                        data.streaming_function_restarts
+                            .get_or_create(&FunctionLabels { function })
+                            .inc();
+                    }
+                    MetricsMessage::StreamingFunctionStatus(status) => {
+                        let worker = status.worker.to_string();
+                        for (state, name) in FUNCTION_STATES {
+                            data.streaming_function_state
+                                .get_or_create(&FunctionStateLabels {
+                                    function: status.function.clone(),
+                                    worker: worker.clone(),
+                                    state: name.to_string(),
+                                })
+                                .set((status.state == state) as i64);
+                        }
+                        if let Some(exit_code) = status.last_exit_code {
+                            data.streaming_function_exit_code
+                                .get_or_create(&FunctionWorkerLabels {
+                                    function: status.function.clone(),
+                                    worker: worker.clone(),
+                                })
+                                .set(exit_code as i64);
+                        }
+
+                        let key = format!("{}/{}", status.function, worker);
+                        if status.state == FunctionState::Stopped {
+                            data.streaming_function_statuses.remove(&key);
+                        } else {
+                            data.streaming_function_statuses.insert(key, status);
+                        }
+                    }
                 };
             }
         });
@@ -33,6 +33,7 @@ use crate::cli::settings::Features;
 use crate::framework::languages::SupportedLanguages;
 use crate::framework::python::templates::PTYHON_BASE_AGG_SAMPLE_TEMPLATE;
 use crate::framework::python::templates::PTYHON_BASE_BLOCKS_SAMPLE_TEMPLATE;
+use crate::framework::python::templates::PYTHON_BASE_APIS_SAMPLE_TEMPLATE;
 use crate::framework::python::templates::PYTHON_BASE_MODEL_TEMPLATE;
 use crate::framework::python::templates::PYTHON_BASE_STREAMING_FUNCTION_TEMPLATE;
 use crate::framework::streaming::loader::{
@@ -52,6 +53,9 @@ use crate::infrastructure::olap::clickhouse::version_sync::{parse_version, versi
 use crate::infrastructure::stream::redpanda::RedpandaConfig;
 use crate::project::typescript_project::TypescriptProject;
 
+use crate::framework::core::guardrails::MigrationConfig;
+use crate::framework::streaming::model::StreamingFunctionConfig;
+use crate::infrastructure::state_storage::StateConfig;
 use crate::utilities::constants::BLOCKS_DIR;
 use crate::utilities::constants::CLI_DEV_CLICKHOUSE_VOLUME_DIR_CONFIG_SCRIPTS;
 use crate::utilities::constants::CLI_DEV_CLICKHOUSE_VOLUME_DIR_CONFIG_USERS;
@@ -67,7 +71,7 @@ use crate::utilities::constants::{
     AGGREGATIONS_DIR, CONSUMPTION_DIR, FUNCTIONS_DIR, OLD_PROJECT_CONFIG_FILE,
     SAMPLE_STREAMING_FUNCTION_DEST, SAMPLE_STREAMING_FUNCTION_SOURCE, TS_FLOW_FILE,
 };
-use crate::utilities::constants::{API_FILE, PYTHON_INIT_FILE};
+use crate::utilities::constants::{API_FILE, PYTHON_INIT_FILE, PY_API_FILE};
 use crate::utilities::constants::{APP_DIR, APP_DIR_LAYOUT, CLI_PROJECT_INTERNAL_DIR, SCHEMAS_DIR};
 use crate::utilities::constants::{VSCODE_DIR, VSCODE_EXT_FILE, VSCODE_SETTINGS_FILE};
 use crate::utilities::git::GitConfig;
@@ -100,6 +104,12 @@ pub struct Project {
     pub http_server_config: LocalWebserverConfig,
     #[serde(default)]
     pub git_config: GitConfig,
+    #[serde(default)]
+    pub migration_config: MigrationConfig,
+    #[serde(default)]
+    pub state_config: StateConfig,
+    #[serde(default)]
+    pub streaming_function_config: StreamingFunctionConfig,
 
     // This part of the configuration for the project is dynamic and not saved
     // to disk. It is loaded from the language specific configuration file or the currently
@@ -172,6 +182,9 @@ impl Project {
             language_project_config,
             supported_old_versions: HashMap::new(),
             git_config: GitConfig::default(),
+            migration_config: MigrationConfig::default(),
+            state_config: StateConfig::default(),
+            streaming_function_config: StreamingFunctionConfig::default(),
         }
     }
 
@@ -251,7 +264,6 @@ impl Project {
     pub fn create_base_app_files(&self, features: &Features) -> Result<(), std::io::Error> {
         // Common file paths
         let readme_file_path = self.project_location.join("README.md");
-        let apis_file_path = self.consumption_dir().join(API_FILE);
         let aggregations_dir = if features.blocks {
             self.blocks_dir()
         } else {
@@ -262,7 +274,6 @@ impl Project {
             &readme_file_path,
             README_PREFIX.to_owned() + include_str!("../../../README.md"),
         )?;
-        self.write_file(&apis_file_path, BASE_APIS_SAMPLE_TEMPLATE.to_string())?;
 
         match self.language {
             SupportedLanguages::Typescript => {
@@ -272,9 +283,11 @@ impl Project {
                     SAMPLE_STREAMING_FUNCTION_SOURCE, SAMPLE_STREAMING_FUNCTION_DEST
                 ));
                 let aggregations_file_path = aggregations_dir.join(TS_AGGREGATIONS_FILE);
+                let apis_file_path = self.consumption_dir().join(API_FILE);
 
                 // Write TypeScript specific templates
                 self.write_file(&base_model_file_path, TS_BASE_MODEL_TEMPLATE.to_string())?;
+                self.write_file(&apis_file_path, BASE_APIS_SAMPLE_TEMPLATE.to_string())?;
                 self.write_file(
                     &function_file_path,
                     TS_BASE_STREAMING_FUNCTION_SAMPLE_TEMPLATE.to_string(),
@@ -298,12 +311,17 @@ impl Project {
                     SAMPLE_STREAMING_FUNCTION_SOURCE, SAMPLE_STREAMING_FUNCTION_DEST
                 ));
                 let aggregations_file_path = aggregations_dir.join(PY_AGGREGATIONS_FILE);
+                let apis_file_path = self.consumption_dir().join(PY_API_FILE);
 
                 // Write Python specific templates
                 self.write_file(
                     &base_model_file_path,
                     PYTHON_BASE_MODEL_TEMPLATE.to_string(),
                 )?;
+                self.write_file(
+                    &apis_file_path,
+                    PYTHON_BASE_APIS_SAMPLE_TEMPLATE.to_string(),
+                )?;
                 self.write_file(
                     &function_file_path,
                     PYTHON_BASE_STREAMING_FUNCTION_TEMPLATE.to_string(),
@@ -509,6 +527,24 @@ impl Project {
         Ok(old_base_path)
     }
 
+    /// Snapshots of the app directory of the old versions, one directory per version, for their
+    /// consumption apis. The whole app directory is kept for the relative imports of the apis,
+    /// e.g. of the data models, to resolve in the snapshot. Kept under the versions directory so
+    /// that they are packaged with the data models.
+    pub fn old_versions_consumption_dir(&self) -> PathBuf {
+        self.project_location
+            .join(CLI_PROJECT_INTERNAL_DIR)
+            .join(CLI_INTERNAL_VERSIONS_DIR)
+            .join(CONSUMPTION_DIR)
+    }
+
+    /// The consumption apis of an old version, in the snapshot of its app directory.
+    pub fn old_version_consumption_dir(&self, version: &str) -> PathBuf {
+        self.old_versions_consumption_dir()
+            .join(version)
+            .join(CONSUMPTION_DIR)
+    }
+
     pub fn delete_old_versions(&self) -> Result<(), ProjectFileError> {
         let mut old_versions = self.internal_dir()?;
         old_versions.push(CLI_INTERNAL_VERSIONS_DIR);
@@ -553,11 +589,11 @@ impl Project {
                             .as_ref(),
                     )
                     .iter()
-                    .for_each(|(input, output)| {
+                    .for_each(|(input, outputs)| {
                         functions_map
                             .entry(input.to_string())
                             .or_insert_with(Vec::new)
-                            .push(output.to_string())
+                            .extend(outputs.iter().map(|output| output.to_string()))
                     });
                 } else if let Some((input_model, output_models)) =
                     self.process_function_input(&entry)
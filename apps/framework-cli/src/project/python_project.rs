@@ -21,6 +21,10 @@ pub enum PythonProjectError {
     PythonParserError(#[from] PythonParserError),
 }
 
+// The Kafka client of the streaming function runner. The projects created before the functions
+// ran in Kafka transactions depend on kafka-python-ng instead.
+pub const KAFKA_CLIENT_DEPENDENCY: &str = "confluent-kafka";
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct PythonProject {
@@ -36,7 +40,7 @@ impl Default for PythonProject {
             name: "new_project".to_string(),
             version: "0.0".to_string(),
             dependencies: vec![
-                "kafka-python-ng==2.2.2".to_string(),
+                format!("{}==2.4.0", KAFKA_CLIENT_DEPENDENCY),
                 "clickhouse_connect==0.7.12".to_string(),
             ],
         }
@@ -59,6 +63,17 @@ impl PythonProject {
             .map_err(|_| ConfigError::Message("Failed to load Python project".to_string()))
     }
 
+    // Whether a requirement of the project is the package, whatever its version
+    pub fn depends_on(&self, package: &str) -> bool {
+        self.dependencies.iter().any(|dependency| {
+            let name = dependency
+                .split(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.'))
+                .next()
+                .unwrap_or_default();
+            name.replace('_', "-").eq_ignore_ascii_case(package)
+        })
+    }
+
     pub fn write_to_disk(&self, project_location: &Path) -> Result<(), PythonProjectError> {
         let mut setup_py_location = project_location.to_path_buf();
         setup_py_location.push("setup.py");
@@ -97,5 +112,7 @@ mod tests {
                 "clickhouse_connect==0.7.12".to_string(),
             ]
         );
+        assert!(project.depends_on("clickhouse-connect"));
+        assert!(!project.depends_on(KAFKA_CLIENT_DEPENDENCY));
     }
 }
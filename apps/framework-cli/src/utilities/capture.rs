@@ -32,12 +32,24 @@ pub enum ActivityType {
     DevCommand,
     #[serde(rename = "dockerCommand")]
     DockerCommand,
+    #[serde(rename = "funcBuildCommand")]
+    FuncBuildCommand,
     #[serde(rename = "funcInitCommand")]
     FuncInitCommand,
+    #[serde(rename = "funcReplayCommand")]
+    FuncReplayCommand,
+    #[serde(rename = "funcSwapCommand")]
+    FuncSwapCommand,
+    #[serde(rename = "funcTestCommand")]
+    FuncTestCommand,
+    #[serde(rename = "historyCommand")]
+    HistoryCommand,
     #[serde(rename = "initCommand")]
     InitCommand,
     #[serde(rename = "initTemplateCommand")]
     InitTemplateCommand,
+    #[serde(rename = "lineageCommand")]
+    LineageCommand,
     #[serde(rename = "logsCommand")]
     LogsCommand,
     #[serde(rename = "lsCommand")]
@@ -46,6 +58,10 @@ pub enum ActivityType {
     ProdCommand,
     #[serde(rename = "psCommand")]
     PsCommand,
+    #[serde(rename = "rollbackCommand")]
+    RollbackCommand,
+    #[serde(rename = "stateCommand")]
+    StateCommand,
     #[serde(rename = "stopCommand")]
     StopCommand,
 }
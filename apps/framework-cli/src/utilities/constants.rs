@@ -25,6 +25,8 @@ pub const FUNCTIONS_DIR: &str = "functions";
 pub const AGGREGATIONS_DIR: &str = "aggregations";
 pub const BLOCKS_DIR: &str = "blocks";
 pub const CONSUMPTION_DIR: &str = "apis";
+// The crates of the streaming functions compiled to WebAssembly, next to the app directory
+pub const WASM_FUNCTIONS_DIR: &str = "wasm";
 pub const VSCODE_DIR: &str = ".vscode";
 pub const SAMPLE_STREAMING_FUNCTION_SOURCE: &str = "UserActivity";
 pub const SAMPLE_STREAMING_FUNCTION_DEST: &str = "ParsedActivity";
@@ -52,15 +54,19 @@ pub const PY_FLOW_FILE: &str = "flow.py";
 pub const TS_AGGREGATIONS_FILE: &str = "DailyActiveUsers.ts";
 pub const PY_AGGREGATIONS_FILE: &str = "DailyActiveUsers.py";
 pub const API_FILE: &str = "dailyActiveUsers.ts";
+pub const PY_API_FILE: &str = "dailyActiveUsers.py";
 
 pub const VSCODE_EXT_FILE: &str = "extensions.json";
 pub const VSCODE_SETTINGS_FILE: &str = "settings.json";
 
 pub const CTX_SESSION_ID: &str = "session_id";
 
+pub const TRACER_NAME: &str = "moose";
+
 pub const PYTHON_FILE_EXTENSION: &str = "py";
 pub const TYPESCRIPT_FILE_EXTENSION: &str = "ts";
 pub const SQL_FILE_EXTENSION: &str = "sql";
+pub const WASM_FILE_EXTENSION: &str = "wasm";
 
 pub const PYTHON_INIT_FILE: &str = "__init__.py";
 
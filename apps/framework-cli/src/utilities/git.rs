@@ -120,15 +120,17 @@ pub fn create_init_commit(project: Arc<Project>, dir_path: &Path) {
         .expect("Failed to create initial commit");
 }
 
+/// Dumps the content of `source_dir` as it was at `commit_hash` into `dest`.
+/// `source_dir` is a directory of the project, e.g. the data models or the consumption apis.
 pub fn dump_old_version_schema(
     project: &Project,
     commit_hash: String,
+    source_dir: &Path,
     dest: &Path,
 ) -> Result<(), Error> {
     let repo = Repository::discover(project.project_location.clone())?;
 
-    let schema_dir = project.data_models_dir();
-    let schema_relative_path_from_repo_root = schema_dir
+    let schema_relative_path_from_repo_root = source_dir
         .strip_prefix(repo.path().parent().unwrap())
         .unwrap();
 
@@ -189,6 +191,15 @@ pub fn current_commit_hash(project: &Project) -> Result<String, Error> {
     Ok(hash)
 }
 
+// The commit checked out in the project, whatever its branch
+pub fn head_commit_hash(project: &Project) -> Result<String, Error> {
+    let repo = Repository::discover(project.project_location.clone())?;
+    let head = repo.head()?.peel_to_commit()?;
+    let mut hash = head.id().to_string();
+    hash.truncate(7);
+    Ok(hash)
+}
+
 fn file_system_error_to_git_error(err: std::io::Error) -> Error {
     Error::new(
         ErrorCode::GenericError,
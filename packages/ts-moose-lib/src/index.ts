@@ -25,9 +25,15 @@ export type DataModelConfig<T> = Partial<{
   storage: {
     enabled?: boolean;
     order_by_fields?: (keyof T)[];
+    // new field name -> previous field name
+    renamed_fields?: Partial<Record<keyof T, string>>;
+  };
+  egress: {
+    enabled?: boolean;
   };
 }>;
 
 export * from "./blocks";
 export * from "./commons";
 export * from "./consumption-helpers";
+export * from "./tracing";
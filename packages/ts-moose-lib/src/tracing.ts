@@ -0,0 +1,101 @@
+import {
+  Attributes,
+  Context,
+  ROOT_CONTEXT,
+  SpanKind,
+  SpanStatusCode,
+  TextMapGetter,
+  propagation,
+  trace,
+} from "@opentelemetry/api";
+import { W3CTraceContextPropagator } from "@opentelemetry/core";
+import { OTLPTraceExporter } from "@opentelemetry/exporter-trace-otlp-http";
+import { Resource } from "@opentelemetry/resources";
+import {
+  BasicTracerProvider,
+  BatchSpanProcessor,
+} from "@opentelemetry/sdk-trace-base";
+import { IHeaders } from "kafkajs";
+import process from "node:process";
+
+// Set by the moose CLI when it exports traces
+const TRACES_ENDPOINT_ENV = "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT";
+const TRACER_NAME = "moose";
+
+let tracerProvider: BasicTracerProvider | undefined;
+
+/**
+ * Spans are only exported when moose is configured to export traces. The trace context
+ * is propagated in the Kafka headers either way.
+ */
+export const setupTracing = (serviceName: string): void => {
+  if (process.env[TRACES_ENDPOINT_ENV]) {
+    tracerProvider = new BasicTracerProvider({
+      resource: new Resource({ "service.name": serviceName }),
+    });
+    tracerProvider.addSpanProcessor(
+      new BatchSpanProcessor(new OTLPTraceExporter()),
+    );
+    tracerProvider.register();
+  } else {
+    propagation.setGlobalPropagator(new W3CTraceContextPropagator());
+  }
+};
+
+export const shutdownTracing = async (): Promise<void> => {
+  await tracerProvider?.shutdown();
+};
+
+const kafkaHeadersGetter: TextMapGetter<IHeaders> = {
+  keys: (carrier) => Object.keys(carrier),
+  get: (carrier, key) => {
+    const value = carrier[key];
+    if (value === undefined) {
+      return undefined;
+    }
+    return Array.isArray(value)
+      ? value.map((v) => v.toString())
+      : value.toString();
+  },
+};
+
+export interface KafkaSpan {
+  // Headers propagating the span to the records sent while processing the message
+  headers: Record<string, string>;
+  end: (error?: unknown) => void;
+}
+
+/**
+ * Starts a span for the processing of a Kafka message, continuing the trace propagated
+ * in the headers of the message.
+ */
+export const startKafkaSpan = (
+  name: string,
+  messageHeaders: IHeaders | undefined,
+  attributes: Attributes,
+): KafkaSpan => {
+  const parentContext: Context = propagation.extract(
+    ROOT_CONTEXT,
+    messageHeaders ?? {},
+    kafkaHeadersGetter,
+  );
+  const span = trace
+    .getTracer(TRACER_NAME)
+    .startSpan(name, { kind: SpanKind.CONSUMER, attributes }, parentContext);
+
+  const headers: Record<string, string> = {};
+  propagation.inject(trace.setSpan(parentContext, span), headers);
+
+  return {
+    headers,
+    end: (error?: unknown) => {
+      if (error !== undefined) {
+        span.setStatus({
+          code: SpanStatusCode.ERROR,
+          message: error instanceof Error ? error.message : String(error),
+        });
+      }
+      span.end();
+    },
+  };
+};